@@ -0,0 +1,560 @@
+//! A standalone CPython for machines without one: python-build-standalone's
+//! `install_only` build for the platform, downloaded on request into the
+//! app data dir, checked against the SHA-256 pinned here and unpacked there. `curl` and
+//! `tar` do the transfer and unpacking, as Windows 10 and later ship both
+//! like macOS and Linux do. An interrupted download is resumed from the
+//! `.part` file it left.
+
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::process::Command;
+
+pub const RUNTIME_DIR: &str = "python-runtime";
+pub const PYTHON_VERSION: &str = "3.12.7";
+const RELEASE: &str = "20241016";
+const RELEASE_URL: &str = "https://github.com/astral-sh/python-build-standalone/releases/download";
+/// The SHA-256 of each platform's archive in `RELEASE`, by target triple,
+/// copied from the release's `SHA256SUMS`. Pinned in the source rather than
+/// fetched with the archive, so whoever can replace one can't replace the
+/// other; update it with `RELEASE`. A platform missing here needs the
+/// caller's `sha256`.
+const ARCHIVE_SHA256: &[(&str, &str)] = &[];
+const CURL: &str = "curl";
+const TAR: &str = "tar";
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(30);
+/// A download slower than `STALL_BYTES_PER_SEC` for this long is given up,
+/// leaving its `.part` file to resume from.
+const STALL_TIME: Duration = Duration::from_secs(60);
+const STALL_BYTES_PER_SEC: u32 = 1024;
+const UNPACK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// Of the progress reported, the rest is verifying and unpacking.
+const DOWNLOAD_SHARE: f64 = 90.0;
+/// What curl exits with when the server can't resume a download.
+const CURL_RANGE_ERROR: i32 = 33;
+
+static INSTALLING: AtomicBool = AtomicBool::new(false);
+
+/// Holds `INSTALLING` for one install, clearing it however the install ends,
+/// including a panic or the future being dropped mid-download.
+struct InstallGuard;
+
+impl InstallGuard {
+    fn acquire() -> Option<Self> {
+        (!INSTALLING.swap(true, Ordering::SeqCst)).then_some(InstallGuard)
+    }
+}
+
+impl Drop for InstallGuard {
+    fn drop(&mut self) {
+        INSTALLING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Progress reports: the percentage when it is known, and what is going on.
+pub type Progress<'a> = &'a (dyn Fn(Option<f64>, String) + Send + Sync);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Installed {
+    pub interpreter: PathBuf,
+    /// `false` when the runtime was already there.
+    pub downloaded: bool,
+    /// Whether an interrupted download was picked up again.
+    pub resumed: bool,
+    /// Bytes transferred by this call.
+    pub downloaded_bytes: u64,
+}
+
+/// python-build-standalone's target triple for this platform, if it is
+/// offered.
+fn triple() -> Option<&'static str> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("windows", "x86_64") => Some("x86_64-pc-windows-msvc"),
+        ("macos", "x86_64") => Some("x86_64-apple-darwin"),
+        ("macos", "aarch64") => Some("aarch64-apple-darwin"),
+        ("linux", "x86_64") => Some("x86_64-unknown-linux-gnu"),
+        ("linux", "aarch64") => Some("aarch64-unknown-linux-gnu"),
+        _ => None,
+    }
+}
+
+/// The archive offered for this platform, if any.
+fn asset() -> Option<String> {
+    Some(asset_for(triple()?))
+}
+
+fn asset_for(triple: &str) -> String {
+    format!(
+        "cpython-{}+{}-{}-install_only.tar.gz",
+        PYTHON_VERSION, RELEASE, triple
+    )
+}
+
+/// The digest pinned in `ARCHIVE_SHA256` for `triple`'s archive.
+fn pinned_sha256(triple: &str) -> Option<&'static str> {
+    ARCHIVE_SHA256
+        .iter()
+        .find(|(pinned, _)| *pinned == triple)
+        .map(|(_, sha256)| *sha256)
+}
+
+/// Whether there is a runtime to download for this platform.
+pub fn is_offered() -> bool {
+    asset().is_some()
+}
+
+fn release_url(file: &str) -> String {
+    format!("{}/{}/{}", RELEASE_URL, RELEASE, file.replace('+', "%2B"))
+}
+
+fn install_dir(root: &Path) -> PathBuf {
+    root.join(format!("cpython-{}", PYTHON_VERSION))
+}
+
+/// Where an unpacked archive keeps its interpreter.
+fn interpreter_in(dir: &Path) -> PathBuf {
+    let python = dir.join("python");
+    match cfg!(windows) {
+        true => python.join("python.exe"),
+        false => python.join("bin").join("python3"),
+    }
+}
+
+/// Where the runtime's interpreter is once it is installed under `root`.
+pub fn interpreter(root: &Path) -> PathBuf {
+    interpreter_in(&install_dir(root))
+}
+
+/// Makes sure the runtime is installed under `root`, downloading it from
+/// `url`, or from the release for this platform, first. A `url` of one's
+/// own needs its `sha256`; the release's is checked against `ARCHIVE_SHA256`.
+pub async fn install(
+    root: &Path,
+    url: Option<&str>,
+    sha256: Option<&str>,
+    progress: Progress<'_>,
+) -> Result<Installed, String> {
+    let interpreter = interpreter(root);
+    if interpreter.is_file() {
+        return Ok(Installed {
+            interpreter,
+            downloaded: false,
+            resumed: false,
+            downloaded_bytes: 0,
+        });
+    }
+    let Some(_installing) = InstallGuard::acquire() else {
+        return Err("the bundled Python runtime is already being installed".to_string());
+    };
+    download_and_unpack(root, url, sha256, progress).await
+}
+
+async fn download_and_unpack(
+    root: &Path,
+    url: Option<&str>,
+    sha256: Option<&str>,
+    progress: Progress<'_>,
+) -> Result<Installed, String> {
+    let url = url.map(str::trim).filter(|url| !url.is_empty());
+    let sha256 = sha256
+        .map(str::trim)
+        .filter(|sha256| !sha256.is_empty())
+        .map(str::to_ascii_lowercase);
+    if let Some(sha256) = &sha256 {
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("sha256 is not a SHA-256 digest: {:?}", sha256));
+        }
+    }
+    let (url, expected) = match (url, sha256) {
+        (Some(url), Some(sha256)) => (url.to_string(), sha256),
+        (Some(_), None) => return Err("url needs the archive's sha256".to_string()),
+        (None, sha256) => {
+            let triple = triple().ok_or_else(|| {
+                format!(
+                    "no bundled Python runtime is offered for {} on {}",
+                    std::env::consts::OS,
+                    std::env::consts::ARCH
+                )
+            })?;
+            let asset = asset_for(triple);
+            let expected = match (sha256, pinned_sha256(triple)) {
+                (Some(sha256), _) => sha256,
+                (None, Some(pinned)) => pinned.to_string(),
+                (None, None) => {
+                    return Err(format!(
+                        "no checksum is pinned for {}; pass its sha256",
+                        asset
+                    ))
+                }
+            };
+            (release_url(&asset), expected)
+        }
+    };
+
+    let downloads = root.join("downloads");
+    std::fs::create_dir_all(&downloads)
+        .map_err(|error| format!("failed to create {}: {}", downloads.display(), error))?;
+    let part = downloads.join(format!("{}.part", archive_name(&url)));
+    let (resumed, downloaded_bytes) = download(&url, &part, &expected, progress).await?;
+
+    progress(Some(DOWNLOAD_SHARE + 5.0), "unpacking".to_string());
+    let staging = root.join(format!("cpython-{}.partial", PYTHON_VERSION));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(&staging)
+        .map_err(|error| format!("failed to create {}: {}", staging.display(), error))?;
+    unpack(&part, &staging).await?;
+    if !interpreter_in(&staging).is_file() {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(format!(
+            "{} is not a python-build-standalone install_only archive",
+            url
+        ));
+    }
+    let dir = install_dir(root);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::rename(&staging, &dir)
+        .map_err(|error| format!("failed to move the runtime to {}: {}", dir.display(), error))?;
+    let _ = std::fs::remove_file(&part);
+    progress(Some(100.0), "installed".to_string());
+    Ok(Installed {
+        interpreter: interpreter_in(&dir),
+        downloaded: true,
+        resumed,
+        downloaded_bytes,
+    })
+}
+
+/// Completes `part` from `url` and checks it has the `expected` digest,
+/// returning whether it was resumed and the bytes transferred. A download
+/// that fails is kept to resume from; one with the wrong digest is not.
+async fn download(
+    url: &str,
+    part: &Path,
+    expected: &str,
+    progress: Progress<'_>,
+) -> Result<(bool, u64), String> {
+    let existing = std::fs::metadata(part).map(|meta| meta.len()).unwrap_or(0);
+    // A previous call may have finished downloading but not unpacking.
+    if existing > 0 && sha256_file(part).await? == expected {
+        return Ok((true, 0));
+    }
+    let total = content_length(url).await;
+    let mut resumed = existing > 0;
+    let mut status = fetch(url, part, total, progress).await?;
+    if status.code() == Some(CURL_RANGE_ERROR) {
+        log::warn!("{} can't resume downloads; starting over", url);
+        let _ = std::fs::remove_file(part);
+        resumed = false;
+        status = fetch(url, part, total, progress).await?;
+    }
+    let size = std::fs::metadata(part).map(|meta| meta.len()).unwrap_or(0);
+    let downloaded_bytes = match resumed {
+        true => size.saturating_sub(existing),
+        false => size,
+    };
+    if !status.success() {
+        return Err(format!(
+            "downloading {} failed ({}); calling again resumes it",
+            url,
+            curl_error(&status)
+        ));
+    }
+
+    progress(Some(DOWNLOAD_SHARE), "verifying".to_string());
+    let actual = sha256_file(part).await?;
+    if actual != expected {
+        let _ = std::fs::remove_file(part);
+        return Err(format!(
+            "checksum mismatch for {}: expected {}, got {}; the download was discarded",
+            url, expected, actual
+        ));
+    }
+    Ok((resumed, downloaded_bytes))
+}
+
+/// Runs curl to append the rest of `url` to `part`, reporting how far it
+/// got against `total`.
+async fn fetch(
+    url: &str,
+    part: &Path,
+    total: Option<u64>,
+    progress: Progress<'_>,
+) -> Result<std::process::ExitStatus, String> {
+    let mut command = Command::new(CURL);
+    command
+        .args(["--fail", "--location", "--silent", "--show-error"])
+        .args(["--continue-at", "-", "--retry", "3"])
+        .args(["--connect-timeout", &METADATA_TIMEOUT.as_secs().to_string()])
+        .args(["--speed-limit", &STALL_BYTES_PER_SEC.to_string()])
+        .args(["--speed-time", &STALL_TIME.as_secs().to_string()])
+        .arg("--output")
+        .arg(part)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    crate::process_tree::hide_console(&mut command);
+    let mut child = command.spawn().map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => format!("{} was not found on PATH", CURL),
+        _ => format!("failed to start {}: {}", CURL, error),
+    })?;
+    loop {
+        match tokio::time::timeout(PROGRESS_INTERVAL, child.wait()).await {
+            Ok(status) => return status.map_err(|error| format!("{} failed: {}", CURL, error)),
+            Err(_) => {
+                let size = std::fs::metadata(part).map(|meta| meta.len()).unwrap_or(0);
+                progress(download_percent(size, total), download_message(size, total));
+            }
+        }
+    }
+}
+
+fn download_percent(size: u64, total: Option<u64>) -> Option<f64> {
+    let total = total.filter(|total| *total > 0)?;
+    Some((size as f64 / total as f64).min(1.0) * DOWNLOAD_SHARE)
+}
+
+fn download_message(size: u64, total: Option<u64>) -> String {
+    let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+    match total {
+        Some(total) => format!("downloading {:.1} of {:.1} MB", mb(size), mb(total)),
+        None => format!("downloading {:.1} MB", mb(size)),
+    }
+}
+
+fn curl_error(status: &std::process::ExitStatus) -> String {
+    match status.code() {
+        Some(22) => "the server returned an error".to_string(),
+        Some(28) => "the transfer stalled".to_string(),
+        Some(code) => format!("curl exit code {}", code),
+        None => "curl was killed".to_string(),
+    }
+}
+
+/// The size of what `url` redirects to, from a HEAD request. `None` when the
+/// server doesn't say.
+async fn content_length(url: &str) -> Option<u64> {
+    let headers = run_curl(&["--head"], url).await.ok()?;
+    parse_content_length(&headers)
+}
+
+/// The last response's `Content-Length` in curl's `--head` output, which
+/// has the headers of every redirect in turn.
+fn parse_content_length(headers: &str) -> Option<u64> {
+    headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .rfind(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
+
+async fn run_curl(args: &[&str], url: &str) -> Result<String, String> {
+    let mut command = Command::new(CURL);
+    command
+        .args(["--fail", "--location", "--silent", "--show-error"])
+        .args(args)
+        .arg(url)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    crate::process_tree::hide_console(&mut command);
+    let output = match tokio::time::timeout(METADATA_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(format!("{} was not found on PATH", CURL))
+        }
+        Ok(Err(error)) => return Err(format!("failed to start {}: {}", CURL, error)),
+        Err(_) => return Err(format!("timed out fetching {}", url)),
+    };
+    if !output.status.success() {
+        return Err(format!(
+            "fetching {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The file name for the download of `url`, safe on every platform.
+fn archive_name(url: &str) -> String {
+    let last = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or("python-runtime.tar.gz");
+    last.chars()
+        .map(|c| match c.is_ascii_alphanumeric() || "._+-".contains(c) {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// The lowercase hex SHA-256 of the file at `path`.
+async fn sha256_file(path: &Path) -> Result<String, String> {
+    let path = path.to_path_buf();
+    let digest = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                return Ok::<_, std::io::Error>(hasher.finalize());
+            }
+            hasher.update(&buffer[..read]);
+        }
+    })
+    .await
+    .map_err(|error| format!("checksum failed: {}", error))?
+    .map_err(|error| format!("failed to read the download: {}", error))?;
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Unpacks the archive `part` into `dir`. tar runs in `dir` and gets the
+/// archive by a relative path: a GNU tar ahead of Windows' own on PATH,
+/// like Git's, takes `C:` for a remote host.
+async fn unpack(part: &Path, dir: &Path) -> Result<(), String> {
+    let archive = relative_to(part, dir);
+    let mut command = Command::new(TAR);
+    command
+        .arg("-xzf")
+        .arg(&archive)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    crate::process_tree::hide_console(&mut command);
+    let output = match tokio::time::timeout(UNPACK_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(format!("{} was not found on PATH", TAR))
+        }
+        Ok(Err(error)) => return Err(format!("failed to start {}: {}", TAR, error)),
+        Err(_) => return Err("timed out unpacking the Python runtime".to_string()),
+    };
+    if !output.status.success() {
+        let _ = std::fs::remove_dir_all(dir);
+        return Err(format!(
+            "unpacking the Python runtime failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// `path` from `dir`, when both are under the same parent as they are here.
+fn relative_to(path: &Path, dir: &Path) -> PathBuf {
+    dir.parent()
+        .and_then(|parent| path.strip_prefix(parent).ok())
+        .map(|rest| Path::new("..").join(rest))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_and_headers_are_read() {
+        for (triple, sha256) in ARCHIVE_SHA256 {
+            assert_eq!(sha256.len(), 64, "{}", triple);
+            assert!(sha256.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+            assert_eq!(pinned_sha256(triple), Some(*sha256));
+        }
+        assert_eq!(pinned_sha256("sparc-sun-solaris"), None);
+
+        let headers = "HTTP/2 302\r\ncontent-length: 0\r\nlocation: x\r\n\r\n\
+                       HTTP/2 200\r\nContent-Length: 31457280\r\n\r\n";
+        assert_eq!(parse_content_length(headers), Some(31_457_280));
+        assert_eq!(parse_content_length("HTTP/2 200\r\n"), None);
+        assert_eq!(download_percent(15_728_640, Some(31_457_280)), Some(45.0));
+        assert_eq!(download_percent(1, None), None);
+
+        assert_eq!(
+            archive_name(&release_url("cpython-3.12.7+20241016-x.tar.gz")),
+            "cpython-3.12.7_2B20241016-x.tar.gz"
+        );
+        assert_eq!(archive_name("https://mirror/a b.tgz?token=1"), "a_b.tgz");
+    }
+
+    /// Installs a fake runtime from a `file://` URL, resuming a download cut
+    /// off halfway.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn runtimes_are_verified_resumed_and_unpacked() {
+        let dir = std::env::temp_dir().join(format!("pdd-bundled-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let bin = dir.join("build").join("python").join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        // Big enough that compression leaves something to cut in half.
+        let filler: String = (0..20_000).map(|i| format!("{:x}", i * 7919)).collect();
+        std::fs::write(bin.join("python3"), format!("#!/bin/sh\n# {}\n", filler)).unwrap();
+        let archive = dir.join("runtime.tar.gz");
+        let status = std::process::Command::new(TAR)
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(dir.join("build"))
+            .arg("python")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let url = format!("file://{}", archive.display());
+        let sha256 = sha256_file(&archive).await.unwrap();
+        let root = dir.join("data").join(RUNTIME_DIR);
+        let reports = std::sync::Mutex::new(Vec::new());
+        let progress = |percent: Option<f64>, message: String| {
+            reports.lock().unwrap().push((percent, message));
+        };
+
+        let wrong = install(&root, Some(&url), Some(&"0".repeat(64)), &progress).await;
+        assert!(wrong.unwrap_err().starts_with("checksum mismatch"));
+        assert!(install(&root, Some(&url), None, &progress)
+            .await
+            .unwrap_err()
+            .contains("sha256"));
+
+        let bytes = std::fs::read(&archive).unwrap();
+        let part = root
+            .join("downloads")
+            .join(format!("{}.part", archive_name(&url)));
+        std::fs::write(&part, &bytes[..bytes.len() / 2]).unwrap();
+        let installed = install(&root, Some(&url), Some(&sha256), &progress)
+            .await
+            .unwrap();
+        assert_eq!(installed.interpreter, interpreter(&root));
+        assert!(installed.interpreter.is_file());
+        assert!(installed.downloaded && installed.resumed);
+        assert_eq!(
+            installed.downloaded_bytes,
+            (bytes.len() - bytes.len() / 2) as u64
+        );
+        assert!(!part.exists());
+        assert_eq!(
+            reports.lock().unwrap().last(),
+            Some(&(Some(100.0), "installed".to_string()))
+        );
+
+        let again = install(&root, None, None, &progress).await.unwrap();
+        assert!(!again.downloaded);
+
+        // An install dropped part way, as when the window closes, frees the
+        // next one rather than leaving it "already being installed".
+        let elsewhere = dir.join("elsewhere").join(RUNTIME_DIR);
+        let dropped = install(&elsewhere, Some(&url), Some(&sha256), &progress);
+        assert!(tokio::time::timeout(Duration::ZERO, dropped).await.is_err());
+        assert!(install(&elsewhere, Some(&url), None, &progress)
+            .await
+            .unwrap_err()
+            .contains("sha256"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+//! Opt-in reuse of recent results for runs with identical inputs
+//! (`cache_ttl_ms`). In memory only; entries die with the app.
+
+use crate::commands::{canonical_or_given, RunPythonScriptRequest, RunPythonScriptResponse};
+use crate::python_settings::PythonSettings;
+use crate::settings::TimeoutBounds;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAX_CACHE_ENTRIES: usize = 100;
+const MAX_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Everything that can change what a run prints, returns or writes, or
+/// whether it is allowed at all. Serialized as the cache key, and as the
+/// coalescing key.
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    /// Canonical, so `./a.py` and `/abs/a.py` share entries.
+    script_path: &'a str,
+    module: Option<&'a str>,
+    allow_any_extension: bool,
+    script_extensions: &'a [String],
+    args: &'a [String],
+    env: Option<BTreeMap<&'a String, &'a String>>,
+    inherit_env: Option<bool>,
+    clean_env: bool,
+    strip_env: &'a [String],
+    utf8_io: Option<bool>,
+    python_path: Option<&'a str>,
+    conda_env: Option<&'a str>,
+    py_version: Option<&'a str>,
+    python_requirement: Option<&'a str>,
+    runner: Option<&'a str>,
+    wsl_distro: Option<&'a str>,
+    allow_python2: Option<bool>,
+    use_local_venv: Option<bool>,
+    use_project_env: Option<bool>,
+    local_venv_depth: u32,
+    python_settings: &'a PythonSettings,
+    min_python_version: Option<&'a str>,
+    interpreter_args: &'a [String],
+    working_dir: Option<&'a str>,
+    stdin: Option<&'a str>,
+    json_payload: Option<&'a serde_json::Value>,
+    extra_python_paths: &'a [String],
+    extra_path_dirs: &'a [String],
+    shell_path: Option<&'a str>,
+    output_encoding: Option<&'a str>,
+    output_format: Option<&'a str>,
+    parse_json: Option<bool>,
+    stdout_filter: Option<&'a str>,
+    stderr_filter: Option<&'a str>,
+    stop_on_pattern: Option<&'a str>,
+    keep_scratch: bool,
+    dry_run: bool,
+    stream: bool,
+    stream_line_limit: Option<usize>,
+    max_output_bytes: Option<u64>,
+    capture_combined: bool,
+    stdout_file: Option<&'a str>,
+    stderr_file: Option<&'a str>,
+    append: bool,
+    output_dir: Option<&'a Path>,
+    timeout_ms: Option<u64>,
+    timeout_bounds: TimeoutBounds,
+    grace_period_ms: Option<u64>,
+    idle_timeout_ms: Option<u64>,
+    deadline_ms: Option<u64>,
+    max_memory_bytes: Option<u64>,
+    max_cpu_time_ms: Option<u64>,
+    retries: Option<u32>,
+}
+
+pub fn key(request: &RunPythonScriptRequest) -> String {
+    let script_path = canonical_or_given(request.script_path.trim());
+    let key = CacheKey {
+        script_path: &script_path.to_string_lossy(),
+        module: request.module.as_deref(),
+        allow_any_extension: request.allow_any_extension,
+        script_extensions: &request.script_extensions,
+        args: &request.args,
+        env: request.env.as_ref().map(|env| env.iter().collect()),
+        inherit_env: request.inherit_env,
+        clean_env: request.clean_env,
+        strip_env: &request.strip_env,
+        utf8_io: request.utf8_io,
+        python_path: request.python_path.as_deref(),
+        conda_env: request.conda_env.as_deref(),
+        py_version: request.py_version.as_deref(),
+        python_requirement: request.python_requirement.as_deref(),
+        runner: request.runner.as_deref(),
+        wsl_distro: request.wsl_distro.as_deref(),
+        allow_python2: request.allow_python2,
+        use_local_venv: request.use_local_venv,
+        use_project_env: request.use_project_env,
+        local_venv_depth: request.local_venv_depth,
+        python_settings: &request.python_settings,
+        min_python_version: request.min_python_version.as_deref(),
+        interpreter_args: &request.interpreter_args,
+        working_dir: request.working_dir.as_deref(),
+        stdin: request.stdin.as_deref(),
+        json_payload: request.json_payload.as_ref(),
+        extra_python_paths: &request.extra_python_paths,
+        extra_path_dirs: &request.extra_path_dirs,
+        shell_path: request.shell_path.as_deref(),
+        output_encoding: request.output_encoding.as_deref(),
+        output_format: request.output_format.as_deref(),
+        parse_json: request.parse_json,
+        stdout_filter: request.stdout_filter.as_deref(),
+        stderr_filter: request.stderr_filter.as_deref(),
+        stop_on_pattern: request.stop_on_pattern.as_deref(),
+        keep_scratch: request.keep_scratch,
+        dry_run: request.dry_run,
+        stream: request.stream,
+        stream_line_limit: request.stream_line_limit,
+        max_output_bytes: request.max_output_bytes,
+        capture_combined: request.capture_combined,
+        stdout_file: request.stdout_file.as_deref(),
+        stderr_file: request.stderr_file.as_deref(),
+        append: request.append,
+        output_dir: request.output_dir.as_deref(),
+        timeout_ms: request.timeout_ms,
+        timeout_bounds: request.timeout_bounds,
+        grace_period_ms: request.grace_period_ms,
+        idle_timeout_ms: request.idle_timeout_ms,
+        deadline_ms: request.deadline_ms,
+        max_memory_bytes: request.max_memory_bytes,
+        max_cpu_time_ms: request.max_cpu_time_ms,
+        retries: request.retries,
+    };
+    serde_json::to_string(&key).unwrap_or_default()
+}
+
+#[derive(Debug)]
+struct Entry {
+    script_path: String,
+    response: RunPythonScriptResponse,
+    expires_at: Instant,
+}
+
+/// Recent responses by request key. Managed Tauri state.
+#[derive(Debug, Clone, Default)]
+pub struct ResultCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl ResultCache {
+    pub fn get(&self, key: &str) -> Option<RunPythonScriptResponse> {
+        let mut entries = self.lock();
+        let entry = entries.get(key)?;
+        if entry.expires_at <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+
+        let mut response = entry.response.clone();
+        response.from_cache = true;
+        Some(response)
+    }
+
+    pub fn insert(
+        &self,
+        key: String,
+        script_path: &str,
+        ttl: Duration,
+        response: &RunPythonScriptResponse,
+    ) {
+        let now = Instant::now();
+        let mut entries = self.lock();
+        entries.retain(|_, entry| entry.expires_at > now);
+        if entries.len() >= MAX_CACHE_ENTRIES && !entries.contains_key(&key) {
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+
+        entries.insert(
+            key,
+            Entry {
+                script_path: canonical_or_given(script_path.trim())
+                    .to_string_lossy()
+                    .to_string(),
+                response: response.clone(),
+                expires_at: now + ttl.min(MAX_CACHE_TTL),
+            },
+        );
+    }
+
+    /// Drops the entries for one script, given as in the request or as its
+    /// canonical path, or everything. Returns how many entries were removed.
+    pub fn invalidate(&self, script_path: Option<&str>) -> usize {
+        let mut entries = self.lock();
+        let before = entries.len();
+        match script_path.map(str::trim) {
+            Some(script_path) => {
+                let canonical = canonical_or_given(script_path);
+                let canonical = canonical.to_string_lossy();
+                entries.retain(|_, entry| {
+                    entry.script_path != script_path
+                        && entry.script_path != canonical
+                        && entry.response.script_path.as_deref() != Some(script_path)
+                })
+            }
+            None => entries.clear(),
+        }
+        before - entries.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(args: &[&str], env: &[(&str, &str)]) -> RunPythonScriptRequest {
+        RunPythonScriptRequest {
+            script_path: "/scripts/weather.py".to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            env: Some(
+                env.iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keys_depend_on_inputs_but_not_on_env_order() {
+        let paris = key(&request(&["--city", "Paris"], &[("A", "1"), ("B", "2")]));
+        assert_eq!(
+            paris,
+            key(&request(&["--city", "Paris"], &[("B", "2"), ("A", "1")]))
+        );
+        assert_ne!(paris, key(&request(&["--city", "Oslo"], &[])));
+        assert_ne!(
+            paris,
+            key(&request(&["--city", "Paris"], &[("A", "1"), ("B", "3")]))
+        );
+    }
+
+    #[test]
+    fn keys_cover_output_sinks_and_limits_and_share_one_script() {
+        let base = request(&[], &[]);
+        let plain = key(&base);
+        let changed = [
+            RunPythonScriptRequest {
+                stdout_file: Some("weather.txt".to_string()),
+                ..request(&[], &[])
+            },
+            RunPythonScriptRequest {
+                capture_combined: true,
+                ..request(&[], &[])
+            },
+            RunPythonScriptRequest {
+                timeout_ms: Some(1_000),
+                ..request(&[], &[])
+            },
+            RunPythonScriptRequest {
+                max_memory_bytes: Some(1 << 20),
+                ..request(&[], &[])
+            },
+            RunPythonScriptRequest {
+                allow_any_extension: true,
+                ..request(&[], &[])
+            },
+        ];
+        for request in changed {
+            assert_ne!(plain, key(&request));
+        }
+
+        let dir = std::env::temp_dir().join(format!("pdd-cache-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("weather.py");
+        std::fs::write(&script, "print('sunny')\n").unwrap();
+        let roundabout = dir.join(".").join("weather.py");
+        let named = |path: &Path| RunPythonScriptRequest {
+            script_path: path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        assert_eq!(key(&named(&script)), key(&named(&roundabout)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
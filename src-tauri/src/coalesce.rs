@@ -0,0 +1,90 @@
+//! Single-flight for `coalesce: true` runs: while a run is in progress, an
+//! identical request waits for it instead of starting another interpreter.
+//! Identical by `cache::key`, so requests that write to other files or run
+//! under other limits are never merged.
+
+use crate::commands::RunPythonScriptResponse;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+type Outcome = Result<RunPythonScriptResponse, String>;
+type InFlight = Arc<Mutex<HashMap<String, watch::Receiver<Option<Outcome>>>>>;
+
+/// In-progress runs by request key. Held by the `RunRegistry`.
+#[derive(Debug, Clone, Default)]
+pub struct RunCoalescer {
+    in_flight: InFlight,
+}
+
+pub enum Role {
+    /// No identical run is in progress; this caller runs and reports back.
+    Leader(Leader),
+    /// Wait for the leader's outcome with [`follow`].
+    Follower(watch::Receiver<Option<Outcome>>),
+}
+
+impl RunCoalescer {
+    pub fn join(&self, key: String) -> Role {
+        let mut in_flight = lock(&self.in_flight);
+        if let Some(receiver) = in_flight.get(&key) {
+            return Role::Follower(receiver.clone());
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        in_flight.insert(key.clone(), receiver);
+        Role::Leader(Leader {
+            key: Some(key),
+            sender,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+}
+
+/// Leaves the table when finished or dropped, so a later request never joins
+/// a run that is already over.
+pub struct Leader {
+    key: Option<String>,
+    sender: watch::Sender<Option<Outcome>>,
+    in_flight: InFlight,
+}
+
+impl Leader {
+    pub fn finish(mut self, outcome: &Outcome) {
+        self.leave();
+        let _ = self.sender.send(Some(outcome.clone()));
+    }
+
+    fn leave(&mut self) {
+        if let Some(key) = self.key.take() {
+            lock(&self.in_flight).remove(&key);
+        }
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+/// The leader's outcome, marked as coalesced.
+pub async fn follow(mut receiver: watch::Receiver<Option<Outcome>>) -> Outcome {
+    loop {
+        if let Some(outcome) = receiver.borrow_and_update().clone() {
+            return outcome.map(|mut response| {
+                response.coalesced = true;
+                response
+            });
+        }
+        if receiver.changed().await.is_err() {
+            return Err("the run this request was coalesced with was abandoned".to_string());
+        }
+    }
+}
+
+fn lock(
+    in_flight: &InFlight,
+) -> std::sync::MutexGuard<'_, HashMap<String, watch::Receiver<Option<Outcome>>>> {
+    in_flight.lock().unwrap_or_else(|error| error.into_inner())
+}
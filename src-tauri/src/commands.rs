@@ -1,272 +1,12041 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::process::Stdio;
-use std::time::{Duration, Instant};
-use tokio::io::AsyncReadExt;
-use tokio::process::Command;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
 
-#[derive(Debug, Deserialize)]
+use crate::bundled_python;
+use crate::cache::{self, ResultCache};
+use crate::coalesce::{self, Role};
+use crate::conda;
+use crate::decoding::{self, OutputEncoding};
+use crate::discovery::{self, DiscoveredInterpreter};
+use crate::failure::{self, ErrorLocation};
+use crate::interpreters::{
+    InterpreterInfo, InterpreterInfoCache, InterpreterVersion, PythonVersion, VersionRequirement,
+};
+use crate::json_schema::{self, SchemaViolation};
+use crate::login_shell;
+use crate::metadata::{self, ScriptMetadata};
+use crate::outdated::{self, OutdatedCache, OutdatedPackage};
+use crate::output_filter::{self, LineFilter, StopMarker, StopSignal};
+use crate::pipenv;
+use crate::poetry;
+use crate::process_tree::{self, ProcessTree};
+use crate::profiles::{ProfileStore, ScriptProfile};
+use crate::progress::{Progress, ProgressEvent, ProgressReporter, ProgressSink, PROGRESS_EVENT};
+use crate::py_launcher;
+use crate::pyenv;
+use crate::python_settings::{
+    self, CandidateEntry, PythonSettings, PythonSettingsStore, PythonSettingsView,
+};
+use crate::queue::{Admission, Priority, QueuePosition, RunQueue};
+use crate::resources::{self, ResourceLimits, ResourceMonitor};
+use crate::runner::{self, Runner};
+use crate::runs::{
+    ActiveRunInfo, CancelSignal, KillAllSummary, RunDescription, RunGuard, RunRegistry, RunState,
+    RunTracker,
+};
+use crate::script_watcher::{ScriptChange, ScriptWatcher, SCRIPT_CHANGED_EVENT};
+use crate::settings::{ExecutionSettings, SettingsStore, TimeoutBounds, DEFAULT_SCRIPT_EXTENSIONS};
+use crate::shebang;
+use crate::source_format::{self, SourceIssue};
+use crate::templating::{ArgTemplate, TemplateDirs};
+use crate::termination;
+use crate::traceback::{self, ParsedTraceback};
+use crate::validated_interpreters::{ValidatedInterpreter, ValidatedInterpreters};
+use crate::validation_cache::{self, ValidationCache};
+use crate::virtualenv::{self, Target};
+use crate::wsl;
+
+const SCRIPT_OUTPUT_EVENT: &str = "script-output";
+const DEFAULT_STREAM_LINE_LIMIT: usize = 16 * 1024;
+const DEFAULT_MAX_OUTPUT_BYTES: u64 = 10 * 1024 * 1024;
+const MAX_GRACE_PERIOD_MS: u64 = 30_000;
+const MAX_INLINE_CODE_BYTES: usize = 64 * 1024;
+/// Default allowed directory for `stdout_file` / `stderr_file`, inside the
+/// app data dir.
+const OUTPUT_FILES_DIR: &str = "script-output";
+/// Payloads up to this size travel in `PDD_PAYLOAD_JSON`; larger ones go
+/// through a temp file so they stay clear of OS environment size limits.
+const MAX_ENV_PAYLOAD_BYTES: usize = 8 * 1024;
+const PAYLOAD_JSON_ENV: &str = "PDD_PAYLOAD_JSON";
+const PAYLOAD_FILE_ENV: &str = "PDD_PAYLOAD_FILE";
+const SCRATCH_DIR_ENV: &str = "PDD_SCRATCH_DIR";
+/// How long pipe readers get to finish after a run was killed. A reader can
+/// hang if something outside the process tree still holds the pipe open.
+const KILLED_READER_DRAIN: Duration = Duration::from_millis(1_000);
+const MAX_RETRIES: u32 = 5;
+const DEFAULT_RETRY_DELAY_MS: u64 = 1_000;
+const MAX_RETRY_DELAY_MS: u64 = 30_000;
+/// Wall-clock budget for a run including every retry and delay.
+const DEFAULT_RUN_DEADLINE_MS: u64 = 120_000;
+const MAX_RUN_DEADLINE_MS: u64 = 600_000;
+const MIN_ATTEMPT_TIME: Duration = Duration::from_millis(1_000);
+const MAX_BATCH_SIZE: usize = 64;
+const MAX_VALIDATION_BATCH_SIZE: usize = 500;
+const DEFAULT_VALIDATION_PARALLELISM: usize = 4;
+const MAX_VALIDATION_PARALLELISM: usize = 16;
+const DEFAULT_VALIDATION_FILE_TIMEOUT_MS: u64 = 30_000;
+/// `test_script_output` runs are a quick sanity check, not real work.
+const DEFAULT_OUTPUT_TEST_TIMEOUT_MS: u64 = 10_000;
+/// Creating a venv, pip included, takes 10 to 30 seconds on a slow disk.
+const DEFAULT_VENV_TIMEOUT_MS: u64 = 120_000;
+/// Builds of packages without wheels can take many minutes.
+const DEFAULT_PIP_TIMEOUT_MS: u64 = 15 * 60 * 1_000;
+/// How long `kill_all_runs` waits for running scripts to stop.
+const KILL_ALL_WAIT: Duration = Duration::from_secs(3);
+const MAX_LABELS: usize = 16;
+const MAX_LABEL_BYTES: usize = 256;
+
+#[derive(Debug, Default, Deserialize)]
 pub struct RunPythonScriptRequest {
+    /// Either `script_path` or `module` must be set, not both.
+    #[serde(default)]
     pub script_path: String,
+    /// Installed module to run with `python -m`, e.g. `mytools.fetch_weather`.
+    pub module: Option<String>,
+    /// Run `script_path` whatever its extension.
+    #[serde(default)]
+    pub allow_any_extension: bool,
+    /// May contain placeholders such as `{{date:%Y-%m-%d}}`; see `templating`.
     pub args: Vec<String>,
+    /// An interpreter executable, or the name of an interpreter alias from
+    /// the Python settings. Aliases are looked up first.
     pub python_path: Option<String>,
+    /// A conda environment, by name or prefix, instead of `python_path`.
+    /// On Windows the script runs under `conda run`, so the environment's
+    /// DLLs are found as in an activated shell.
+    pub conda_env: Option<String>,
+    /// A version registered with the Windows `py` launcher, like "3.11" or
+    /// "3.12-32", instead of `python_path`: runs `py -3.11`. Fails, listing
+    /// the registered versions, when the launcher doesn't have it.
+    pub py_version: Option<String>,
+    /// A constraint like ">=3.10,<3.13" instead of `python_path`: runs the
+    /// newest installed Python that satisfies it, outside virtualenvs.
+    /// The pick only changes when the installed Pythons do. Unlike
+    /// `min_python_version` it isn't limited to the usual candidates.
+    pub python_requirement: Option<String>,
+    /// "python" (default), "uv": `uv run --script` with the resolved
+    /// interpreter, which installs what the script's PEP 723 block declares
+    /// first, or "wsl": the `python3` of a WSL distribution, through
+    /// `wsl.exe`, with the script and working directory as
+    /// `/mnt/<drive>/...` paths. uv's own output goes to stderr. For "wsl",
+    /// `python_path` names the interpreter inside the distribution.
+    pub runner: Option<String>,
+    /// For runner "wsl". Defaults to the default distribution.
+    pub wsl_distro: Option<String>,
+    /// Prefer the interpreter of a `.venv`, `venv` or `.env` virtualenv in
+    /// the script's directory or a parent. Defaults to `true`.
+    pub use_local_venv: Option<bool>,
+    /// For a script in a Poetry project (a `pyproject.toml` with
+    /// `[tool.poetry]`): `true` runs it with `poetry run python` from the
+    /// project directory, unless `working_dir` is set. In a Pipenv project
+    /// (a `Pipfile`) it runs with the interpreter of the environment
+    /// `pipenv --venv` reports. Unset only points the project out in
+    /// `warnings`; `false` ignores it.
+    pub use_project_env: Option<bool>,
+    /// e.g. `>=3.10`. Interpreters that don't satisfy it are skipped.
+    /// Overrides the script's `# pdd-requires-python` header.
+    pub min_python_version: Option<String>,
+    /// Allow Python 2 interpreters, which are passed over with a warning
+    /// otherwise. Defaults to the `allow_python2` execution setting.
+    pub allow_python2: Option<bool>,
+    /// Per attempt. Values above the `max_timeout_ms` setting are lowered
+    /// with a warning; values below `min_timeout_ms` are rejected.
     pub timeout_ms: Option<u64>,
+    /// Extra environment variables for the child. Applied after the inherited
+    /// environment, so these keys win. Never echoed back in the response.
+    pub env: Option<HashMap<String, String>>,
+    /// `false` starts the child from an empty environment. Defaults to `true`.
+    pub inherit_env: Option<bool>,
+    /// Inherit only the few variables a script needs to run (see
+    /// `CLEAN_ENV_KEEP`); `env` is still applied on top.
+    #[serde(default)]
+    pub clean_env: bool,
+    /// Set `PYTHONIOENCODING=utf-8` so printing any Unicode text works.
+    /// Defaults to `true` on Windows, where the child would otherwise use the
+    /// ANSI code page; `false` there also decodes output with `auto`. An
+    /// explicit `PYTHONIOENCODING` in `env` wins.
+    pub utf8_io: Option<bool>,
+    /// Directory the script runs in. Defaults to the script's parent directory.
+    pub working_dir: Option<String>,
+    /// Written to the child's stdin, which is closed afterwards so scripts
+    /// reading until EOF terminate.
+    pub stdin: Option<String>,
+    /// Emit `script-output` events line by line while the script runs.
+    #[serde(default)]
+    pub stream: bool,
+    /// Streamed lines longer than this many bytes are split into several events.
+    pub stream_line_limit: Option<usize>,
+    /// Client-chosen id for the run, so it can be cancelled before it returns.
+    /// Generated when omitted.
+    pub run_id: Option<String>,
+    /// Per-stream capture cap. Output past it is drained and discarded.
+    pub max_output_bytes: Option<u64>,
+    /// How long a timed-out script gets to exit after SIGTERM (CTRL_BREAK on
+    /// Windows) before it is force-killed. Defaults to an immediate kill.
+    pub grace_period_ms: Option<u64>,
+    /// Interpreter flags such as `-u` or `-X utf8`, placed after the
+    /// candidate's own `pre_args` and before the script path. For the Windows
+    /// launcher that gives `py -3 -u script.py`: the launcher must see its
+    /// version selector first, so these can never precede it.
+    #[serde(default)]
+    pub interpreter_args: Vec<String>,
+    /// Encoding of the script's output: a label such as `gbk` or `shift_jis`,
+    /// or `auto` for UTF-8 with a fallback to the Windows ANSI code page.
+    pub output_encoding: Option<String>,
+    /// `text` (default) or `binary`. Binary stdout is returned untouched in
+    /// `stdout_base64`, after the `max_output_bytes` cap; stderr stays text.
+    pub output_format: Option<String>,
+    /// Write stdout to this file instead of keeping it in memory. Relative
+    /// paths are resolved against the allowed output directory (the
+    /// `output_dir` setting, or `script-output` in the app data dir), and
+    /// the file must end up inside it. `max_output_bytes` does not apply.
+    pub stdout_file: Option<String>,
+    /// Same as `stdout_file`, for stderr.
+    pub stderr_file: Option<String>,
+    /// Append to `stdout_file` / `stderr_file` instead of truncating them.
+    #[serde(default)]
+    pub append: bool,
+    /// Parse stdout as JSON into `data`. Leading log lines are tolerated.
+    pub parse_json: Option<bool>,
+    /// Also record stdout and stderr lines in arrival order in
+    /// `combined_output`.
+    #[serde(default)]
+    pub capture_combined: bool,
+    /// How long to wait for an execution slot before failing with "queue
+    /// full". Waits indefinitely when unset.
+    pub queue_timeout_ms: Option<u64>,
+    /// `high`, `normal` (default) or `low`. Decides who gets the next free
+    /// execution slot; long-waiting runs are promoted over time.
+    pub priority: Option<String>,
+    /// Re-run the script up to this many times when it exits with a non-zero
+    /// code. Timeouts and cancellations are never retried.
+    pub retries: Option<u32>,
+    /// Delay before the first retry. Defaults to one second.
+    pub retry_delay_ms: Option<u64>,
+    /// Double the delay after every failed retry.
+    #[serde(default)]
+    pub retry_backoff: bool,
+    /// Overall wall-clock budget across all attempts. Each attempt's timeout
+    /// is cut short so the run ends within it.
+    pub deadline_ms: Option<u64>,
+    /// Client-chosen key echoed back verbatim in the response and in every
+    /// streamed output event.
+    pub correlation_id: Option<String>,
+    /// Free-form tags such as `{"widget": "weather"}`, echoed like
+    /// `correlation_id` and shown in `list_active_runs`. At most 16, each
+    /// key and value at most 256 bytes.
+    pub labels: Option<HashMap<String, String>>,
+    /// Regex; matching stdout lines are dropped before they are captured or
+    /// streamed, and count towards `filtered_line_count`.
+    pub stdout_filter: Option<String>,
+    /// Same as `stdout_filter`, for stderr.
+    pub stderr_filter: Option<String>,
+    /// Regex; once a stdout line matches, output up to and including that
+    /// line is kept, the process tree is killed and the run counts as `ok`
+    /// with `stopped_on_pattern` set. Whatever the script would have done
+    /// after printing the line, cleanup and other side effects included,
+    /// does not happen.
+    pub stop_on_pattern: Option<String>,
+    /// Memory cap enforced by the OS: an address-space limit per process on
+    /// Unix, a job memory limit for the whole tree on Windows.
+    pub max_memory_bytes: Option<u64>,
+    /// CPU-time budget, independent of the wall-clock `timeout_ms`. Enforced
+    /// with RLIMIT_CPU on Unix (whole seconds, rounded up) and by polling the
+    /// job's CPU accounting on Windows.
+    pub max_cpu_time_ms: Option<u64>,
+    /// Kill the run when neither stdout nor stderr produced a byte for this
+    /// long. For scripts that hang on a dead connection; leave unset for
+    /// scripts that compute silently.
+    pub idle_timeout_ms: Option<u64>,
+    /// Run at reduced OS priority. Falls back to the `low_priority` execution
+    /// setting when unset.
+    pub low_priority: Option<bool>,
+    /// Windows only: let the script open a console window. Scripts run
+    /// without one by default.
+    pub show_console: Option<bool>,
+    /// Directories prepended to the child's `PYTHONPATH`, ahead of any value
+    /// it already has. The `extra_python_paths` execution setting is
+    /// appended after these.
+    #[serde(default)]
+    pub extra_python_paths: Vec<String>,
+    /// Directories prepended to the child's `PATH`, for the tools the script
+    /// starts. The `extra_path_dirs` execution setting is appended after
+    /// these. Ignored when running inside WSL.
+    #[serde(default)]
+    pub extra_path_dirs: Vec<String>,
+    /// Structured input for the script. Exactly one of two variables is set:
+    /// `PDD_PAYLOAD_JSON` holds the JSON text when it is small, otherwise
+    /// `PDD_PAYLOAD_FILE` names a private temp file containing it. The file
+    /// is deleted once the run is over, whatever the outcome.
+    pub json_payload: Option<serde_json::Value>,
+    /// Every run gets an empty private directory, named by `PDD_SCRATCH_DIR`,
+    /// that is deleted once the run is over. Set this to keep it and get its
+    /// path back in `scratch_dir`.
+    #[serde(default)]
+    pub keep_scratch: bool,
+    /// Reuse a result from an identical earlier run (same script, args, env
+    /// and other inputs) if it is younger than this. `run_python_script`
+    /// only.
+    pub cache_ttl_ms: Option<u64>,
+    /// Also cache runs that did not succeed.
+    #[serde(default)]
+    pub cache_failures: bool,
+    /// Share one execution with an identical request that is already
+    /// running instead of starting another. `run_python_script` only; leave
+    /// off for scripts with side effects.
+    #[serde(default)]
+    pub coalesce: bool,
+    /// Resolve the interpreter and command line and return them in
+    /// `dry_run` without running the script.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Saved profile whose defaults fill in the fields left unset here.
+    pub profile: Option<String>,
+    /// Taken from the profile or the execution settings by the command layer;
+    /// requests can't lift it themselves.
+    #[serde(skip)]
+    pub min_interval_ms: Option<u64>,
+    /// Filled in by the command layer for `{{app_data_dir}}`.
+    #[serde(skip)]
+    pub app_data_dir: Option<PathBuf>,
+    /// Filled in by the command layer; scratch directories go under it.
+    /// Falls back to the system temp dir.
+    #[serde(skip)]
+    pub app_cache_dir: Option<PathBuf>,
+    /// Taken from the execution settings by the command layer.
+    #[serde(skip)]
+    pub timeout_bounds: TimeoutBounds,
+    /// The `output_dir` execution setting.
+    #[serde(skip)]
+    pub output_dir: Option<PathBuf>,
+    /// The `strip_env` execution setting.
+    #[serde(skip)]
+    pub strip_env: Vec<String>,
+    /// The `script_extensions` execution setting. Empty means the defaults.
+    #[serde(skip)]
+    pub script_extensions: Vec<String>,
+    /// The `local_venv_depth` execution setting.
+    #[serde(skip)]
+    pub local_venv_depth: u32,
+    /// The login shell's `PATH`, with the `login_shell_path` execution
+    /// setting.
+    #[serde(skip)]
+    pub shell_path: Option<String>,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Deserialize)]
+pub struct RunPythonCodeRequest {
+    pub code: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub python_path: Option<String>,
+    pub timeout_ms: Option<u64>,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct RunPythonScriptResponse {
+    pub run_id: String,
+    pub correlation_id: Option<String>,
+    pub labels: Option<HashMap<String, String>>,
     pub ok: bool,
+    /// Served from the result cache; `run_id` is the run that produced it.
+    pub from_cache: bool,
+    /// Shared with an identical run that was already in progress.
+    pub coalesced: bool,
+    /// Earliest allowed start (unix ms) when the run was refused for coming
+    /// too soon after the previous one. Nothing ran then, `run_id` is empty
+    /// and `error_kind` is "rate_limited".
+    pub rate_limited_until_ms: Option<u64>,
+    /// Empty in binary mode.
     pub stdout: String,
+    /// Raw stdout, base64-encoded, when `output_format` is `binary`.
+    pub stdout_base64: Option<String>,
     pub stderr: String,
+    /// Set when stdout went to `stdout_file`; `stdout` is empty then.
+    pub stdout_file: Option<OutputFile>,
+    /// Set when stderr went to `stderr_file`; `stderr` is empty then.
+    pub stderr_file: Option<OutputFile>,
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+    /// Encoding that was used to decode stdout and stderr.
+    pub detected_encoding: String,
+    /// `args` after placeholder expansion, as passed to the last attempt.
+    pub expanded_args: Vec<String>,
+    /// Canonical path of the script; `None` for modules and inline code.
+    pub script_path: Option<String>,
+    /// Program followed by every argument of the last attempt.
+    pub resolved_command: Vec<String>,
+    /// Where the interpreter came from: "request" (`python_path`),
+    /// "conda", "py_launcher" (`py_version`), "python_requirement",
+    /// "poetry", "pipenv", "wsl",
+    /// "local_venv", "pyenv", "shebang", "settings" (the global
+    /// `python_path`), "default" or "bundled".
+    pub interpreter_source: Option<String>,
+    /// The interpreter alias `python_path` named; `python_executable` is
+    /// what it resolved to.
+    pub python_alias: Option<String>,
+    /// The virtualenv directory, when a local one was picked.
+    pub local_venv: Option<String>,
+    /// The project's virtualenv, from `poetry env info -p` or
+    /// `pipenv --venv`, for "poetry" and "pipenv". `None` before Poetry has
+    /// created it.
+    pub project_env: Option<String>,
+    /// Version of the interpreter that ran, e.g. "3.11.4". `None` if it
+    /// could not be probed.
+    pub python_version: Option<String>,
+    /// `sys.executable` of that interpreter, so a launcher like `py -3.11`
+    /// is known by the Python it started. `None` if it could not be probed.
+    pub python_executable: Option<String>,
+    /// What its `--version` printed, parsed. `None` if that was unexpected.
+    pub interpreter_version: Option<InterpreterVersion>,
+    /// What `uv --version` printed, for runner "uv".
+    pub runner_version: Option<String>,
+    /// Set instead of running when the request had `dry_run`.
+    pub dry_run: Option<ResolvedRun>,
     pub exit_code: Option<i32>,
+    /// Signal that terminated the interpreter. Unix only.
+    pub signal: Option<i32>,
+    /// Why the run ended abnormally: "timed out", "cancelled", "killed by
+    /// SIGKILL", "crashed: segmentation fault (SIGSEGV)", "exited with code
+    /// 2", ... `None` for a clean exit. This is the field to show; the
+    /// boolean flags are kept for compatibility.
+    pub termination_reason: Option<String>,
+    /// The interpreter itself died (a crash signal or Windows crash status),
+    /// as opposed to the script raising or exiting with an error.
+    pub crashed: bool,
+    /// The `faulthandler` dump from stderr, when the script enabled it and
+    /// the run failed.
+    pub crash_report: Option<String>,
+    /// Failure category for failed runs: "module_not_found",
+    /// "syntax_error", "permission_denied", "timeout", "cancelled",
+    /// "resource_limit", "crashed", "interpreter_not_found",
+    /// "runtime_exception", "architecture_mismatch", "rate_limited" or
+    /// "unknown".
+    pub error_kind: Option<String>,
+    /// What to do about the failure, when the interpreter that ran says
+    /// more than stderr: an extension built for the other architecture.
+    pub error_hint: Option<String>,
+    /// Innermost traceback frame, or where a syntax error was found.
+    pub error_location: Option<ErrorLocation>,
+    /// Module named by a "module_not_found" error.
+    pub missing_module: Option<String>,
+    /// The traceback stderr ends with, for failed runs. `stderr` itself is
+    /// left as it was.
+    pub traceback: Option<ParsedTraceback>,
     pub timed_out: bool,
+    /// Killed after `idle_timeout_ms` without output.
+    pub idle_timed_out: bool,
+    /// The interpreter did not exit on its own and had to be killed.
+    pub force_killed: bool,
+    /// The run failed because it hit `max_memory_bytes`.
+    pub oom_killed: bool,
+    /// The run was killed for using up `max_cpu_time_ms`.
+    pub cpu_limit_exceeded: bool,
+    pub cancelled: bool,
+    /// Covers every attempt and the delays between them.
+    pub duration_ms: u128,
+    /// Where the time of the final attempt went.
+    pub timings: RunTimings,
+    /// Peak resident memory of the interpreter on Linux, peak committed
+    /// memory of the whole process tree on Windows. `None` when unknown.
+    pub peak_memory_bytes: Option<u64>,
+    /// User plus system CPU time. `None` when unknown.
+    pub cpu_time_ms: Option<u64>,
+    /// How many times the script was started, including the first run.
+    pub attempts: u32,
+    /// stderr of the last failed attempt, when a retry followed it.
+    pub retried_stderr: Option<String>,
+    /// Time spent waiting for an execution slot before spawning.
+    pub queued_ms: u64,
+    /// Priority the run was admitted with, after any age-based promotion.
+    pub priority: Priority,
+    /// How the run's place in the queue changed while it waited.
+    pub queue_positions: Vec<QueuePosition>,
+    pub working_dir: String,
+    /// Parsed stdout when `parse_json` was requested and parsing succeeded.
+    pub data: Option<serde_json::Value>,
+    /// Why stdout could not be parsed. Does not affect `ok`.
+    pub parse_error: Option<String>,
+    /// Both streams interleaved by arrival, when `capture_combined` was set.
+    pub combined_output: Option<Vec<OutputLine>>,
+    /// Lines dropped by `stdout_filter` and `stderr_filter` in the final
+    /// attempt.
+    pub filtered_line_count: u64,
+    /// The run's scratch directory, when `keep_scratch` was set.
+    pub scratch_dir: Option<String>,
+    /// A stdout line matched `stop_on_pattern`. `exit_code` is `None` when
+    /// the script was killed for it.
+    pub stopped_on_pattern: bool,
+    /// Last well-formed `##PDD-PROGRESS` line of the final attempt.
+    pub progress: Option<Progress>,
+    /// How many progress lines the final attempt reported.
+    pub progress_events: u64,
+    /// The first entries of the child's `PATH`, when the login shell's
+    /// stood in for the app's or `extra_path_dirs` moved an entry ahead.
+    pub path_head: Option<Vec<String>>,
+    /// Adjustments made without failing the run: a lowered timeout,
+    /// truncated output, a fallback interpreter and the like.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RunPythonScriptsRequest {
+    pub requests: Vec<RunPythonScriptRequest>,
+    /// "parallel" (default) or "sequential".
+    pub mode: Option<String>,
+    /// Sequential mode only: skip the rest of the batch after the first
+    /// script that fails.
+    #[serde(default)]
+    pub stop_on_failure: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchMode {
+    Parallel,
+    Sequential,
+}
+
+impl BatchMode {
+    fn parse(label: Option<&str>) -> Result<Self, String> {
+        match label.map(str::trim).filter(|label| !label.is_empty()) {
+            None => Ok(BatchMode::Parallel),
+            Some(label) if label.eq_ignore_ascii_case("parallel") => Ok(BatchMode::Parallel),
+            Some(label) if label.eq_ignore_ascii_case("sequential") => Ok(BatchMode::Sequential),
+            Some(label) => Err(format!("unsupported mode: {}", label)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchEntry {
+    /// Position in the request list.
+    pub index: usize,
+    pub response: Option<RunPythonScriptResponse>,
+    /// Why the script could not be run at all.
+    pub error: Option<String>,
+    /// Not run because an earlier script failed and `stop_on_failure` was set.
+    pub skipped: bool,
+}
+
+impl BatchEntry {
+    fn new(index: usize, result: Result<RunPythonScriptResponse, String>) -> Self {
+        match result {
+            Ok(response) => BatchEntry {
+                index,
+                response: Some(response),
+                ..Default::default()
+            },
+            Err(error) => BatchEntry {
+                index,
+                error: Some(error),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn succeeded(&self) -> bool {
+        self.response.as_ref().is_some_and(|response| response.ok)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunPythonScriptsResponse {
+    /// In request order.
+    pub results: Vec<BatchEntry>,
+    pub succeeded: usize,
+    /// Scripts that failed or could not be started.
+    pub failed: usize,
+    pub skipped: usize,
+    /// Wall-clock time of the whole batch.
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RunPythonPipelineRequest {
+    /// Run in order; each stage's stdout is the next one's stdin.
+    pub stages: Vec<RunPythonScriptRequest>,
+    /// Budget for the whole pipeline, on top of each stage's own timeout.
+    pub deadline_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PipelineStage {
+    pub index: usize,
+    pub run_id: String,
+    pub ok: bool,
+    pub exit_code: Option<i32>,
+    pub termination_reason: Option<String>,
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunPythonPipelineResponse {
+    pub ok: bool,
+    /// The stages that ran, in order.
+    pub stages: Vec<PipelineStage>,
+    /// Full response of the last stage when every stage succeeded.
+    pub output: Option<RunPythonScriptResponse>,
+    /// The stage that stopped the pipeline.
+    pub failed_stage: Option<usize>,
+    /// stderr of that stage, or why it could not run.
+    pub failed_stderr: Option<String>,
     pub duration_ms: u128,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunTimings {
+    /// Trying interpreters that turned out to be missing.
+    pub resolve_ms: u64,
+    /// Building the command and starting the process.
+    pub spawn_ms: u64,
+    /// From spawn until the process exited.
+    pub execute_ms: u64,
+    /// From exit until all output was read.
+    pub drain_ms: u64,
+}
+
+/// What a dry run would have started.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedRun {
+    pub program: String,
+    pub pre_args: Vec<String>,
+    pub interpreter_args: Vec<String>,
+    /// Script path, `-m <module>` or `<inline code>`.
+    pub script: String,
+    pub args: Vec<String>,
+    pub working_dir: String,
+    /// Names of the variables set for the run, sorted. Values are left out
+    /// since they may hold secrets.
+    pub env_keys: Vec<String>,
+    /// Whether the rest of the app's environment is passed on too.
+    pub inherit_env: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputFile {
+    pub path: String,
+    pub bytes_written: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptOutputEvent {
+    pub run_id: String,
+    pub correlation_id: Option<String>,
+    pub labels: Option<HashMap<String, String>>,
+    /// Position of the run in its `run_python_scripts` batch or
+    /// `run_python_pipeline`.
+    pub batch_index: Option<usize>,
+    pub stream: OutputStream,
+    pub line: String,
+    pub ts_ms: u64,
+}
+
+type OutputSink = Arc<dyn Fn(ScriptOutputEvent) + Send + Sync>;
+
+/// Where a run's events go. Empty for runs with no webview to notify.
+#[derive(Clone, Default)]
+struct EventSinks {
+    /// Only set when the request asked for streaming.
+    output: Option<OutputSink>,
+    progress: Option<ProgressSink>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputLine {
+    pub stream: OutputStream,
+    pub text: String,
+    /// Milliseconds since the interpreter was spawned.
+    pub offset_ms: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StartPythonScriptResponse {
+    /// Empty when the run was refused.
+    pub run_id: String,
+    /// As for runs: set, with `error_kind` "rate_limited", when the
+    /// script's `min_interval_ms` hadn't passed and nothing was started.
+    pub rate_limited_until_ms: Option<u64>,
+    pub termination_reason: Option<String>,
+    pub error_kind: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunStatusResponse {
+    pub run_id: String,
+    pub state: RunState,
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct ValidatePythonScriptRequest {
+    #[serde(default)]
     pub script_path: String,
+    /// Checks that the module is importable instead of validating a file.
+    pub module: Option<String>,
+    /// Accept `script_path` whatever its extension.
+    #[serde(default)]
+    pub allow_any_extension: bool,
+    /// As for runs: a path or an interpreter alias.
     pub python_path: Option<String>,
+    /// As for runs.
+    pub conda_env: Option<String>,
+    /// As for runs.
+    pub py_version: Option<String>,
+    /// As for runs.
+    pub python_requirement: Option<String>,
+    /// As for runs.
+    pub use_local_venv: Option<bool>,
+    /// As for runs.
+    pub use_project_env: Option<bool>,
+    /// "python" (default), "uv" or "wsl". With uv, uv must be installed
+    /// and the script's PEP 723 dependencies are left for uv to install.
+    /// With wsl, the distribution must be installed and the script is
+    /// checked with its `python3`.
+    pub runner: Option<String>,
+    /// As for runs.
+    pub wsl_distro: Option<String>,
+    /// As for runs: overrides the `# pdd-requires-python` header.
+    pub min_python_version: Option<String>,
+    /// As for runs.
+    pub allow_python2: Option<bool>,
+    /// Also compile the script with the resolved interpreter. Nothing is
+    /// written next to the script. Skipped for modules.
+    #[serde(default)]
+    pub check_syntax: bool,
+    /// Also look up every module the script imports at its top level in the
+    /// resolved interpreter, without importing any of them.
+    #[serde(default)]
+    pub check_imports: bool,
+    /// Standard library modules to import in the resolved interpreter, on
+    /// top of the script's `# pdd-requires-stdlib` header. Some Linux
+    /// builds of Python lack `tkinter` or `sqlite3`.
+    #[serde(default)]
+    pub required_stdlib: Vec<String>,
+    /// Rewrite the script as UTF-8 without a byte order mark and with LF
+    /// line endings before validating it. The original is kept as
+    /// `<script>.bak`. Skips the validation cache.
+    #[serde(default)]
+    pub fix: bool,
+    /// The `script_extensions` execution setting. Empty means the defaults.
+    #[serde(skip)]
+    pub script_extensions: Vec<String>,
+    /// The `local_venv_depth` execution setting.
+    #[serde(skip)]
+    pub local_venv_depth: u32,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+    /// What the script last validated with, which tells which Python a
+    /// `python_path` that no longer starts was.
+    #[serde(skip)]
+    pub last_validated: Option<ValidatedInterpreter>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct ValidatePythonScriptResponse {
     pub valid: bool,
     pub message: Option<String>,
+    /// Reused from an earlier validation of the unchanged script.
+    pub from_cache: bool,
+    /// Canonical path of the script, once it was found.
+    pub script_path: Option<String>,
     pub resolved_python: Option<String>,
+    /// The interpreter alias `python_path` named, as for runs.
+    pub python_alias: Option<String>,
+    /// "request", "conda", "py_launcher", "python_requirement", "poetry",
+    /// "pipenv", "wsl", "local_venv", "pyenv", "shebang", "settings",
+    /// "default" or "bundled", as for runs.
+    pub interpreter_source: Option<String>,
+    /// As for runs.
+    pub local_venv: Option<String>,
+    /// As for runs.
+    pub project_env: Option<String>,
+    /// What `uv --version` printed, for runner "uv".
+    pub runner_version: Option<String>,
+    /// What `resolved_python` turned out to be. `None` if it could not be
+    /// probed.
+    pub interpreter_info: Option<InterpreterInfo>,
+    /// As for runs.
+    pub interpreter_version: Option<InterpreterVersion>,
+    /// Interpreters that were tried and could not be used, in order.
+    pub failed_candidates: Vec<CandidateAttempt>,
+    /// Replacements for a `python_path` that wasn't found, best first. Run
+    /// errors carry them too, next to `attempts`.
+    pub python_path_suggestions: Vec<PythonPathSuggestion>,
+    /// Every interpreter that was tried, in order, up to the one used.
+    pub candidates_tried: Vec<CandidateDiagnostic>,
+    /// The bundled runtime's turn came but it isn't installed:
+    /// `ensure_bundled_python` would make it the interpreter used.
+    pub needs_bundled_python: bool,
+    /// `false` only when `check_syntax` found an error.
+    pub syntax_ok: bool,
+    pub syntax_error: Option<ScriptSyntaxError>,
+    /// Top-level imports the interpreter can't find, when `check_imports`
+    /// was set. `message` suggests what to `pip install`.
+    pub missing_modules: Vec<String>,
+    /// Requirements from the script's PEP 723 block that the interpreter
+    /// doesn't have installed, when `check_imports` was set, then required
+    /// standard library modules it can't import.
+    pub missing_dependencies: Vec<MissingDependency>,
+    /// Read from the file itself, so set even when no interpreter is found.
+    /// `None` for modules.
+    pub metadata: Option<ScriptMetadata>,
+    /// Problems that don't make the script invalid, like malformed metadata
+    /// headers.
+    pub warnings: Vec<String>,
+    /// Encoding and line ending problems in the file, after any `fix`.
+    pub source_issues: Vec<SourceIssue>,
+    /// Copy of the original file, when `fix` rewrote it.
+    pub backup_path: Option<String>,
 }
 
-#[derive(Debug, Clone)]
-struct PythonCandidate {
-    program: String,
-    pre_args: Vec<String>,
-    display_name: String,
+#[derive(Debug, Serialize)]
+pub struct ScriptMetadataResponse {
+    pub metadata: ScriptMetadata,
+    pub warnings: Vec<String>,
 }
 
-fn python_candidates(python_path: &Option<String>) -> Vec<PythonCandidate> {
-    if let Some(path) = python_path {
-        let trimmed = path.trim();
-        if !trimmed.is_empty() {
-            return vec![PythonCandidate {
-                program: trimmed.to_string(),
-                pre_args: vec![],
-                display_name: trimmed.to_string(),
-            }];
-        }
-    }
+#[derive(Debug, Default, Deserialize)]
+pub struct GetScriptArgumentsRequest {
+    #[serde(default)]
+    pub script_path: String,
+    pub python_path: Option<String>,
+    /// As for runs.
+    pub conda_env: Option<String>,
+    /// As for runs.
+    pub py_version: Option<String>,
+    /// As for runs.
+    pub python_requirement: Option<String>,
+    /// As for runs.
+    pub use_local_venv: Option<bool>,
+    /// As for runs.
+    pub use_project_env: Option<bool>,
+    #[serde(default)]
+    pub allow_any_extension: bool,
+    /// The `script_extensions` execution setting. Empty means the defaults.
+    #[serde(skip)]
+    pub script_extensions: Vec<String>,
+    /// The `local_venv_depth` execution setting.
+    #[serde(skip)]
+    pub local_venv_depth: u32,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+    /// The `allow_python2` execution setting.
+    #[serde(skip)]
+    pub allow_python2: bool,
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        vec![
-            PythonCandidate {
-                program: "python".to_string(),
-                pre_args: vec![],
-                display_name: "python".to_string(),
-            },
-            PythonCandidate {
-                program: "py".to_string(),
-                pre_args: vec!["-3".to_string()],
-                display_name: "py -3".to_string(),
-            },
-        ]
-    }
+#[derive(Debug, Default, Serialize)]
+pub struct ScriptArgumentsResponse {
+    /// In the order the script declares them. Empty for scripts that don't
+    /// use argparse.
+    pub arguments: Vec<ScriptArgument>,
+    /// Output of `script --help`, set only when the script uses argparse in
+    /// a way the source alone doesn't reveal.
+    pub help_text: Option<String>,
+    pub resolved_python: Option<String>,
+}
 
-    #[cfg(not(target_os = "windows"))]
-    {
-        vec![
-            PythonCandidate {
-                program: "python3".to_string(),
-                pre_args: vec![],
-                display_name: "python3".to_string(),
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ScriptArgument {
+    /// Where argparse stores the value: `city` for `--city`, `dest` if set.
+    pub name: String,
+    /// As passed to `add_argument`, e.g. `["-d", "--days"]`.
+    pub flags: Vec<String>,
+    pub required: bool,
+    /// `None` when there is none or it isn't a literal.
+    pub default: Option<serde_json::Value>,
+    pub help: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TestScriptOutputRequest {
+    #[serde(default)]
+    pub script_path: String,
+    pub module: Option<String>,
+    pub python_path: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Appended to `args`, e.g. "--probe", so the script can print sample
+    /// output without doing real work.
+    pub probe_arg: Option<String>,
+    pub env: Option<HashMap<String, String>>,
+    /// Defaults to `DEFAULT_OUTPUT_TEST_TIMEOUT_MS`.
+    pub timeout_ms: Option<u64>,
+    /// JSON Schema that stdout, parsed as for `parse_json`, must match.
+    pub schema: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestScriptOutputResponse {
+    pub passed: bool,
+    /// Why the test failed: "run_failed", "not_json" or "schema_mismatch".
+    pub failure_kind: Option<String>,
+    pub message: Option<String>,
+    /// Set for "schema_mismatch".
+    pub violations: Vec<SchemaViolation>,
+    pub run: RunPythonScriptResponse,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ValidatePythonScriptsRequest {
+    #[serde(default)]
+    pub dir_path: String,
+    #[serde(default)]
+    pub recursive: bool,
+    /// Also validate dot-files and look inside dot-directories.
+    #[serde(default)]
+    pub include_hidden: bool,
+    pub python_path: Option<String>,
+    /// As for runs.
+    pub conda_env: Option<String>,
+    /// As for runs.
+    pub py_version: Option<String>,
+    /// As for runs.
+    pub python_requirement: Option<String>,
+    /// As for runs.
+    pub use_local_venv: Option<bool>,
+    /// As for runs.
+    pub use_project_env: Option<bool>,
+    /// As for `validate_python_script`.
+    pub runner: Option<String>,
+    /// As for runs.
+    pub wsl_distro: Option<String>,
+    /// As for runs.
+    pub allow_python2: Option<bool>,
+    /// Defaults to `true`.
+    pub check_syntax: Option<bool>,
+    /// Defaults to `true`.
+    pub check_imports: Option<bool>,
+    /// How many scripts are validated at once. Defaults to 4.
+    pub max_parallel: Option<usize>,
+    /// Per script. A script that takes longer is reported as invalid.
+    pub file_timeout_ms: Option<u64>,
+    /// The `script_extensions` execution setting. Empty means the defaults.
+    #[serde(skip)]
+    pub script_extensions: Vec<String>,
+    /// The `local_venv_depth` execution setting.
+    #[serde(skip)]
+    pub local_venv_depth: u32,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptValidationEntry {
+    pub path: String,
+    pub valid: bool,
+    pub message: Option<String>,
+    pub syntax_ok: bool,
+    pub missing_modules: Vec<String>,
+}
+
+impl ScriptValidationEntry {
+    fn new(path: String, result: Result<ValidatePythonScriptResponse, String>) -> Self {
+        match result {
+            Ok(response) => ScriptValidationEntry {
+                path,
+                valid: response.valid,
+                message: response.message,
+                syntax_ok: response.syntax_ok,
+                missing_modules: response.missing_modules,
             },
-            PythonCandidate {
-                program: "python".to_string(),
-                pre_args: vec![],
-                display_name: "python".to_string(),
+            Err(message) => ScriptValidationEntry {
+                path,
+                valid: false,
+                message: Some(message),
+                syntax_ok: true,
+                missing_modules: Vec::new(),
             },
-        ]
+        }
     }
 }
 
-async fn is_candidate_available(candidate: &PythonCandidate) -> bool {
-    let mut command = Command::new(&candidate.program);
-    for arg in &candidate.pre_args {
-        command.arg(arg);
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ScriptSyntaxError {
+    pub line: Option<u32>,
+    /// 1-based, as Python reports it.
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MissingDependency {
+    /// The PEP 723 requirement as written, or the module name.
+    pub name: String,
+    /// "package" or "stdlib".
+    pub kind: &'static str,
+    /// For "stdlib", the Debian/Ubuntu package that provides the module,
+    /// where known.
+    pub system_package: Option<String>,
+}
+
+/// One interpreter that could not be started.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CandidateAttempt {
+    pub candidate: String,
+    /// `std::io::ErrorKind` in snake case, e.g. "not_found" or
+    /// "permission_denied", "not_installed" for a pyenv version that is
+    /// pinned but not installed or a Pipenv environment that was never
+    /// created, or "unsupported_version" when the
+    /// interpreter ran but is too old or too new for the script, or is
+    /// Python 2 without `allow_python2`.
+    pub error_kind: String,
+    pub message: String,
+    /// The interpreter's version, for "unsupported_version".
+    pub version: Option<String>,
+}
+
+impl CandidateAttempt {
+    fn new(candidate: &PythonCandidate, error: &std::io::Error) -> Self {
+        if let (Some(missing), std::io::ErrorKind::NotFound) = (&candidate.missing, error.kind()) {
+            return CandidateAttempt {
+                candidate: candidate.display_name.clone(),
+                error_kind: "not_installed".to_string(),
+                message: missing.clone(),
+                version: None,
+            };
+        }
+        let mut error_kind = String::new();
+        for c in format!("{:?}", error.kind()).chars() {
+            if c.is_uppercase() && !error_kind.is_empty() {
+                error_kind.push('_');
+            }
+            error_kind.push(c.to_ascii_lowercase());
+        }
+        CandidateAttempt {
+            candidate: candidate.display_name.clone(),
+            error_kind,
+            message: error.to_string(),
+            version: None,
+        }
     }
 
-    command
-        .arg("--version")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null());
+    fn unsupported_version(
+        candidate: &PythonCandidate,
+        requirement: &VersionRequirement,
+        info: &InterpreterInfo,
+    ) -> Self {
+        CandidateAttempt {
+            candidate: candidate.display_name.clone(),
+            error_kind: "unsupported_version".to_string(),
+            message: format!("requires Python {}, found {}", requirement, info.version),
+            version: Some(info.version.clone()),
+        }
+    }
 
-    match command.status().await {
-        Ok(status) => status.success(),
-        Err(_) => false,
+    fn python2(candidate: &PythonCandidate, version: &InterpreterVersion) -> Self {
+        CandidateAttempt {
+            candidate: candidate.display_name.clone(),
+            error_kind: "unsupported_version".to_string(),
+            message: format!(
+                "Python {} is not supported; set allow_python2 to use it",
+                version
+            ),
+            version: Some(version.to_string()),
+        }
     }
 }
 
-async fn execute_with_candidate(
-    request: &RunPythonScriptRequest,
-    timeout: Duration,
-    candidate: &PythonCandidate,
-) -> Result<RunPythonScriptResponse, std::io::Error> {
-    let start_time = Instant::now();
+/// How one interpreter fared during validation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CandidateDiagnostic {
+    pub display_name: String,
+    /// What was started, launcher arguments like `-3` included.
+    pub program: Vec<String>,
+    /// Started and, with a version requirement, new enough.
+    pub available: bool,
+    /// As `CandidateAttempt::error_kind`.
+    pub error_kind: Option<String>,
+    /// Why it isn't available, e.g. "--version exited with exit status: 1".
+    pub error: Option<String>,
+}
 
-    let mut command = Command::new(&candidate.program);
-    for arg in &candidate.pre_args {
-        command.arg(arg);
+impl CandidateDiagnostic {
+    fn of(candidate: &PythonCandidate, failure: Option<&CandidateAttempt>) -> Self {
+        CandidateDiagnostic {
+            display_name: candidate.display_name.clone(),
+            program: std::iter::once(candidate.program.clone())
+                .chain(candidate.pre_args.iter().cloned())
+                .collect(),
+            available: failure.is_none(),
+            error_kind: failure.map(|attempt| attempt.error_kind.clone()),
+            error: failure.map(|attempt| attempt.message.clone()),
+        }
     }
+}
 
-    command
-        .arg(&request.script_path)
-        .args(&request.args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-
-    let mut child = command.spawn()?;
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateVirtualenvRequest {
+    /// Where the virtualenv goes; created if missing.
+    pub target_dir: String,
+    /// The base interpreter. Picked like a run's when unset.
+    pub python_path: Option<String>,
+    /// Replace the contents of a non-empty `target_dir`.
+    #[serde(default)]
+    pub force: bool,
+    /// Run `pip install --upgrade pip` in the new environment.
+    #[serde(default)]
+    pub upgrade_pip: bool,
+    /// Emit `script-output` events while the environment is set up.
+    #[serde(default)]
+    pub stream: bool,
+    /// For `cancel_python_script`; shared by every step.
+    pub run_id: Option<String>,
+    /// For each step. Defaults to `DEFAULT_VENV_TIMEOUT_MS`.
+    pub timeout_ms: Option<u64>,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+}
 
-    let mut stdout = child
-        .stdout
-        .take()
-        .expect("stdout pipe should be available");
-    let mut stderr = child
-        .stderr
-        .take()
-        .expect("stderr pipe should be available");
+#[derive(Debug, Serialize)]
+pub struct CreateVirtualenvResponse {
+    pub run_id: String,
+    pub venv_path: String,
+    /// The new environment's interpreter, usable as `python_path`.
+    pub python_path: String,
+    /// `sys.executable` of the base interpreter; a launcher like `py -3` is
+    /// resolved to the Python it starts.
+    pub base_python: String,
+    pub base_version: String,
+    pub pip_upgraded: bool,
+    /// Output of every step, in order.
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u128,
+    pub warnings: Vec<String>,
+}
 
-    let stdout_handle = tokio::spawn(async move {
-        let mut buffer = Vec::new();
-        stdout.read_to_end(&mut buffer).await.map(|_| buffer)
-    });
-    let stderr_handle = tokio::spawn(async move {
-        let mut buffer = Vec::new();
-        stderr.read_to_end(&mut buffer).await.map(|_| buffer)
-    });
+/// What `get_candidates` and friends return.
+#[derive(Debug, Serialize)]
+pub struct CandidatesView {
+    /// "windows" or "unix".
+    pub platform: &'static str,
+    pub candidates: Vec<CandidateEntry>,
+    /// `false` while the built-in defaults are in use.
+    pub customized: bool,
+}
 
-    let mut timed_out = false;
-    let status = match tokio::time::timeout(timeout, child.wait()).await {
-        Ok(status_result) => status_result?,
-        Err(_) => {
-            timed_out = true;
-            let _ = child.kill().await;
-            child.wait().await?
+impl CandidatesView {
+    fn of(settings: &PythonSettings) -> Self {
+        CandidatesView {
+            platform: python_settings::PLATFORM,
+            candidates: settings.candidates(),
+            customized: settings.candidates.contains_key(python_settings::PLATFORM),
         }
-    };
-
-    let stdout_bytes = stdout_handle.await.unwrap_or_else(|_| Ok(Vec::new()))?;
-    let stderr_bytes = stderr_handle.await.unwrap_or_else(|_| Ok(Vec::new()))?;
+    }
+}
 
-    let stdout = String::from_utf8_lossy(&stdout_bytes).to_string();
-    let stderr = String::from_utf8_lossy(&stderr_bytes).to_string();
+#[derive(Debug, Default, Deserialize)]
+pub struct EnsureBundledPythonRequest {
+    /// Identifies the progress events. A new id when not given.
+    pub run_id: Option<String>,
+    /// A mirror to download the archive from instead of the release.
+    pub url: Option<String>,
+    /// Of the archive, required with `url`. The release's is looked up in
+    /// its published checksums when not given.
+    pub sha256: Option<String>,
+}
 
-    Ok(RunPythonScriptResponse {
-        ok: !timed_out && status.success(),
-        stdout,
-        stderr,
-        exit_code: status.code(),
-        timed_out,
-        duration_ms: start_time.elapsed().as_millis(),
-    })
+#[derive(Debug, Serialize)]
+pub struct EnsureBundledPythonResponse {
+    pub run_id: String,
+    /// The runtime's interpreter, usable as `python_path`.
+    pub python_path: String,
+    pub version: String,
+    /// `false` when it was already installed.
+    pub downloaded: bool,
+    /// Whether an interrupted download was picked up where it stopped.
+    pub resumed: bool,
+    /// Transferred by this call.
+    pub downloaded_bytes: u64,
 }
 
-fn validate_script_path(script_path: &str) -> Result<(), String> {
-    if script_path.trim().is_empty() {
-        return Err("script_path is required".to_string());
-    }
+#[derive(Debug, Default, Deserialize)]
+pub struct InstallPythonPackagesRequest {
+    /// The interpreter to install into. Picked like a run's when neither
+    /// this nor `venv_path` is set.
+    pub python_path: Option<String>,
+    /// A virtualenv directory, instead of `python_path`.
+    pub venv_path: Option<String>,
+    /// Requirement specs, e.g. "requests" or "pandas>=2".
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Passed to pip as `-r`.
+    pub requirements_file: Option<String>,
+    #[serde(default)]
+    pub upgrade: bool,
+    /// Defaults to the `pip_index_url` execution setting.
+    pub index_url: Option<String>,
+    /// Install into an interpreter that isn't in a virtualenv.
+    #[serde(default)]
+    pub allow_system_site: bool,
+    /// Emit `script-output` events with pip's output.
+    #[serde(default)]
+    pub stream: bool,
+    /// For `cancel_python_script`.
+    pub run_id: Option<String>,
+    /// Defaults to `DEFAULT_PIP_TIMEOUT_MS`, which the timeout ceiling in
+    /// the execution settings doesn't lower.
+    pub timeout_ms: Option<u64>,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+}
 
-    let path = Path::new(script_path);
-    if !path.exists() {
-        return Err(format!("script file not found: {}", script_path));
-    }
+#[derive(Debug, Serialize)]
+pub struct InstallPythonPackagesResponse {
+    pub run_id: String,
+    /// `sys.executable` of the interpreter pip ran in.
+    pub python_path: String,
+    /// Each package named by `packages` or the requirements file, with the
+    /// version now installed.
+    pub installed: Vec<InstalledPackage>,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u128,
+    pub warnings: Vec<String>,
+}
 
-    if !path.is_file() {
-        return Err(format!("script path is not a file: {}", script_path));
-    }
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstalledPackage {
+    pub name: String,
+    /// `None` when pip reported success but the package still can't be
+    /// found, as with a name that differs from the distribution's.
+    pub version: Option<String>,
+}
 
-    if path.extension().and_then(|ext| ext.to_str()) != Some("py") {
-        return Err("script must be a .py file".to_string());
-    }
+#[derive(Debug, Default, Deserialize)]
+pub struct ListInstalledPackagesRequest {
+    pub python_path: Option<String>,
+    /// List what the interpreter picked for this script has installed,
+    /// local venvs and shebangs included.
+    pub script_path: Option<String>,
+    /// As for runs.
+    pub use_local_venv: Option<bool>,
+    /// Only names containing this, ignoring case.
+    pub filter: Option<String>,
+    /// Leave out packages that another installed package requires, like
+    /// `pip list --not-required`.
+    #[serde(default)]
+    pub top_level_only: bool,
+    /// The `script_extensions` execution setting. Empty means the defaults.
+    #[serde(skip)]
+    pub script_extensions: Vec<String>,
+    /// The `local_venv_depth` execution setting.
+    #[serde(skip)]
+    pub local_venv_depth: u32,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+}
 
-    Ok(())
+#[derive(Debug, Serialize)]
+pub struct ListInstalledPackagesResponse {
+    /// `sys.executable` of the interpreter that was inspected.
+    pub python_path: String,
+    /// Sorted by name. A distribution installed twice is listed as the one
+    /// imports would find.
+    pub packages: Vec<InstalledDistribution>,
+    pub warnings: Vec<String>,
 }
 
-#[tauri::command]
-pub async fn run_python_script(
-    request: RunPythonScriptRequest,
-) -> Result<RunPythonScriptResponse, String> {
-    validate_script_path(&request.script_path)?;
+#[derive(Debug, Default, Deserialize)]
+pub struct CheckOutdatedPackagesRequest {
+    pub python_path: Option<String>,
+    /// Check the interpreter picked for this script, local venvs and
+    /// shebangs included.
+    pub script_path: Option<String>,
+    /// As for runs.
+    pub use_local_venv: Option<bool>,
+    /// Ask the index again even if a recent result is cached.
+    #[serde(default)]
+    pub refresh: bool,
+    /// The `script_extensions` execution setting. Empty means the defaults.
+    #[serde(skip)]
+    pub script_extensions: Vec<String>,
+    /// The `local_venv_depth` execution setting.
+    #[serde(skip)]
+    pub local_venv_depth: u32,
+    /// The `pip_index_url` execution setting.
+    #[serde(skip)]
+    pub index_url: Option<String>,
+    /// The `outdated_check_ttl_ms` execution setting.
+    #[serde(skip)]
+    pub cache_ttl_ms: u64,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+}
 
-    let timeout_ms = request.timeout_ms.unwrap_or(10_000).clamp(1_000, 120_000);
-    let timeout = Duration::from_millis(timeout_ms);
+#[derive(Debug, Serialize)]
+pub struct CheckOutdatedPackagesResponse {
+    /// `sys.executable` of the interpreter that was checked.
+    pub python_path: String,
+    /// "ok", or "offline" when the package index couldn't be reached.
+    pub status: &'static str,
+    /// Sorted by name. Empty when offline.
+    pub packages: Vec<OutdatedPackage>,
+    /// Why the index counts as unreachable.
+    pub offline_reason: Option<String>,
+    /// When the index was asked, in milliseconds since the Unix epoch.
+    pub checked_at_ms: u64,
+    pub from_cache: bool,
+}
 
-    let candidates = python_candidates(&request.python_path);
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportRequirementsRequest {
+    /// The interpreter whose environment is exported. Picked like a run's
+    /// when not set.
+    pub python_path: Option<String>,
+    /// Export only what this script imports, with what that requires, from
+    /// the interpreter picked for it. Everything installed otherwise.
+    pub script_path: Option<String>,
+    /// As for runs.
+    pub use_local_venv: Option<bool>,
+    /// Also save the result as `requirements.txt` next to the script.
+    #[serde(default)]
+    pub write: bool,
+    /// Let `write` replace an existing `requirements.txt`.
+    #[serde(default)]
+    pub overwrite: bool,
+    /// The `script_extensions` execution setting. Empty means the defaults.
+    #[serde(skip)]
+    pub script_extensions: Vec<String>,
+    /// The `local_venv_depth` execution setting.
+    #[serde(skip)]
+    pub local_venv_depth: u32,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+}
 
-    let mut last_error: Option<String> = None;
+#[derive(Debug, Serialize)]
+pub struct ExportRequirementsResponse {
+    /// `sys.executable` of the interpreter that was inspected.
+    pub python_path: String,
+    /// requirements.txt text. `install_python_packages` takes it back as
+    /// `requirements_file`.
+    pub content: String,
+    /// Sorted by name.
+    pub requirements: Vec<ExportedRequirement>,
+    /// Where `write` saved `content`.
+    pub written_to: Option<String>,
+    pub warnings: Vec<String>,
+}
 
-    for candidate in &candidates {
-        match execute_with_candidate(&request, timeout, candidate).await {
-            Ok(response) => return Ok(response),
-            Err(error) => {
-                if error.kind() == std::io::ErrorKind::NotFound {
-                    last_error = Some(format!("python interpreter not found: {}", candidate.display_name));
-                    continue;
-                }
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ExportedRequirement {
+    pub name: String,
+    pub version: String,
+    /// "index", "url" (an archive or wheel URL), "vcs", "editable" or
+    /// "local" (a file or directory on this machine).
+    pub source: String,
+    /// Where it was installed from, except for "index".
+    pub url: Option<String>,
+}
 
-                return Err(format!(
-                    "failed to execute script with {}: {}",
-                    candidate.display_name, error
-                ));
-            }
+impl ExportedRequirement {
+    /// The requirements.txt line. `None` for editable and local installs,
+    /// which only exist on this machine.
+    pub fn line(&self) -> Option<String> {
+        match (self.source.as_str(), &self.url) {
+            ("url" | "vcs", Some(url)) => Some(format!("{} @ {}", self.name, url)),
+            ("editable" | "local", _) => None,
+            _ => Some(format!("{}=={}", self.name, self.version)),
         }
     }
+}
 
-    Err(last_error.unwrap_or_else(|| "failed to find available python interpreter".to_string()))
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct InstalledDistribution {
+    pub name: String,
+    pub version: String,
+    /// The directory the distribution is installed in, like site-packages.
+    pub location: String,
 }
 
-#[tauri::command]
-pub async fn validate_python_script(
-    request: ValidatePythonScriptRequest,
-) -> Result<ValidatePythonScriptResponse, String> {
-    if let Err(message) = validate_script_path(&request.script_path) {
-        return Ok(ValidatePythonScriptResponse {
-            valid: false,
-            message: Some(message),
-            resolved_python: None,
-        });
+/// How one interpreter fared in `check_interpreter`.
+#[derive(Debug, Clone, Serialize)]
+pub struct InterpreterCheck {
+    pub display_name: String,
+    /// As `CandidateDiagnostic::program`.
+    pub program: Vec<String>,
+    /// As for runs' `interpreter_source`.
+    pub source: String,
+    /// It started and reported on itself.
+    pub ok: bool,
+    /// Why it didn't.
+    pub error: Option<String>,
+    pub health: Option<InterpreterHealth>,
+    /// What the report shows to be broken, e.g. "ssl can't be imported:
+    /// ImportError: libssl.so.3: cannot open shared object file".
+    pub problems: Vec<String>,
+}
+
+/// What the interpreter says about itself.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct InterpreterHealth {
+    pub executable: String,
+    pub version: String,
+    pub prefix: String,
+    /// Differs from `prefix` in a virtualenv.
+    pub base_prefix: String,
+    /// Whether "pip", "venv", "ssl" and "sqlite3" import. pip is only
+    /// looked up, as importing it is slow.
+    pub modules: BTreeMap<String, ModuleHealth>,
+    /// Where installed packages go, the user site last when enabled.
+    pub site_packages: Vec<String>,
+    pub encodings: InterpreterEncodings,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ModuleHealth {
+    pub importable: bool,
+    /// The exception, when not.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct InterpreterEncodings {
+    /// `sys.getfilesystemencoding()`.
+    pub filesystem: String,
+    /// `locale.getpreferredencoding()`, what `open()` uses by default.
+    pub preferred: String,
+    /// Of stdout when piped, as it is for runs.
+    pub stdout: Option<String>,
+}
+
+/// The error returned when no interpreter could run the script: a JSON
+/// object with a summary `message` and the `attempts`, in order.
+fn candidates_failed(message: String, attempts: &[CandidateAttempt]) -> String {
+    serde_json::json!({ "message": message, "attempts": attempts }).to_string()
+}
+
+/// Spawn errors that mean this interpreter is unusable, so the next one is
+/// worth a try.
+fn try_next_candidate(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+    )
+}
+
+#[derive(Debug, Clone)]
+struct PythonCandidate {
+    program: String,
+    pre_args: Vec<String>,
+    display_name: String,
+    /// Where the candidate came from: "request", "conda", "py_launcher",
+    /// "poetry", "pipenv", "wsl", "local_venv", "pyenv", "shebang", "settings",
+    /// "default" or "bundled".
+    source: &'static str,
+    /// The virtualenv directory, for "local_venv".
+    local_venv: Option<PathBuf>,
+    /// Why `program` won't be found, for a pinned version or the bundled
+    /// runtime that isn't installed.
+    missing: Option<String>,
+    /// The project whose tool set up the interpreter, for "poetry" and
+    /// "pipenv".
+    project: Option<ProjectEnv>,
+}
+
+impl PythonCandidate {
+    fn local_venv(&self) -> Option<String> {
+        self.local_venv
+            .as_ref()
+            .map(|venv| venv.to_string_lossy().to_string())
     }
 
-    let candidates = python_candidates(&request.python_path);
-    for candidate in candidates {
-        if is_candidate_available(&candidate).await {
-            return Ok(ValidatePythonScriptResponse {
-                valid: true,
-                message: Some("script and interpreter are valid".to_string()),
-                resolved_python: Some(candidate.display_name),
-            });
+    fn project_env(&self) -> Option<String> {
+        self.project
+            .as_ref()
+            .and_then(|project| project.env.as_ref())
+            .map(|env| env.to_string_lossy().to_string())
+    }
+
+    /// Where the run starts: a project's directory, so its tool finds it,
+    /// unless the request has a `working_dir`.
+    fn working_dir<'a>(&'a self, request: &RunPythonScriptRequest, plan: &'a RunPlan) -> &'a Path {
+        match (&request.working_dir, &self.project) {
+            (None, Some(project)) if project.start_in_dir => &project.dir,
+            _ => &plan.working_dir,
         }
     }
 
-    Ok(ValidatePythonScriptResponse {
-        valid: false,
-        message: Some("python interpreter is not available".to_string()),
-        resolved_python: None,
-    })
+    /// A `NotFound` error for a candidate known not to exist, so nothing
+    /// is started for it.
+    fn check_installed(&self) -> Result<(), std::io::Error> {
+        match &self.missing {
+            Some(missing) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                missing.clone(),
+            )),
+            None => Ok(()),
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug, Clone)]
+struct ProjectEnv {
+    dir: PathBuf,
+    /// The virtualenv the tool reports. `None` until it has created one.
+    env: Option<PathBuf>,
+    /// Whether runs start in `dir`, for a tool that starts the interpreter
+    /// itself.
+    start_in_dir: bool,
+}
+
+/// Directory names virtualenvs are usually created under.
+const LOCAL_VENV_DIRS: &[&str] = &[".venv", "venv", ".env"];
+
+/// The nearest virtualenv next to the script or in one of the `depth`
+/// directories above it, with its interpreter. `pyvenv.cfg` tells a venv
+/// from, say, a `.env` directory of something else.
+fn find_local_venv(script: &Path, depth: u32) -> Option<(PathBuf, PathBuf)> {
+    local_venvs(script, depth).next()
+}
+
+/// Every virtualenv `find_local_venv` could find, nearest first.
+fn local_venvs(script: &Path, depth: u32) -> impl Iterator<Item = (PathBuf, PathBuf)> + '_ {
+    script
+        .ancestors()
+        .skip(1)
+        .take(depth as usize + 1)
+        .flat_map(|dir| LOCAL_VENV_DIRS.iter().map(move |name| dir.join(name)))
+        .filter(|venv| virtualenv::is_venv(venv))
+        .map(|venv| {
+            let python = virtualenv::python_in(&venv);
+            (venv, python)
+        })
+}
+
+/// An explicit `python_path` is the only candidate. Otherwise candidates
+/// come in the order `settings` gives their sources, by default: a local
+/// venv, when `venv_depth` allows looking for one, the version a
+/// `.python-version` file pins when pyenv is installed, a file's shebang,
+/// when it names a Python that exists, the global `python_path`, then the
+/// defaults.
+fn python_candidates(
+    python_path: &Option<String>,
+    target: &ScriptTarget,
+    venv_depth: Option<u32>,
+    settings: &PythonSettings,
+) -> Vec<PythonCandidate> {
+    if let Some(path) = python_path {
+        let trimmed = path.trim();
+        if !trimmed.is_empty() {
+            let program = settings.resolve_alias(trimmed);
+            return vec![PythonCandidate {
+                program: program.to_string(),
+                pre_args: vec![],
+                display_name: match program == trimmed {
+                    true => trimmed.to_string(),
+                    false => format!("{} ({})", trimmed, program),
+                },
+                source: "request",
+                local_venv: None,
+                missing: None,
+                project: None,
+            }];
+        }
+    }
+
+    let local_venv = match (target, venv_depth) {
+        (ScriptTarget::File(path), Some(depth)) => find_local_venv(path, depth),
+        _ => None,
+    };
+    let local_venv = local_venv.map(|(venv, python)| {
+        let program = python.to_string_lossy().to_string();
+        PythonCandidate {
+            display_name: program.clone(),
+            program,
+            pre_args: Vec::new(),
+            source: "local_venv",
+            local_venv: Some(venv),
+            missing: None,
+            project: None,
+        }
+    });
+    let pyenv = match target {
+        ScriptTarget::File(path) => pyenv_candidate(path),
+        _ => None,
+    };
+    let shebang = match target {
+        ScriptTarget::File(path) => shebang::interpreter(path),
+        _ => None,
+    };
+    let shebang = shebang.map(|shebang| PythonCandidate {
+        display_name: std::iter::once(&shebang.program)
+            .chain(&shebang.pre_args)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" "),
+        program: shebang.program,
+        pre_args: shebang.pre_args,
+        source: "shebang",
+        local_venv: None,
+        missing: None,
+        project: None,
+    });
+    let configured = settings.python_path().map(|path| PythonCandidate {
+        program: path.to_string(),
+        pre_args: Vec::new(),
+        display_name: path.to_string(),
+        source: "settings",
+        local_venv: None,
+        missing: None,
+        project: None,
+    });
+    let bundled = settings
+        .bundled_python
+        .as_ref()
+        .map(|python| PythonCandidate {
+            program: python.to_string_lossy().to_string(),
+            pre_args: Vec::new(),
+            display_name: format!("bundled Python {}", bundled_python::PYTHON_VERSION),
+            source: "bundled",
+            local_venv: None,
+            missing: (!python.is_file()).then(|| {
+                "the bundled Python runtime is not installed; ensure_bundled_python downloads it"
+                    .to_string()
+            }),
+            project: None,
+        });
+    let mut sources = [
+        ("local_venv", local_venv.into_iter().collect::<Vec<_>>()),
+        ("pyenv", pyenv.into_iter().collect()),
+        ("shebang", shebang.into_iter().collect()),
+        ("settings", configured.into_iter().collect()),
+        ("default", default_candidates(settings)),
+        ("bundled", bundled.into_iter().collect()),
+    ];
+    let mut candidates = Vec::new();
+    for source in settings.order() {
+        if let Some((_, found)) = sources.iter_mut().find(|(name, _)| *name == source) {
+            candidates.append(found);
+        }
+    }
+    candidates
+}
+
+/// What a request says about the interpreter to use.
+struct InterpreterChoice<'a> {
+    python_path: &'a Option<String>,
+    conda_env: &'a Option<String>,
+    py_version: &'a Option<String>,
+    python_requirement: &'a Option<String>,
+    use_project_env: Option<bool>,
+    venv_depth: Option<u32>,
+    settings: &'a PythonSettings,
+}
+
+impl RunPythonScriptRequest {
+    fn interpreter_choice(&self) -> InterpreterChoice<'_> {
+        InterpreterChoice {
+            python_path: &self.python_path,
+            conda_env: &self.conda_env,
+            py_version: &self.py_version,
+            python_requirement: &self.python_requirement,
+            use_project_env: self.use_project_env,
+            venv_depth: local_venv_depth(self.use_local_venv, self.local_venv_depth),
+            settings: &self.python_settings,
+        }
+    }
+}
+
+/// The conda environment's interpreter alone when `conda_env` names one,
+/// the `py` launcher alone for `py_version`, `python_candidates` otherwise,
+/// led by `poetry run` for a script in a Poetry project when
+/// `use_project_env` is set. Only then is conda, the launcher or Poetry
+/// needed.
+async fn request_candidates(
+    choice: &InterpreterChoice<'_>,
+    target: &ScriptTarget,
+    interpreters: &InterpreterInfoCache,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<PythonCandidate>, String> {
+    let InterpreterChoice {
+        python_path,
+        conda_env,
+        py_version,
+        python_requirement,
+        use_project_env,
+        venv_depth,
+        settings,
+    } = *choice;
+    let has_python_path = python_path
+        .as_deref()
+        .is_some_and(|path| !path.trim().is_empty());
+    let is_set = |value: &Option<String>| value.as_deref().is_some_and(|v| !v.trim().is_empty());
+    if let Some(requirement) = python_requirement
+        .as_deref()
+        .map(str::trim)
+        .filter(|requirement| !requirement.is_empty())
+    {
+        let conflict = [
+            ("python_path", has_python_path),
+            ("conda_env", is_set(conda_env)),
+            ("py_version", is_set(py_version)),
+            ("use_project_env", use_project_env == Some(true)),
+        ]
+        .into_iter()
+        .find(|(_, set)| *set);
+        if let Some((field, _)) = conflict {
+            return Err(format!(
+                "provide either {} or python_requirement, not both",
+                field
+            ));
+        }
+        return Ok(vec![
+            requirement_candidate(requirement, interpreters).await?,
+        ]);
+    }
+    if let Some(py_version) = py_version
+        .as_deref()
+        .map(str::trim)
+        .filter(|version| !version.is_empty())
+    {
+        if has_python_path {
+            return Err("provide either python_path or py_version, not both".to_string());
+        }
+        if conda_env
+            .as_deref()
+            .is_some_and(|env| !env.trim().is_empty())
+        {
+            return Err("provide either conda_env or py_version, not both".to_string());
+        }
+        if use_project_env == Some(true) {
+            return Err("provide either py_version or use_project_env, not both".to_string());
+        }
+        return Ok(vec![py_launcher_candidate(py_version).await?]);
+    }
+    let Some(name) = conda_env
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    else {
+        if use_project_env == Some(true) && has_python_path {
+            return Err("provide either python_path or use_project_env, not both".to_string());
+        }
+        let mut candidates = python_candidates(python_path, target, venv_depth, settings);
+        if !has_python_path {
+            let project = project_candidate(target, use_project_env, interpreters, warnings).await;
+            candidates.splice(0..0, project);
+        }
+        return Ok(candidates);
+    };
+    if has_python_path {
+        return Err("provide either python_path or conda_env, not both".to_string());
+    }
+    if use_project_env == Some(true) {
+        return Err("provide either conda_env or use_project_env, not both".to_string());
+    }
+
+    let envs = interpreters
+        .conda_environments()
+        .await
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => "conda not found on PATH".to_string(),
+            _ => format!("failed to list conda environments: {}", error),
+        })?;
+    let Some(env) = envs.iter().find(|env| env.is(name)) else {
+        let names: Vec<&str> = envs.iter().map(|env| env.name.as_str()).collect();
+        return Err(format!(
+            "conda environment not found: {} (found: {})",
+            name,
+            names.join(", ")
+        ));
+    };
+    let (program, pre_args) = match cfg!(windows) {
+        true => (conda::program(), conda::run_args(env)),
+        false => (env.python().to_string_lossy().to_string(), Vec::new()),
+    };
+    Ok(vec![PythonCandidate {
+        display_name: format!("conda env {}", env.name),
+        program,
+        pre_args,
+        source: "conda",
+        local_venv: None,
+        missing: None,
+        project: None,
+    }])
+}
+
+/// The newest installed Python `discovery` finds that satisfies
+/// `requirement`. Virtualenvs belong to their projects and are passed over.
+/// Equal versions go to the first executable by path, so the same Pythons
+/// always give the same pick.
+async fn requirement_candidate(
+    requirement: &str,
+    interpreters: &InterpreterInfoCache,
+) -> Result<PythonCandidate, String> {
+    let requirement = VersionRequirement::parse(requirement)?;
+    let discovered = discovery::discover(interpreters, None).await;
+    let best = discovered
+        .iter()
+        .filter(|python| !python.is_venv && requirement.matches(&python.version))
+        .max_by(|a, b| {
+            PythonVersion::parse(&a.version)
+                .cmp(&PythonVersion::parse(&b.version))
+                .then_with(|| b.executable.cmp(&a.executable))
+        });
+    let Some(best) = best else {
+        let found: Vec<String> = discovered
+            .iter()
+            .map(|python| match python.is_venv {
+                true => format!("{} {} (virtualenv)", python.version, python.executable),
+                false => format!("{} {}", python.version, python.executable),
+            })
+            .collect();
+        return Err(format!(
+            "no installed Python matches {} (found: {})",
+            requirement,
+            match found.is_empty() {
+                true => "none".to_string(),
+                false => found.join("; "),
+            }
+        ));
+    };
+    Ok(PythonCandidate {
+        display_name: format!("{} (Python {})", best.executable, best.version),
+        program: best.executable.clone(),
+        pre_args: Vec::new(),
+        source: "python_requirement",
+        local_venv: None,
+        missing: None,
+        project: None,
+    })
+}
+
+/// `py -<py_version>`, named after the Python it starts, once the launcher
+/// confirms it has that version.
+async fn py_launcher_candidate(py_version: &str) -> Result<PythonCandidate, String> {
+    let arg = py_launcher::version_arg(py_version)?;
+    let registered = py_launcher::list()
+        .await
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => format!(
+                "py_version needs the {} launcher, which was not found on PATH",
+                py_launcher::PROGRAM
+            ),
+            _ => format!("failed to list the py launcher's Pythons: {}", error),
+        })?;
+    let Some(python) = py_launcher::find(&registered, py_version) else {
+        let versions: Vec<&str> = registered
+            .iter()
+            .map(|python| python.version.as_str())
+            .collect();
+        return Err(format!(
+            "Python {} is not registered with the py launcher (registered: {})",
+            py_version,
+            match versions.is_empty() {
+                true => "none".to_string(),
+                false => versions.join(", "),
+            }
+        ));
+    };
+    Ok(PythonCandidate {
+        display_name: format!(
+            "{} {} ({})",
+            py_launcher::PROGRAM,
+            arg,
+            python.executable.display()
+        ),
+        program: py_launcher::PROGRAM.to_string(),
+        pre_args: vec![arg],
+        source: "py_launcher",
+        local_venv: None,
+        missing: None,
+        project: None,
+    })
+}
+
+/// The environment of the Poetry or Pipenv project around the script.
+/// Without `use_project_env` the project is only pointed out;
+/// `Some(false)` ignores it. A missing tool leaves the usual candidates,
+/// with a warning.
+async fn project_candidate(
+    target: &ScriptTarget,
+    use_project_env: Option<bool>,
+    interpreters: &InterpreterInfoCache,
+    warnings: &mut Vec<String>,
+) -> Option<PythonCandidate> {
+    let ScriptTarget::File(script) = target else {
+        return None;
+    };
+    if use_project_env == Some(false) {
+        return None;
+    }
+    let use_project_env = use_project_env.is_some();
+    if let Some(dir) = poetry::project(script) {
+        let project = format!("{} is a Poetry project", dir.display());
+        if !project_tool_usable(
+            poetry::PROGRAM,
+            &project,
+            use_project_env,
+            interpreters,
+            warnings,
+        )
+        .await
+        {
+            return None;
+        }
+        let env = interpreters.poetry_env(&dir).await.ok();
+        return Some(PythonCandidate {
+            program: poetry::PROGRAM.to_string(),
+            pre_args: poetry::run_args(&dir),
+            display_name: format!("poetry run python ({})", dir.display()),
+            source: "poetry",
+            local_venv: None,
+            missing: None,
+            project: Some(ProjectEnv {
+                dir,
+                env,
+                start_in_dir: true,
+            }),
+        });
+    }
+    if let Some(pipfile) = pipenv::pipfile(script) {
+        let project = format!("{} is a Pipenv project", pipfile.display());
+        if !project_tool_usable(
+            pipenv::PROGRAM,
+            &project,
+            use_project_env,
+            interpreters,
+            warnings,
+        )
+        .await
+        {
+            return None;
+        }
+        return pipenv_candidate(pipfile, interpreters, warnings).await;
+    }
+    if use_project_env {
+        warnings.push(format!(
+            "use_project_env: no Poetry or Pipenv project found above {}",
+            script.display()
+        ));
+    }
+    None
+}
+
+/// Whether `program` can be used for `project`, which it manages. Says why
+/// not in `warnings`, or, without `use_project_env`, that it could be.
+async fn project_tool_usable(
+    program: &str,
+    project: &str,
+    use_project_env: bool,
+    interpreters: &InterpreterInfoCache,
+    warnings: &mut Vec<String>,
+) -> bool {
+    if let Err(error) = interpreters.tool_version(program).await {
+        if use_project_env {
+            warnings.push(match error.kind() {
+                std::io::ErrorKind::NotFound => format!(
+                    "{} but {} was not found on PATH; using the usual interpreters",
+                    project, program
+                ),
+                _ => format!(
+                    "{} is unusable ({}); using the usual interpreters",
+                    program, error
+                ),
+            });
+        }
+        return false;
+    }
+    if !use_project_env {
+        warnings.push(format!(
+            "{}; pass use_project_env: true to use its environment",
+            project
+        ));
+        return false;
+    }
+    true
+}
+
+/// The interpreter of the virtualenv `pipenv --venv` reports for the
+/// project. One that was never created can't be started, and says to run
+/// `pipenv install`.
+async fn pipenv_candidate(
+    pipfile: PathBuf,
+    interpreters: &InterpreterInfoCache,
+    warnings: &mut Vec<String>,
+) -> Option<PythonCandidate> {
+    let dir = pipfile.parent()?.to_path_buf();
+    let env = match interpreters.pipenv_env(&dir).await {
+        Ok(env) => Some(env).filter(|env| virtualenv::python_in(env).is_file()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+        Err(error) => {
+            warnings.push(format!(
+                "pipenv --venv failed for {} ({}); using the usual interpreters",
+                pipfile.display(),
+                error
+            ));
+            return None;
+        }
+    };
+    let (program, missing) = match &env {
+        Some(env) => {
+            warnings.push(format!(
+                "interpreter selected from Pipfile at {}",
+                pipfile.display()
+            ));
+            (
+                virtualenv::python_in(env).to_string_lossy().to_string(),
+                None,
+            )
+        }
+        None => (
+            pipenv::PROGRAM.to_string(),
+            Some(format!(
+                "the Pipfile at {} has no environment yet; run `pipenv install` in {}",
+                pipfile.display(),
+                dir.display()
+            )),
+        ),
+    };
+    Some(PythonCandidate {
+        display_name: format!("pipenv environment of {}", dir.display()),
+        program,
+        pre_args: Vec::new(),
+        source: "pipenv",
+        local_venv: None,
+        missing,
+        project: Some(ProjectEnv {
+            dir,
+            env,
+            start_in_dir: false,
+        }),
+    })
+}
+
+/// The pyenv interpreter pinned for `script`, bypassing pyenv's shims. When
+/// the pinned version isn't installed the candidate can't be started, and
+/// says why.
+fn pyenv_candidate(script: &Path) -> Option<PythonCandidate> {
+    let root = pyenv::root().filter(|root| root.join("versions").is_dir())?;
+    let pin = pyenv::pin(script)?;
+    let installed = pyenv::installed(&root, &pin.version);
+    let missing = installed.is_none().then(|| {
+        format!(
+            "pyenv version {} from {} is not installed",
+            pin.version,
+            pin.file.display()
+        )
+    });
+    let python =
+        installed.unwrap_or_else(|| pyenv::python_in(&root.join("versions").join(&pin.version)));
+    Some(PythonCandidate {
+        program: python.to_string_lossy().to_string(),
+        pre_args: Vec::new(),
+        display_name: format!("pyenv {}", pin.version),
+        source: "pyenv",
+        local_venv: None,
+        missing,
+        project: None,
+    })
+}
+
+/// How far up to look for a local venv, `None` when not to look.
+fn local_venv_depth(use_local_venv: Option<bool>, depth: u32) -> Option<u32> {
+    use_local_venv.unwrap_or(true).then_some(depth)
+}
+
+/// The default candidates from the Python settings, source "default".
+fn default_candidates(settings: &PythonSettings) -> Vec<PythonCandidate> {
+    settings
+        .candidates()
+        .into_iter()
+        .map(|entry| PythonCandidate {
+            program: entry.program,
+            pre_args: entry.pre_args,
+            display_name: entry.display_name,
+            source: "default",
+            local_venv: None,
+            missing: None,
+            project: None,
+        })
+        .collect()
+}
+
+/// Runs `--version`, or reuses a recent result, for the version it prints.
+/// A non-zero exit is reported as an `Other` error; Python 2 is passed over
+/// unless allowed.
+async fn probe_candidate(
+    interpreters: &InterpreterInfoCache,
+    candidate: &PythonCandidate,
+    allow_python2: bool,
+) -> Result<Option<InterpreterVersion>, CandidateAttempt> {
+    let version = candidate
+        .check_installed()
+        .and(
+            interpreters
+                .available(&candidate.program, &candidate.pre_args)
+                .await,
+        )
+        .map_err(|error| CandidateAttempt::new(candidate, &error))?;
+    match version {
+        Some(version) if version.major < 3 && !allow_python2 => {
+            Err(CandidateAttempt::python2(candidate, &version))
+        }
+        version => Ok(version),
+    }
+}
+
+/// What the interpreter is asked to run.
+#[derive(Debug, Clone)]
+enum ScriptTarget {
+    /// Absolute path, so changing the working directory never changes which
+    /// file runs.
+    File(PathBuf),
+    Module(String),
+    /// Single-line inline code passed with `-c`.
+    Code(String),
+}
+
+impl ScriptTarget {
+    fn resolve(
+        script_path: &str,
+        module: Option<&str>,
+        extensions: ScriptExtensions,
+    ) -> Result<Self, String> {
+        let module = module.map(str::trim).filter(|module| !module.is_empty());
+        match (script_path.trim().is_empty(), module) {
+            (false, Some(_)) => Err("provide either script_path or module, not both".to_string()),
+            (true, None) => Err("script_path is required".to_string()),
+            (true, Some(module)) => {
+                validate_module_name(module)?;
+                Ok(ScriptTarget::Module(module.to_string()))
+            }
+            (false, None) => Ok(ScriptTarget::File(validate_script_path(
+                script_path,
+                extensions,
+            )?)),
+        }
+    }
+
+    fn apply(&self, command: &mut Command) {
+        match self {
+            ScriptTarget::File(path) => command.arg(path),
+            ScriptTarget::Module(module) => command.arg("-m").arg(module),
+            ScriptTarget::Code(code) => command.arg("-c").arg(code),
+        };
+    }
+
+    /// The canonical path, for files.
+    fn script_path(&self) -> Option<String> {
+        match self {
+            ScriptTarget::File(path) => Some(path.to_string_lossy().to_string()),
+            ScriptTarget::Module(_) | ScriptTarget::Code(_) => None,
+        }
+    }
+
+    /// How the run is shown in `list_active_runs`.
+    fn describe(&self) -> String {
+        match self {
+            ScriptTarget::File(path) => path.to_string_lossy().to_string(),
+            ScriptTarget::Module(module) => format!("-m {}", module),
+            ScriptTarget::Code(_) => "<inline code>".to_string(),
+        }
+    }
+
+    /// Where a run goes when the request has no `working_dir`.
+    fn default_working_dir(&self) -> Result<PathBuf, String> {
+        match self {
+            ScriptTarget::File(path) => Ok(path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| path.to_path_buf())),
+            ScriptTarget::Module(_) | ScriptTarget::Code(_) => std::env::current_dir()
+                .map_err(|error| format!("failed to resolve current directory: {}", error)),
+        }
+    }
+}
+
+/// Inline code written to a private temp file. The file is removed on drop,
+/// which covers timeouts and errors as well as normal completion.
+struct TempScript {
+    path: PathBuf,
+}
+
+impl TempScript {
+    fn create(code: &str) -> Result<Self, String> {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("pdd-inline-{}.py", next_run_id()));
+        let mut file = create_private_file(&path)
+            .map_err(|error| format!("failed to create temp script: {}", error))?;
+        let script = TempScript { path };
+        file.write_all(code.as_bytes())
+            .map_err(|error| format!("failed to write temp script: {}", error))?;
+        Ok(script)
+    }
+}
+
+/// Creates a new file only the current user can read.
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+impl Drop for TempScript {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// How `json_payload` reaches the script. A file payload lives as long as the
+/// plan, so it outlasts retries and is removed on every exit path.
+enum Payload {
+    Env(String),
+    File(TempPayload),
+}
+
+impl Payload {
+    fn prepare(value: Option<&serde_json::Value>) -> Result<Option<Self>, String> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+
+        let json = serde_json::to_string(value)
+            .map_err(|error| format!("failed to serialize json_payload: {}", error))?;
+        if json.len() <= MAX_ENV_PAYLOAD_BYTES {
+            return Ok(Some(Payload::Env(json)));
+        }
+        TempPayload::create(&json).map(|file| Some(Payload::File(file)))
+    }
+
+    fn apply(&self, command: &mut Command) {
+        match self {
+            Payload::Env(json) => command
+                .env(PAYLOAD_JSON_ENV, json)
+                .env_remove(PAYLOAD_FILE_ENV),
+            Payload::File(file) => command
+                .env(PAYLOAD_FILE_ENV, &file.path)
+                .env_remove(PAYLOAD_JSON_ENV),
+        };
+    }
+}
+
+struct TempPayload {
+    path: PathBuf,
+}
+
+impl TempPayload {
+    fn create(json: &str) -> Result<Self, String> {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("pdd-payload-{}.json", next_run_id()));
+        let mut file = create_private_file(&path)
+            .map_err(|error| format!("failed to create payload file: {}", error))?;
+        let payload = TempPayload { path };
+        file.write_all(json.as_bytes())
+            .map_err(|error| format!("failed to write payload file: {}", error))?;
+        Ok(payload)
+    }
+}
+
+impl Drop for TempPayload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A run's `PDD_SCRATCH_DIR`. Like a file payload it lives as long as the
+/// plan, so it is removed on every exit path unless `keep_scratch` was set.
+struct ScratchDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl ScratchDir {
+    fn create(cache_dir: Option<&Path>, keep: bool) -> Result<Self, String> {
+        let root = cache_dir
+            .map(Path::to_path_buf)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("pdd-scratch");
+        let path = root.join(next_run_id());
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        std::fs::create_dir_all(&root)
+            .and_then(|_| builder.create(&path))
+            .map_err(|error| format!("failed to create scratch directory: {}", error))?;
+        Ok(ScratchDir { path, keep })
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        // The script may have swapped the directory for a link to somewhere
+        // else; only the link itself goes then. `remove_dir_all` doesn't
+        // follow links found inside either.
+        match std::fs::symlink_metadata(&self.path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let _ =
+                    std::fs::remove_file(&self.path).or_else(|_| std::fs::remove_dir(&self.path));
+            }
+            Ok(_) => {
+                let _ = std::fs::remove_dir_all(&self.path);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+fn validate_module_name(module: &str) -> Result<(), String> {
+    let valid = module.split('.').all(|part| {
+        let mut chars = part.chars();
+        chars
+            .next()
+            .is_some_and(|first| first == '_' || first.is_alphabetic())
+            && chars.all(|c| c == '_' || c.is_alphanumeric())
+    });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid module name: {}", module))
+    }
+}
+
+const MODULE_IMPORT_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Prints the error importing `sys.argv[1]` as JSON, or `null`.
+const MODULE_IMPORT_CHECK_CODE: &str = r#"
+import importlib, json, sys
+try:
+    importlib.import_module(sys.argv[1])
+except BaseException as error:
+    print(json.dumps("%s: %s" % (type(error).__name__, error)))
+else:
+    print("null")
+"#;
+
+/// The module name goes through argv rather than being spliced into code.
+async fn check_module_importable(candidate: &PythonCandidate, module: &str) -> Result<(), String> {
+    let error: Option<String> = run_check_within(
+        MODULE_IMPORT_CHECK_TIMEOUT,
+        candidate,
+        MODULE_IMPORT_CHECK_CODE,
+        &[OsStr::new(module)],
+        &format!("module {}", module),
+    )
+    .await?;
+    match error {
+        None => Ok(()),
+        Some(error) => Err(format!("module is not importable: {} ({})", module, error)),
+    }
+}
+
+const SYNTAX_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Compiles in memory, so no `__pycache__` is created, and prints the error
+/// as JSON, or `null`.
+const SYNTAX_CHECK_CODE: &str = r#"
+import json, sys
+try:
+    with open(sys.argv[1], "rb") as file:
+        compile(file.read(), sys.argv[1], "exec", dont_inherit=True)
+except SyntaxError as error:
+    print(json.dumps({"line": error.lineno, "column": error.offset, "message": error.msg}))
+else:
+    print("null")
+"#;
+
+/// `Ok(None)` when the script compiles.
+async fn check_script_syntax(
+    candidate: &PythonCandidate,
+    path: &Path,
+) -> Result<Option<ScriptSyntaxError>, String> {
+    run_check_within(
+        SYNTAX_CHECK_TIMEOUT,
+        candidate,
+        SYNTAX_CHECK_CODE,
+        &[path.as_os_str()],
+        "syntax",
+    )
+    .await
+}
+
+/// Defines `top_level_imports(path)`: the top-level names imported by
+/// statements at the script's top level. A script that doesn't parse
+/// imports nothing.
+const IMPORT_SCAN_CODE: &str = r#"
+import ast
+def top_level_imports(path):
+    try:
+        with open(path, "rb") as file:
+            tree = ast.parse(file.read(), path)
+    except (SyntaxError, ValueError):
+        return set()
+    names = set()
+    for node in tree.body:
+        if isinstance(node, ast.Import):
+            names.update(alias.name.split(".")[0] for alias in node.names)
+        elif isinstance(node, ast.ImportFrom) and node.level == 0 and node.module:
+            names.add(node.module.split(".")[0])
+    return names
+"#;
+
+/// `code` with `IMPORT_SCAN_CODE` ahead of it.
+fn with_import_scan(code: &str) -> String {
+    format!("{}{}", IMPORT_SCAN_CODE, code)
+}
+
+/// Lists the top-level names imported by statements at the script's top
+/// level that `find_spec` can't locate. Nothing is imported, so no module
+/// code runs. The script's own directory is searched first, as in a run.
+const IMPORT_CHECK_CODE: &str = r#"
+import importlib.util, json, os, sys
+path = sys.argv[1]
+sys.path[0] = os.path.dirname(os.path.abspath(path))
+names = top_level_imports(path)
+missing = []
+for name in sorted(names):
+    try:
+        found = importlib.util.find_spec(name) is not None
+    except (ImportError, ValueError):
+        found = False
+    if not found:
+        missing.append(name)
+print(json.dumps(missing))
+"#;
+
+/// Lists the PEP 508 requirements in `sys.argv[1]` (JSON) that aren't
+/// installed. Versions and markers are only checked when `packaging` is
+/// available; otherwise any installed version counts.
+const DEPENDENCY_CHECK_CODE: &str = r#"
+import json, re, sys
+from importlib import metadata
+try:
+    from packaging.requirements import Requirement
+except ImportError:
+    Requirement = None
+def installed(name):
+    try:
+        return metadata.version(name)
+    except metadata.PackageNotFoundError:
+        return None
+missing = []
+for spec in json.loads(sys.argv[1]):
+    requirement = None
+    if Requirement is not None:
+        try:
+            requirement = Requirement(spec)
+        except Exception:
+            pass
+    if requirement is not None:
+        if requirement.marker is not None and not requirement.marker.evaluate():
+            continue
+        version = installed(requirement.name)
+        if version is None or not requirement.specifier.contains(version, prereleases=True):
+            missing.append(spec)
+        continue
+    name = re.match(r"\s*([A-Za-z0-9][A-Za-z0-9._-]*)", spec)
+    if name is not None and installed(name.group(1)) is None:
+        missing.append(spec)
+print(json.dumps(missing))
+"#;
+
+/// Lists the modules in `sys.argv[1]` (JSON) that fail to import. They are
+/// really imported: `tkinter` is found even when `_tkinter` is missing.
+const STDLIB_CHECK_CODE: &str = r#"
+import importlib, json, sys
+missing = []
+for name in json.loads(sys.argv[1]):
+    try:
+        importlib.import_module(name)
+    except Exception:
+        missing.append(name)
+print(json.dumps(missing))
+"#;
+
+/// Reads `add_argument` calls from the script's source. Only literal values
+/// are reported; nothing in the script runs.
+const ARGUMENTS_CHECK_CODE: &str = r#"
+import ast, json, sys
+path = sys.argv[1]
+with open(path, "rb") as file:
+    tree = ast.parse(file.read(), path)
+uses_argparse = any(
+    (isinstance(node, ast.Import) and any(alias.name == "argparse" for alias in node.names))
+    or (isinstance(node, ast.ImportFrom) and node.module == "argparse")
+    for node in ast.walk(tree)
+)
+def literal(keywords, name):
+    try:
+        return True, ast.literal_eval(keywords[name])
+    except (KeyError, ValueError, TypeError, SyntaxError, RecursionError):
+        return False, None
+calls = sorted(
+    (node for node in ast.walk(tree)
+     if isinstance(node, ast.Call)
+     and isinstance(node.func, ast.Attribute)
+     and node.func.attr == "add_argument"),
+    key=lambda node: (node.lineno, node.col_offset),
+)
+arguments = []
+for call in calls:
+    flags = [arg.value for arg in call.args
+             if isinstance(arg, ast.Constant) and isinstance(arg.value, str)]
+    if not flags:
+        continue
+    keywords = {keyword.arg: keyword.value for keyword in call.keywords if keyword.arg}
+    positional = not flags[0].startswith("-")
+    has_dest, dest = literal(keywords, "dest")
+    if has_dest and isinstance(dest, str):
+        name = dest
+    elif positional:
+        name = flags[0]
+    else:
+        long_flags = [flag for flag in flags if flag.startswith("--")]
+        name = (long_flags or flags)[0].lstrip("-").replace("-", "_")
+    if positional:
+        has_nargs, nargs = literal(keywords, "nargs")
+        required = not (has_nargs and nargs in ("?", "*"))
+    else:
+        required = literal(keywords, "required") == (True, True)
+    has_default, default = literal(keywords, "default")
+    has_action, action = literal(keywords, "action")
+    if not has_default and has_action and action in ("store_true", "store_false"):
+        default = action == "store_false"
+    _, help_text = literal(keywords, "help")
+    arguments.append({
+        "name": name,
+        "flags": flags,
+        "required": required,
+        "default": default,
+        "help": help_text if isinstance(help_text, str) else None,
+    })
+print(json.dumps({"uses_argparse": uses_argparse, "arguments": arguments}, default=repr))
+"#;
+
+const ARGUMENTS_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// `--help` runs the script's top-level code, so it gets little time.
+const HELP_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_HELP_TEXT_BYTES: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+struct ArgumentsCheck {
+    uses_argparse: bool,
+    arguments: Vec<ScriptArgument>,
+}
+
+async fn read_script_arguments(
+    candidate: &PythonCandidate,
+    path: &Path,
+) -> Result<ArgumentsCheck, String> {
+    run_check_within(
+        ARGUMENTS_CHECK_TIMEOUT,
+        candidate,
+        ARGUMENTS_CHECK_CODE,
+        &[path.as_os_str()],
+        "arguments",
+    )
+    .await
+}
+
+/// Runs `script --help` from an empty scratch directory with no stdin.
+/// The script's top-level code does run, so this is only the fallback.
+async fn script_help_text(candidate: &PythonCandidate, path: &Path) -> Result<String, String> {
+    let scratch = ScratchDir::create(None, false)?;
+    let mut command = Command::new(&candidate.program);
+    command
+        .args(&candidate.pre_args)
+        .arg(path)
+        .arg("--help")
+        .current_dir(&scratch.path)
+        .env("PYTHONDONTWRITEBYTECODE", "1")
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(HELP_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) => return Err(format!("failed to run --help: {}", error)),
+        Err(_) => return Err("timed out running --help".to_string()),
+    };
+    let text = if output.stdout.is_empty() {
+        output.stderr
+    } else {
+        output.stdout
+    };
+    let text = &text[..text.len().min(MAX_HELP_TEXT_BYTES)];
+    Ok(String::from_utf8_lossy(text).trim_end().to_string())
+}
+
+/// Import names whose PyPI package is called something else.
+const PIP_PACKAGE_NAMES: &[(&str, &str)] = &[
+    ("bs4", "beautifulsoup4"),
+    ("cv2", "opencv-python"),
+    ("dateutil", "python-dateutil"),
+    ("dotenv", "python-dotenv"),
+    ("Crypto", "pycryptodome"),
+    ("PIL", "pillow"),
+    ("serial", "pyserial"),
+    ("sklearn", "scikit-learn"),
+    ("win32api", "pywin32"),
+    ("yaml", "pyyaml"),
+];
+
+fn pip_package_name(module: &str) -> &str {
+    PIP_PACKAGE_NAMES
+        .iter()
+        .find(|(import_name, _)| *import_name == module)
+        .map_or(module, |(_, package)| package)
+}
+
+async fn find_missing_imports(
+    candidate: &PythonCandidate,
+    path: &Path,
+) -> Result<Vec<String>, String> {
+    run_check_within(
+        MODULE_IMPORT_CHECK_TIMEOUT,
+        candidate,
+        &with_import_scan(IMPORT_CHECK_CODE),
+        &[path.as_os_str()],
+        "imports",
+    )
+    .await
+}
+
+/// Standard library modules that Linux distributions ship separately.
+const STDLIB_SYSTEM_PACKAGES: &[(&str, &str)] = &[
+    ("dbm.gnu", "python3-gdbm"),
+    ("distutils", "python3-distutils"),
+    ("ensurepip", "python3-venv"),
+    ("lib2to3", "python3-lib2to3"),
+    ("tkinter", "python3-tk"),
+    ("turtle", "python3-tk"),
+    ("venv", "python3-venv"),
+];
+
+fn stdlib_system_package(module: &str) -> Option<&'static str> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let top_level = module.split('.').next().unwrap_or(module);
+    STDLIB_SYSTEM_PACKAGES
+        .iter()
+        .find(|(name, _)| *name == module)
+        .or_else(|| {
+            STDLIB_SYSTEM_PACKAGES
+                .iter()
+                .find(|(name, _)| *name == top_level)
+        })
+        .map(|(_, package)| *package)
+}
+
+async fn find_missing_dependencies(
+    candidate: &PythonCandidate,
+    dependencies: &[String],
+) -> Result<Vec<String>, String> {
+    run_check(
+        candidate,
+        DEPENDENCY_CHECK_CODE,
+        dependencies,
+        "dependencies",
+    )
+    .await
+}
+
+/// All of `modules` are tried in one interpreter run.
+async fn find_missing_stdlib(
+    candidate: &PythonCandidate,
+    modules: &[String],
+) -> Result<Vec<String>, String> {
+    run_check(
+        candidate,
+        STDLIB_CHECK_CODE,
+        modules,
+        "standard library modules",
+    )
+    .await
+}
+
+/// Runs `check_code` with `names` as JSON and reads back its JSON answer,
+/// such as the list of those that are missing.
+async fn run_check<T: serde::de::DeserializeOwned>(
+    candidate: &PythonCandidate,
+    check_code: &str,
+    names: &[String],
+    what: &str,
+) -> Result<T, String> {
+    let names = serde_json::to_string(names)
+        .map_err(|error| format!("failed to check {}: {}", what, error))?;
+    run_check_within(
+        MODULE_IMPORT_CHECK_TIMEOUT,
+        candidate,
+        check_code,
+        &[OsStr::new(&names)],
+        what,
+    )
+    .await
+}
+
+/// Runs `check_code` with `-c` and `args` and reads its JSON answer from
+/// the last line of stdout, as modules a check imports may print too.
+/// `-B` keeps imports from writing bytecode into the user's project or
+/// environment. `what` names the check in errors.
+async fn run_check_within<T: serde::de::DeserializeOwned>(
+    timeout: Duration,
+    candidate: &PythonCandidate,
+    check_code: &str,
+    args: &[&OsStr],
+    what: &str,
+) -> Result<T, String> {
+    let mut command = Command::new(&candidate.program);
+    command
+        .args(&candidate.pre_args)
+        .arg("-B")
+        .arg("-c")
+        .arg(check_code)
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(timeout, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) => return Err(format!("failed to check {}: {}", what, error)),
+        Err(_) => return Err(format!("timed out checking {}", what)),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let answer = stdout
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default();
+    serde_json::from_str(answer.trim()).map_err(|_| {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("no result");
+        format!("failed to check {}: {}", what, reason.trim())
+    })
+}
+
+/// The `InterpreterHealth` report. Everything is guarded so that a broken
+/// module shows up in the report rather than as a crash.
+const HEALTH_CHECK_CODE: &str = r#"
+import json, locale, sys
+def check(name):
+    try:
+        if name == "pip":
+            from importlib.util import find_spec
+            if find_spec("pip") is None:
+                raise ImportError("No module named 'pip'")
+        else:
+            __import__(name)
+        return {"importable": True, "error": None}
+    except Exception as error:
+        return {"importable": False, "error": "%s: %s" % (type(error).__name__, error)}
+try:
+    import site
+    site_packages = list(site.getsitepackages())
+    if site.ENABLE_USER_SITE:
+        site_packages.append(site.getusersitepackages())
+except Exception:
+    site_packages = [path for path in sys.path if path.endswith("site-packages")]
+print(json.dumps({
+    "executable": sys.executable,
+    "version": "%d.%d.%d" % tuple(sys.version_info[:3]),
+    "prefix": sys.prefix,
+    "base_prefix": getattr(sys, "base_prefix", sys.prefix),
+    "modules": dict((name, check(name)) for name in ("pip", "venv", "ssl", "sqlite3")),
+    "site_packages": site_packages,
+    "encodings": {
+        "filesystem": sys.getfilesystemencoding(),
+        "preferred": locale.getpreferredencoding(False),
+        "stdout": getattr(sys.stdout, "encoding", None),
+    },
+}))
+"#;
+
+/// A stuck interpreter fails the check rather than the diagnostics page.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Maps each name in `sys.argv[1]` (JSON) to its installed version, or
+/// `None`.
+const INSTALLED_VERSIONS_CODE: &str = r#"
+import json, sys
+from importlib import metadata
+versions = {}
+for name in json.loads(sys.argv[1]):
+    try:
+        versions[name] = metadata.version(name)
+    except metadata.PackageNotFoundError:
+        versions[name] = None
+print(json.dumps(versions))
+"#;
+
+/// Every distribution `importlib.metadata` finds, first one per name, with
+/// whether another one requires it (extras aside) and whether pip is
+/// importable.
+const INSTALLED_PACKAGES_CODE: &str = r#"
+import json, re
+from importlib import metadata
+from importlib.util import find_spec
+def normalize(name):
+    return re.sub(r"[-_.]+", "-", name).lower()
+packages = {}
+required = set()
+for dist in metadata.distributions():
+    name = dist.metadata["Name"]
+    if not name or normalize(name) in packages:
+        continue
+    packages[normalize(name)] = {
+        "name": name,
+        "version": dist.version,
+        "location": str(dist.locate_file("")),
+    }
+    for requirement in dist.requires or []:
+        match = re.match(r"[A-Za-z0-9._-]+", requirement)
+        if match and not re.search(r"\bextra\s*==", requirement):
+            required.add(normalize(match.group(0)))
+print(json.dumps({
+    "packages": [packages[key] for key in sorted(packages)],
+    "required": sorted(required & set(packages)),
+    "has_pip": find_spec("pip") is not None,
+}))
+"#;
+
+/// For `sys.argv[1]` (JSON) holding a script path: the distributions that
+/// provide its imports and, transitively, what they require. For an empty
+/// list: every distribution but the packaging tools, as `pip freeze`
+/// leaves them out. Each comes with how it was installed, from its
+/// `direct_url.json` (PEP 610). Imports that neither a distribution nor
+/// the script's directory provides are `missing`.
+const EXPORT_REQUIREMENTS_CODE: &str = r#"
+import json, os, re, sys
+from importlib import metadata
+from importlib.util import find_spec
+def normalize(name):
+    return re.sub(r"[-_.]+", "-", name).lower()
+dists = {}
+for dist in metadata.distributions():
+    name = dist.metadata["Name"]
+    if name and normalize(name) not in dists:
+        dists[normalize(name)] = dist
+def provides(dist):
+    text = dist.read_text("top_level.txt")
+    if text:
+        return set(text.split())
+    names = set()
+    for file in dist.files or []:
+        top = file.parts[0] if file.parts else ""
+        if top in ("", "..", "__pycache__") or top.endswith((".dist-info", ".egg-info", ".pth")):
+            continue
+        names.add(top.split(".")[0] if len(file.parts) == 1 else top)
+    return names
+def requires(dist):
+    for requirement in dist.requires or []:
+        match = re.match(r"[A-Za-z0-9._-]+", requirement)
+        if match and not re.search(r"\bextra\s*==", requirement):
+            yield normalize(match.group(0))
+def source(dist):
+    try:
+        direct = json.loads(dist.read_text("direct_url.json") or "null")
+    except ValueError:
+        direct = None
+    if not isinstance(direct, dict) or not direct.get("url"):
+        return {"source": "index", "url": None}
+    url = direct["url"]
+    vcs = direct.get("vcs_info")
+    if direct.get("dir_info", {}).get("editable"):
+        return {"source": "editable", "url": url}
+    if vcs:
+        return {"source": "vcs", "url": "%s+%s@%s" % (vcs["vcs"], url, vcs["commit_id"])}
+    if url.startswith("file:"):
+        return {"source": "local", "url": url}
+    return {"source": "url", "url": url}
+scripts = json.loads(sys.argv[1])
+missing = []
+if scripts:
+    sys.path[0] = os.path.dirname(os.path.abspath(scripts[0]))
+    by_module = {}
+    for key in sorted(dists):
+        for module in provides(dists[key]):
+            by_module.setdefault(module, key)
+    wanted = []
+    for module in sorted(top_level_imports(scripts[0])):
+        if module in by_module:
+            wanted.append(by_module[module])
+            continue
+        try:
+            found = find_spec(module) is not None
+        except (ImportError, ValueError):
+            found = False
+        if not found:
+            missing.append(module)
+    selected = set()
+    while wanted:
+        key = wanted.pop()
+        if key in dists and key not in selected:
+            selected.add(key)
+            wanted.extend(requires(dists[key]))
+else:
+    selected = set(dists) - {"pip", "setuptools", "wheel", "distribute"}
+packages = []
+for key in sorted(selected):
+    package = {"name": dists[key].metadata["Name"], "version": dists[key].version}
+    package.update(source(dists[key]))
+    packages.append(package)
+print(json.dumps({"packages": packages, "missing": missing}))
+"#;
+
+#[derive(Deserialize)]
+struct FrozenEnvironment {
+    packages: Vec<ExportedRequirement>,
+    missing: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct InstalledPackages {
+    packages: Vec<InstalledDistribution>,
+    /// Normalized names.
+    required: Vec<String>,
+    has_pip: bool,
+}
+
+/// Everything resolved for a run before any interpreter is tried.
+struct RunPlan {
+    run_id: String,
+    target: ScriptTarget,
+    working_dir: PathBuf,
+    /// Validated `extra_python_paths`, in order.
+    python_paths: Vec<PathBuf>,
+    /// The child's `PATH`, when it isn't the one it would inherit.
+    path: Option<OsString>,
+    /// See `RunPythonScriptResponse::path_head`.
+    path_head: Option<Vec<String>>,
+    payload: Option<Payload>,
+    scratch: ScratchDir,
+    args: ArgTemplate,
+    output_format: OutputFormat,
+    stdout_filter: Option<regex::bytes::Regex>,
+    stderr_filter: Option<regex::bytes::Regex>,
+    stop_on_pattern: Option<regex::bytes::Regex>,
+    /// Validated `stdout_file` / `stderr_file`.
+    stdout_file: Option<PathBuf>,
+    stderr_file: Option<PathBuf>,
+    /// Found while preparing the run; copied into every response.
+    warnings: Vec<String>,
+    /// Per attempt.
+    timeout: Duration,
+    /// Across all attempts, measured from the first spawn.
+    deadline: Duration,
+    output_sink: Option<OutputSink>,
+    progress_sink: Option<ProgressSink>,
+    /// From `min_python_version`, or else the script's header.
+    python_requirement: Option<VersionRequirement>,
+    runner: Runner,
+    /// For runner "wsl".
+    wsl: Option<wsl::Session>,
+    interpreters: InterpreterInfoCache,
+    validated_interpreters: ValidatedInterpreters,
+    cancel: CancelSignal,
+    tracker: RunTracker,
+    output_encoding: OutputEncoding,
+    priority: Priority,
+    /// Filled in once the run leaves the queue.
+    queued_ms: u64,
+    admission: Admission,
+}
+
+fn next_run_id() -> String {
+    static NEXT_RUN: AtomicU64 = AtomicU64::new(1);
+    format!(
+        "run-{:x}-{}",
+        unix_time_ms(),
+        NEXT_RUN.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+pub(crate) fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Largest index `<= max` that does not split a UTF-8 sequence in `bytes`.
+fn utf8_boundary(bytes: &[u8], max: usize) -> usize {
+    if max >= bytes.len() {
+        return bytes.len();
+    }
+
+    let mut index = max;
+    while index > 0 && (bytes[index] & 0xC0) == 0x80 {
+        index -= 1;
+    }
+    index
+}
+
+/// Splits a byte stream into lines and forwards them to the output sink.
+struct LineEmitter {
+    run_id: String,
+    correlation_id: Option<String>,
+    labels: Option<HashMap<String, String>>,
+    stream: OutputStream,
+    sink: OutputSink,
+    max_line_bytes: usize,
+    encoding: &'static encoding_rs::Encoding,
+    pending: Vec<u8>,
+}
+
+impl LineEmitter {
+    fn push(&mut self, chunk: &[u8]) {
+        self.pending.extend_from_slice(chunk);
+
+        while let Some(newline) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let mut line: Vec<u8> = self.pending.drain(..=newline).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            self.emit_chunked(&line);
+        }
+
+        while self.pending.len() > self.max_line_bytes {
+            let cut = match utf8_boundary(&self.pending, self.max_line_bytes) {
+                0 => self.max_line_bytes,
+                cut => cut,
+            };
+            let head: Vec<u8> = self.pending.drain(..cut).collect();
+            self.emit(&head);
+        }
+    }
+
+    fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            let rest = std::mem::take(&mut self.pending);
+            self.emit_chunked(&rest);
+        }
+    }
+
+    fn emit_chunked(&self, mut line: &[u8]) {
+        if line.is_empty() {
+            self.emit(line);
+            return;
+        }
+
+        while !line.is_empty() {
+            let cut = match utf8_boundary(line, self.max_line_bytes) {
+                0 => self.max_line_bytes.min(line.len()),
+                cut => cut,
+            };
+            self.emit(&line[..cut]);
+            line = &line[cut..];
+        }
+    }
+
+    fn emit(&self, line: &[u8]) {
+        (self.sink)(ScriptOutputEvent {
+            run_id: self.run_id.clone(),
+            correlation_id: self.correlation_id.clone(),
+            labels: self.labels.clone(),
+            batch_index: None,
+            stream: self.stream,
+            line: decoding::decode(line, self.encoding),
+            ts_ms: unix_time_ms(),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Binary,
+}
+
+impl OutputFormat {
+    fn parse(label: Option<&str>) -> Result<Self, String> {
+        match label.map(str::trim).filter(|label| !label.is_empty()) {
+            None => Ok(OutputFormat::Text),
+            Some(label) if label.eq_ignore_ascii_case("text") => Ok(OutputFormat::Text),
+            Some(label) if label.eq_ignore_ascii_case("binary") => Ok(OutputFormat::Binary),
+            Some(label) => Err(format!("unsupported output_format: {}", label)),
+        }
+    }
+}
+
+/// Lines from both pipes in the order the readers saw them. Stops recording
+/// once `limit` bytes of text are held, like the per-stream captures.
+struct CombinedOutput {
+    started: Instant,
+    limit: usize,
+    bytes: usize,
+    lines: Vec<OutputLine>,
+}
+
+impl CombinedOutput {
+    fn record(&mut self, stream: OutputStream, text: &str) {
+        if self.bytes >= self.limit {
+            return;
+        }
+        self.bytes += text.len();
+        self.lines.push(OutputLine {
+            stream,
+            text: text.to_string(),
+            offset_ms: self.started.elapsed().as_millis() as u64,
+        });
+    }
+}
+
+#[derive(Debug, Default)]
+struct CapturedStream {
+    bytes: Vec<u8>,
+    truncated: bool,
+    /// Cut exactly at the limit instead of at a character boundary.
+    binary: bool,
+    /// Output goes here instead of into `bytes`, without a size cap.
+    file: Option<OutputFileWriter>,
+}
+
+#[derive(Debug)]
+struct OutputFileWriter {
+    path: PathBuf,
+    writer: std::io::BufWriter<std::fs::File>,
+    written: u64,
+    /// First write error; later output is discarded.
+    error: Option<std::io::Error>,
+}
+
+impl OutputFileWriter {
+    fn open(path: &Path, append: bool) -> Result<Self, std::io::Error> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        Ok(OutputFileWriter {
+            path: path.to_path_buf(),
+            writer: std::io::BufWriter::new(file),
+            written: 0,
+            error: None,
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        use std::io::Write;
+
+        if self.error.is_some() {
+            return;
+        }
+        match self.writer.write_all(data) {
+            Ok(()) => self.written += data.len() as u64,
+            Err(error) => self.error = Some(error),
+        }
+    }
+
+    fn close(mut self) -> Result<OutputFile, std::io::Error> {
+        use std::io::Write;
+
+        let path = self.path.display().to_string();
+        let failed = |error: std::io::Error| {
+            std::io::Error::new(error.kind(), format!("failed to write {}: {}", path, error))
+        };
+        if let Some(error) = self.error.take() {
+            return Err(failed(error));
+        }
+        self.writer.flush().map_err(failed)?;
+        Ok(OutputFile {
+            path: path.clone(),
+            bytes_written: self.written,
+        })
+    }
+}
+
+/// Reads a pipe to EOF, keeping at most `limit` bytes. The pipe keeps being
+/// drained past the limit so the child never blocks on a full buffer.
+async fn capture_stream<R: AsyncRead + Unpin>(
+    mut reader: R,
+    limit: usize,
+    mut emitter: Option<LineEmitter>,
+    captured: &Mutex<CapturedStream>,
+    activity: &ActivityClock,
+    mut stages: LineStages,
+) -> Result<(), std::io::Error> {
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        activity.touch();
+
+        let output = stages.apply(&chunk[..read]);
+        keep_output(&output, limit, emitter.as_mut(), captured);
+    }
+
+    keep_output(&stages.finish(), limit, emitter.as_mut(), captured);
+    if let Some(emitter) = emitter.as_mut() {
+        emitter.finish();
+    }
+
+    Ok(())
+}
+
+/// Line-wise processing between a pipe and its capture: progress lines are
+/// taken out first, then filtered lines are dropped, then output past the
+/// stop pattern.
+#[derive(Default)]
+struct LineStages {
+    progress: Option<ProgressReporter>,
+    filter: Option<LineFilter>,
+    stop: Option<StopMarker>,
+}
+
+impl LineStages {
+    fn apply<'a>(&mut self, chunk: &'a [u8]) -> std::borrow::Cow<'a, [u8]> {
+        let mut output = std::borrow::Cow::Borrowed(chunk);
+        if let Some(progress) = self.progress.as_mut() {
+            output = progress.filter(&output).into();
+        }
+        if let Some(filter) = self.filter.as_mut() {
+            output = filter.filter(&output).into();
+        }
+        if let Some(stop) = self.stop.as_mut() {
+            output = stop.apply(&output).into();
+        }
+        output
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let mut output = self
+            .progress
+            .as_mut()
+            .map(ProgressReporter::finish)
+            .unwrap_or_default();
+        if let Some(filter) = self.filter.as_mut() {
+            output = filter.filter(&output);
+            output.extend(filter.finish());
+        }
+        if let Some(stop) = self.stop.as_mut() {
+            output = stop.apply(&output);
+            stop.finish();
+        }
+        output
+    }
+}
+
+fn keep_output(
+    data: &[u8],
+    limit: usize,
+    emitter: Option<&mut LineEmitter>,
+    captured: &Mutex<CapturedStream>,
+) {
+    if data.is_empty() {
+        return;
+    }
+
+    if let Some(emitter) = emitter {
+        emitter.push(data);
+    }
+
+    let mut captured = captured.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some(file) = captured.file.as_mut() {
+        file.write(data);
+        return;
+    }
+    if captured.truncated {
+        return;
+    }
+
+    let room = limit - captured.bytes.len();
+    if data.len() <= room {
+        captured.bytes.extend_from_slice(data);
+    } else {
+        // Keep a few bytes past the limit so the cut can back off to a
+        // UTF-8 boundary instead of splitting a character.
+        captured
+            .bytes
+            .extend_from_slice(&data[..data.len().min(room + 4)]);
+        let cut = if captured.binary {
+            limit
+        } else {
+            utf8_boundary(&captured.bytes, limit)
+        };
+        captured.bytes.truncate(cut);
+        captured.truncated = true;
+    }
+}
+
+/// When a run last produced output on either stream.
+struct ActivityClock {
+    started: Instant,
+    last_output_ms: AtomicU64,
+}
+
+impl ActivityClock {
+    fn new() -> Self {
+        ActivityClock {
+            started: Instant::now(),
+            last_output_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_output_ms.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    fn last_output(&self) -> Instant {
+        self.started + Duration::from_millis(self.last_output_ms.load(Ordering::Relaxed))
+    }
+
+    /// Resolves once `idle` has passed without output. Never resolves when
+    /// `idle` is `None`.
+    async fn idle_for(&self, idle: Option<Duration>) {
+        let Some(idle) = idle else {
+            return std::future::pending().await;
+        };
+
+        loop {
+            let due = self.last_output() + idle;
+            if Instant::now() >= due {
+                return;
+            }
+            tokio::time::sleep_until(due.into()).await;
+        }
+    }
+}
+
+/// A pipe reader running on its own task. Whatever it has collected stays
+/// reachable even if the task never finishes.
+struct StreamCapture {
+    captured: Arc<Mutex<CapturedStream>>,
+    task: JoinHandle<Result<(), std::io::Error>>,
+}
+
+impl StreamCapture {
+    fn spawn<R: AsyncRead + Unpin + Send + 'static>(
+        reader: R,
+        limit: usize,
+        emitter: Option<LineEmitter>,
+        activity: Arc<ActivityClock>,
+        stages: LineStages,
+        target: CapturedStream,
+    ) -> Self {
+        let captured = Arc::new(Mutex::new(target));
+        let task = tokio::spawn({
+            let captured = captured.clone();
+            async move { capture_stream(reader, limit, emitter, &captured, &activity, stages).await }
+        });
+        StreamCapture { captured, task }
+    }
+
+    /// Waits for the reader, or at most `deadline` before settling for the
+    /// bytes collected so far.
+    async fn finish(
+        mut self,
+        deadline: Option<Duration>,
+    ) -> Result<CapturedStream, std::io::Error> {
+        let result = match deadline {
+            None => (&mut self.task).await,
+            Some(deadline) => match tokio::time::timeout(deadline, &mut self.task).await {
+                Ok(result) => result,
+                Err(_) => {
+                    self.task.abort();
+                    Ok(Ok(()))
+                }
+            },
+        };
+        if let Ok(Err(error)) = result {
+            return Err(error);
+        }
+
+        let mut captured = self
+            .captured
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        Ok(std::mem::take(&mut *captured))
+    }
+}
+
+/// Stops a running child, giving it `grace` to exit voluntarily first.
+/// Returns the exit status and whether a force-kill was needed. Under WSL
+/// the Linux processes are signalled too, as they outlive `wsl.exe`.
+async fn stop_child(
+    child: &mut Child,
+    process_tree: &ProcessTree,
+    wsl: Option<&wsl::Session>,
+    grace: Duration,
+) -> Result<(ExitStatus, bool), std::io::Error> {
+    if !grace.is_zero() {
+        process_tree.terminate();
+        if let Some(session) = wsl {
+            session.signal("TERM").await;
+        }
+        if let Ok(status) = tokio::time::timeout(grace, child.wait()).await {
+            let status = status?;
+            // The interpreter left on its own; sweep anything it left behind.
+            if let Some(session) = wsl {
+                session.signal("KILL").await;
+            }
+            process_tree.kill();
+            return Ok((status, false));
+        }
+    }
+
+    if let Some(session) = wsl {
+        session.signal("KILL").await;
+    }
+    process_tree.kill();
+    let _ = child.start_kill();
+    Ok((child.wait().await?, true))
+}
+
+/// The interpreter command for one attempt, without any process setup.
+fn build_command(
+    request: &RunPythonScriptRequest,
+    plan: &RunPlan,
+    candidate: &PythonCandidate,
+    expanded_args: &[String],
+) -> Command {
+    let mut command = Command::new(&candidate.program);
+    match &plan.wsl {
+        Some(session) => command.args(session.launch_args()),
+        None => command.args(&candidate.pre_args),
+    };
+
+    command.args(&request.interpreter_args);
+    match plan
+        .wsl
+        .as_ref()
+        .and_then(|session| session.script.as_ref())
+    {
+        Some(script) => {
+            command.arg(script);
+        }
+        None => plan.target.apply(&mut command),
+    }
+    command
+        .args(expanded_args)
+        .current_dir(candidate.working_dir(request, plan));
+    apply_request_env(
+        &mut command,
+        request,
+        plan.path.as_deref(),
+        &plan.python_paths,
+    );
+    if let Some(payload) = &plan.payload {
+        payload.apply(&mut command);
+    }
+    command.env(SCRATCH_DIR_ENV, &plan.scratch.path);
+    if plan.wsl.is_some() {
+        wsl::forward_env(&mut command, &[PAYLOAD_FILE_ENV, SCRATCH_DIR_ENV]);
+    }
+    command
+}
+
+fn command_line(command: &Command) -> Vec<String> {
+    let command = command.as_std();
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect()
+}
+
+async fn execute_with_candidate(
+    request: &RunPythonScriptRequest,
+    plan: &RunPlan,
+    candidate: &PythonCandidate,
+    timeout: Duration,
+) -> Result<RunPythonScriptResponse, std::io::Error> {
+    let start_time = Instant::now();
+
+    let expanded_args = plan.args.render(&chrono::Local::now());
+    let mut command = build_command(request, plan, candidate, &expanded_args);
+    let resolved_command = command_line(&command);
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    if request.stdin.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    process_tree::isolate(&mut command, request.show_console.unwrap_or(false));
+    let limits = resource_limits(request);
+    limits.apply_before_spawn(&mut command);
+
+    let stdout_file = plan
+        .stdout_file
+        .as_deref()
+        .map(|path| OutputFileWriter::open(path, request.append))
+        .transpose()?;
+    let stderr_file = plan
+        .stderr_file
+        .as_deref()
+        .map(|path| OutputFileWriter::open(path, request.append))
+        .transpose()?;
+    let mut child = command.spawn()?;
+    let spawned_at = Instant::now();
+    let process_tree = ProcessTree::attach(&child);
+    limits.apply_after_spawn(&child, &process_tree);
+    let resource_monitor = ResourceMonitor::start(&child);
+    plan.tracker
+        .set_running(&candidate.display_name, child.id());
+
+    // Written from its own task so a large payload can't deadlock against a
+    // child that is blocked on a full stdout/stderr pipe.
+    let stdin_handle = match (child.stdin.take(), &request.stdin) {
+        (Some(mut stdin), Some(input)) => {
+            let input = input.clone().into_bytes();
+            Some(tokio::spawn(async move {
+                let _ = stdin.write_all(&input).await;
+                let _ = stdin.shutdown().await;
+            }))
+        }
+        _ => None,
+    };
+
+    let stdout = child
+        .stdout
+        .take()
+        .expect("stdout pipe should be available");
+    let stderr = child
+        .stderr
+        .take()
+        .expect("stderr pipe should be available");
+
+    let output_limit = request
+        .max_output_bytes
+        .unwrap_or(DEFAULT_MAX_OUTPUT_BYTES)
+        .try_into()
+        .unwrap_or(usize::MAX);
+    let combined = request.capture_combined.then(|| {
+        Arc::new(Mutex::new(CombinedOutput {
+            started: start_time,
+            limit: output_limit,
+            bytes: 0,
+            lines: Vec::new(),
+        }))
+    });
+    // Combined capture rides on the line emitters, wrapping the caller's sink
+    // (if any) so both see the same lines.
+    let output_sink = match &combined {
+        Some(combined) => {
+            let combined = combined.clone();
+            let forward = plan.output_sink.clone();
+            Some(Arc::new(move |event: ScriptOutputEvent| {
+                combined
+                    .lock()
+                    .unwrap_or_else(|error| error.into_inner())
+                    .record(event.stream, &event.line);
+                if let Some(forward) = &forward {
+                    forward(event);
+                }
+            }) as OutputSink)
+        }
+        None => plan.output_sink.clone(),
+    };
+    let line_emitter = |stream| {
+        output_sink.clone().map(|sink| LineEmitter {
+            run_id: plan.run_id.clone(),
+            correlation_id: request.correlation_id.clone(),
+            labels: request.labels.clone(),
+            stream,
+            sink,
+            max_line_bytes: request
+                .stream_line_limit
+                .unwrap_or(DEFAULT_STREAM_LINE_LIMIT)
+                .max(1),
+            // `auto` can only be decided once all output is in.
+            encoding: match plan.output_encoding {
+                OutputEncoding::Fixed(encoding) => encoding,
+                OutputEncoding::Auto => encoding_rs::UTF_8,
+            },
+            pending: Vec::new(),
+        })
+    };
+    let activity = Arc::new(ActivityClock::new());
+    let progress = ProgressReporter::new(
+        plan.run_id.clone(),
+        request.correlation_id.clone(),
+        request.labels.clone(),
+        plan.progress_sink.clone(),
+    );
+    let progress_summary = progress.summary();
+    let filtered_lines = Arc::new(AtomicU64::new(0));
+    let stop_signal = Arc::new(StopSignal::default());
+    // Binary stdout is captured untouched: no line processing, no streaming.
+    let binary = plan.output_format == OutputFormat::Binary;
+    let stdout_target = CapturedStream {
+        binary,
+        file: stdout_file,
+        ..Default::default()
+    };
+    let stderr_target = CapturedStream {
+        file: stderr_file,
+        ..Default::default()
+    };
+    let stdout_capture = if binary {
+        StreamCapture::spawn(
+            stdout,
+            output_limit,
+            None,
+            activity.clone(),
+            LineStages::default(),
+            stdout_target,
+        )
+    } else {
+        StreamCapture::spawn(
+            stdout,
+            output_limit,
+            line_emitter(OutputStream::Stdout),
+            activity.clone(),
+            LineStages {
+                progress: Some(progress),
+                filter: plan
+                    .stdout_filter
+                    .clone()
+                    .map(|regex| LineFilter::new(regex, filtered_lines.clone())),
+                stop: plan
+                    .stop_on_pattern
+                    .clone()
+                    .map(|regex| StopMarker::new(regex, stop_signal.clone())),
+            },
+            stdout_target,
+        )
+    };
+    let stderr_capture = StreamCapture::spawn(
+        stderr,
+        output_limit,
+        line_emitter(OutputStream::Stderr),
+        activity.clone(),
+        LineStages {
+            progress: None,
+            filter: plan
+                .stderr_filter
+                .clone()
+                .map(|regex| LineFilter::new(regex, filtered_lines.clone())),
+            stop: None,
+        },
+        stderr_target,
+    );
+    let idle_timeout = request.idle_timeout_ms.map(Duration::from_millis);
+
+    let grace = Duration::from_millis(
+        request
+            .grace_period_ms
+            .unwrap_or(0)
+            .min(MAX_GRACE_PERIOD_MS),
+    );
+    let mut timed_out = false;
+    let mut idle_timed_out = false;
+    let mut cancelled = false;
+    let mut cpu_limit_exceeded = false;
+    let mut force_killed = false;
+    let mut killed_on_pattern = false;
+    let status = tokio::select! {
+        status_result = tokio::time::timeout(timeout, resource_monitor.wait(&mut child)) => match status_result {
+            Ok(status_result) => status_result?,
+            Err(_) => {
+                timed_out = true;
+                plan.tracker.set_killing();
+                let (status, killed) = stop_child(&mut child, &process_tree, plan.wsl.as_ref(), grace).await?;
+                force_killed = killed;
+                status
+            }
+        },
+        _ = plan.cancel.cancelled() => {
+            cancelled = true;
+            plan.tracker.set_killing();
+            let (status, killed) =
+                stop_child(&mut child, &process_tree, plan.wsl.as_ref(), Duration::ZERO).await?;
+            force_killed = killed;
+            status
+        }
+        _ = activity.idle_for(idle_timeout) => {
+            idle_timed_out = true;
+            plan.tracker.set_killing();
+            let (status, killed) = stop_child(&mut child, &process_tree, plan.wsl.as_ref(), grace).await?;
+            force_killed = killed;
+            status
+        }
+        _ = resources::cpu_limit_reached(&process_tree, limits.max_cpu_time) => {
+            cpu_limit_exceeded = true;
+            plan.tracker.set_killing();
+            let (status, killed) =
+                stop_child(&mut child, &process_tree, plan.wsl.as_ref(), Duration::ZERO).await?;
+            force_killed = killed;
+            status
+        }
+        _ = stop_signal.triggered() => {
+            killed_on_pattern = true;
+            plan.tracker.set_killing();
+            let (status, _) = stop_child(&mut child, &process_tree, plan.wsl.as_ref(), Duration::ZERO).await?;
+            status
+        }
+    };
+
+    let exited_at = Instant::now();
+    let usage = resource_monitor.finish(&process_tree);
+    cpu_limit_exceeded |=
+        !timed_out && !idle_timed_out && !cancelled && limits.cpu_exceeded(&status, &usage);
+
+    // After a kill the readers only get a short deadline, so partial output is
+    // returned even when a pipe is still held open by a stray process.
+    let drain_deadline =
+        (timed_out || idle_timed_out || cancelled || cpu_limit_exceeded || killed_on_pattern)
+            .then_some(KILLED_READER_DRAIN);
+    if let Some(handle) = stdin_handle {
+        match drain_deadline {
+            Some(deadline) => {
+                if tokio::time::timeout(deadline, handle).await.is_err() {
+                    log::warn!("stdin writer for {} did not finish", plan.run_id);
+                }
+            }
+            None => {
+                let _ = handle.await;
+            }
+        }
+    }
+
+    let mut stdout_capture = stdout_capture.finish(drain_deadline).await?;
+    let mut stderr_capture = stderr_capture.finish(drain_deadline).await?;
+    let stdout_file = stdout_capture
+        .file
+        .take()
+        .map(OutputFileWriter::close)
+        .transpose()?;
+    let stderr_file = stderr_capture
+        .file
+        .take()
+        .map(OutputFileWriter::close)
+        .transpose()?;
+    let drain_ms = exited_at.elapsed().as_millis() as u64;
+    let progress = std::mem::take(
+        &mut *progress_summary
+            .lock()
+            .unwrap_or_else(|error| error.into_inner()),
+    );
+
+    let encoding = if binary {
+        plan.output_encoding.resolve(&[&stderr_capture.bytes])
+    } else {
+        plan.output_encoding
+            .resolve(&[&stdout_capture.bytes, &stderr_capture.bytes])
+    };
+    let (stdout, stdout_base64) = if binary && stdout_file.is_none() {
+        (String::new(), Some(BASE64.encode(&stdout_capture.bytes)))
+    } else {
+        (decoding::decode(&stdout_capture.bytes, encoding), None)
+    };
+    let stderr = decoding::decode(&stderr_capture.bytes, encoding);
+    let combined_output = combined.map(|combined| {
+        std::mem::take(
+            &mut combined
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .lines,
+        )
+    });
+    let oom_killed = !timed_out && !cancelled && limits.memory_exceeded(&status, &stderr, &usage);
+    let (data, parse_error) = if request.parse_json.unwrap_or(false) {
+        match extract_json(&stdout) {
+            Ok(data) => (Some(data), None),
+            Err(error) => (None, Some(error)),
+        }
+    } else {
+        (None, None)
+    };
+
+    let mut response = RunPythonScriptResponse {
+        run_id: plan.run_id.clone(),
+        correlation_id: request.correlation_id.clone(),
+        labels: request.labels.clone(),
+        from_cache: false,
+        coalesced: false,
+        rate_limited_until_ms: None,
+        ok: !timed_out
+            && !idle_timed_out
+            && !cancelled
+            && !cpu_limit_exceeded
+            && (stop_signal.is_triggered() || status.success()),
+        stdout,
+        stdout_base64,
+        stderr,
+        stdout_file,
+        stderr_file,
+        stdout_truncated: stdout_capture.truncated,
+        stderr_truncated: stderr_capture.truncated,
+        detected_encoding: encoding.name().to_string(),
+        expanded_args,
+        script_path: plan.target.script_path(),
+        resolved_command,
+        interpreter_source: Some(candidate.source.to_string()),
+        local_venv: candidate.local_venv(),
+        project_env: candidate.project_env(),
+        python_version: None,
+        python_executable: None,
+        python_alias: None,
+        interpreter_version: None,
+        runner_version: None,
+        dry_run: None,
+        exit_code: status.code().filter(|_| !killed_on_pattern),
+        signal: exit_signal(&status).filter(|_| !killed_on_pattern),
+        termination_reason: None,
+        crashed: false,
+        crash_report: None,
+        error_kind: None,
+        error_hint: None,
+        error_location: None,
+        missing_module: None,
+        traceback: None,
+        timed_out,
+        idle_timed_out,
+        force_killed,
+        oom_killed,
+        cpu_limit_exceeded,
+        cancelled,
+        duration_ms: start_time.elapsed().as_millis(),
+        timings: RunTimings {
+            resolve_ms: 0,
+            spawn_ms: spawned_at.duration_since(start_time).as_millis() as u64,
+            execute_ms: exited_at.duration_since(spawned_at).as_millis() as u64,
+            drain_ms,
+        },
+        peak_memory_bytes: usage.peak_memory_bytes,
+        cpu_time_ms: usage.cpu_time_ms,
+        attempts: 1,
+        retried_stderr: None,
+        queued_ms: plan.queued_ms,
+        priority: plan.admission.priority,
+        queue_positions: plan.admission.positions.clone(),
+        working_dir: candidate
+            .working_dir(request, plan)
+            .to_string_lossy()
+            .to_string(),
+        data,
+        parse_error,
+        combined_output,
+        filtered_line_count: filtered_lines.load(Ordering::Relaxed),
+        scratch_dir: None,
+        stopped_on_pattern: stop_signal.is_triggered(),
+        progress: progress.last,
+        progress_events: progress.count,
+        path_head: plan.path_head.clone(),
+        warnings: plan.warnings.clone(),
+    };
+    for (stream, truncated) in [
+        ("stdout", response.stdout_truncated),
+        ("stderr", response.stderr_truncated),
+    ] {
+        if truncated {
+            response.warnings.push(format!(
+                "{} exceeded {} bytes and was truncated",
+                stream, output_limit
+            ));
+        }
+    }
+    describe_ending(&mut response);
+    Ok(response)
+}
+
+/// Fills in the fields derived from how the run ended.
+fn describe_ending(response: &mut RunPythonScriptResponse) {
+    response.termination_reason = termination::reason(response);
+    response.crashed = termination::crashed(response);
+    response.crash_report = (!response.ok)
+        .then(|| termination::fatal_error_report(&response.stderr))
+        .flatten();
+    let failure = failure::classify(response);
+    response.error_kind = failure.kind;
+    response.error_location = failure.location;
+    response.missing_module = failure.missing_module;
+    response.traceback = (!response.ok)
+        .then(|| traceback::parse(&response.stderr))
+        .flatten();
+}
+
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+/// Runs the script with `candidate`, retrying non-zero exits as the request
+/// allows while staying within the run's deadline.
+async fn execute_with_retries(
+    request: &RunPythonScriptRequest,
+    plan: &RunPlan,
+    candidate: &PythonCandidate,
+) -> Result<RunPythonScriptResponse, std::io::Error> {
+    let start_time = Instant::now();
+    let deadline = start_time + plan.deadline;
+    let max_attempts = request.retries.unwrap_or(0).min(MAX_RETRIES) + 1;
+    let mut delay = Duration::from_millis(
+        request
+            .retry_delay_ms
+            .unwrap_or(DEFAULT_RETRY_DELAY_MS)
+            .min(MAX_RETRY_DELAY_MS),
+    );
+    let mut retried_stderr = None;
+    let mut attempt = 1;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut response =
+            execute_with_candidate(request, plan, candidate, plan.timeout.min(remaining)).await?;
+        response.attempts = attempt;
+
+        let retryable = !response.ok
+            && !response.timed_out
+            && !response.idle_timed_out
+            && !response.cancelled
+            && !response.oom_killed
+            && !response.cpu_limit_exceeded
+            && response.exit_code.is_some_and(|code| code != 0);
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        // Retrying is pointless if the next attempt couldn't get a second of
+        // run time before the deadline.
+        if !retryable || attempt >= max_attempts || remaining <= delay + MIN_ATTEMPT_TIME {
+            response.retried_stderr = retried_stderr;
+            response.duration_ms = start_time.elapsed().as_millis();
+            return Ok(response);
+        }
+
+        log::info!(
+            "run {} exited with {:?}, retrying in {} ms",
+            plan.run_id,
+            response.exit_code,
+            delay.as_millis()
+        );
+        retried_stderr = Some(response.stderr.clone());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = plan.cancel.cancelled() => {
+                response.cancelled = true;
+                describe_ending(&mut response);
+                response.retried_stderr = retried_stderr;
+                response.duration_ms = start_time.elapsed().as_millis();
+                return Ok(response);
+            }
+        }
+
+        attempt += 1;
+        if request.retry_backoff {
+            delay = (delay * 2).min(Duration::from_millis(MAX_RETRY_DELAY_MS));
+        }
+    }
+}
+
+/// Parses stdout as one JSON document. When the whole output is not JSON,
+/// each line starting with `{` or `[` is tried as the start of a document,
+/// preferring one that runs to the end of the output, so log lines printed
+/// before the payload don't break parsing.
+fn extract_json(stdout: &str) -> Result<serde_json::Value, String> {
+    let trimmed = stdout.trim();
+    if trimmed.is_empty() {
+        return Err("stdout is empty".to_string());
+    }
+
+    let whole_error = match serde_json::from_str(trimmed) {
+        Ok(value) => return Ok(value),
+        Err(error) => error,
+    };
+
+    let mut first_complete = None;
+    let mut offset = 0;
+    for line in stdout.split_inclusive('\n') {
+        let start = offset + (line.len() - line.trim_start().len());
+        offset += line.len();
+        if !stdout[start..].starts_with(['{', '[']) {
+            continue;
+        }
+
+        let mut documents =
+            serde_json::Deserializer::from_str(&stdout[start..]).into_iter::<serde_json::Value>();
+        let Some(Ok(value)) = documents.next() else {
+            continue;
+        };
+
+        if stdout[start + documents.byte_offset()..].trim().is_empty() {
+            return Ok(value);
+        }
+        first_complete.get_or_insert(value);
+    }
+
+    first_complete.ok_or_else(|| format!("stdout is not valid JSON: {}", whole_error))
+}
+
+fn resource_limits(request: &RunPythonScriptRequest) -> ResourceLimits {
+    ResourceLimits {
+        max_memory_bytes: request.max_memory_bytes,
+        max_cpu_time: request.max_cpu_time_ms.map(Duration::from_millis),
+        low_priority: request.low_priority.unwrap_or(false),
+    }
+}
+
+/// What `clean_env` passes on from the app's environment.
+#[cfg(windows)]
+const CLEAN_ENV_KEEP: &[&str] = &["PATH", "SYSTEMROOT", "TEMP", "TMP"];
+#[cfg(not(windows))]
+const CLEAN_ENV_KEEP: &[&str] = &["PATH", "HOME", "LANG", "TMPDIR"];
+
+/// Inherited variables that change how the interpreter starts up. Dropping
+/// one of these is worth a warning.
+const PYTHON_STARTUP_VARS: &[&str] = &[
+    "PYTHONHOME",
+    "PYTHONPATH",
+    "PYTHONSTARTUP",
+    "PYTHONUSERBASE",
+];
+
+fn same_env_key(a: &str, b: &str) -> bool {
+    if cfg!(windows) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+/// Whether the child gets the app's value of `key`, unless `env` sets it.
+fn inherits_env_var(request: &RunPythonScriptRequest, key: &str) -> bool {
+    if !request.inherit_env.unwrap_or(true) {
+        return false;
+    }
+    if request.clean_env {
+        return CLEAN_ENV_KEEP.iter().any(|keep| same_env_key(keep, key));
+    }
+    !request
+        .strip_env
+        .iter()
+        .any(|strip| same_env_key(strip, key))
+}
+
+/// Warns about interpreter-affecting variables set in the app's environment
+/// that `clean_env` or the `strip_env` setting keeps from the script.
+fn removed_env_warning(request: &RunPythonScriptRequest) -> Option<String> {
+    if !request.inherit_env.unwrap_or(true) {
+        return None;
+    }
+    let startup_vars: &[&str] = if request.clean_env {
+        PYTHON_STARTUP_VARS
+    } else {
+        &[]
+    };
+    let suspects = startup_vars
+        .iter()
+        .copied()
+        .chain(request.strip_env.iter().map(String::as_str));
+    let mut removed: Vec<&str> = Vec::new();
+    for key in suspects {
+        let overridden = request
+            .env
+            .as_ref()
+            .is_some_and(|env| env.keys().any(|set| same_env_key(set, key)));
+        if std::env::var_os(key).is_some()
+            && !inherits_env_var(request, key)
+            && !overridden
+            && !removed.iter().any(|seen| same_env_key(seen, key))
+        {
+            removed.push(key);
+        }
+    }
+    (!removed.is_empty()).then(|| {
+        format!(
+            "removed {} inherited from the app's environment",
+            removed.join(", ")
+        )
+    })
+}
+
+fn forces_utf8_io(request: &RunPythonScriptRequest) -> bool {
+    request.utf8_io.unwrap_or(cfg!(windows))
+}
+
+fn apply_request_env(
+    command: &mut Command,
+    request: &RunPythonScriptRequest,
+    path: Option<&OsStr>,
+    python_paths: &[PathBuf],
+) {
+    let inherit_env = request.inherit_env.unwrap_or(true);
+    if !inherit_env || request.clean_env {
+        command.env_clear();
+    }
+    if request.clean_env {
+        for key in CLEAN_ENV_KEEP {
+            if let Some(value) = std::env::var_os(key) {
+                command.env(key, value);
+            }
+        }
+    } else if inherit_env {
+        for key in &request.strip_env {
+            command.env_remove(key);
+        }
+    }
+
+    if forces_utf8_io(request) {
+        command.env("PYTHONIOENCODING", "utf-8");
+    }
+    if let Some(env) = &request.env {
+        command.envs(env);
+    }
+    if let Some(path) = path {
+        command.env("PATH", path);
+    }
+
+    if python_paths.is_empty() {
+        return;
+    }
+
+    let existing = match request.env.as_ref().and_then(|env| env.get("PYTHONPATH")) {
+        Some(value) => Some(OsString::from(value)),
+        None if inherits_env_var(request, "PYTHONPATH") => std::env::var_os("PYTHONPATH"),
+        None => None,
+    };
+    let existing = existing.filter(|value| !value.is_empty());
+    let entries = python_paths
+        .iter()
+        .cloned()
+        .chain(existing.iter().flat_map(std::env::split_paths));
+    // Every entry was checked by `resolve_dirs`.
+    if let Ok(joined) = std::env::join_paths(entries) {
+        command.env("PYTHONPATH", joined);
+    }
+}
+
+/// Checks the directories of a path-list option such as
+/// `extra_python_paths`, named by `field`.
+fn resolve_dirs(field: &str, paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    paths
+        .iter()
+        .map(|entry| {
+            let trimmed = entry.trim();
+            if trimmed.is_empty() {
+                return Err(format!("{} entries must not be empty", field));
+            }
+
+            let path = absolute_path(trimmed)?;
+            if !path.is_dir() {
+                return Err(format!(
+                    "{} entry is not an existing directory: {}",
+                    field, entry
+                ));
+            }
+
+            if std::env::join_paths([&path]).is_err() {
+                return Err(format!(
+                    "{} entry contains the path separator: {}",
+                    field, entry
+                ));
+            }
+
+            Ok(path)
+        })
+        .collect()
+}
+
+/// How many `PATH` entries `path_head` shows.
+const PATH_HEAD_LEN: usize = 5;
+
+/// The child's `PATH` when it isn't simply inherited: `dirs` in front of
+/// the `PATH` from `env`, the login shell's or the app's. Entries of `dirs`
+/// already on it move to the front instead of appearing twice.
+fn child_path(
+    request: &RunPythonScriptRequest,
+    dirs: &[PathBuf],
+    runner: Runner,
+    warnings: &mut Vec<String>,
+) -> (Option<OsString>, Option<Vec<String>>) {
+    if runner == Runner::Wsl {
+        if !dirs.is_empty() {
+            warnings.push("extra_path_dirs are not used for runs inside WSL".to_string());
+        }
+        return (None, None);
+    }
+    let requested = request.env.as_ref().and_then(|env| {
+        env.iter()
+            .find(|(key, _)| same_env_key(key, "PATH"))
+            .map(|(_, value)| OsString::from(value))
+    });
+    let mut substituted = false;
+    let base = match requested {
+        Some(value) => Some(value),
+        None if !inherits_env_var(request, "PATH") => None,
+        None => match &request.shell_path {
+            Some(shell_path) => {
+                substituted = true;
+                Some(OsString::from(shell_path))
+            }
+            None => std::env::var_os("PATH"),
+        },
+    };
+    if dirs.is_empty() && !substituted {
+        return (None, None);
+    }
+
+    let base: Vec<PathBuf> = base
+        .iter()
+        .flat_map(std::env::split_paths)
+        .filter(|entry| !entry.as_os_str().is_empty())
+        .collect();
+    if substituted {
+        warnings.push("started with the login shell's PATH instead of the app's".to_string());
+    }
+    let mut moved = false;
+    for dir in dirs.iter().filter(|dir| base.contains(dir)) {
+        moved = true;
+        warnings.push(format!(
+            "extra_path_dirs entry {} was already on PATH and is now searched first",
+            dir.display()
+        ));
+    }
+    let entries: Vec<PathBuf> = dirs
+        .iter()
+        .cloned()
+        .chain(base.into_iter().filter(|entry| !dirs.contains(entry)))
+        .collect();
+    let head = (substituted || moved).then(|| {
+        entries
+            .iter()
+            .take(PATH_HEAD_LEN)
+            .map(|entry| entry.display().to_string())
+            .collect()
+    });
+    // Every entry of `dirs` was checked by `resolve_dirs`; the rest came
+    // from a PATH already.
+    (std::env::join_paths(&entries).ok(), head)
+}
+
+/// Reads the login shell's `PATH` in the background, for the
+/// `login_shell_path` setting. Runs started before it's read get the app's.
+pub fn read_login_shell_path(store: SettingsStore) {
+    tauri::async_runtime::spawn(async move {
+        match login_shell::read_path().await {
+            Ok(path) => store.set_shell_path(Some(path)),
+            Err(error) => log::warn!("login_shell_path: {}", error),
+        }
+    });
+}
+
+/// Interpreter flags whose value is passed as a separate argument.
+const INTERPRETER_FLAGS_WITH_VALUE: [&str; 3] = ["-X", "-W", "--check-hash-based-pycs"];
+
+fn validate_labels(labels: Option<&HashMap<String, String>>) -> Result<(), String> {
+    let Some(labels) = labels else {
+        return Ok(());
+    };
+    if labels.len() > MAX_LABELS {
+        return Err(format!("labels: at most {} labels are allowed", MAX_LABELS));
+    }
+    for (key, value) in labels {
+        if key.trim().is_empty() {
+            return Err("labels: keys must not be empty".to_string());
+        }
+        if key.len() > MAX_LABEL_BYTES || value.len() > MAX_LABEL_BYTES {
+            return Err(format!(
+                "labels: {} is longer than {} bytes",
+                key, MAX_LABEL_BYTES
+            ));
+        }
+        if key.chars().chain(value.chars()).any(char::is_control) {
+            return Err(format!("labels: {} contains control characters", key));
+        }
+    }
+    Ok(())
+}
+
+/// Only flags are allowed, and none that would replace the script that runs
+/// (`-c`, `-m`, `-` or a second positional path).
+fn validate_interpreter_args(args: &[String]) -> Result<(), String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if INTERPRETER_FLAGS_WITH_VALUE.contains(&arg.as_str()) {
+            if args.next().is_none() {
+                return Err(format!("interpreter flag {} requires a value", arg));
+            }
+            continue;
+        }
+
+        if arg == "-" || arg == "--" || !arg.starts_with('-') {
+            return Err(format!(
+                "interpreter_args may only contain flags, found: {}",
+                arg
+            ));
+        }
+
+        if arg.starts_with("--") {
+            continue;
+        }
+
+        // Short flags can be combined (`-uB`); `-X`/`-W` swallow the rest.
+        for flag in arg.chars().skip(1) {
+            match flag {
+                'X' | 'W' => break,
+                'c' | 'm' => {
+                    return Err(format!(
+                        "interpreter_args must not change what runs, found: {}",
+                        arg
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn absolute_path(path: &str) -> Result<PathBuf, String> {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+
+    std::env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .map_err(|error| format!("failed to resolve current directory: {}", error))
+}
+
+fn resolve_working_dir(
+    request: &RunPythonScriptRequest,
+    target: &ScriptTarget,
+) -> Result<PathBuf, String> {
+    let Some(working_dir) = request.working_dir.as_deref().map(str::trim) else {
+        return target.default_working_dir();
+    };
+
+    if working_dir.is_empty() {
+        return Err("working_dir must not be empty".to_string());
+    }
+
+    let path = absolute_path(working_dir)?;
+    if !path.exists() {
+        return Err(format!("working directory not found: {}", working_dir));
+    }
+
+    if !path.is_dir() {
+        return Err(format!(
+            "working directory is not a directory: {}",
+            working_dir
+        ));
+    }
+
+    Ok(path)
+}
+
+/// Which files count as scripts: see the `script_extensions` setting.
+#[derive(Debug, Clone, Copy, Default)]
+struct ScriptExtensions<'a> {
+    /// Empty means `DEFAULT_SCRIPT_EXTENSIONS`.
+    accepted: &'a [String],
+    allow_any: bool,
+}
+
+impl<'a> ScriptExtensions<'a> {
+    fn of(accepted: &'a [String], allow_any: bool) -> Self {
+        ScriptExtensions {
+            accepted,
+            allow_any,
+        }
+    }
+
+    fn accepted(&self) -> Vec<String> {
+        if self.accepted.is_empty() {
+            DEFAULT_SCRIPT_EXTENSIONS
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect()
+        } else {
+            self.accepted.to_vec()
+        }
+    }
+
+    /// Ignores `allow_any`; used to pick scripts out of a directory.
+    fn has_accepted_extension(&self, path: &Path) -> bool {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        self.accepted().iter().any(|extension| {
+            name.len() > extension.len() && name.ends_with(&extension.to_lowercase())
+        })
+    }
+
+    fn check(&self, path: &Path) -> Result<(), String> {
+        if self.allow_any
+            || self.has_accepted_extension(path)
+            || (path.extension().is_none() && shebang::names_python(path))
+        {
+            return Ok(());
+        }
+        Err(format!(
+            "script must end in {} or have no extension and a python shebang",
+            self.accepted().join(", ")
+        ))
+    }
+}
+
+/// Returns the canonical path: symlinks and junctions resolved, so a script
+/// reached through different links is still one script.
+fn validate_script_path(
+    script_path: &str,
+    extensions: ScriptExtensions,
+) -> Result<PathBuf, String> {
+    if script_path.trim().is_empty() {
+        return Err("script_path is required".to_string());
+    }
+
+    let path = Path::new(script_path);
+    let canonical = match std::fs::canonicalize(path) {
+        Ok(canonical) => simplify_verbatim_path(canonical),
+        Err(error) => return Err(unresolvable_script_path(path, script_path, &error)),
+    };
+
+    if !canonical.is_file() {
+        return Err(format!("script path is not a file: {}", script_path));
+    }
+
+    extensions.check(path)?;
+    // Otherwise an unreadable script only fails once spawned, with an error
+    // that reads as if the interpreter were the problem.
+    if let Err(error) = std::fs::File::open(&canonical) {
+        return Err(match error.kind() {
+            std::io::ErrorKind::PermissionDenied => {
+                format!("permission denied reading {}", script_path)
+            }
+            _ => format!("failed to open script {}: {}", script_path, error),
+        });
+    }
+    Ok(canonical)
+}
+
+/// Problems with a script file that don't stop it from running.
+fn script_file_warnings(path: &Path) -> Vec<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let world_writable = std::fs::metadata(path)
+            .is_ok_and(|metadata| metadata.permissions().mode() & 0o002 != 0);
+        if world_writable {
+            return vec![format!(
+                "{} is world-writable; any user on this machine can change what it runs",
+                path.display()
+            )];
+        }
+    }
+    let _ = path;
+    Vec::new()
+}
+
+fn unresolvable_script_path(path: &Path, script_path: &str, error: &std::io::Error) -> String {
+    if is_symlink_loop(error) {
+        return format!("script path is a symlink loop: {}", script_path);
+    }
+    if error.kind() == std::io::ErrorKind::NotFound {
+        if let Ok(target) = std::fs::read_link(path) {
+            return format!(
+                "script path is a dangling symlink: {} points to {}, which does not exist",
+                script_path,
+                target.display()
+            );
+        }
+        return format!("script file not found: {}", script_path);
+    }
+    if error.kind() == std::io::ErrorKind::PermissionDenied {
+        // The deepest directory that can still be looked at is the one that
+        // can't be entered.
+        let blocked = path
+            .ancestors()
+            .skip(1)
+            .find(|dir| !dir.as_os_str().is_empty() && std::fs::metadata(dir).is_ok());
+        return match blocked {
+            Some(dir) => format!(
+                "permission denied: cannot enter directory {} to reach {}",
+                dir.display(),
+                script_path
+            ),
+            None => format!("permission denied resolving {}", script_path),
+        };
+    }
+    format!("failed to resolve script path {}: {}", script_path, error)
+}
+
+fn is_symlink_loop(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        error.raw_os_error() == Some(libc::ELOOP)
+    }
+    #[cfg(windows)]
+    {
+        const ERROR_CANT_RESOLVE_FILENAME: i32 = 1921;
+        error.raw_os_error() == Some(ERROR_CANT_RESOLVE_FILENAME)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = error;
+        false
+    }
+}
+
+/// `canonicalize` on Windows returns `\\?\C:\...` paths, which some
+/// tools and most people don't expect. The prefix is dropped unless the path
+/// is too long to work without it.
+fn simplify_verbatim_path(path: PathBuf) -> PathBuf {
+    #[cfg(windows)]
+    {
+        if let Some(simplified) = path.to_str().and_then(strip_verbatim_prefix) {
+            return PathBuf::from(simplified);
+        }
+    }
+    path
+}
+
+/// The canonical form of `path`, or `path` itself when it can't be
+/// resolved, e.g. because the file is gone.
+pub fn canonical_or_given(path: &str) -> PathBuf {
+    std::fs::canonicalize(path)
+        .map(simplify_verbatim_path)
+        .unwrap_or_else(|_| PathBuf::from(path))
+}
+
+#[cfg(any(windows, test))]
+fn strip_verbatim_prefix(path: &str) -> Option<String> {
+    const MAX_PATH: usize = 260;
+    let simplified = if let Some(share) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", share)
+    } else {
+        let rest = path.strip_prefix(r"\\?\")?;
+        let is_drive = rest.as_bytes().get(1) == Some(&b':');
+        if !is_drive {
+            return None;
+        }
+        rest.to_string()
+    };
+    (simplified.len() < MAX_PATH).then_some(simplified)
+}
+
+#[tauri::command]
+pub async fn run_python_script(
+    app: AppHandle,
+    registry: State<'_, RunRegistry>,
+    queue: State<'_, RunQueue>,
+    settings: State<'_, SettingsStore>,
+    profiles: State<'_, ProfileStore>,
+    cache: State<'_, ResultCache>,
+    mut request: RunPythonScriptRequest,
+) -> Result<RunPythonScriptResponse, String> {
+    apply_profile(&mut request, &profiles)?;
+    apply_settings(&mut request, &settings.get());
+    apply_python_settings(&mut request, &app.state::<PythonSettingsStore>().get());
+    request.app_data_dir = app.path().app_data_dir().ok();
+    request.app_cache_dir = app.path().app_cache_dir().ok();
+    let sinks = event_sinks(app, request.stream, None);
+    run_cached(request, &cache, &registry, &queue, sinks).await
+}
+
+/// Runs several scripts in one call. Failures are reported per entry and
+/// never abort the batch, unless `stop_on_failure` is set in sequential
+/// mode.
+#[tauri::command]
+pub async fn run_python_scripts(
+    app: AppHandle,
+    registry: State<'_, RunRegistry>,
+    queue: State<'_, RunQueue>,
+    settings: State<'_, SettingsStore>,
+    profiles: State<'_, ProfileStore>,
+    cache: State<'_, ResultCache>,
+    request: RunPythonScriptsRequest,
+) -> Result<RunPythonScriptsResponse, String> {
+    let mode = BatchMode::parse(request.mode.as_deref())?;
+    if request.requests.len() > MAX_BATCH_SIZE {
+        return Err(format!(
+            "a batch may hold at most {} scripts",
+            MAX_BATCH_SIZE
+        ));
+    }
+
+    let settings = settings.get();
+    let python_settings = app.state::<PythonSettingsStore>().get();
+    let app_data_dir = app.path().app_data_dir().ok();
+    let app_cache_dir = app.path().app_cache_dir().ok();
+    let entries = request
+        .requests
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut request)| {
+            apply_profile(&mut request, &profiles)?;
+            apply_settings(&mut request, &settings);
+            apply_python_settings(&mut request, &python_settings);
+            request.app_data_dir = app_data_dir.clone();
+            request.app_cache_dir = app_cache_dir.clone();
+            let sinks = event_sinks(app.clone(), request.stream, Some(index));
+            Ok((request, sinks))
+        })
+        .collect();
+    Ok(run_batch(
+        entries,
+        mode,
+        request.stop_on_failure,
+        &cache,
+        &registry,
+        &queue,
+    )
+    .await)
+}
+
+/// Drops cached results for `script_path`, or all of them. Returns how many
+/// were dropped.
+#[tauri::command]
+pub fn invalidate_script_cache(
+    cache: State<'_, ResultCache>,
+    script_path: Option<String>,
+) -> usize {
+    cache.invalidate(script_path.as_deref())
+}
+
+/// Reports changes to the script at `script_path` as `script-changed`
+/// events until `unwatch_script`, dropping its cached results and
+/// validations each time. Returns the canonical path the events carry.
+#[tauri::command]
+pub fn watch_script(
+    watcher: State<'_, ScriptWatcher>,
+    script_path: String,
+) -> Result<String, String> {
+    let path = script_path.trim();
+    let canonical = std::fs::canonicalize(path)
+        .map(simplify_verbatim_path)
+        .map_err(|error| format!("failed to watch {}: {}", path, error))?;
+    if !canonical.is_file() {
+        return Err(format!("failed to watch {}: not a file", path));
+    }
+    watcher.watch(&canonical)?;
+    Ok(canonical.to_string_lossy().to_string())
+}
+
+/// Undoes one `watch_script`. `false` if the script wasn't watched.
+#[tauri::command]
+pub fn unwatch_script(watcher: State<'_, ScriptWatcher>, script_path: String) -> bool {
+    let path = script_path.trim();
+    // A deleted script can only be named by the path `watch_script` returned.
+    watcher.unwatch(&canonical_or_given(path))
+}
+
+/// Called by the `ScriptWatcher`: the frontend hears of the change only once
+/// nothing stale is left in the caches.
+pub fn script_changed(app: &AppHandle, change: ScriptChange) {
+    app.state::<ResultCache>().invalidate(Some(&change.path));
+    app.state::<ValidationCache>()
+        .invalidate(Some(Path::new(&change.path)));
+    let _ = app.emit(SCRIPT_CHANGED_EVENT, change);
+}
+
+/// Validates and registers the run, then returns its id without waiting.
+/// The result is kept in the registry for `get_run_result`, so a reloaded
+/// webview can pick the run up again by id.
+#[tauri::command]
+pub fn start_python_script(
+    app: AppHandle,
+    registry: State<'_, RunRegistry>,
+    queue: State<'_, RunQueue>,
+    settings: State<'_, SettingsStore>,
+    profiles: State<'_, ProfileStore>,
+    mut request: RunPythonScriptRequest,
+) -> Result<StartPythonScriptResponse, String> {
+    apply_profile(&mut request, &profiles)?;
+    apply_settings(&mut request, &settings.get());
+    apply_python_settings(&mut request, &app.state::<PythonSettingsStore>().get());
+    request.app_data_dir = app.path().app_data_dir().ok();
+    request.app_cache_dir = app.path().app_cache_dir().ok();
+    let sinks = event_sinks(app, request.stream, None);
+    start_script(request, registry.inner(), queue.inner(), sinks)
+}
+
+#[tauri::command]
+pub fn get_run_status(
+    registry: State<'_, RunRegistry>,
+    run_id: String,
+) -> Result<RunStatusResponse, String> {
+    let state = registry.state(&run_id)?;
+    Ok(RunStatusResponse { run_id, state })
+}
+
+#[tauri::command]
+pub fn get_run_result(
+    registry: State<'_, RunRegistry>,
+    run_id: String,
+) -> Result<RunPythonScriptResponse, String> {
+    registry.result(&run_id)
+}
+
+#[tauri::command]
+pub fn set_run_result_retention(
+    registry: State<'_, RunRegistry>,
+    max_results: usize,
+    retention_ms: u64,
+) -> Result<(), String> {
+    registry.set_result_retention(max_results, retention_ms)
+}
+
+/// Emergency stop: cancels every queued and running script and waits briefly
+/// for the running ones to die. Their pending calls resolve with
+/// `cancelled: true`.
+#[tauri::command]
+pub async fn kill_all_runs(registry: State<'_, RunRegistry>) -> Result<KillAllSummary, String> {
+    Ok(registry.kill_all(KILL_ALL_WAIT).await)
+}
+
+/// With `labels`, only runs carrying all of them are listed.
+#[tauri::command]
+pub fn list_active_runs(
+    registry: State<'_, RunRegistry>,
+    labels: Option<HashMap<String, String>>,
+) -> Vec<ActiveRunInfo> {
+    registry.list_active(labels.as_ref())
+}
+
+fn event_sinks(app: AppHandle, stream: bool, batch_index: Option<usize>) -> EventSinks {
+    let output = stream.then(|| {
+        let app = app.clone();
+        Arc::new(move |mut event: ScriptOutputEvent| {
+            event.batch_index = batch_index;
+            let _ = app.emit(SCRIPT_OUTPUT_EVENT, event);
+        }) as OutputSink
+    });
+    let progress = Arc::new(move |mut event: ProgressEvent| {
+        event.batch_index = batch_index;
+        let _ = app.emit(PROGRESS_EVENT, event);
+    }) as ProgressSink;
+    EventSinks {
+        output,
+        progress: Some(progress),
+    }
+}
+
+fn start_script(
+    request: RunPythonScriptRequest,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+    sinks: EventSinks,
+) -> Result<StartPythonScriptResponse, String> {
+    let target = ScriptTarget::resolve(
+        &request.script_path,
+        request.module.as_deref(),
+        ScriptExtensions::of(&request.script_extensions, request.allow_any_extension),
+    )?;
+    let (mut plan, run_guard) = match prepare_limited(&request, target, registry, sinks)? {
+        Ok(prepared) => prepared,
+        Err(refusal) => {
+            return Ok(StartPythonScriptResponse {
+                rate_limited_until_ms: Some(refusal.allowed_at_ms),
+                termination_reason: Some(refusal.reason),
+                error_kind: Some("rate_limited".to_string()),
+                ..Default::default()
+            })
+        }
+    };
+    let run_id = plan.run_id.clone();
+
+    let queue = queue.clone();
+    tauri::async_runtime::spawn(async move {
+        let result = execute_plan(&request, &mut plan, &queue).await;
+        run_guard.finish(result);
+    });
+
+    Ok(StartPythonScriptResponse {
+        run_id,
+        ..Default::default()
+    })
+}
+
+#[tauri::command]
+pub fn cancel_python_script(
+    registry: State<'_, RunRegistry>,
+    run_id: String,
+) -> Result<(), String> {
+    registry.cancel(&run_id)
+}
+
+#[tauri::command]
+pub async fn run_python_code(
+    registry: State<'_, RunRegistry>,
+    queue: State<'_, RunQueue>,
+    settings: State<'_, SettingsStore>,
+    python_settings: State<'_, PythonSettingsStore>,
+    mut request: RunPythonCodeRequest,
+) -> Result<RunPythonScriptResponse, String> {
+    request.python_settings = python_settings.get();
+    run_code(request, &settings.get(), &registry, &queue).await
+}
+
+#[tauri::command]
+pub fn get_execution_settings(store: State<'_, SettingsStore>) -> ExecutionSettings {
+    store.get()
+}
+
+#[tauri::command]
+pub fn set_execution_settings(
+    store: State<'_, SettingsStore>,
+    interpreters: State<'_, InterpreterInfoCache>,
+    settings: ExecutionSettings,
+) -> Result<(), String> {
+    let ttl = Duration::from_millis(settings.interpreter_cache_ttl_ms);
+    let read_shell_path = settings.login_shell_path && store.get().shell_path.is_none();
+    store.set(settings)?;
+    interpreters.set_ttl(ttl);
+    if read_shell_path {
+        read_login_shell_path(store.inner().clone());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_python_settings(store: State<'_, PythonSettingsStore>) -> PythonSettingsView {
+    store.view()
+}
+
+#[tauri::command]
+pub fn get_candidates(store: State<'_, PythonSettingsStore>) -> CandidatesView {
+    CandidatesView::of(&store.get())
+}
+
+/// Replaces this platform's default candidates. Entries are checked before
+/// anything is saved.
+#[tauri::command]
+pub fn set_candidates(
+    store: State<'_, PythonSettingsStore>,
+    candidates: Vec<CandidateEntry>,
+) -> Result<CandidatesView, String> {
+    update_candidates(&store, Some(candidates))
+}
+
+/// Goes back to the built-in default candidates for this platform.
+#[tauri::command]
+pub fn reset_candidates(store: State<'_, PythonSettingsStore>) -> Result<CandidatesView, String> {
+    update_candidates(&store, None)
+}
+
+fn update_candidates(
+    store: &PythonSettingsStore,
+    candidates: Option<Vec<CandidateEntry>>,
+) -> Result<CandidatesView, String> {
+    let mut settings = store.get();
+    settings.set_candidates(candidates);
+    store.set(settings.clone())?;
+    Ok(CandidatesView::of(&settings))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InterpreterAlias {
+    pub name: String,
+    pub python_path: String,
+    /// Whether the executable is still there.
+    pub exists: bool,
+}
+
+/// A saved profile that names an alias as its `python_path`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AliasUsage {
+    pub profile: String,
+    pub script_path: String,
+}
+
+/// An interpreter to use instead of a `python_path` that no longer starts.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PythonPathSuggestion {
+    /// `sys.executable`; usable as `python_path`.
+    pub python_path: String,
+    pub version: String,
+    /// "local_venv" for a virtualenv next to the script or above it,
+    /// "same_version" for an installed Python of the old one's major and
+    /// minor version, or "default" for what runs would use without a
+    /// `python_path`.
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RepairPythonPathRequest {
+    /// The path, or alias, that stopped working.
+    pub python_path: String,
+    pub script_path: String,
+    /// The `script_extensions` execution setting. Empty means the defaults.
+    #[serde(skip)]
+    pub script_extensions: Vec<String>,
+    /// The `local_venv_depth` execution setting.
+    #[serde(skip)]
+    pub local_venv_depth: u32,
+    /// The Python settings.
+    #[serde(skip)]
+    pub python_settings: PythonSettings,
+    /// As for validations.
+    #[serde(skip)]
+    pub last_validated: Option<ValidatedInterpreter>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepairPythonPathResponse {
+    /// `python_path` starts after all, e.g. because its venv was rebuilt
+    /// in place. Nothing is suggested then.
+    pub available: bool,
+    /// Best first.
+    pub suggestions: Vec<PythonPathSuggestion>,
+}
+
+#[tauri::command]
+pub fn list_interpreter_aliases(store: State<'_, PythonSettingsStore>) -> Vec<InterpreterAlias> {
+    interpreter_aliases(&store.get())
+}
+
+fn interpreter_aliases(settings: &PythonSettings) -> Vec<InterpreterAlias> {
+    settings
+        .aliases
+        .iter()
+        .map(|(name, python_path)| InterpreterAlias {
+            name: name.clone(),
+            python_path: python_path.clone(),
+            exists: Path::new(python_path).is_file(),
+        })
+        .collect()
+}
+
+/// Names an interpreter, or points an existing name somewhere else. The
+/// executable must start as a Python.
+#[tauri::command]
+pub async fn add_interpreter_alias(
+    store: State<'_, PythonSettingsStore>,
+    interpreters: State<'_, InterpreterInfoCache>,
+    name: String,
+    python_path: String,
+) -> Result<Vec<InterpreterAlias>, String> {
+    add_alias(&store, &interpreters, &name, &python_path).await
+}
+
+async fn add_alias(
+    store: &PythonSettingsStore,
+    interpreters: &InterpreterInfoCache,
+    name: &str,
+    python_path: &str,
+) -> Result<Vec<InterpreterAlias>, String> {
+    let name = name.trim();
+    python_settings::validate_alias_name(name)?;
+    let path = absolute_path(python_path.trim())?;
+    if !path.is_file() {
+        return Err(format!("{} is not an existing file", path.display()));
+    }
+    let program = path.to_string_lossy().to_string();
+    if let Err(error) = interpreters.get(&program, &[]).await {
+        return Err(format!("{} is not a usable Python: {}", program, error));
+    }
+    let mut settings = store.get();
+    settings.aliases.insert(name.to_string(), program);
+    store.set(settings.clone())?;
+    Ok(interpreter_aliases(&settings))
+}
+
+/// Forgets an alias. Profiles still naming it fail to find an interpreter
+/// afterwards; `find_alias_usages` lists them. `false` if there was none.
+#[tauri::command]
+pub fn remove_interpreter_alias(
+    store: State<'_, PythonSettingsStore>,
+    name: String,
+) -> Result<bool, String> {
+    let mut settings = store.get();
+    if settings.aliases.remove(name.trim()).is_none() {
+        return Ok(false);
+    }
+    store.set(settings)?;
+    Ok(true)
+}
+
+/// The saved profiles whose `python_path` is the alias `name`, to check
+/// before renaming or removing it.
+#[tauri::command]
+pub fn find_alias_usages(profiles: State<'_, ProfileStore>, name: String) -> Vec<AliasUsage> {
+    alias_usages(&profiles.list(), &name)
+}
+
+fn alias_usages(profiles: &[ScriptProfile], name: &str) -> Vec<AliasUsage> {
+    let name = name.trim();
+    profiles
+        .iter()
+        .filter(|profile| profile.python_path.as_deref().map(str::trim) == Some(name))
+        .map(|profile| AliasUsage {
+            profile: profile.name.clone(),
+            script_path: profile.script_path.clone(),
+        })
+        .collect()
+}
+
+/// Ranked replacements for a `python_path` that stopped working for a
+/// script, e.g. after its virtualenv was deleted and created again.
+#[tauri::command]
+pub async fn repair_python_path(
+    interpreters: State<'_, InterpreterInfoCache>,
+    validated: State<'_, ValidatedInterpreters>,
+    settings: State<'_, SettingsStore>,
+    python_settings: State<'_, PythonSettingsStore>,
+    mut request: RepairPythonPathRequest,
+) -> Result<RepairPythonPathResponse, String> {
+    let settings = settings.get();
+    request.python_settings = python_settings.get();
+    request.script_extensions = settings.script_extensions;
+    request.local_venv_depth = settings.local_venv_depth;
+    request.last_validated = validate_script_path(
+        &request.script_path,
+        ScriptExtensions::of(&request.script_extensions, false),
+    )
+    .ok()
+    .and_then(|path| validated.get(&path.to_string_lossy()));
+    repair_path(&request, &interpreters).await
+}
+
+async fn repair_path(
+    request: &RepairPythonPathRequest,
+    interpreters: &InterpreterInfoCache,
+) -> Result<RepairPythonPathResponse, String> {
+    let dead = request.python_path.trim();
+    if dead.is_empty() {
+        return Err("python_path is required".to_string());
+    }
+    let extensions = ScriptExtensions::of(&request.script_extensions, false);
+    let target = ScriptTarget::resolve(&request.script_path, None, extensions)?;
+    let program = request.python_settings.resolve_alias(dead);
+    if interpreters.get(program, &[]).await.is_ok() {
+        return Ok(RepairPythonPathResponse {
+            available: true,
+            suggestions: Vec::new(),
+        });
+    }
+    let suggestions = python_path_suggestions(
+        dead,
+        &target,
+        &PathRepair {
+            venv_depth: request.local_venv_depth,
+            settings: &request.python_settings,
+            last_validated: request.last_validated.as_ref(),
+        },
+        interpreters,
+    )
+    .await;
+    Ok(RepairPythonPathResponse {
+        available: false,
+        suggestions,
+    })
+}
+
+/// Interpreter probes are dropped, as the candidates they were for may
+/// have changed.
+#[tauri::command]
+pub fn set_python_settings(
+    store: State<'_, PythonSettingsStore>,
+    interpreters: State<'_, InterpreterInfoCache>,
+    settings: PythonSettings,
+) -> Result<(), String> {
+    store.set(settings)?;
+    interpreters.clear();
+    Ok(())
+}
+
+/// Forgets which interpreters were found and what they are, so the next
+/// run or validation probes them again, as after installing a Python.
+#[tauri::command]
+pub fn refresh_interpreters(interpreters: State<'_, InterpreterInfoCache>) {
+    interpreters.clear();
+}
+
+/// Downloads, verifies and unpacks the bundled Python runtime into the app
+/// data dir unless it is already there, reporting `script-progress` events
+/// for `run_id` on the way. From then on it is the candidate of last
+/// resort, source "bundled".
+#[tauri::command]
+pub async fn ensure_bundled_python(
+    app: AppHandle,
+    interpreters: State<'_, InterpreterInfoCache>,
+    validations: State<'_, ValidationCache>,
+    request: EnsureBundledPythonRequest,
+) -> Result<EnsureBundledPythonResponse, String> {
+    let root = app
+        .path()
+        .app_data_dir()
+        .map_err(|error| format!("no app data dir: {}", error))?
+        .join(bundled_python::RUNTIME_DIR);
+    let run_id = request.run_id.clone().unwrap_or_else(next_run_id);
+    let progress = |percent: Option<f64>, message: String| {
+        let _ = app.emit(
+            PROGRESS_EVENT,
+            ProgressEvent {
+                run_id: run_id.clone(),
+                correlation_id: None,
+                labels: None,
+                batch_index: None,
+                percent,
+                message: Some(message),
+                ts_ms: unix_time_ms(),
+            },
+        );
+    };
+    let installed = bundled_python::install(
+        &root,
+        request.url.as_deref(),
+        request.sha256.as_deref(),
+        &progress,
+    )
+    .await?;
+    app.state::<PythonSettingsStore>()
+        .set_bundled_python(Some(installed.interpreter.clone()));
+    if installed.downloaded {
+        // Validations that found no interpreter would find this one now.
+        interpreters.clear();
+        validations.invalidate(None);
+    }
+    let python_path = installed.interpreter.to_string_lossy().to_string();
+    let info = interpreters.get(&python_path, &[]).await.map_err(|error| {
+        format!(
+            "the bundled Python runtime at {} does not start: {}",
+            python_path, error
+        )
+    })?;
+    Ok(EnsureBundledPythonResponse {
+        run_id,
+        python_path,
+        version: info.version,
+        downloaded: installed.downloaded,
+        resumed: installed.resumed,
+        downloaded_bytes: installed.downloaded_bytes,
+    })
+}
+
+#[tauri::command]
+pub fn save_script_profile(
+    profiles: State<'_, ProfileStore>,
+    profile: ScriptProfile,
+) -> Result<(), String> {
+    profiles.save(profile)
+}
+
+#[tauri::command]
+pub fn get_script_profile(
+    profiles: State<'_, ProfileStore>,
+    name: String,
+) -> Result<ScriptProfile, String> {
+    profiles.get(&name)
+}
+
+#[tauri::command]
+pub fn list_script_profiles(profiles: State<'_, ProfileStore>) -> Vec<ScriptProfile> {
+    profiles.list()
+}
+
+#[tauri::command]
+pub fn delete_script_profile(
+    profiles: State<'_, ProfileStore>,
+    name: String,
+) -> Result<(), String> {
+    profiles.delete(&name)
+}
+
+fn apply_profile(
+    request: &mut RunPythonScriptRequest,
+    profiles: &ProfileStore,
+) -> Result<(), String> {
+    let Some(name) = request.profile.as_deref() else {
+        return Ok(());
+    };
+
+    profiles.get(name)?.apply_to(request);
+    Ok(())
+}
+
+/// Fills in whatever the request leaves to the execution settings.
+fn apply_settings(request: &mut RunPythonScriptRequest, settings: &ExecutionSettings) {
+    request.low_priority.get_or_insert(settings.low_priority);
+    request.timeout_bounds = settings.timeout_bounds();
+    request.output_dir = settings.output_dir.clone().map(PathBuf::from);
+    request.strip_env = settings.strip_env.clone();
+    request.script_extensions = settings.script_extensions.clone();
+    request.local_venv_depth = settings.local_venv_depth;
+    request.allow_python2.get_or_insert(settings.allow_python2);
+    request
+        .extra_python_paths
+        .extend(settings.extra_python_paths.iter().cloned());
+    request
+        .extra_path_dirs
+        .extend(settings.extra_path_dirs.iter().cloned());
+    request.shell_path = settings
+        .shell_path
+        .clone()
+        .filter(|_| settings.login_shell_path);
+    if request.min_interval_ms.is_none() {
+        // `./weather.py` and a symlink to it are still the same script.
+        let script = canonical_or_given(request.script_path.trim());
+        request.min_interval_ms = settings
+            .min_interval_ms
+            .iter()
+            .find(|(path, _)| canonical_or_given(path.trim()) == script)
+            .map(|(_, min_interval_ms)| *min_interval_ms);
+    }
+}
+
+/// Hands the Python settings to the run, with their interpreter arguments
+/// when the request has none. uv takes none.
+fn apply_python_settings(request: &mut RunPythonScriptRequest, settings: &PythonSettings) {
+    if request.interpreter_args.is_empty()
+        && Runner::parse(request.runner.as_deref()) != Ok(Runner::Uv)
+    {
+        request.interpreter_args = settings.interpreter_args.clone();
+    }
+    request.python_settings = settings.clone();
+}
+
+#[tauri::command]
+pub fn set_max_concurrent_runs(queue: State<'_, RunQueue>, max: usize) -> Result<(), String> {
+    queue.set_max(max)
+}
+
+async fn run_code(
+    request: RunPythonCodeRequest,
+    settings: &ExecutionSettings,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+) -> Result<RunPythonScriptResponse, String> {
+    if request.code.trim().is_empty() {
+        return Err("code is required".to_string());
+    }
+
+    if request.code.len() > MAX_INLINE_CODE_BYTES {
+        return Err(format!(
+            "code is too large: {} bytes (limit {})",
+            request.code.len(),
+            MAX_INLINE_CODE_BYTES
+        ));
+    }
+
+    let mut script_request = RunPythonScriptRequest {
+        args: request.args,
+        python_path: request.python_path,
+        timeout_ms: request.timeout_ms,
+        ..Default::default()
+    };
+    apply_settings(&mut script_request, settings);
+    apply_python_settings(&mut script_request, &request.python_settings);
+
+    // Multi-line snippets go through a file so tracebacks carry line numbers
+    // and nothing depends on command-line quoting.
+    if request.code.contains('\n') {
+        let temp_script = TempScript::create(&request.code)?;
+        let target = ScriptTarget::File(temp_script.path.clone());
+        return run_target(
+            script_request,
+            target,
+            registry,
+            queue,
+            EventSinks::default(),
+        )
+        .await;
+    }
+
+    let target = ScriptTarget::Code(request.code);
+    run_target(
+        script_request,
+        target,
+        registry,
+        queue,
+        EventSinks::default(),
+    )
+    .await
+}
+
+/// Feeds each stage's stdout to the next stage's stdin. Stages run one at a
+/// time, each with its own timeout; the first failure stops the pipeline.
+#[tauri::command]
+pub async fn run_python_pipeline(
+    app: AppHandle,
+    registry: State<'_, RunRegistry>,
+    queue: State<'_, RunQueue>,
+    settings: State<'_, SettingsStore>,
+    profiles: State<'_, ProfileStore>,
+    request: RunPythonPipelineRequest,
+) -> Result<RunPythonPipelineResponse, String> {
+    let settings = settings.get();
+    let python_settings = app.state::<PythonSettingsStore>().get();
+    let app_data_dir = app.path().app_data_dir().ok();
+    let app_cache_dir = app.path().app_cache_dir().ok();
+    let mut stages = Vec::with_capacity(request.stages.len());
+    for (index, mut stage) in request.stages.into_iter().enumerate() {
+        apply_profile(&mut stage, &profiles)
+            .map_err(|error| format!("stage {}: {}", index, error))?;
+        apply_settings(&mut stage, &settings);
+        apply_python_settings(&mut stage, &python_settings);
+        stage.app_data_dir = app_data_dir.clone();
+        stage.app_cache_dir = app_cache_dir.clone();
+        let sinks = event_sinks(app.clone(), stage.stream, Some(index));
+        stages.push((stage, sinks));
+    }
+    run_pipeline(stages, request.deadline_ms, &registry, &queue).await
+}
+
+async fn run_pipeline(
+    stages: Vec<(RunPythonScriptRequest, EventSinks)>,
+    deadline_ms: Option<u64>,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+) -> Result<RunPythonPipelineResponse, String> {
+    if stages.is_empty() {
+        return Err("a pipeline needs at least one stage".to_string());
+    }
+    let last = stages.len() - 1;
+    for (index, (stage, _)) in stages.iter().enumerate() {
+        let problem = if index > 0 && stage.stdin.is_some() {
+            "only the first stage may set stdin"
+        } else if index < last
+            && OutputFormat::parse(stage.output_format.as_deref())? == OutputFormat::Binary
+        {
+            "only the last stage may use binary output"
+        } else if index < last && stage.stdout_file.is_some() {
+            "only the last stage may write stdout to a file"
+        } else {
+            continue;
+        };
+        return Err(format!("stage {}: {}", index, problem));
+    }
+
+    let started = Instant::now();
+    let deadline = deadline_ms.map(|deadline_ms| started + Duration::from_millis(deadline_ms));
+    let mut pipeline = RunPythonPipelineResponse::default();
+    let mut input = None;
+    for (index, (mut stage, sinks)) in stages.into_iter().enumerate() {
+        if let Some(deadline) = deadline {
+            let remaining = deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis() as u64;
+            if remaining == 0 {
+                pipeline.failed_stage = Some(index);
+                pipeline.failed_stderr =
+                    Some("pipeline deadline reached before this stage".to_string());
+                break;
+            }
+            stage.deadline_ms = Some(
+                stage
+                    .deadline_ms
+                    .map_or(remaining, |own| own.min(remaining)),
+            );
+        }
+        if input.is_some() {
+            stage.stdin = input.take();
+        }
+
+        let response = match run_limited(stage, registry, queue, sinks).await {
+            Ok(response) => response,
+            Err(error) => {
+                pipeline.failed_stage = Some(index);
+                pipeline.failed_stderr = Some(error);
+                break;
+            }
+        };
+        pipeline.stages.push(PipelineStage {
+            index,
+            run_id: response.run_id.clone(),
+            ok: response.ok,
+            exit_code: response.exit_code,
+            termination_reason: response.termination_reason.clone(),
+            duration_ms: response.duration_ms,
+        });
+        if !response.ok {
+            pipeline.failed_stage = Some(index);
+            pipeline.failed_stderr = Some(response.stderr);
+            break;
+        }
+        if index == last {
+            pipeline.output = Some(response);
+            break;
+        }
+        // Passing on a cut-off document would only fail further down.
+        if response.stdout_truncated {
+            pipeline.failed_stage = Some(index);
+            pipeline.failed_stderr =
+                Some("stdout exceeded max_output_bytes and was truncated".to_string());
+            break;
+        }
+        input = Some(response.stdout);
+    }
+
+    pipeline.ok = pipeline.output.is_some();
+    pipeline.duration_ms = started.elapsed().as_millis();
+    Ok(pipeline)
+}
+
+type BatchInput = Result<(RunPythonScriptRequest, EventSinks), String>;
+
+async fn run_batch(
+    entries: Vec<BatchInput>,
+    mode: BatchMode,
+    stop_on_failure: bool,
+    cache: &ResultCache,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+) -> RunPythonScriptsResponse {
+    let started = Instant::now();
+    let mut results = Vec::with_capacity(entries.len());
+    match mode {
+        // The run queue bounds how many of these actually execute at once.
+        BatchMode::Parallel => {
+            let handles: Vec<_> = entries
+                .into_iter()
+                .map(|entry| {
+                    let (cache, registry, queue) = (cache.clone(), registry.clone(), queue.clone());
+                    tauri::async_runtime::spawn(async move {
+                        let (request, sinks) = entry?;
+                        run_cached(request, &cache, &registry, &queue, sinks).await
+                    })
+                })
+                .collect();
+            for (index, handle) in handles.into_iter().enumerate() {
+                let result = handle
+                    .await
+                    .unwrap_or_else(|error| Err(format!("batch entry failed: {}", error)));
+                results.push(BatchEntry::new(index, result));
+            }
+        }
+        BatchMode::Sequential => {
+            let mut failed = false;
+            for (index, entry) in entries.into_iter().enumerate() {
+                if failed && stop_on_failure {
+                    results.push(BatchEntry {
+                        index,
+                        skipped: true,
+                        ..Default::default()
+                    });
+                    continue;
+                }
+
+                let result = match entry {
+                    Ok((request, sinks)) => {
+                        run_cached(request, cache, registry, queue, sinks).await
+                    }
+                    Err(error) => Err(error),
+                };
+                let entry = BatchEntry::new(index, result);
+                failed |= !entry.succeeded();
+                results.push(entry);
+            }
+        }
+    }
+
+    let succeeded = results.iter().filter(|entry| entry.succeeded()).count();
+    let skipped = results.iter().filter(|entry| entry.skipped).count();
+    RunPythonScriptsResponse {
+        succeeded,
+        failed: results.len() - succeeded - skipped,
+        skipped,
+        duration_ms: started.elapsed().as_millis(),
+        results,
+    }
+}
+
+async fn run_cached(
+    request: RunPythonScriptRequest,
+    cache: &ResultCache,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+    sinks: EventSinks,
+) -> Result<RunPythonScriptResponse, String> {
+    let ttl_ms = request.cache_ttl_ms.filter(|_| !request.dry_run);
+    let Some(ttl_ms) = ttl_ms.filter(|ttl_ms| *ttl_ms > 0) else {
+        return run_coalesced(request, registry, queue, sinks).await;
+    };
+
+    let key = cache::key(&request);
+    if let Some(mut cached) = cache.get(&key) {
+        cached.correlation_id = request.correlation_id.clone();
+        cached.labels = request.labels.clone();
+        return Ok(cached);
+    }
+
+    let script_path = request.script_path.clone();
+    let cache_failures = request.cache_failures;
+    let response = run_coalesced(request, registry, queue, sinks).await?;
+    if !response.coalesced && (response.ok || cache_failures) {
+        cache.insert(key, &script_path, Duration::from_millis(ttl_ms), &response);
+    }
+    Ok(response)
+}
+
+async fn run_coalesced(
+    request: RunPythonScriptRequest,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+    sinks: EventSinks,
+) -> Result<RunPythonScriptResponse, String> {
+    if !request.coalesce {
+        return run_limited(request, registry, queue, sinks).await;
+    }
+
+    let correlation_id = request.correlation_id.clone();
+    let labels = request.labels.clone();
+    match registry.coalescer().join(cache::key(&request)) {
+        Role::Leader(leader) => {
+            let outcome = run_limited(request, registry, queue, sinks).await;
+            leader.finish(&outcome);
+            outcome
+        }
+        Role::Follower(receiver) => coalesce::follow(receiver).await.map(|mut response| {
+            response.correlation_id = correlation_id;
+            response.labels = labels;
+            response
+        }),
+    }
+}
+
+/// Starts the run unless its `min_interval_ms` hasn't passed yet. Only runs
+/// that actually start count; cached and coalesced responses don't get here.
+async fn run_limited(
+    request: RunPythonScriptRequest,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+    sinks: EventSinks,
+) -> Result<RunPythonScriptResponse, String> {
+    let target = ScriptTarget::resolve(
+        &request.script_path,
+        request.module.as_deref(),
+        ScriptExtensions::of(&request.script_extensions, request.allow_any_extension),
+    )?;
+    let (mut plan, _run_guard) = match prepare_limited(&request, target, registry, sinks)? {
+        Ok(prepared) => prepared,
+        Err(refusal) => {
+            return Ok(RunPythonScriptResponse {
+                correlation_id: request.correlation_id.clone(),
+                labels: request.labels.clone(),
+                rate_limited_until_ms: Some(refusal.allowed_at_ms),
+                termination_reason: Some(refusal.reason),
+                error_kind: Some("rate_limited".to_string()),
+                ..Default::default()
+            })
+        }
+    };
+    execute_plan(&request, &mut plan, queue).await
+}
+
+/// `prepare_run` behind `rate_limit`. A request that `prepare_run` rejects
+/// gives its start back, so retrying it once fixed isn't refused.
+fn prepare_limited(
+    request: &RunPythonScriptRequest,
+    target: ScriptTarget,
+    registry: &RunRegistry,
+    sinks: EventSinks,
+) -> Result<Result<(RunPlan, RunGuard), RateLimited>, String> {
+    let script = target.describe();
+    if let Some(refusal) = rate_limit(request, &target, registry) {
+        return Ok(Err(refusal));
+    }
+    prepare_run(request, target, registry, sinks)
+        .map(Ok)
+        .inspect_err(|_| {
+            if request.min_interval_ms.is_some_and(|interval| interval > 0) {
+                registry.rate_limiter().release(&script);
+            }
+        })
+}
+
+/// Why a start was refused by `rate_limit`.
+struct RateLimited {
+    allowed_at_ms: u64,
+    reason: String,
+}
+
+/// Counts a start of `target` against its `min_interval_ms`, or refuses it.
+/// Scripts are told apart by canonical path, however the request names
+/// them, so only a target that resolved gets here.
+fn rate_limit(
+    request: &RunPythonScriptRequest,
+    target: &ScriptTarget,
+    registry: &RunRegistry,
+) -> Option<RateLimited> {
+    let min_interval_ms = request.min_interval_ms.filter(|interval| *interval > 0)?;
+    let script = target.describe();
+    let allowed_at_ms = registry
+        .rate_limiter()
+        .acquire(&script, min_interval_ms, unix_time_ms())
+        .err()?;
+    Some(RateLimited {
+        allowed_at_ms,
+        reason: format!(
+            "rate limited: {} may run once every {} ms",
+            script, min_interval_ms
+        ),
+    })
+}
+
+async fn run_script(
+    request: RunPythonScriptRequest,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+    sinks: EventSinks,
+) -> Result<RunPythonScriptResponse, String> {
+    let target = ScriptTarget::resolve(
+        &request.script_path,
+        request.module.as_deref(),
+        ScriptExtensions::of(&request.script_extensions, request.allow_any_extension),
+    )?;
+    run_target(request, target, registry, queue, sinks).await
+}
+
+async fn run_target(
+    request: RunPythonScriptRequest,
+    target: ScriptTarget,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+    sinks: EventSinks,
+) -> Result<RunPythonScriptResponse, String> {
+    let (mut plan, _run_guard) = prepare_run(&request, target, registry, sinks)?;
+    execute_plan(&request, &mut plan, queue).await
+}
+
+fn template_dirs(request: &RunPythonScriptRequest, target: &ScriptTarget) -> TemplateDirs {
+    let display = |path: &Path| path.display().to_string();
+    TemplateDirs {
+        app_data_dir: request.app_data_dir.as_deref().map(display),
+        script_dir: match target {
+            ScriptTarget::File(path) => path.parent().map(display),
+            ScriptTarget::Module(_) | ScriptTarget::Code(_) => None,
+        },
+    }
+}
+
+/// Output files may only be created inside the allowed output directory, so
+/// a request can't overwrite arbitrary files.
+fn resolve_output_file(
+    request: &RunPythonScriptRequest,
+    field: &str,
+    path: Option<&str>,
+) -> Result<Option<PathBuf>, String> {
+    let Some(path) = path.map(str::trim) else {
+        return Ok(None);
+    };
+    if path.is_empty() {
+        return Err(format!("{} must not be empty", field));
+    }
+
+    let allowed_dir = request
+        .output_dir
+        .clone()
+        .or_else(|| {
+            request
+                .app_data_dir
+                .as_ref()
+                .map(|dir| dir.join(OUTPUT_FILES_DIR))
+        })
+        .ok_or_else(|| format!("{} requires an output directory", field))?;
+    std::fs::create_dir_all(&allowed_dir).map_err(|error| {
+        format!(
+            "failed to create output directory {}: {}",
+            allowed_dir.display(),
+            error
+        )
+    })?;
+    let allowed_dir = allowed_dir.canonicalize().map_err(|error| {
+        format!(
+            "failed to resolve output directory {}: {}",
+            allowed_dir.display(),
+            error
+        )
+    })?;
+
+    let requested = allowed_dir.join(path);
+    let file_name = requested
+        .file_name()
+        .filter(|_| !path.ends_with(['/', '\\']))
+        .ok_or_else(|| format!("{} must name a file: {}", field, path))?;
+    let parent = requested
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .ok_or_else(|| format!("{} directory does not exist: {}", field, path))?;
+    if !parent.starts_with(&allowed_dir) {
+        return Err(format!(
+            "{} must be inside {}: {}",
+            field,
+            allowed_dir.display(),
+            path
+        ));
+    }
+
+    let resolved = parent.join(file_name);
+    match std::fs::symlink_metadata(&resolved) {
+        Ok(metadata) if !metadata.is_file() => {
+            Err(format!("{} is not a regular file: {}", field, path))
+        }
+        _ => Ok(Some(resolved)),
+    }
+}
+
+fn resolve_timeout_ms(
+    request: &RunPythonScriptRequest,
+    runner: Runner,
+    warnings: &mut Vec<String>,
+) -> Result<u64, String> {
+    let bounds = request.timeout_bounds;
+    let Some(requested) = request.timeout_ms else {
+        let default_ms = match runner {
+            Runner::Uv => bounds.default_ms.max(runner::UV_DEFAULT_TIMEOUT_MS),
+            Runner::Python | Runner::Wsl => bounds.default_ms,
+        };
+        return Ok(default_ms.clamp(bounds.min_ms, bounds.max_ms));
+    };
+
+    if requested < bounds.min_ms {
+        return Err(format!(
+            "timeout_ms {} is below the minimum of {} ms",
+            requested, bounds.min_ms
+        ));
+    }
+
+    if requested > bounds.max_ms {
+        warnings.push(format!(
+            "timeout_ms {} exceeds the maximum of {} ms; the run was limited to {} ms",
+            requested, bounds.max_ms, bounds.max_ms
+        ));
+        return Ok(bounds.max_ms);
+    }
+
+    Ok(requested)
+}
+
+/// The default deadline never cuts a single attempt short, even when the
+/// timeout ceiling was raised above it.
+fn resolve_deadline_ms(
+    request: &RunPythonScriptRequest,
+    timeout_ms: u64,
+    warnings: &mut Vec<String>,
+) -> u64 {
+    let max_ms = MAX_RUN_DEADLINE_MS.max(timeout_ms);
+    let Some(requested) = request.deadline_ms else {
+        return DEFAULT_RUN_DEADLINE_MS.max(timeout_ms);
+    };
+
+    let deadline_ms = requested.clamp(1_000, max_ms);
+    if deadline_ms != requested {
+        warnings.push(format!(
+            "deadline_ms {} is outside 1000..={}; using {} ms",
+            requested, max_ms, deadline_ms
+        ));
+    }
+    deadline_ms
+}
+
+/// Validates the request and registers the run. Everything that can be
+/// rejected up front fails here, before a background run is reported as
+/// started.
+fn prepare_run(
+    request: &RunPythonScriptRequest,
+    target: ScriptTarget,
+    registry: &RunRegistry,
+    sinks: EventSinks,
+) -> Result<(RunPlan, RunGuard), String> {
+    validate_interpreter_args(&request.interpreter_args)?;
+    validate_labels(request.labels.as_ref())?;
+    if request.clean_env && request.inherit_env == Some(false) {
+        return Err("clean_env cannot be used with inherit_env: false".to_string());
+    }
+    resource_limits(request).validate()?;
+    let output_encoding = match request.output_encoding.as_deref() {
+        None if cfg!(windows) && !forces_utf8_io(request) => OutputEncoding::Auto,
+        label => OutputEncoding::parse(label)?,
+    };
+    let working_dir = resolve_working_dir(request, &target)?;
+    let python_paths = resolve_dirs("extra_python_paths", &request.extra_python_paths)?;
+    let path_dirs = resolve_dirs("extra_path_dirs", &request.extra_path_dirs)?;
+    let payload = Payload::prepare(request.json_payload.as_ref())?;
+    let scratch = ScratchDir::create(request.app_cache_dir.as_deref(), request.keep_scratch)?;
+    let args = ArgTemplate::parse(&request.args, &template_dirs(request, &target))?;
+    let stdout_filter = output_filter::compile("stdout_filter", request.stdout_filter.as_deref())?;
+    let stop_on_pattern =
+        output_filter::compile("stop_on_pattern", request.stop_on_pattern.as_deref())?;
+    let stderr_filter = output_filter::compile("stderr_filter", request.stderr_filter.as_deref())?;
+    let priority = Priority::parse(request.priority.as_deref())?;
+    let output_format = OutputFormat::parse(request.output_format.as_deref())?;
+    let python_requirement = python_requirement(request.min_python_version.as_deref(), &target)?;
+    let runner = Runner::parse(request.runner.as_deref())?;
+    check_runner_request(runner, &target, &request.conda_env, request.use_project_env)?;
+    if runner == Runner::Uv && !request.interpreter_args.is_empty() {
+        return Err("interpreter_args cannot be used with runner uv".to_string());
+    }
+    let stdout_file = resolve_output_file(request, "stdout_file", request.stdout_file.as_deref())?;
+    let stderr_file = resolve_output_file(request, "stderr_file", request.stderr_file.as_deref())?;
+    if stdout_file.is_some() && stdout_file == stderr_file {
+        return Err("stdout_file and stderr_file must be different files".to_string());
+    }
+    if output_format == OutputFormat::Binary {
+        if request.parse_json.unwrap_or(false) {
+            return Err("parse_json cannot be used with binary output".to_string());
+        }
+        if request.stdout_filter.is_some() {
+            return Err("stdout_filter cannot be used with binary output".to_string());
+        }
+        if request.stop_on_pattern.is_some() {
+            return Err("stop_on_pattern cannot be used with binary output".to_string());
+        }
+    }
+
+    let run_id = request
+        .run_id
+        .as_deref()
+        .map(str::trim)
+        .filter(|run_id| !run_id.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(next_run_id);
+    let description = RunDescription {
+        script: target.describe(),
+        streaming: sinks.output.is_some(),
+        labels: request.labels.clone().unwrap_or_default(),
+    };
+    let run_guard = registry.register(&run_id, description)?;
+
+    let mut warnings: Vec<String> = removed_env_warning(request).into_iter().collect();
+    if let ScriptTarget::File(path) = &target {
+        warnings.extend(script_file_warnings(path));
+    }
+    let timeout_ms = resolve_timeout_ms(request, runner, &mut warnings)?;
+    let deadline_ms = resolve_deadline_ms(request, timeout_ms, &mut warnings);
+    let wsl = match runner {
+        Runner::Wsl => Some(wsl::Session::new(
+            request.wsl_distro.as_deref(),
+            request.python_path.as_deref(),
+            match &target {
+                ScriptTarget::File(path) => Some(path),
+                _ => None,
+            },
+            &working_dir,
+            &scratch.path,
+        )?),
+        _ => None,
+    };
+    if let Some(retries) = request.retries.filter(|retries| *retries > MAX_RETRIES) {
+        warnings.push(format!(
+            "retries {} exceeds the maximum of {}; at most {} retries will run",
+            retries, MAX_RETRIES, MAX_RETRIES
+        ));
+    }
+    let (path, path_head) = child_path(request, &path_dirs, runner, &mut warnings);
+
+    let plan = RunPlan {
+        run_id,
+        target,
+        working_dir,
+        python_paths,
+        path,
+        path_head,
+        payload,
+        scratch,
+        args,
+        output_format,
+        stdout_filter,
+        stderr_filter,
+        stop_on_pattern,
+        stdout_file,
+        stderr_file,
+        warnings,
+        timeout: Duration::from_millis(timeout_ms),
+        deadline: Duration::from_millis(deadline_ms),
+        output_sink: sinks.output,
+        progress_sink: sinks.progress,
+        python_requirement,
+        runner,
+        wsl,
+        interpreters: registry.interpreters().clone(),
+        validated_interpreters: registry.validated_interpreters().clone(),
+        cancel: run_guard.cancel_signal(),
+        tracker: run_guard.tracker(),
+        output_encoding,
+        priority,
+        queued_ms: 0,
+        admission: Admission::default(),
+    };
+    Ok((plan, run_guard))
+}
+
+/// Waits for an execution slot, then runs the plan with the first available
+/// interpreter.
+async fn execute_plan(
+    request: &RunPythonScriptRequest,
+    plan: &mut RunPlan,
+    queue: &RunQueue,
+) -> Result<RunPythonScriptResponse, String> {
+    let run_id = plan.run_id.clone();
+    let result = if request.dry_run {
+        dry_run(request, plan).await
+    } else {
+        queue_and_execute(request, plan, queue).await
+    };
+    result
+        .map(|mut response| {
+            response.scratch_dir = plan
+                .scratch
+                .keep
+                .then(|| plan.scratch.path.to_string_lossy().to_string());
+            response
+        })
+        .map_err(|error| {
+            let error = prefix_run_error(&run_id, error);
+            log::warn!("{}", error);
+            error
+        })
+}
+
+async fn queue_and_execute(
+    request: &RunPythonScriptRequest,
+    plan: &mut RunPlan,
+    queue: &RunQueue,
+) -> Result<RunPythonScriptResponse, String> {
+    // The run is registered by now, so it can be cancelled while queued.
+    let queued_at = Instant::now();
+    let queue_timeout = request.queue_timeout_ms.map(Duration::from_millis);
+    let _permit = tokio::select! {
+        permit = queue.acquire(plan.priority, queue_timeout) => {
+            let permit = permit.ok_or_else(|| {
+                format!(
+                    "queue full: no execution slot became free within {} ms",
+                    request.queue_timeout_ms.unwrap_or_default()
+                )
+            })?;
+            plan.admission = permit.admission().clone();
+            permit
+        }
+        _ = plan.cancel.cancelled() => {
+            let queued_ms = queued_at.elapsed().as_millis() as u64;
+            let mut response = RunPythonScriptResponse {
+                run_id: plan.run_id.clone(),
+                correlation_id: request.correlation_id.clone(),
+                labels: request.labels.clone(),
+                script_path: plan.target.script_path(),
+                cancelled: true,
+                attempts: 0,
+                duration_ms: queued_ms.into(),
+                queued_ms,
+                priority: plan.priority,
+                working_dir: plan.working_dir.to_string_lossy().to_string(),
+                path_head: plan.path_head.clone(),
+                warnings: plan.warnings.clone(),
+                ..Default::default()
+            };
+            describe_ending(&mut response);
+            return Ok(response);
+        }
+    };
+    plan.queued_ms = queued_at.elapsed().as_millis() as u64;
+    let plan = &*plan;
+
+    let mut resolve_warnings = Vec::new();
+    let candidates = match &plan.wsl {
+        Some(session) => vec![wsl_candidate(session.distro.as_deref(), &session.python)],
+        None => {
+            request_candidates(
+                &request.interpreter_choice(),
+                &plan.target,
+                &plan.interpreters,
+                &mut resolve_warnings,
+            )
+            .await?
+        }
+    };
+
+    let mut attempts = Vec::new();
+    let resolve_started = Instant::now();
+    let runner_version = runner_version(plan).await?;
+
+    let allow_python2 = request.allow_python2.unwrap_or_default();
+    for candidate in &candidates {
+        let version = match probe_candidate(&plan.interpreters, candidate, allow_python2).await {
+            Ok(version) => version,
+            Err(attempt) => {
+                attempts.push(attempt);
+                continue;
+            }
+        };
+        let info = match check_python_version(
+            &plan.interpreters,
+            plan.python_requirement.as_ref(),
+            candidate,
+        )
+        .await
+        {
+            Ok(info) => info,
+            Err(attempt) => {
+                attempts.push(attempt);
+                continue;
+            }
+        };
+        let wrapped = runner_candidate(plan.runner, candidate, info.as_ref());
+        let candidate = wrapped.as_ref().unwrap_or(candidate);
+        let resolve_ms = resolve_started.elapsed().as_millis() as u64;
+        match execute_with_retries(request, plan, candidate).await {
+            Ok(mut response) => {
+                response.timings.resolve_ms = resolve_ms;
+                response.runner_version = runner_version.clone();
+                response.warnings.extend(resolve_warnings);
+                if let (Some(info), Some(script_path)) = (&info, &response.script_path) {
+                    let validated = &plan.validated_interpreters;
+                    response
+                        .warnings
+                        .extend(validated.changed(script_path, info));
+                }
+                response.error_hint = info
+                    .as_ref()
+                    .and_then(|info| failure::hint(&response, info));
+                response.python_executable = info.as_ref().map(|info| info.executable.clone());
+                response.python_alias = request
+                    .python_settings
+                    .alias_name(request.python_path.as_deref());
+                response.python_version = info.map(|info| info.version);
+                response.interpreter_version = version;
+                if let Some(warning) = fallback_warning(&attempts, candidate, "ran") {
+                    response.warnings.push(warning);
+                }
+                return Ok(response);
+            }
+            Err(error) => {
+                attempts.push(CandidateAttempt::new(candidate, &error));
+                if !try_next_candidate(&error) {
+                    let message = format!(
+                        "failed to execute script with {}: {}",
+                        candidate.display_name, error
+                    );
+                    return Err(candidates_failed(message, &attempts));
+                }
+            }
+        }
+    }
+
+    Err(no_interpreter_error(request, plan, &attempts).await)
+}
+
+/// `candidates_failed` for a run no interpreter could be found for, with
+/// replacements when that was because `python_path` is gone.
+async fn no_interpreter_error(
+    request: &RunPythonScriptRequest,
+    plan: &RunPlan,
+    attempts: &[CandidateAttempt],
+) -> String {
+    let message = no_interpreter_message(attempts, plan.python_requirement.as_ref());
+    let Some(dead) =
+        stale_python_path(&request.python_path, attempts).filter(|_| plan.wsl.is_none())
+    else {
+        return candidates_failed(message, attempts);
+    };
+    let last_validated = plan
+        .target
+        .script_path()
+        .and_then(|script_path| plan.validated_interpreters.get(&script_path));
+    let repair = PathRepair {
+        venv_depth: request.local_venv_depth,
+        settings: &request.python_settings,
+        last_validated: last_validated.as_ref(),
+    };
+    let suggestions =
+        python_path_suggestions(dead, &plan.target, &repair, &plan.interpreters).await;
+    serde_json::json!({
+        "message": with_suggestions(message, &suggestions),
+        "attempts": attempts,
+        "python_path_suggestions": suggestions,
+    })
+    .to_string()
+}
+
+/// Prefixed so frontend errors line up with streamed events and logs. For
+/// structured errors (see `candidates_failed`) the prefix goes on `message`
+/// so the error stays valid JSON.
+fn prefix_run_error(run_id: &str, error: String) -> String {
+    match serde_json::from_str::<serde_json::Value>(&error) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            let message = fields
+                .get("message")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or_default();
+            let message = format!("run {}: {}", run_id, message);
+            fields.insert("message".to_string(), message.into());
+            fields.insert("run_id".to_string(), run_id.into());
+            serde_json::Value::Object(fields).to_string()
+        }
+        _ => format!("run {}: {}", run_id, error),
+    }
+}
+
+/// "python3 not found; ran with python instead", when earlier candidates
+/// were passed over.
+fn fallback_warning(
+    attempts: &[CandidateAttempt],
+    candidate: &PythonCandidate,
+    verb: &str,
+) -> Option<String> {
+    if attempts.is_empty() {
+        return None;
+    }
+    let reasons: Vec<String> = attempts
+        .iter()
+        .map(|attempt| match attempt.error_kind.as_str() {
+            "not_found" => format!("{} not found", attempt.candidate),
+            _ => format!("{} failed ({})", attempt.candidate, attempt.message),
+        })
+        .collect();
+    Some(format!(
+        "{}; {} with {} instead",
+        reasons.join(", "),
+        verb,
+        candidate.display_name
+    ))
+}
+
+fn no_interpreter_message(
+    attempts: &[CandidateAttempt],
+    requirement: Option<&VersionRequirement>,
+) -> String {
+    if let Some(message) =
+        requirement.and_then(|requirement| unsupported_versions_message(requirement, attempts))
+    {
+        return message;
+    }
+    match attempts {
+        [.., last]
+            if attempts
+                .iter()
+                .all(|attempt| attempt.error_kind == "not_found") =>
+        {
+            format!("python interpreter not found: {}", last.candidate)
+        }
+        [] => "failed to find available python interpreter".to_string(),
+        _ => "no python interpreter could be started".to_string(),
+    }
+}
+
+/// "requires Python >= 3.10, found 3.8 (python3), 3.7 (py -3)" when some
+/// interpreters were passed over for their version.
+fn unsupported_versions_message(
+    requirement: &VersionRequirement,
+    attempts: &[CandidateAttempt],
+) -> Option<String> {
+    let found: Vec<String> = attempts
+        .iter()
+        .filter(|attempt| attempt.error_kind == "unsupported_version")
+        .map(|attempt| {
+            let version = attempt.version.as_deref().unwrap_or_default();
+            let short: Vec<&str> = version.split('.').take(2).collect();
+            format!("{} ({})", short.join("."), attempt.candidate)
+        })
+        .collect();
+    (!found.is_empty()).then(|| {
+        format!(
+            "requires Python {}, found {}",
+            requirement,
+            found.join(", ")
+        )
+    })
+}
+
+/// The explicit `python_path` when it was the only candidate and wasn't
+/// found, as opposed to one that exists but fails. Suggestions are for the
+/// first kind.
+fn stale_python_path<'a>(
+    python_path: &'a Option<String>,
+    attempts: &[CandidateAttempt],
+) -> Option<&'a str> {
+    let python_path = python_path.as_deref().map(str::trim)?;
+    match attempts {
+        [attempt] if attempt.error_kind == "not_found" && !python_path.is_empty() => {
+            Some(python_path)
+        }
+        _ => None,
+    }
+}
+
+/// What `python_path_suggestions` looks at besides the dead path.
+struct PathRepair<'a> {
+    /// How far up from the script to look for virtualenvs, whether or not
+    /// `use_local_venv` is on.
+    venv_depth: u32,
+    settings: &'a PythonSettings,
+    last_validated: Option<&'a ValidatedInterpreter>,
+}
+
+/// Interpreters that start, to try instead of `dead`: virtualenvs near the
+/// script, nearest first, installed Pythons with the major and minor
+/// version `dead` had, then what the script runs with when `python_path`
+/// is dropped. That version comes from the script's last validation, or
+/// else from a name like `python3.11` or the `pyvenv.cfg` left behind.
+async fn python_path_suggestions(
+    dead: &str,
+    target: &ScriptTarget,
+    repair: &PathRepair<'_>,
+    interpreters: &InterpreterInfoCache,
+) -> Vec<PythonPathSuggestion> {
+    let mut suggestions: Vec<PythonPathSuggestion> = Vec::new();
+    let mut suggest = |info: InterpreterInfo, reason: &'static str| {
+        if !suggestions
+            .iter()
+            .any(|suggestion| suggestion.python_path == info.executable)
+        {
+            suggestions.push(PythonPathSuggestion {
+                python_path: info.executable,
+                version: info.version,
+                reason,
+            });
+        }
+    };
+
+    if let ScriptTarget::File(script) = target {
+        for (_, python) in local_venvs(script, repair.venv_depth) {
+            if let Ok(info) = interpreters.get(&python.to_string_lossy(), &[]).await {
+                suggest(info, "local_venv");
+            }
+        }
+    }
+
+    let dead = Path::new(repair.settings.resolve_alias(dead));
+    let version = repair
+        .last_validated
+        .map(|validated| validated.version)
+        .or_else(|| dead_python_version(dead));
+    if let Some(version) = version {
+        for python in discovery::discover(interpreters, None).await {
+            if major_minor(&python.version) != Some(version) {
+                continue;
+            }
+            if let Ok(info) = interpreters.get(&python.executable, &[]).await {
+                suggest(info, "same_version");
+            }
+        }
+    }
+
+    let venv_depth = Some(repair.venv_depth);
+    for candidate in python_candidates(&None, target, venv_depth, repair.settings) {
+        if let Ok(info) = interpreters
+            .get(&candidate.program, &candidate.pre_args)
+            .await
+        {
+            suggest(info, "default");
+            break;
+        }
+    }
+    suggestions
+}
+
+/// The major and minor version in a name like `python3.11` or
+/// `python3.11.exe`, or in the `pyvenv.cfg` of the venv `python` was in.
+fn dead_python_version(python: &Path) -> Option<(u32, u32)> {
+    let name = python.file_name()?.to_string_lossy();
+    let name = name.strip_suffix(".exe").unwrap_or(&name);
+    if let Some(version) = name.strip_prefix("python").and_then(major_minor) {
+        return Some(version);
+    }
+    let venv = python.parent()?.parent()?;
+    let config = std::fs::read_to_string(venv.join(virtualenv::VENV_CONFIG_FILE)).ok()?;
+    config.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        matches!(key.trim(), "version" | "version_info")
+            .then(|| major_minor(value.trim()))
+            .flatten()
+    })
+}
+
+fn major_minor(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor: String = parts
+        .next()?
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    Some((major, minor.parse().ok()?))
+}
+
+/// The error message with the suggestions appended, for people reading it
+/// without the structured fields.
+fn with_suggestions(message: String, suggestions: &[PythonPathSuggestion]) -> String {
+    if suggestions.is_empty() {
+        return message;
+    }
+    let listed: Vec<String> = suggestions
+        .iter()
+        .map(|suggestion| {
+            let reason = match suggestion.reason {
+                "local_venv" => "virtualenv near the script",
+                "same_version" => "same version",
+                _ => "default",
+            };
+            format!(
+                "{} (Python {}, {})",
+                suggestion.python_path, suggestion.version, reason
+            )
+        })
+        .collect();
+    format!("{}; try instead: {}", message, listed.join(", "))
+}
+
+/// The requirement a run or validation must meet: `min_python_version` if
+/// given, else the `# pdd-requires-python` header of a script file.
+fn python_requirement(
+    min_python_version: Option<&str>,
+    target: &ScriptTarget,
+) -> Result<Option<VersionRequirement>, String> {
+    if let Some(text) = min_python_version
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+    {
+        return VersionRequirement::parse(text).map(Some);
+    }
+    let ScriptTarget::File(path) = target else {
+        return Ok(None);
+    };
+    Ok(metadata::read(path)
+        .ok()
+        .and_then(|(metadata, _)| metadata.python_requirement().map(str::to_string))
+        .and_then(|text| VersionRequirement::parse(&text).ok()))
+}
+
+/// Combinations a runner can't do.
+fn check_runner_request(
+    runner: Runner,
+    target: &ScriptTarget,
+    conda_env: &Option<String>,
+    use_project_env: Option<bool>,
+) -> Result<(), String> {
+    match runner {
+        Runner::Uv if !matches!(target, ScriptTarget::File(_)) => {
+            Err("runner uv only runs script files".to_string())
+        }
+        Runner::Wsl if conda_env.is_some() => {
+            Err("conda_env cannot be used with runner wsl".to_string())
+        }
+        Runner::Wsl if use_project_env == Some(true) => {
+            Err("use_project_env cannot be used with runner wsl".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fails up front when the runner can't start anything: uv must be
+/// installed, and is reported as `uv --version`; WSL needs the
+/// distribution.
+async fn check_runner(
+    runner: Runner,
+    wsl_distro: Option<&str>,
+    interpreters: &InterpreterInfoCache,
+) -> Result<Option<String>, String> {
+    match runner {
+        Runner::Python => Ok(None),
+        Runner::Uv => interpreters
+            .tool_version(runner::UV_PROGRAM)
+            .await
+            .map(Some)
+            .map_err(|error| runner::uv_unavailable(&error)),
+        Runner::Wsl => {
+            let distributions = interpreters.wsl_distributions().await;
+            match runner::wsl_unavailable(wsl_distro, distributions.as_deref()) {
+                Some(message) => Err(message),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+async fn runner_version(plan: &RunPlan) -> Result<Option<String>, String> {
+    let distro = plan
+        .wsl
+        .as_ref()
+        .and_then(|session| session.distro.as_deref());
+    check_runner(plan.runner, distro, &plan.interpreters).await
+}
+
+/// The interpreter inside the WSL distribution, started through `wsl.exe`.
+fn wsl_candidate(distro: Option<&str>, python: &str) -> PythonCandidate {
+    PythonCandidate {
+        program: wsl::PROGRAM.to_string(),
+        pre_args: wsl::python_args(distro, python),
+        display_name: format!(
+            "{} in WSL ({})",
+            python,
+            distro.unwrap_or("default distribution")
+        ),
+        source: "wsl",
+        local_venv: None,
+        missing: None,
+        project: None,
+    }
+}
+
+/// What actually gets started for `candidate` under a runner other than
+/// Python itself. uv is given the probed executable, since it can't run a
+/// launcher like `py -3`.
+fn runner_candidate(
+    runner: Runner,
+    candidate: &PythonCandidate,
+    info: Option<&InterpreterInfo>,
+) -> Option<PythonCandidate> {
+    match runner {
+        Runner::Python | Runner::Wsl => None,
+        Runner::Uv => {
+            let python = info.map_or(candidate.program.as_str(), |info| info.executable.as_str());
+            Some(PythonCandidate {
+                program: runner::UV_PROGRAM.to_string(),
+                pre_args: runner::uv_run_args(python),
+                display_name: format!("uv run --python {}", python),
+                source: candidate.source,
+                local_venv: candidate.local_venv.clone(),
+                missing: None,
+                project: candidate.project.clone(),
+            })
+        }
+    }
+}
+
+/// Probes the candidate's version. With a requirement, a candidate that
+/// can't be probed or doesn't satisfy it is an attempt to record and skip;
+/// without one, probe failures only lose the version.
+async fn check_python_version(
+    interpreters: &InterpreterInfoCache,
+    requirement: Option<&VersionRequirement>,
+    candidate: &PythonCandidate,
+) -> Result<Option<InterpreterInfo>, CandidateAttempt> {
+    if let Err(error) = candidate.check_installed() {
+        return Err(CandidateAttempt::new(candidate, &error));
+    }
+    let info = match interpreters
+        .get(&candidate.program, &candidate.pre_args)
+        .await
+    {
+        Ok(info) => info,
+        Err(error) if requirement.is_some() => {
+            return Err(CandidateAttempt::new(candidate, &error));
+        }
+        Err(error) => {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("{}", error);
+            }
+            return Ok(None);
+        }
+    };
+    match requirement {
+        Some(requirement) if !requirement.matches(&info.version) => Err(
+            CandidateAttempt::unsupported_version(candidate, requirement, &info),
+        ),
+        _ => Ok(Some(info)),
+    }
+}
+
+/// Picks the interpreter the run would use and reports the command line,
+/// without queueing or running anything but the interpreter probes.
+async fn dry_run(
+    request: &RunPythonScriptRequest,
+    plan: &RunPlan,
+) -> Result<RunPythonScriptResponse, String> {
+    let mut resolve_warnings = Vec::new();
+    let candidates = match &plan.wsl {
+        Some(session) => vec![wsl_candidate(session.distro.as_deref(), &session.python)],
+        None => {
+            request_candidates(
+                &request.interpreter_choice(),
+                &plan.target,
+                &plan.interpreters,
+                &mut resolve_warnings,
+            )
+            .await?
+        }
+    };
+    let mut attempts = Vec::new();
+    let resolve_started = Instant::now();
+    let runner_version = runner_version(plan).await?;
+    let allow_python2 = request.allow_python2.unwrap_or_default();
+    for candidate in &candidates {
+        let version = match probe_candidate(&plan.interpreters, candidate, allow_python2).await {
+            Ok(version) => version,
+            Err(attempt) => {
+                attempts.push(attempt);
+                continue;
+            }
+        };
+        let info = match check_python_version(
+            &plan.interpreters,
+            plan.python_requirement.as_ref(),
+            candidate,
+        )
+        .await
+        {
+            Ok(info) => info,
+            Err(attempt) => {
+                attempts.push(attempt);
+                continue;
+            }
+        };
+
+        let wrapped = runner_candidate(plan.runner, candidate, info.as_ref());
+        let candidate = wrapped.as_ref().unwrap_or(candidate);
+        let expanded_args = plan.args.render(&chrono::Local::now());
+        let command = build_command(request, plan, candidate, &expanded_args);
+        let env_keys: BTreeSet<String> = command
+            .as_std()
+            .get_envs()
+            .filter(|(_, value)| value.is_some())
+            .map(|(key, _)| key.to_string_lossy().to_string())
+            .collect();
+        let working_dir = candidate
+            .working_dir(request, plan)
+            .to_string_lossy()
+            .to_string();
+        let mut warnings = plan.warnings.clone();
+        warnings.extend(resolve_warnings);
+        warnings.extend(fallback_warning(&attempts, candidate, "would run"));
+
+        return Ok(RunPythonScriptResponse {
+            run_id: plan.run_id.clone(),
+            correlation_id: request.correlation_id.clone(),
+            labels: request.labels.clone(),
+            ok: true,
+            script_path: plan.target.script_path(),
+            resolved_command: command_line(&command),
+            interpreter_source: Some(candidate.source.to_string()),
+            local_venv: candidate.local_venv(),
+            project_env: candidate.project_env(),
+            python_executable: info.as_ref().map(|info| info.executable.clone()),
+            python_alias: request
+                .python_settings
+                .alias_name(request.python_path.as_deref()),
+            python_version: info.map(|info| info.version),
+            interpreter_version: version,
+            runner_version,
+            dry_run: Some(ResolvedRun {
+                program: candidate.program.clone(),
+                pre_args: candidate.pre_args.clone(),
+                interpreter_args: request.interpreter_args.clone(),
+                script: plan.target.describe(),
+                args: expanded_args.clone(),
+                working_dir: working_dir.clone(),
+                env_keys: env_keys.into_iter().collect(),
+                inherit_env: request.inherit_env.unwrap_or(true) && !request.clean_env,
+            }),
+            expanded_args,
+            working_dir,
+            priority: plan.priority,
+            warnings,
+            timings: RunTimings {
+                resolve_ms: resolve_started.elapsed().as_millis() as u64,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
+
+    Err(no_interpreter_error(request, plan, &attempts).await)
+}
+
+/// Runs a script once and checks that its output is JSON matching the
+/// widget's schema, so a script can be tried before it is wired up.
+#[tauri::command]
+pub async fn test_script_output(
+    app: AppHandle,
+    registry: State<'_, RunRegistry>,
+    queue: State<'_, RunQueue>,
+    settings: State<'_, SettingsStore>,
+    request: TestScriptOutputRequest,
+) -> Result<TestScriptOutputResponse, String> {
+    let mut run_request = RunPythonScriptRequest {
+        script_path: request.script_path,
+        module: request.module,
+        python_path: request.python_path,
+        args: request.args.into_iter().chain(request.probe_arg).collect(),
+        env: request.env,
+        timeout_ms: Some(request.timeout_ms.unwrap_or(DEFAULT_OUTPUT_TEST_TIMEOUT_MS)),
+        ..Default::default()
+    };
+    apply_settings(&mut run_request, &settings.get());
+    apply_python_settings(&mut run_request, &app.state::<PythonSettingsStore>().get());
+    run_request.app_data_dir = app.path().app_data_dir().ok();
+    run_request.app_cache_dir = app.path().app_cache_dir().ok();
+    check_script_output(run_request, &request.schema, &registry, &queue).await
+}
+
+async fn check_script_output(
+    mut request: RunPythonScriptRequest,
+    schema: &serde_json::Value,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+) -> Result<TestScriptOutputResponse, String> {
+    request.parse_json = Some(true);
+    let mut run = run_script(request, registry, queue, EventSinks::default()).await?;
+    let failed = |kind: &str, message: String, run| TestScriptOutputResponse {
+        passed: false,
+        failure_kind: Some(kind.to_string()),
+        message: Some(message),
+        violations: Vec::new(),
+        run,
+    };
+
+    if !run.ok {
+        let reason = run
+            .termination_reason
+            .clone()
+            .unwrap_or_else(|| "failed".to_string());
+        return Ok(failed("run_failed", format!("script {}", reason), run));
+    }
+    let Some(data) = run.data.take() else {
+        let reason = run.parse_error.clone().unwrap_or_default();
+        return Ok(failed(
+            "not_json",
+            format!("output is not JSON: {}", reason),
+            run,
+        ));
+    };
+
+    let violations = json_schema::validate(schema, &data)
+        .map_err(|error| format!("invalid schema: {}", error))?;
+    run.data = Some(data);
+    if violations.is_empty() {
+        return Ok(TestScriptOutputResponse {
+            passed: true,
+            failure_kind: None,
+            message: None,
+            violations,
+            run,
+        });
+    }
+    let message = format!(
+        "output does not match the schema: {} problem{}",
+        violations.len(),
+        if violations.len() == 1 { "" } else { "s" }
+    );
+    Ok(TestScriptOutputResponse {
+        violations,
+        ..failed("schema_mismatch", message, run)
+    })
+}
+
+/// The docstring and `# pdd-` headers of a script, for prefilling a data
+/// source. Needs no interpreter.
+#[tauri::command]
+pub fn get_script_metadata(
+    settings: State<'_, SettingsStore>,
+    script_path: String,
+    allow_any_extension: Option<bool>,
+) -> Result<ScriptMetadataResponse, String> {
+    script_metadata(
+        &script_path,
+        ScriptExtensions::of(
+            &settings.get().script_extensions,
+            allow_any_extension.unwrap_or(false),
+        ),
+    )
+}
+
+fn script_metadata(
+    script_path: &str,
+    extensions: ScriptExtensions,
+) -> Result<ScriptMetadataResponse, String> {
+    let path = validate_script_path(script_path, extensions)?;
+    let (metadata, warnings) = metadata::read(&path)?;
+    Ok(ScriptMetadataResponse { metadata, warnings })
+}
+
+/// The arguments a script declares with argparse, read from its source.
+/// Falls back to `--help` output when the source doesn't spell them out.
+#[tauri::command]
+pub async fn get_script_arguments(
+    interpreters: State<'_, InterpreterInfoCache>,
+    settings: State<'_, SettingsStore>,
+    python_settings: State<'_, PythonSettingsStore>,
+    mut request: GetScriptArgumentsRequest,
+) -> Result<ScriptArgumentsResponse, String> {
+    let settings = settings.get();
+    request.python_settings = python_settings.get();
+    request.script_extensions = settings.script_extensions;
+    request.local_venv_depth = settings.local_venv_depth;
+    request.allow_python2 = settings.allow_python2;
+    script_arguments(request, &interpreters).await
+}
+
+async fn script_arguments(
+    request: GetScriptArgumentsRequest,
+    interpreters: &InterpreterInfoCache,
+) -> Result<ScriptArgumentsResponse, String> {
+    let extensions = ScriptExtensions::of(&request.script_extensions, request.allow_any_extension);
+    let path = validate_script_path(&request.script_path, extensions)?;
+    let target = ScriptTarget::File(path.clone());
+
+    let mut attempts = Vec::new();
+    let choice = InterpreterChoice {
+        python_path: &request.python_path,
+        conda_env: &request.conda_env,
+        py_version: &request.py_version,
+        python_requirement: &request.python_requirement,
+        use_project_env: request.use_project_env,
+        venv_depth: local_venv_depth(request.use_local_venv, request.local_venv_depth),
+        settings: &request.python_settings,
+    };
+    let candidates = request_candidates(&choice, &target, interpreters, &mut Vec::new()).await?;
+    for candidate in candidates {
+        if let Err(attempt) = probe_candidate(interpreters, &candidate, request.allow_python2).await
+        {
+            attempts.push(attempt);
+            continue;
+        }
+
+        let check = read_script_arguments(&candidate, &path).await;
+        let help_text = match &check {
+            Ok(check) if !check.uses_argparse || !check.arguments.is_empty() => None,
+            _ => Some(script_help_text(&candidate, &path).await?),
+        };
+        return Ok(ScriptArgumentsResponse {
+            arguments: check.map(|check| check.arguments).unwrap_or_default(),
+            help_text,
+            resolved_python: Some(candidate.display_name),
+        });
+    }
+
+    Err(candidates_failed(
+        no_interpreter_message(&attempts, None),
+        &attempts,
+    ))
+}
+
+/// Results for files are cached until the file changes; see
+/// `validation_cache`.
+#[tauri::command]
+pub async fn validate_python_script(
+    interpreters: State<'_, InterpreterInfoCache>,
+    validated: State<'_, ValidatedInterpreters>,
+    cache: State<'_, ValidationCache>,
+    settings: State<'_, SettingsStore>,
+    python_settings: State<'_, PythonSettingsStore>,
+    mut request: ValidatePythonScriptRequest,
+) -> Result<ValidatePythonScriptResponse, String> {
+    let settings = settings.get();
+    request.python_settings = python_settings.get();
+    request.script_extensions = settings.script_extensions;
+    request.local_venv_depth = settings.local_venv_depth;
+    request.allow_python2.get_or_insert(settings.allow_python2);
+    let extensions = ScriptExtensions::of(&request.script_extensions, request.allow_any_extension);
+    let cached = match request.module.as_deref().map(str::trim) {
+        Some(module) if !module.is_empty() => None,
+        _ if request.fix => None,
+        _ => validate_script_path(&request.script_path, extensions)
+            .ok()
+            .and_then(|path| Some((validation_cache::key(&request, &path)?, path))),
+    };
+    if let Some(response) = cached.as_ref().and_then(|(key, _)| cache.get(key)) {
+        return Ok(response);
+    }
+
+    request.last_validated = cached
+        .as_ref()
+        .and_then(|(_, path)| validated.get(&path.to_string_lossy()));
+    let response = validate_script(request, &interpreters).await?;
+    validated.record(&response);
+    if let Some((key, path)) = cached {
+        cache.insert(key, &path, &response);
+    }
+    Ok(response)
+}
+
+/// Drops cached validation results for `script_path`, or all of them.
+/// Returns how many were dropped.
+#[tauri::command]
+pub fn invalidate_validation_cache(
+    cache: State<'_, ValidationCache>,
+    script_path: Option<String>,
+) -> usize {
+    let script_path = script_path.map(|path| canonical_or_given(path.trim()));
+    cache.invalidate(script_path.as_deref())
+}
+
+async fn validate_script(
+    request: ValidatePythonScriptRequest,
+    interpreters: &InterpreterInfoCache,
+) -> Result<ValidatePythonScriptResponse, String> {
+    let python_alias = request
+        .python_settings
+        .alias_name(request.python_path.as_deref());
+    let mut response = validate_resolved(request, interpreters).await?;
+    response.python_alias = python_alias;
+    Ok(response)
+}
+
+async fn validate_resolved(
+    request: ValidatePythonScriptRequest,
+    interpreters: &InterpreterInfoCache,
+) -> Result<ValidatePythonScriptResponse, String> {
+    let extensions = ScriptExtensions::of(&request.script_extensions, request.allow_any_extension);
+    let target =
+        match ScriptTarget::resolve(&request.script_path, request.module.as_deref(), extensions) {
+            Ok(target) => target,
+            Err(message) => {
+                return Ok(ValidatePythonScriptResponse {
+                    valid: false,
+                    message: Some(message),
+                    syntax_ok: true,
+                    ..Default::default()
+                });
+            }
+        };
+
+    let backup_path = match (&target, request.fix) {
+        (ScriptTarget::File(path), true) => source_format::fix(path)?,
+        _ => None,
+    };
+    let (metadata, mut warnings) = match &target {
+        ScriptTarget::File(path) => match metadata::read(path) {
+            Ok((metadata, warnings)) => (Some(metadata), warnings),
+            Err(error) => (None, vec![error]),
+        },
+        _ => (None, Vec::new()),
+    };
+    let mut source_issues = Vec::new();
+    if let ScriptTarget::File(path) = &target {
+        warnings.extend(script_file_warnings(path));
+        match source_format::inspect(path) {
+            Ok(issues) => source_issues = issues,
+            Err(error) => warnings.push(error),
+        }
+    }
+    let requirement = match request
+        .min_python_version
+        .as_deref()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .or_else(|| metadata.as_ref()?.python_requirement())
+        .map(VersionRequirement::parse)
+        .transpose()
+    {
+        Ok(requirement) => requirement,
+        Err(message) => {
+            return Ok(ValidatePythonScriptResponse {
+                valid: false,
+                message: Some(message),
+                syntax_ok: true,
+                ..Default::default()
+            });
+        }
+    };
+    let runner = Runner::parse(request.runner.as_deref()).and_then(|runner| {
+        check_runner_request(runner, &target, &request.conda_env, request.use_project_env)
+            .map(|()| runner)
+    });
+    let runner_version = match &runner {
+        Ok(runner) => {
+            let distro = request
+                .wsl_distro
+                .as_deref()
+                .map(str::trim)
+                .filter(|distro| !distro.is_empty());
+            check_runner(*runner, distro, interpreters).await
+        }
+        Err(message) => Err(message.clone()),
+    };
+    let runner_version = match runner_version {
+        Ok(version) => version,
+        Err(message) => {
+            return Ok(ValidatePythonScriptResponse {
+                valid: false,
+                message: Some(message),
+                script_path: target.script_path(),
+                metadata,
+                warnings,
+                syntax_ok: true,
+                ..Default::default()
+            });
+        }
+    };
+    let inline = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.inline.as_ref());
+    // uv installs the declared dependencies itself when the script runs.
+    let dependencies = match runner {
+        Ok(Runner::Uv) => {
+            if inline.is_none() {
+                warnings.push(
+                    "no `# /// script` block; uv runs the script without extra dependencies"
+                        .to_string(),
+                );
+            }
+            &[]
+        }
+        _ => inline
+            .map(|inline| inline.dependencies.as_slice())
+            .unwrap_or_default(),
+    };
+    let mut stdlib_modules: Vec<String> = Vec::new();
+    for module in request
+        .required_stdlib
+        .iter()
+        .map(|module| module.trim())
+        .filter(|module| !module.is_empty())
+        .chain(
+            metadata
+                .iter()
+                .flat_map(|metadata| metadata.requires_stdlib.iter().map(String::as_str)),
+        )
+    {
+        if !stdlib_modules.iter().any(|listed| listed == module) {
+            stdlib_modules.push(module.to_string());
+        }
+    }
+    let mut candidates_tried = Vec::new();
+    let needs = ScriptNeeds {
+        requirement: requirement.as_ref(),
+        dependencies,
+        stdlib_modules: &stdlib_modules,
+    };
+    let mut response = validate_target(
+        &request,
+        &target,
+        &needs,
+        interpreters,
+        &mut candidates_tried,
+        &mut warnings,
+    )
+    .await?;
+    response.candidates_tried = candidates_tried;
+    response.runner_version = runner_version;
+    response.script_path = target.script_path();
+    response.metadata = metadata;
+    response.warnings = warnings;
+    response.source_issues = source_issues;
+    response.backup_path = backup_path.map(|path| path.to_string_lossy().to_string());
+    if let Some(bundled) = &request.python_settings.bundled_python {
+        let bundled = bundled.to_string_lossy();
+        response.needs_bundled_python = response.candidates_tried.iter().any(|tried| {
+            tried.error_kind.as_deref() == Some("not_installed")
+                && tried.program.first().map(String::as_str) == Some(&*bundled)
+        });
+    }
+    Ok(response)
+}
+
+/// What a script asks of the interpreter that validates it.
+struct ScriptNeeds<'a> {
+    requirement: Option<&'a VersionRequirement>,
+    /// PEP 723 requirements, checked with `check_imports`.
+    dependencies: &'a [String],
+    stdlib_modules: &'a [String],
+}
+
+async fn validate_target(
+    request: &ValidatePythonScriptRequest,
+    target: &ScriptTarget,
+    needs: &ScriptNeeds<'_>,
+    interpreters: &InterpreterInfoCache,
+    candidates_tried: &mut Vec<CandidateDiagnostic>,
+    warnings: &mut Vec<String>,
+) -> Result<ValidatePythonScriptResponse, String> {
+    let ScriptNeeds {
+        requirement,
+        dependencies,
+        stdlib_modules,
+    } = *needs;
+    // Under WSL the distribution's interpreter checks the file at its
+    // `/mnt` path.
+    let (candidates, target) = match Runner::parse(request.runner.as_deref())? {
+        Runner::Wsl => {
+            let distro = request
+                .wsl_distro
+                .as_deref()
+                .map(str::trim)
+                .filter(|distro| !distro.is_empty());
+            let python = request
+                .python_path
+                .as_deref()
+                .map(str::trim)
+                .filter(|python| !python.is_empty())
+                .unwrap_or(wsl::DEFAULT_PYTHON);
+            let target = match target {
+                ScriptTarget::File(path) => {
+                    ScriptTarget::File(PathBuf::from(wsl::to_linux_path(&path.to_string_lossy())?))
+                }
+                other => other.clone(),
+            };
+            (vec![wsl_candidate(distro, python)], target)
+        }
+        _ => {
+            let choice = InterpreterChoice {
+                python_path: &request.python_path,
+                conda_env: &request.conda_env,
+                py_version: &request.py_version,
+                python_requirement: &request.python_requirement,
+                use_project_env: request.use_project_env,
+                venv_depth: local_venv_depth(request.use_local_venv, request.local_venv_depth),
+                settings: &request.python_settings,
+            };
+            let candidates = request_candidates(&choice, target, interpreters, warnings).await?;
+            (candidates, target.clone())
+        }
+    };
+    let target = &target;
+    let mut failed_candidates = Vec::new();
+    let allow_python2 = request.allow_python2.unwrap_or_default();
+    for candidate in candidates {
+        let interpreter_version =
+            match probe_candidate(interpreters, &candidate, allow_python2).await {
+                Ok(version) => version,
+                Err(attempt) => {
+                    candidates_tried.push(CandidateDiagnostic::of(&candidate, Some(&attempt)));
+                    failed_candidates.push(attempt);
+                    continue;
+                }
+            };
+
+        let interpreter_info =
+            match check_python_version(interpreters, requirement, &candidate).await {
+                Ok(info) => info,
+                Err(attempt) => {
+                    candidates_tried.push(CandidateDiagnostic::of(&candidate, Some(&attempt)));
+                    failed_candidates.push(attempt);
+                    continue;
+                }
+            };
+        candidates_tried.push(CandidateDiagnostic::of(&candidate, None));
+        let local_venv = candidate.local_venv();
+        let project_env = candidate.project_env();
+
+        if let ScriptTarget::Module(module) = target {
+            if let Err(message) = check_module_importable(&candidate, module).await {
+                return Ok(ValidatePythonScriptResponse {
+                    valid: false,
+                    message: Some(message),
+                    resolved_python: Some(candidate.display_name),
+                    interpreter_source: Some(candidate.source.to_string()),
+                    local_venv,
+                    project_env,
+                    interpreter_info: interpreter_info.clone(),
+                    interpreter_version: interpreter_version.clone(),
+                    failed_candidates,
+                    syntax_ok: true,
+                    ..Default::default()
+                });
+            }
+        }
+
+        let syntax_error = match target {
+            ScriptTarget::File(path) if request.check_syntax => {
+                check_script_syntax(&candidate, path).await?
+            }
+            _ => None,
+        };
+        if let Some(error) = syntax_error {
+            let location = match (error.line, error.column) {
+                (Some(line), Some(column)) => format!(" at line {}, column {}", line, column),
+                (Some(line), None) => format!(" at line {}", line),
+                _ => String::new(),
+            };
+            return Ok(ValidatePythonScriptResponse {
+                valid: false,
+                message: Some(format!("syntax error{}: {}", location, error.message)),
+                resolved_python: Some(candidate.display_name),
+                interpreter_source: Some(candidate.source.to_string()),
+                local_venv,
+                project_env,
+                interpreter_info,
+                interpreter_version: interpreter_version.clone(),
+                failed_candidates,
+                syntax_ok: false,
+                syntax_error: Some(error),
+                missing_modules: Vec::new(),
+                ..Default::default()
+            });
+        }
+
+        let missing_modules = match target {
+            ScriptTarget::File(path) if request.check_imports => {
+                find_missing_imports(&candidate, path).await?
+            }
+            _ => Vec::new(),
+        };
+        let missing_packages = if request.check_imports && !dependencies.is_empty() {
+            find_missing_dependencies(&candidate, dependencies).await?
+        } else {
+            Vec::new()
+        };
+        let missing_stdlib = if stdlib_modules.is_empty() {
+            Vec::new()
+        } else {
+            find_missing_stdlib(&candidate, stdlib_modules).await?
+        };
+        let missing_dependencies: Vec<MissingDependency> = missing_packages
+            .iter()
+            .map(|requirement| MissingDependency {
+                name: requirement.clone(),
+                kind: "package",
+                system_package: None,
+            })
+            .chain(missing_stdlib.iter().map(|module| MissingDependency {
+                name: module.clone(),
+                kind: "stdlib",
+                system_package: stdlib_system_package(module).map(str::to_string),
+            }))
+            .collect();
+        if !missing_modules.is_empty() {
+            let packages: Vec<&str> = missing_modules
+                .iter()
+                .map(|module| pip_package_name(module))
+                .collect();
+            return Ok(ValidatePythonScriptResponse {
+                valid: false,
+                message: Some(format!(
+                    "missing modules: {} (try `pip install {}`)",
+                    missing_modules.join(", "),
+                    packages.join(" ")
+                )),
+                resolved_python: Some(candidate.display_name),
+                interpreter_source: Some(candidate.source.to_string()),
+                local_venv,
+                project_env,
+                interpreter_info,
+                interpreter_version: interpreter_version.clone(),
+                failed_candidates,
+                syntax_ok: true,
+                syntax_error: None,
+                missing_modules,
+                missing_dependencies,
+                ..Default::default()
+            });
+        }
+        if !missing_dependencies.is_empty() {
+            return Ok(ValidatePythonScriptResponse {
+                valid: false,
+                message: Some(missing_dependencies_message(
+                    &missing_packages,
+                    &missing_dependencies,
+                )),
+                resolved_python: Some(candidate.display_name),
+                interpreter_source: Some(candidate.source.to_string()),
+                local_venv,
+                project_env,
+                interpreter_info,
+                interpreter_version: interpreter_version.clone(),
+                failed_candidates,
+                syntax_ok: true,
+                missing_dependencies,
+                ..Default::default()
+            });
+        }
+
+        let message = match target {
+            ScriptTarget::Module(_) => "module and interpreter are valid",
+            _ => "script and interpreter are valid",
+        };
+        return Ok(ValidatePythonScriptResponse {
+            valid: true,
+            message: Some(message.to_string()),
+            resolved_python: Some(candidate.display_name),
+            interpreter_source: Some(candidate.source.to_string()),
+            local_venv,
+            project_env,
+            interpreter_info,
+            interpreter_version: interpreter_version.clone(),
+            failed_candidates,
+            syntax_ok: true,
+            syntax_error: None,
+            missing_modules: Vec::new(),
+            ..Default::default()
+        });
+    }
+
+    let not_installed = failed_candidates
+        .iter()
+        .find(|attempt| attempt.error_kind == "not_installed")
+        .map(|attempt| attempt.message.clone());
+    let message = requirement
+        .and_then(|requirement| unsupported_versions_message(requirement, &failed_candidates))
+        .or(not_installed)
+        .unwrap_or_else(|| "python interpreter is not available".to_string());
+    let stale = match Runner::parse(request.runner.as_deref())? {
+        Runner::Wsl => None,
+        _ => stale_python_path(&request.python_path, &failed_candidates),
+    };
+    let python_path_suggestions = match stale {
+        Some(dead) => {
+            let repair = PathRepair {
+                venv_depth: request.local_venv_depth,
+                settings: &request.python_settings,
+                last_validated: request.last_validated.as_ref(),
+            };
+            python_path_suggestions(dead, target, &repair, interpreters).await
+        }
+        None => Vec::new(),
+    };
+    Ok(ValidatePythonScriptResponse {
+        valid: false,
+        message: Some(with_suggestions(message, &python_path_suggestions)),
+        failed_candidates,
+        python_path_suggestions,
+        syntax_ok: true,
+        ..Default::default()
+    })
+}
+
+fn missing_dependencies_message(
+    missing_packages: &[String],
+    missing_dependencies: &[MissingDependency],
+) -> String {
+    let mut parts = Vec::new();
+    if !missing_packages.is_empty() {
+        let requirements: Vec<String> = missing_packages
+            .iter()
+            .map(|requirement| format!("{:?}", requirement))
+            .collect();
+        parts.push(format!(
+            "missing dependencies: {} (try `pip install {}`)",
+            missing_packages.join(", "),
+            requirements.join(" ")
+        ));
+    }
+    let stdlib: Vec<&MissingDependency> = missing_dependencies
+        .iter()
+        .filter(|dependency| dependency.kind == "stdlib")
+        .collect();
+    if !stdlib.is_empty() {
+        let modules: Vec<&str> = stdlib.iter().map(|module| module.name.as_str()).collect();
+        let mut packages: Vec<&str> = Vec::new();
+        for package in stdlib
+            .iter()
+            .filter_map(|module| module.system_package.as_deref())
+        {
+            if !packages.contains(&package) {
+                packages.push(package);
+            }
+        }
+        let hint = match packages.is_empty() {
+            true => "this Python was built without them".to_string(),
+            false => format!("try installing {}", packages.join(" ")),
+        };
+        parts.push(format!(
+            "missing standard library modules: {} ({})",
+            modules.join(", "),
+            hint
+        ));
+    }
+    parts.join("; ")
+}
+
+/// Pythons found on this machine, for choosing `python_path`.
+#[tauri::command]
+pub async fn list_python_interpreters(
+    interpreters: State<'_, InterpreterInfoCache>,
+    python_settings: State<'_, PythonSettingsStore>,
+) -> Result<Vec<DiscoveredInterpreter>, String> {
+    Ok(list_interpreters(&interpreters, &python_settings.get()).await)
+}
+
+async fn list_interpreters(
+    interpreters: &InterpreterInfoCache,
+    settings: &PythonSettings,
+) -> Vec<DiscoveredInterpreter> {
+    let mut default = None;
+    for candidate in default_candidates(settings) {
+        if let Ok(info) = interpreters
+            .get(&candidate.program, &candidate.pre_args)
+            .await
+        {
+            default = Some(info);
+            break;
+        }
+    }
+    discovery::discover(interpreters, default).await
+}
+
+/// Runs `python -m venv` with the base interpreter and, if asked, upgrades
+/// pip in the result. Cancel it with `cancel_python_script` and `run_id`.
+#[tauri::command]
+pub async fn create_virtualenv(
+    app: AppHandle,
+    registry: State<'_, RunRegistry>,
+    queue: State<'_, RunQueue>,
+    settings: State<'_, SettingsStore>,
+    mut request: CreateVirtualenvRequest,
+) -> Result<CreateVirtualenvResponse, String> {
+    request.python_settings = app.state::<PythonSettingsStore>().get();
+    let sinks = event_sinks(app, request.stream, None);
+    create_venv(request, &settings.get(), &registry, &queue, sinks).await
+}
+
+async fn create_venv(
+    request: CreateVirtualenvRequest,
+    settings: &ExecutionSettings,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+    sinks: EventSinks,
+) -> Result<CreateVirtualenvResponse, String> {
+    if request.target_dir.trim().is_empty() {
+        return Err("target_dir is required".to_string());
+    }
+    let venv = absolute_path(request.target_dir.trim())?;
+    let target = virtualenv::check_target(&venv, request.force)?;
+
+    // The launcher would work for `-m venv` too, but the response should
+    // name the Python the environment is based on.
+    let target_module = ScriptTarget::Module("venv".to_string());
+    let (_, base) = first_interpreter(
+        &request.python_path,
+        &target_module,
+        None,
+        &request.python_settings,
+        registry.interpreters(),
+    )
+    .await?;
+
+    let started = Instant::now();
+    let run_id = request.run_id.clone().unwrap_or_else(next_run_id);
+    let step = |python: &str, module: &str, args: Vec<String>| RunPythonScriptRequest {
+        module: Some(module.to_string()),
+        args,
+        python_path: Some(python.to_string()),
+        timeout_ms: Some(request.timeout_ms.unwrap_or(DEFAULT_VENV_TIMEOUT_MS)),
+        timeout_bounds: settings.timeout_bounds(),
+        run_id: Some(run_id.clone()),
+        stream: request.stream,
+        ..Default::default()
+    };
+    let python = virtualenv::python_in(&venv).to_string_lossy().to_string();
+    let mut steps = Vec::new();
+    let mut venv_args = Vec::new();
+    if target == Target::Replace {
+        venv_args.push("--clear".to_string());
+    }
+    venv_args.push(venv.to_string_lossy().to_string());
+    steps.push(step(&base.executable, "venv", venv_args));
+    if request.upgrade_pip {
+        steps.push(step(
+            &python,
+            "pip",
+            ["install", "--upgrade", "pip"].map(String::from).to_vec(),
+        ));
+    }
+
+    let mut response = CreateVirtualenvResponse {
+        run_id: run_id.clone(),
+        venv_path: venv.to_string_lossy().to_string(),
+        python_path: python,
+        base_python: base.executable,
+        base_version: base.version,
+        pip_upgraded: false,
+        stdout: String::new(),
+        stderr: String::new(),
+        duration_ms: 0,
+        warnings: Vec::new(),
+    };
+    for step in steps {
+        let upgrading_pip = step.module.as_deref() == Some("pip");
+        let failed = match run_script(step, registry, queue, sinks.clone()).await {
+            Ok(run) => {
+                response.stdout.push_str(&run.stdout);
+                response.stderr.push_str(&run.stderr);
+                response.warnings.extend(run.warnings);
+                match run.ok {
+                    true => None,
+                    false => Some(
+                        run.termination_reason
+                            .unwrap_or_else(|| "failed".to_string()),
+                    ),
+                }
+            }
+            Err(error) => Some(error),
+        };
+        if let Some(reason) = failed {
+            // Only a directory this call created is removed again.
+            if target == Target::New && !upgrading_pip {
+                let _ = std::fs::remove_dir_all(&venv);
+            }
+            let what = match upgrading_pip {
+                true => "failed to upgrade pip in",
+                false => "failed to create virtualenv at",
+            };
+            let stderr = response.stderr.trim();
+            return Err(match stderr.is_empty() {
+                true => format!("{} {}: {}", what, venv.display(), reason),
+                false => format!("{} {}: {}: {}", what, venv.display(), reason, stderr),
+            });
+        }
+        response.pip_upgraded |= upgrading_pip;
+    }
+    response.duration_ms = started.elapsed().as_millis();
+    Ok(response)
+}
+
+/// The first interpreter a run of `target` would use, probed, so a launcher
+/// like `py -3` is known by the Python it starts.
+async fn first_interpreter(
+    python_path: &Option<String>,
+    target: &ScriptTarget,
+    venv_depth: Option<u32>,
+    settings: &PythonSettings,
+    interpreters: &InterpreterInfoCache,
+) -> Result<(PythonCandidate, InterpreterInfo), String> {
+    let mut attempts = Vec::new();
+    for candidate in python_candidates(python_path, target, venv_depth, settings) {
+        match interpreters
+            .get(&candidate.program, &candidate.pre_args)
+            .await
+        {
+            Ok(info) => return Ok((candidate, info)),
+            Err(error) => attempts.push(CandidateAttempt::new(&candidate, &error)),
+        }
+    }
+    Err(candidates_failed(
+        no_interpreter_message(&attempts, None),
+        &attempts,
+    ))
+}
+
+/// Runs `python -m pip install` in a virtualenv, or in any interpreter with
+/// `allow_system_site`, then reads back what got installed. Cancel it with
+/// `cancel_python_script` and `run_id`.
+#[tauri::command]
+pub async fn install_python_packages(
+    app: AppHandle,
+    registry: State<'_, RunRegistry>,
+    queue: State<'_, RunQueue>,
+    settings: State<'_, SettingsStore>,
+    mut request: InstallPythonPackagesRequest,
+) -> Result<InstallPythonPackagesResponse, String> {
+    request.python_settings = app.state::<PythonSettingsStore>().get();
+    let sinks = event_sinks(app, request.stream, None);
+    install_packages(request, &settings.get(), &registry, &queue, sinks).await
+}
+
+async fn install_packages(
+    request: InstallPythonPackagesRequest,
+    settings: &ExecutionSettings,
+    registry: &RunRegistry,
+    queue: &RunQueue,
+    sinks: EventSinks,
+) -> Result<InstallPythonPackagesResponse, String> {
+    let packages: Vec<&str> = request
+        .packages
+        .iter()
+        .map(|package| package.trim())
+        .filter(|package| !package.is_empty())
+        .collect();
+    if let Some(option) = packages.iter().find(|package| package.starts_with('-')) {
+        return Err(format!("package spec looks like an option: {}", option));
+    }
+    let requirements_file = match request.requirements_file.as_deref().map(str::trim) {
+        Some(path) if !path.is_empty() => {
+            let path = absolute_path(path)?;
+            if !path.is_file() {
+                return Err(format!("requirements file not found: {}", path.display()));
+            }
+            Some(path)
+        }
+        _ => None,
+    };
+    if packages.is_empty() && requirements_file.is_none() {
+        return Err("packages or requirements_file is required".to_string());
+    }
+    let index_url = request
+        .index_url
+        .as_deref()
+        .or(settings.pip_index_url.as_deref())
+        .map(str::trim)
+        .filter(|url| !url.is_empty());
+    if let Some(url) = index_url.filter(|url| !url.contains("://")) {
+        return Err(format!("index_url is not a URL: {}", url));
+    }
+
+    let python_path = match request.venv_path.as_deref().map(str::trim) {
+        Some(venv) if !venv.is_empty() => {
+            if request
+                .python_path
+                .as_deref()
+                .is_some_and(|path| !path.trim().is_empty())
+            {
+                return Err("provide either python_path or venv_path, not both".to_string());
+            }
+            let venv = absolute_path(venv)?;
+            if !virtualenv::is_venv(&venv) {
+                return Err(format!("{} is not a virtualenv", venv.display()));
+            }
+            Some(virtualenv::python_in(&venv).to_string_lossy().to_string())
+        }
+        _ => request.python_path.clone(),
+    };
+    let target_module = ScriptTarget::Module("pip".to_string());
+    let (candidate, interpreter) = first_interpreter(
+        &python_path,
+        &target_module,
+        None,
+        &request.python_settings,
+        registry.interpreters(),
+    )
+    .await?;
+    if !interpreter.is_virtualenv && !request.allow_system_site {
+        return Err(format!(
+            "{} is not in a virtualenv; pass allow_system_site: true to install into it anyway",
+            interpreter.executable
+        ));
+    }
+
+    let mut args = ["install", "--disable-pip-version-check"]
+        .map(String::from)
+        .to_vec();
+    if request.upgrade {
+        args.push("--upgrade".to_string());
+    }
+    if let Some(url) = index_url {
+        args.extend(["--index-url".to_string(), url.to_string()]);
+    }
+    if let Some(path) = &requirements_file {
+        args.extend(["-r".to_string(), path.to_string_lossy().to_string()]);
+    }
+    args.extend(packages.iter().map(|package| package.to_string()));
+
+    let timeout_ms = request.timeout_ms.unwrap_or(DEFAULT_PIP_TIMEOUT_MS);
+    let bounds = settings.timeout_bounds();
+    let run = RunPythonScriptRequest {
+        module: Some("pip".to_string()),
+        args,
+        python_path: Some(interpreter.executable.clone()),
+        timeout_ms: Some(timeout_ms),
+        // Installs legitimately outlast any script.
+        timeout_bounds: TimeoutBounds {
+            max_ms: bounds.max_ms.max(DEFAULT_PIP_TIMEOUT_MS),
+            ..bounds
+        },
+        run_id: request.run_id.clone(),
+        stream: request.stream,
+        ..Default::default()
+    };
+    let run = run_script(run, registry, queue, sinks).await?;
+    if !run.ok {
+        if run.stderr.contains("externally-managed-environment") {
+            return Err(format!(
+                "{} is externally managed (PEP 668), so pip refuses to install into it; \
+                 create a virtualenv with create_virtualenv and install there",
+                interpreter.executable
+            ));
+        }
+        let reason = run
+            .termination_reason
+            .unwrap_or_else(|| "failed".to_string());
+        let stderr = run.stderr.trim();
+        return Err(match stderr.is_empty() {
+            true => format!("pip install {}", reason),
+            false => format!("pip install {}: {}", reason, stderr),
+        });
+    }
+
+    let mut names: Vec<String> = packages
+        .iter()
+        .filter_map(|package| requirement_name(package))
+        .map(str::to_string)
+        .collect();
+    if let Some(path) = &requirements_file {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("failed to read {}: {}", path.display(), error))?;
+        names.extend(
+            text.lines()
+                .map(|line| line.split('#').next().unwrap_or("").trim())
+                .filter(|line| !line.starts_with('-'))
+                .filter_map(requirement_name)
+                .map(str::to_string),
+        );
+    }
+    let mut seen = BTreeSet::new();
+    names.retain(|name| seen.insert(normalize_package(name)));
+
+    let versions: HashMap<String, Option<String>> = run_check(
+        &candidate,
+        INSTALLED_VERSIONS_CODE,
+        &names,
+        "installed versions",
+    )
+    .await?;
+    let installed = names
+        .into_iter()
+        .map(|name| InstalledPackage {
+            version: versions.get(&name).cloned().flatten(),
+            name,
+        })
+        .collect();
+
+    Ok(InstallPythonPackagesResponse {
+        run_id: run.run_id,
+        python_path: interpreter.executable,
+        installed,
+        stdout: run.stdout,
+        stderr: run.stderr,
+        duration_ms: run.duration_ms,
+        warnings: run.warnings,
+    })
+}
+
+/// The distribution name a requirement like `pandas>=2; python_version >
+/// "3.9"` or `uvicorn[standard]` names. `None` for URLs and paths.
+fn requirement_name(spec: &str) -> Option<&str> {
+    let end = spec
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(spec.len());
+    let rest = spec[end..].trim_start();
+    let named = end > 0
+        && (rest.is_empty() || rest.starts_with(['<', '>', '=', '!', '~', ';', '[', '@', '(']));
+    named.then_some(&spec[..end])
+}
+
+#[tauri::command]
+pub async fn list_installed_packages(
+    interpreters: State<'_, InterpreterInfoCache>,
+    settings: State<'_, SettingsStore>,
+    python_settings: State<'_, PythonSettingsStore>,
+    mut request: ListInstalledPackagesRequest,
+) -> Result<ListInstalledPackagesResponse, String> {
+    let settings = settings.get();
+    request.python_settings = python_settings.get();
+    request.script_extensions = settings.script_extensions;
+    request.local_venv_depth = settings.local_venv_depth;
+    installed_packages(request, &interpreters).await
+}
+
+async fn installed_packages(
+    request: ListInstalledPackagesRequest,
+    interpreters: &InterpreterInfoCache,
+) -> Result<ListInstalledPackagesResponse, String> {
+    let target = match request.script_path.as_deref().map(str::trim) {
+        Some(path) if !path.is_empty() => ScriptTarget::File(validate_script_path(
+            path,
+            ScriptExtensions::of(&request.script_extensions, false),
+        )?),
+        _ => ScriptTarget::Module(String::new()),
+    };
+    let venv_depth = local_venv_depth(request.use_local_venv, request.local_venv_depth);
+    let (candidate, interpreter) = first_interpreter(
+        &request.python_path,
+        &target,
+        venv_depth,
+        &request.python_settings,
+        interpreters,
+    )
+    .await?;
+    let installed: InstalledPackages = run_check(
+        &candidate,
+        INSTALLED_PACKAGES_CODE,
+        &[],
+        "installed packages",
+    )
+    .await?;
+
+    let mut warnings = Vec::new();
+    if !installed.has_pip {
+        warnings.push(format!(
+            "pip is not installed in {}; packages were listed from their metadata",
+            interpreter.executable
+        ));
+    }
+    let filter = request
+        .filter
+        .as_deref()
+        .map(|filter| filter.trim().to_lowercase())
+        .filter(|filter| !filter.is_empty());
+    let packages = installed
+        .packages
+        .into_iter()
+        .filter(|package| {
+            filter
+                .as_ref()
+                .map_or(true, |filter| package.name.to_lowercase().contains(filter))
+        })
+        .filter(|package| {
+            !request.top_level_only
+                || !installed
+                    .required
+                    .contains(&normalize_package(&package.name))
+        })
+        .collect();
+    Ok(ListInstalledPackagesResponse {
+        python_path: interpreter.executable,
+        packages,
+        warnings,
+    })
+}
+
+/// Asks the package index which of an interpreter's packages have newer
+/// releases. Results are reused for the `outdated_check_ttl_ms` setting;
+/// offline checks aren't.
+#[tauri::command]
+pub async fn check_outdated_packages(
+    interpreters: State<'_, InterpreterInfoCache>,
+    cache: State<'_, OutdatedCache>,
+    settings: State<'_, SettingsStore>,
+    python_settings: State<'_, PythonSettingsStore>,
+    mut request: CheckOutdatedPackagesRequest,
+) -> Result<CheckOutdatedPackagesResponse, String> {
+    let settings = settings.get();
+    request.python_settings = python_settings.get();
+    request.script_extensions = settings.script_extensions;
+    request.local_venv_depth = settings.local_venv_depth;
+    request.index_url = settings.pip_index_url;
+    request.cache_ttl_ms = settings.outdated_check_ttl_ms;
+    outdated_packages(request, &interpreters, &cache).await
+}
+
+async fn outdated_packages(
+    request: CheckOutdatedPackagesRequest,
+    interpreters: &InterpreterInfoCache,
+    cache: &OutdatedCache,
+) -> Result<CheckOutdatedPackagesResponse, String> {
+    let target = match request.script_path.as_deref().map(str::trim) {
+        Some(path) if !path.is_empty() => ScriptTarget::File(validate_script_path(
+            path,
+            ScriptExtensions::of(&request.script_extensions, false),
+        )?),
+        _ => ScriptTarget::Module("pip".to_string()),
+    };
+    let venv_depth = local_venv_depth(request.use_local_venv, request.local_venv_depth);
+    let (candidate, interpreter) = first_interpreter(
+        &request.python_path,
+        &target,
+        venv_depth,
+        &request.python_settings,
+        interpreters,
+    )
+    .await?;
+    let index_url = request
+        .index_url
+        .as_deref()
+        .map(str::trim)
+        .filter(|url| !url.is_empty());
+    let ttl = Duration::from_millis(request.cache_ttl_ms);
+    if !request.refresh {
+        if let Some((packages, checked_at_ms)) = cache.get(&interpreter.executable, index_url, ttl)
+        {
+            return Ok(CheckOutdatedPackagesResponse {
+                python_path: interpreter.executable,
+                status: "ok",
+                packages,
+                offline_reason: None,
+                checked_at_ms,
+                from_cache: true,
+            });
+        }
+    }
+
+    let checked_at_ms = unix_time_ms();
+    let (status, packages, offline_reason) =
+        match outdated::check(&candidate.program, &candidate.pre_args, index_url).await? {
+            outdated::Outcome::Checked(packages) => {
+                cache.insert(
+                    &interpreter.executable,
+                    index_url,
+                    packages.clone(),
+                    checked_at_ms,
+                );
+                ("ok", packages, None)
+            }
+            outdated::Outcome::Offline(reason) => ("offline", Vec::new(), Some(reason)),
+        };
+    Ok(CheckOutdatedPackagesResponse {
+        python_path: interpreter.executable,
+        status,
+        packages,
+        offline_reason,
+        checked_at_ms,
+        from_cache: false,
+    })
+}
+
+/// Writes down the environment of an interpreter, or just the part a script
+/// needs, as requirements.txt content.
+#[tauri::command]
+pub async fn export_requirements(
+    interpreters: State<'_, InterpreterInfoCache>,
+    settings: State<'_, SettingsStore>,
+    python_settings: State<'_, PythonSettingsStore>,
+    mut request: ExportRequirementsRequest,
+) -> Result<ExportRequirementsResponse, String> {
+    let settings = settings.get();
+    request.python_settings = python_settings.get();
+    request.script_extensions = settings.script_extensions;
+    request.local_venv_depth = settings.local_venv_depth;
+    export_environment(request, &interpreters).await
+}
+
+async fn export_environment(
+    request: ExportRequirementsRequest,
+    interpreters: &InterpreterInfoCache,
+) -> Result<ExportRequirementsResponse, String> {
+    let script = match request.script_path.as_deref().map(str::trim) {
+        Some(path) if !path.is_empty() => Some(validate_script_path(
+            path,
+            ScriptExtensions::of(&request.script_extensions, false),
+        )?),
+        _ => None,
+    };
+    let destination = match (&script, request.write) {
+        (_, false) => None,
+        (None, true) => return Err("write needs a script_path to write next to".to_string()),
+        (Some(script), true) => {
+            let path = script.with_file_name("requirements.txt");
+            if path.exists() && !request.overwrite {
+                return Err(format!(
+                    "{} already exists; pass overwrite: true to replace it",
+                    path.display()
+                ));
+            }
+            Some(path)
+        }
+    };
+    let target = match &script {
+        Some(script) => ScriptTarget::File(script.clone()),
+        None => ScriptTarget::Module(String::new()),
+    };
+    let venv_depth = local_venv_depth(request.use_local_venv, request.local_venv_depth);
+    let (candidate, interpreter) = first_interpreter(
+        &request.python_path,
+        &target,
+        venv_depth,
+        &request.python_settings,
+        interpreters,
+    )
+    .await?;
+    let scripts: Vec<String> = script
+        .iter()
+        .map(|script| script.to_string_lossy().to_string())
+        .collect();
+    let frozen: FrozenEnvironment = run_check(
+        &candidate,
+        &with_import_scan(EXPORT_REQUIREMENTS_CODE),
+        &scripts,
+        "installed packages",
+    )
+    .await?;
+
+    let mut warnings: Vec<String> = frozen
+        .missing
+        .iter()
+        .map(|module| {
+            format!(
+                "{} is imported but not installed in {}; add {} by hand",
+                module,
+                interpreter.executable,
+                pip_package_name(module)
+            )
+        })
+        .collect();
+    let mut content = format!(
+        "# Exported from {} (Python {})\n",
+        interpreter.executable, interpreter.version
+    );
+    if let Some(script) = &script {
+        content.push_str(&format!("# for {}\n", script.display()));
+    }
+    for requirement in &frozen.packages {
+        match requirement.line() {
+            Some(line) => content.push_str(&format!("{}\n", line)),
+            None => {
+                let url = requirement.url.as_deref().unwrap_or_default();
+                warnings.push(format!(
+                    "{} is a {} install from {}, which other machines don't have; it is left commented out",
+                    requirement.name, requirement.source, url
+                ));
+                content.push_str(&format!(
+                    "# {}=={} ({} install from {})\n",
+                    requirement.name, requirement.version, requirement.source, url
+                ));
+            }
+        }
+    }
+    if let Some(path) = &destination {
+        std::fs::write(path, &content)
+            .map_err(|error| format!("failed to write {}: {}", path.display(), error))?;
+    }
+
+    Ok(ExportRequirementsResponse {
+        python_path: interpreter.executable,
+        content,
+        requirements: frozen.packages,
+        written_to: destination.map(|path| path.to_string_lossy().to_string()),
+        warnings,
+    })
+}
+
+/// Starts `python_path`, or each interpreter a run without one would try,
+/// in order, and has it report on itself.
+#[tauri::command]
+pub async fn check_interpreter(
+    python_settings: State<'_, PythonSettingsStore>,
+    python_path: Option<String>,
+) -> Result<Vec<InterpreterCheck>, String> {
+    Ok(check_interpreters(&python_path, &python_settings.get()).await)
+}
+
+async fn check_interpreters(
+    python_path: &Option<String>,
+    settings: &PythonSettings,
+) -> Vec<InterpreterCheck> {
+    let target = ScriptTarget::Module(String::new());
+    let mut checks = Vec::new();
+    for candidate in python_candidates(python_path, &target, None, settings) {
+        let report = run_check_within::<InterpreterHealth>(
+            HEALTH_CHECK_TIMEOUT,
+            &candidate,
+            HEALTH_CHECK_CODE,
+            &[],
+            "interpreter health",
+        )
+        .await;
+        let problems = match &report {
+            Ok(health) => health_problems(health),
+            Err(_) => Vec::new(),
+        };
+        checks.push(InterpreterCheck {
+            program: std::iter::once(candidate.program.clone())
+                .chain(candidate.pre_args.iter().cloned())
+                .collect(),
+            display_name: candidate.display_name,
+            source: candidate.source.to_string(),
+            ok: report.is_ok(),
+            error: report.as_ref().err().cloned(),
+            health: report.ok(),
+            problems,
+        });
+    }
+    checks
+}
+
+fn health_problems(health: &InterpreterHealth) -> Vec<String> {
+    let mut problems: Vec<String> = health
+        .modules
+        .iter()
+        .filter(|(_, module)| !module.importable)
+        .map(|(name, module)| match name.as_str() {
+            "pip" => "pip is not installed".to_string(),
+            _ => format!(
+                "{} can't be imported: {}",
+                name,
+                module.error.as_deref().unwrap_or("unknown error")
+            ),
+        })
+        .collect();
+    if !health.encodings.filesystem.eq_ignore_ascii_case("utf-8") {
+        problems.push(format!(
+            "the filesystem encoding is {}, not UTF-8",
+            health.encodings.filesystem
+        ));
+    }
+    problems
+}
+
+/// PEP 503 normalization: `Typing_Extensions` and `typing.extensions` are
+/// `typing-extensions`.
+fn normalize_package(name: &str) -> String {
+    let mut normalized = String::new();
+    for c in name.chars() {
+        match c {
+            '-' | '_' | '.' if normalized.ends_with('-') => {}
+            '-' | '_' | '.' => normalized.push('-'),
+            c => normalized.push(c.to_ascii_lowercase()),
+        }
+    }
+    normalized
+}
+
+/// Validates every script in a directory, sharing the interpreter probe
+/// between them. Entries are sorted by path.
+#[tauri::command]
+pub async fn validate_python_scripts(
+    interpreters: State<'_, InterpreterInfoCache>,
+    validated: State<'_, ValidatedInterpreters>,
+    settings: State<'_, SettingsStore>,
+    python_settings: State<'_, PythonSettingsStore>,
+    mut request: ValidatePythonScriptsRequest,
+) -> Result<Vec<ScriptValidationEntry>, String> {
+    let settings = settings.get();
+    request.python_settings = python_settings.get();
+    request.script_extensions = settings.script_extensions;
+    request.local_venv_depth = settings.local_venv_depth;
+    request.allow_python2.get_or_insert(settings.allow_python2);
+    validate_scripts(request, &interpreters, &validated).await
+}
+
+async fn validate_scripts(
+    request: ValidatePythonScriptsRequest,
+    interpreters: &InterpreterInfoCache,
+    validated: &ValidatedInterpreters,
+) -> Result<Vec<ScriptValidationEntry>, String> {
+    let dir_path = request.dir_path.trim();
+    if dir_path.is_empty() {
+        return Err("dir_path is required".to_string());
+    }
+    let dir = Path::new(dir_path);
+    if !dir.is_dir() {
+        return Err(format!("not a directory: {}", dir_path));
+    }
+    let timeout_ms = request
+        .file_timeout_ms
+        .unwrap_or(DEFAULT_VALIDATION_FILE_TIMEOUT_MS);
+    if timeout_ms == 0 {
+        return Err("file_timeout_ms must be positive".to_string());
+    }
+    let timeout = Duration::from_millis(timeout_ms);
+    let parallel = request
+        .max_parallel
+        .unwrap_or(DEFAULT_VALIDATION_PARALLELISM)
+        .clamp(1, MAX_VALIDATION_PARALLELISM);
+
+    let extensions = ScriptExtensions::of(&request.script_extensions, false);
+    let scripts = python_scripts_in(dir, extensions, request.recursive, request.include_hidden)?;
+    if scripts.len() > MAX_VALIDATION_BATCH_SIZE {
+        return Err(format!(
+            "{} holds {} scripts; at most {} can be validated at once",
+            dir_path,
+            scripts.len(),
+            MAX_VALIDATION_BATCH_SIZE
+        ));
+    }
+
+    let permits = Arc::new(tokio::sync::Semaphore::new(parallel));
+    let handles: Vec<_> = scripts
+        .into_iter()
+        .map(|path| {
+            let path = path.to_string_lossy().to_string();
+            let script_request = ValidatePythonScriptRequest {
+                script_path: path.clone(),
+                python_path: request.python_path.clone(),
+                conda_env: request.conda_env.clone(),
+                py_version: request.py_version.clone(),
+                python_requirement: request.python_requirement.clone(),
+                runner: request.runner.clone(),
+                wsl_distro: request.wsl_distro.clone(),
+                allow_python2: request.allow_python2,
+                check_syntax: request.check_syntax.unwrap_or(true),
+                check_imports: request.check_imports.unwrap_or(true),
+                use_local_venv: request.use_local_venv,
+                use_project_env: request.use_project_env,
+                script_extensions: request.script_extensions.clone(),
+                local_venv_depth: request.local_venv_depth,
+                python_settings: request.python_settings.clone(),
+                ..Default::default()
+            };
+            let (permits, interpreters, validated) =
+                (permits.clone(), interpreters.clone(), validated.clone());
+            tauri::async_runtime::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let validation = validate_script(script_request, &interpreters);
+                let result = tokio::time::timeout(timeout, validation)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(format!("validation timed out after {} ms", timeout_ms))
+                    });
+                if let Ok(response) = &result {
+                    validated.record(response);
+                }
+                ScriptValidationEntry::new(path, result)
+            })
+        })
+        .collect();
+
+    let mut entries = Vec::with_capacity(handles.len());
+    for handle in handles {
+        entries.push(
+            handle
+                .await
+                .map_err(|error| format!("validation task failed: {}", error))?,
+        );
+    }
+    Ok(entries)
+}
+
+/// Files under `dir` with an accepted extension, sorted. `__pycache__` is
+/// always skipped, and so are symlinked directories, so a link loop can't
+/// recurse forever.
+fn python_scripts_in(
+    dir: &Path,
+    extensions: ScriptExtensions,
+    recursive: bool,
+    include_hidden: bool,
+) -> Result<Vec<PathBuf>, String> {
+    let mut scripts = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = match std::fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(error) if current == dir => {
+                return Err(format!("failed to read {}: {}", dir.display(), error));
+            }
+            Err(error) => {
+                log::warn!("skipping {}: {}", current.display(), error);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') && !include_hidden {
+                continue;
+            }
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if recursive && name != "__pycache__" {
+                    pending.push(path);
+                }
+            } else if extensions.has_accepted_extension(&path) && path.is_file() {
+                scripts.push(path);
+            }
+        }
+    }
+    scripts.sort();
+    Ok(scripts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_python_path_has_highest_priority() {
+        let target = ScriptTarget::Code("pass".to_string());
+        let candidates = python_candidates(
+            &Some("/custom/python".to_string()),
+            &target,
+            Some(3),
+            &PythonSettings::default(),
+        );
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].program, "/custom/python".to_string());
+    }
+
+    #[test]
+    fn the_python_settings_place_their_interpreter_among_the_candidates() {
+        let target = ScriptTarget::Code("pass".to_string());
+        let mut settings = PythonSettings {
+            python_path: Some(" /opt/python/bin/python3 ".to_string()),
+            ..Default::default()
+        };
+        let sources = |settings: &PythonSettings| {
+            python_candidates(&None, &target, Some(3), settings)
+                .iter()
+                .map(|candidate| candidate.source)
+                .collect::<Vec<_>>()
+        };
+        let candidates = python_candidates(&None, &target, Some(3), &settings);
+        assert_eq!(candidates[0].program, "/opt/python/bin/python3");
+        assert_eq!(sources(&settings)[..2], ["settings", "default"]);
+        settings.candidate_order = vec!["default".to_string()];
+        assert_eq!(sources(&settings).last(), Some(&"settings"));
+        let own = python_candidates(&Some("python3".to_string()), &target, Some(3), &settings);
+        assert_eq!(own.len(), 1);
+        assert_eq!(own[0].source, "request");
+
+        settings.set_candidates(Some(vec![CandidateEntry {
+            program: "python3.12".to_string(),
+            pre_args: Vec::new(),
+            display_name: String::new(),
+        }]));
+        let defaults: Vec<String> = python_candidates(&None, &target, Some(3), &settings)
+            .into_iter()
+            .filter(|candidate| candidate.source == "default")
+            .map(|candidate| candidate.display_name)
+            .collect();
+        assert_eq!(defaults, ["python3.12"]);
+        settings.set_candidates(None);
+
+        settings.interpreter_args = strings(&["-X", "utf8"]);
+        let mut request = RunPythonScriptRequest::default();
+        apply_python_settings(&mut request, &settings);
+        assert_eq!(request.interpreter_args, ["-X", "utf8"]);
+        let mut request = RunPythonScriptRequest {
+            runner: Some("uv".to_string()),
+            ..Default::default()
+        };
+        apply_python_settings(&mut request, &settings);
+        assert!(request.interpreter_args.is_empty());
+        assert_eq!(request.python_settings, settings);
+    }
+
+    #[tokio::test]
+    async fn validation_says_when_the_bundled_runtime_is_wanted() {
+        let script = temp_script("wants_bundled.py", "print('hi')\n");
+        let bundled = std::env::temp_dir()
+            .join("pdd-no-bundled-runtime")
+            .join("python3");
+        let mut settings = PythonSettings {
+            bundled_python: Some(bundled.clone()),
+            ..Default::default()
+        };
+        let candidates = python_candidates(
+            &None,
+            &ScriptTarget::File(script.clone().into()),
+            None,
+            &settings,
+        );
+        let last = candidates.last().unwrap();
+        assert_eq!(last.source, "bundled");
+        assert!(last.check_installed().is_err());
+
+        let interpreters = InterpreterInfoCache::default();
+        let validate = |settings: &PythonSettings| {
+            validate_script(
+                ValidatePythonScriptRequest {
+                    script_path: script.clone(),
+                    python_settings: settings.clone(),
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+        // A working python3 comes first.
+        let validation = validate(&settings).await.unwrap();
+        assert!(validation.valid);
+        assert!(!validation.needs_bundled_python);
+
+        settings.candidate_order = strings(&["bundled"]);
+        let validation = validate(&settings).await.unwrap();
+        assert!(validation.valid);
+        assert!(validation.needs_bundled_python);
+        assert_eq!(validation.failed_candidates[0].error_kind, "not_installed");
+        assert!(validation.failed_candidates[0]
+            .message
+            .contains("ensure_bundled_python"));
+    }
+
+    #[test]
+    fn invalid_script_extension_should_fail_validation() {
+        let result = validate_script_path("/tmp/not_python.txt", ScriptExtensions::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn script_extensions_are_case_insensitive_and_configurable() {
+        let upper = temp_script("UPPER.PY", "print('hi')\n");
+        let gui = temp_script("tray.pyw", "print('hi')\n");
+        let bare = temp_script("generated_tool", "#!/usr/bin/env python3\nprint('hi')\n");
+        let bare_shell = temp_script("generated_shell", "#!/bin/sh\necho hi\n");
+        let text = temp_script("notes.txt", "print('hi')\n");
+        let check = |path: &str, accepted: &[&str], allow_any: bool| {
+            let accepted = strings(accepted);
+            validate_script_path(path, ScriptExtensions::of(&accepted, allow_any)).map(|_| ())
+        };
+
+        assert!(check(&upper, &[], false).is_ok());
+        assert!(check(&gui, &[], false).is_ok());
+        assert!(check(&bare, &[], false).is_ok());
+        assert!(check(&bare_shell, &[], false).is_err());
+        assert_eq!(
+            check(&text, &[], false).unwrap_err(),
+            "script must end in .py, .pyw or have no extension and a python shebang"
+        );
+        assert!(check(&text, &[], true).is_ok());
+        assert!(check(&text, &[".TXT"], false).is_ok());
+        let error = check(&gui, &[".py"], false).unwrap_err();
+        assert!(error.starts_with("script must end in .py or"), "{}", error);
+    }
+
+    async fn run_request(
+        request: RunPythonScriptRequest,
+    ) -> Result<RunPythonScriptResponse, String> {
+        run_script(
+            request,
+            &RunRegistry::default(),
+            &RunQueue::default(),
+            EventSinks::default(),
+        )
+        .await
+    }
+
+    /// What `print` ends lines with on this platform.
+    const NEWLINE: &str = if cfg!(windows) { "\r\n" } else { "\n" };
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn failed_candidates_are_reported_in_order() {
+        let script = temp_script("never_runs.py", "print('unreachable')\n");
+        let not_executable = temp_script("not_an_interpreter.py", "");
+        let run = |python_path: &str| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                python_path: Some(python_path.to_string()),
+                ..Default::default()
+            })
+        };
+
+        let error = run(&not_executable).await.unwrap_err();
+        let error: serde_json::Value = serde_json::from_str(&error).unwrap();
+        let run_id = error["run_id"].as_str().unwrap();
+        assert_eq!(
+            error["message"],
+            format!("run {}: no python interpreter could be started", run_id)
+        );
+        assert_eq!(error["attempts"][0]["candidate"], not_executable.as_str());
+        assert_eq!(error["attempts"][0]["error_kind"], "permission_denied");
+
+        let error = run("/nonexistent/pdd-python").await.unwrap_err();
+        let error: serde_json::Value = serde_json::from_str(&error).unwrap();
+        assert!(error["message"]
+            .as_str()
+            .unwrap()
+            .contains(": python interpreter not found: /nonexistent/pdd-python; try instead: "));
+        assert_eq!(error["python_path_suggestions"][0]["reason"], "default");
+        assert_eq!(error["attempts"][0]["error_kind"], "not_found");
+
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script.clone(),
+                python_path: Some(not_executable.clone()),
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert!(!validation.valid);
+        assert_eq!(
+            validation.failed_candidates[0].error_kind,
+            "permission_denied"
+        );
+        assert_eq!(validation.candidates_tried.len(), 1);
+        assert!(!validation.candidates_tried[0].available);
+        assert_eq!(validation.candidates_tried[0].program, [not_executable]);
+
+        use std::os::unix::fs::PermissionsExt;
+        let crashing = temp_script("crashing_python.sh", "#!/bin/sh\nexit 3\n");
+        std::fs::set_permissions(&crashing, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let interpreters = InterpreterInfoCache::default();
+        let validate = |python_path: Option<String>| {
+            validate_script(
+                ValidatePythonScriptRequest {
+                    script_path: script.clone(),
+                    python_path,
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+        let validation = validate(Some(crashing.clone())).await.unwrap();
+        assert_eq!(
+            validation.message.as_deref(),
+            Some("python interpreter is not available")
+        );
+        let tried = &validation.candidates_tried[0];
+        assert_eq!(tried.error_kind.as_deref(), Some("other"));
+        assert!(tried.error.as_deref().unwrap().contains("exit status: 3"));
+
+        let validation = validate(None).await.unwrap();
+        assert!(validation.valid);
+        let tried = validation.candidates_tried.last().unwrap();
+        assert!(tried.available && tried.error.is_none());
+        assert_eq!(
+            validation.resolved_python.as_ref(),
+            Some(&tried.display_name)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn python2_is_refused_unless_allowed() {
+        use std::os::unix::fs::PermissionsExt;
+        let script = temp_script("python2_refused.py", "print('hi')\n");
+        let python2 = temp_script(
+            "python2.sh",
+            "#!/bin/sh\n[ \"$1\" = --version ] && { echo 'Python 2.7.18' >&2; exit 0; }\nexec python3 \"$@\"\n",
+        );
+        std::fs::set_permissions(&python2, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let run = |python_path: Option<String>, allow_python2: Option<bool>| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                python_path,
+                allow_python2,
+                ..Default::default()
+            })
+        };
+
+        let error = run(Some(python2.clone()), None).await.unwrap_err();
+        assert!(
+            error.contains("Python 2.7.18 is not supported; set allow_python2 to use it"),
+            "{}",
+            error
+        );
+        let response = run(Some(python2.clone()), Some(true)).await.unwrap();
+        assert_eq!(response.stdout.trim(), "hi");
+        let version = response.interpreter_version.unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (2, 7, 18));
+        let response = run(None, None).await.unwrap();
+        assert_eq!(response.interpreter_version.unwrap().major, 3);
+
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script,
+                python_path: Some(python2),
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert!(!validation.valid);
+        let failed = &validation.failed_candidates[0];
+        assert_eq!(failed.error_kind, "unsupported_version");
+        assert_eq!(failed.version.as_deref(), Some("2.7.18"));
+    }
+
+    #[tokio::test]
+    async fn interpreters_report_on_their_health() {
+        let checks = check_interpreters(&None, &PythonSettings::default()).await;
+        let usable = checks.iter().find(|check| check.ok).unwrap();
+        let health = usable.health.as_ref().unwrap();
+        assert!(health.version.starts_with("3."));
+        assert!(Path::new(&health.executable).is_file());
+        assert!(health.modules["venv"].importable, "{:?}", health.modules);
+        assert_eq!(health.modules.len(), 4);
+        assert_eq!(usable.source, "default");
+
+        let missing = check_interpreters(
+            &Some("pdd-no-such-python".to_string()),
+            &PythonSettings::default(),
+        )
+        .await;
+        assert_eq!(missing.len(), 1);
+        assert!(!missing[0].ok && missing[0].health.is_none());
+        assert!(missing[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .starts_with("failed to check interpreter health"));
+    }
+
+    #[tokio::test]
+    async fn installed_interpreters_are_listed_once_each() {
+        let interpreters =
+            list_interpreters(&InterpreterInfoCache::default(), &PythonSettings::default()).await;
+        assert_eq!(
+            interpreters
+                .iter()
+                .filter(|interpreter| interpreter.is_default)
+                .count(),
+            1,
+            "{:?}",
+            interpreters
+        );
+        let mut canonical: Vec<PathBuf> = interpreters
+            .iter()
+            .filter(|interpreter| !interpreter.is_venv)
+            .map(|interpreter| std::fs::canonicalize(&interpreter.executable).unwrap())
+            .collect();
+        let listed = canonical.len();
+        canonical.sort();
+        canonical.dedup();
+        assert_eq!(canonical.len(), listed, "{:?}", interpreters);
+        assert!(interpreters
+            .iter()
+            .all(|interpreter| !interpreter.arch.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn a_virtualenv_is_created_and_not_clobbered() {
+        let dir = temp_dir("create-venv");
+        let settings = ExecutionSettings::default();
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+        let create = |force| {
+            create_venv(
+                CreateVirtualenvRequest {
+                    target_dir: dir.to_string_lossy().to_string(),
+                    force,
+                    ..Default::default()
+                },
+                &settings,
+                &registry,
+                &queue,
+                EventSinks::default(),
+            )
+        };
+
+        let created = create(false).await.unwrap();
+        assert_eq!(
+            PathBuf::from(&created.python_path),
+            virtualenv::python_in(&dir)
+        );
+        assert!(virtualenv::is_venv(&dir));
+        assert!(Path::new(&created.base_python).is_absolute());
+        assert!(!created.pip_upgraded);
+
+        let error = create(false).await.unwrap_err();
+        assert!(error.contains("pass force: true"), "{}", error);
+        assert!(create(true).await.is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn requirement_names_are_read_from_specs() {
+        assert_eq!(requirement_name("requests"), Some("requests"));
+        assert_eq!(requirement_name("pandas>=2,<3"), Some("pandas"));
+        assert_eq!(requirement_name("uvicorn[standard]"), Some("uvicorn"));
+        assert_eq!(
+            requirement_name("tomli; python_version < \"3.11\""),
+            Some("tomli")
+        );
+        assert_eq!(requirement_name("git+https://example.com/pkg.git"), None);
+        assert_eq!(requirement_name("./vendor/pkg"), None);
+    }
+
+    #[tokio::test]
+    async fn packages_go_into_virtualenvs_unless_allowed_otherwise() {
+        let settings = ExecutionSettings::default();
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+        let install = |request| {
+            install_packages(request, &settings, &registry, &queue, EventSinks::default())
+        };
+
+        let error = install(InstallPythonPackagesRequest {
+            packages: strings(&["--pre"]),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("looks like an option"), "{}", error);
+
+        let system = registry
+            .interpreters()
+            .get(
+                &default_candidates(&PythonSettings::default())[0].program,
+                &[],
+            )
+            .await
+            .unwrap();
+        if !system.is_virtualenv {
+            let error = install(InstallPythonPackagesRequest {
+                packages: strings(&["requests"]),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+            assert!(error.contains("allow_system_site: true"), "{}", error);
+        }
+
+        let dir = temp_dir("install");
+        let error = install(InstallPythonPackagesRequest {
+            venv_path: Some(dir.to_string_lossy().to_string()),
+            packages: strings(&["requests"]),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("is not a virtualenv"), "{}", error);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn installed_packages_are_listed_from_metadata() {
+        let interpreters = InterpreterInfoCache::default();
+        let list = |filter: Option<&str>, top_level_only| ListInstalledPackagesRequest {
+            filter: filter.map(str::to_string),
+            top_level_only,
+            ..Default::default()
+        };
+        let all = installed_packages(list(None, false), &interpreters)
+            .await
+            .unwrap();
+        let names: Vec<String> = all
+            .packages
+            .iter()
+            .map(|package| normalize_package(&package.name))
+            .collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(names, sorted);
+        assert!(all
+            .packages
+            .iter()
+            .all(|package| !package.version.is_empty() && !package.location.is_empty()));
+
+        let pip = installed_packages(list(Some("PIP"), false), &interpreters)
+            .await
+            .unwrap();
+        assert!(pip
+            .packages
+            .iter()
+            .all(|package| package.name.to_lowercase().contains("pip")));
+        let top_level = installed_packages(list(None, true), &interpreters)
+            .await
+            .unwrap();
+        assert!(top_level.packages.len() <= all.packages.len());
+        assert_eq!(normalize_package("Typing__Extensions"), "typing-extensions");
+    }
+
+    #[tokio::test]
+    async fn requirements_are_exported_for_what_a_script_imports() {
+        let dir = temp_dir("export");
+        std::fs::write(dir.join("export_helper.py"), "").unwrap();
+        let script = dir.join("share_me.py");
+        std::fs::write(
+            &script,
+            "import json\nimport pip\nimport export_helper\nimport pdd_not_installed_xyz\n",
+        )
+        .unwrap();
+        let interpreters = InterpreterInfoCache::default();
+        let export = |overwrite| ExportRequirementsRequest {
+            script_path: Some(script.display().to_string()),
+            write: true,
+            overwrite,
+            ..Default::default()
+        };
+
+        let exported = export_environment(export(false), &interpreters)
+            .await
+            .unwrap();
+        let names: Vec<&str> = exported
+            .requirements
+            .iter()
+            .map(|requirement| requirement.name.as_str())
+            .collect();
+        assert_eq!(names, ["pip"]);
+        assert!(exported.content.contains("\npip"), "{}", exported.content);
+        assert_eq!(exported.warnings.len(), 1, "{:?}", exported.warnings);
+        assert!(exported.warnings[0].starts_with("pdd_not_installed_xyz is imported"));
+        let written = exported.written_to.unwrap();
+        assert_eq!(std::fs::read_to_string(&written).unwrap(), exported.content);
+
+        let error = export_environment(export(false), &interpreters)
+            .await
+            .unwrap_err();
+        assert!(error.contains("overwrite"), "{}", error);
+        assert!(export_environment(export(true), &interpreters)
+            .await
+            .is_ok());
+
+        let requirement = |source: &str, url: Option<&str>| ExportedRequirement {
+            name: "widgets".to_string(),
+            version: "1.2".to_string(),
+            source: source.to_string(),
+            url: url.map(str::to_string),
+        };
+        assert_eq!(
+            requirement("index", None).line().as_deref(),
+            Some("widgets==1.2")
+        );
+        assert_eq!(
+            requirement("vcs", Some("git+https://example.com/w.git@abc123"))
+                .line()
+                .as_deref(),
+            Some("widgets @ git+https://example.com/w.git@abc123")
+        );
+        assert_eq!(
+            requirement("editable", Some("file:///home/me/widgets")).line(),
+            None
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn aliases_name_interpreters_for_runs_and_validations() {
+        let store = PythonSettingsStore::default();
+        let interpreters = InterpreterInfoCache::default();
+        let python = interpreters
+            .get(if cfg!(windows) { "python" } else { "python3" }, &[])
+            .await
+            .unwrap()
+            .executable;
+        let aliases = add_alias(&store, &interpreters, " work-venv ", &python)
+            .await
+            .unwrap();
+        assert_eq!(aliases.len(), 1);
+        assert_eq!(aliases[0].name, "work-venv");
+        assert!(aliases[0].exists);
+        for bad in ["", "../venv", "my venv", "-x"] {
+            assert!(add_alias(&store, &interpreters, bad, &python)
+                .await
+                .is_err());
+        }
+
+        let script = temp_script("alias_run.py", "print('aliased')\n");
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            python_path: Some("work-venv".to_string()),
+            python_settings: store.get(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.python_alias.as_deref(), Some("work-venv"));
+        assert!(response.python_executable.is_some());
+
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script.clone(),
+                python_path: Some("work-venv".to_string()),
+                python_settings: store.get(),
+                ..Default::default()
+            },
+            &interpreters,
+        )
+        .await
+        .unwrap();
+        assert!(validation.valid, "{:?}", validation.message);
+        assert_eq!(validation.python_alias.as_deref(), Some("work-venv"));
+
+        let profile = |name: &str, python_path: &str| ScriptProfile {
+            name: name.to_string(),
+            script_path: script.clone(),
+            python_path: Some(python_path.to_string()),
+            ..Default::default()
+        };
+        let usages = alias_usages(
+            &[profile("weather", "work-venv"), profile("news", &python)],
+            "work-venv",
+        );
+        assert_eq!(usages.len(), 1);
+        assert_eq!(usages[0].profile, "weather");
+    }
+
+    #[tokio::test]
+    async fn python_requirements_pick_the_newest_matching_interpreter() {
+        let script = temp_script("requirement_run.py", "print('matched')\n");
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            python_requirement: Some(">=3".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(
+            response.interpreter_source.as_deref(),
+            Some("python_requirement")
+        );
+        let version = response.python_version.unwrap();
+        assert!(version.starts_with('3'), "{}", version);
+
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            python_requirement: Some("<2.0".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("no installed Python matches"), "{}", error);
+        assert!(error.contains(&version), "{}", error);
+
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script,
+            python_path: Some("python3".to_string()),
+            python_requirement: Some(">=3".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("not both"), "{}", error);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dead_python_paths_get_replacements_suggested() {
+        let interpreters = InterpreterInfoCache::default();
+        let python = interpreters.get("python3", &[]).await.unwrap();
+        // Next to the script, but kept out of the directory other tests'
+        // scripts share.
+        let dir = temp_dir("repair-tests");
+        let venv = dir.join(".venv");
+        std::fs::create_dir_all(venv.join("bin")).unwrap();
+        let home = Path::new(&python.executable).parent().unwrap();
+        let config = format!("home = {}\n", home.display());
+        std::fs::write(venv.join(virtualenv::VENV_CONFIG_FILE), config).unwrap();
+        let _ = std::os::unix::fs::symlink(&python.executable, virtualenv::python_in(&venv));
+        let script = dir.join("widget.py");
+        std::fs::write(&script, "print('repaired')\n").unwrap();
+        let script = script.to_string_lossy().to_string();
+        let (major, minor, ..) = python.version_info;
+        let dead = format!("/nonexistent/old-venv/bin/python{}.{}", major, minor);
+
+        let response = repair_path(
+            &RepairPythonPathRequest {
+                python_path: dead.clone(),
+                script_path: script.clone(),
+                local_venv_depth: 1,
+                ..Default::default()
+            },
+            &interpreters,
+        )
+        .await
+        .unwrap();
+        assert!(!response.available);
+        let suggestions = &response.suggestions;
+        assert_eq!(suggestions[0].reason, "local_venv");
+        assert!(suggestions[0].python_path.contains(".venv"));
+        assert!(suggestions
+            .iter()
+            .any(|suggestion| suggestion.reason == "same_version"
+                && suggestion
+                    .version
+                    .starts_with(&format!("{}.{}.", major, minor))));
+
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script.clone(),
+                python_path: Some(dead),
+                local_venv_depth: 1,
+                ..Default::default()
+            },
+            &interpreters,
+        )
+        .await
+        .unwrap();
+        assert!(!validation.valid);
+        assert_eq!(validation.python_path_suggestions, response.suggestions);
+        assert!(validation.message.unwrap().contains("; try instead: "));
+
+        let response = repair_path(
+            &RepairPythonPathRequest {
+                python_path: python.executable,
+                script_path: script,
+                ..Default::default()
+            },
+            &interpreters,
+        )
+        .await
+        .unwrap();
+        assert!(response.available && response.suggestions.is_empty());
+    }
+
+    #[test]
+    fn labels_are_limited_in_number_and_size() {
+        let labels =
+            |pairs: Vec<(String, String)>| validate_labels(Some(&pairs.into_iter().collect()));
+        assert!(labels(vec![("widget".to_string(), "weather".to_string())]).is_ok());
+        assert!(labels((0..17).map(|i| (i.to_string(), String::new())).collect()).is_err());
+        assert!(labels(vec![("note".to_string(), "x".repeat(257))]).is_err());
+        assert!(labels(vec![(" ".to_string(), "x".to_string())]).is_err());
+        assert!(labels(vec![("a".to_string(), "b\nc".to_string())]).is_err());
+        assert!(validate_labels(None).is_ok());
+    }
+
+    #[test]
+    fn interpreter_args_accept_plain_flags() {
+        assert!(validate_interpreter_args(&strings(&["-u", "-X", "utf8", "-B"])).is_ok());
+        assert!(
+            validate_interpreter_args(&strings(&["-Xutf8", "-Wignore::DeprecationWarning"]))
+                .is_ok()
+        );
+        assert!(
+            validate_interpreter_args(&strings(&["-I", "--check-hash-based-pycs", "never"]))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn interpreter_args_reject_anything_that_changes_what_runs() {
+        for args in [
+            vec!["-c", "print(1)"],
+            vec!["-uc", "print(1)"],
+            vec!["-m", "http.server"],
+            vec!["other.py"],
+            vec!["-"],
+            vec!["-X"],
+        ] {
+            assert!(
+                validate_interpreter_args(&strings(&args)).is_err(),
+                "{:?} should be rejected",
+                args
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn interpreter_args_reach_the_interpreter() {
+        let script = temp_script(
+            "print_flags.py",
+            "import sys\nprint(sys.flags.dont_write_bytecode, sys.flags.utf8_mode)\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            interpreter_args: strings(&["-B", "-X", "utf8"]),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.stdout.trim(), "1 1");
+    }
+
+    #[tokio::test]
+    async fn modules_run_with_dash_m() {
+        let response = run_request(RunPythonScriptRequest {
+            module: Some("json.tool".to_string()),
+            args: strings(&["--compact"]),
+            stdin: Some("{ \"a\": 1 }".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.stdout.trim(), "{\"a\":1}");
+    }
+
+    #[test]
+    fn exactly_one_of_script_path_and_module_is_required() {
+        assert!(ScriptTarget::resolve("", None, ScriptExtensions::default()).is_err());
+        assert!(
+            ScriptTarget::resolve("/tmp/a.py", Some("json"), ScriptExtensions::default()).is_err()
+        );
+        assert!(
+            ScriptTarget::resolve("", Some("json; import os"), ScriptExtensions::default())
+                .is_err()
+        );
+        assert!(matches!(
+            ScriptTarget::resolve(
+                "  ",
+                Some("mytools.fetch_weather"),
+                ScriptExtensions::default()
+            ),
+            Ok(ScriptTarget::Module(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn shebang_interpreters_go_before_the_defaults() {
+        let output = std::process::Command::new("python3")
+            .args(["-c", "import sys; print(sys.executable)"])
+            .output()
+            .unwrap();
+        let python = String::from_utf8(output.stdout).unwrap().trim().to_string();
+        let with_shebang = temp_script(
+            "shebang_interpreter.py",
+            &format!("#!{}\nprint('hi')\n", python),
+        );
+        let without = temp_script("no_shebang_interpreter.py", "print('hi')\n");
+        let run = |script_path: &str, python_path: Option<&str>| {
+            run_request(RunPythonScriptRequest {
+                script_path: script_path.to_string(),
+                python_path: python_path.map(str::to_string),
+                ..Default::default()
+            })
+        };
+
+        let response = run(&with_shebang, None).await.unwrap();
+        assert!(response.ok);
+        assert_eq!(response.interpreter_source.as_deref(), Some("shebang"));
+        assert_eq!(response.resolved_command[0], python);
+        let response = run(&with_shebang, Some("python3")).await.unwrap();
+        assert_eq!(response.interpreter_source.as_deref(), Some("request"));
+        let response = run(&without, None).await.unwrap();
+        assert_eq!(response.interpreter_source.as_deref(), Some("default"));
+
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: with_shebang,
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(validation.resolved_python.as_deref(), Some(python.as_str()));
+        assert_eq!(validation.interpreter_source.as_deref(), Some("shebang"));
+    }
+
+    #[tokio::test]
+    async fn a_pipfile_without_an_environment_suggests_pipenv_install() {
+        let project = temp_dir("pipenv-run");
+        std::fs::write(project.join(pipenv::PIPFILE), "[packages]\n").unwrap();
+        let script = project.join("report.py");
+        std::fs::write(&script, "print(1)\n").unwrap();
+        let interpreters = InterpreterInfoCache::default();
+        let response = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script.to_string_lossy().to_string(),
+                use_project_env: Some(true),
+                ..Default::default()
+            },
+            &interpreters,
+        )
+        .await
+        .unwrap();
+
+        if interpreters.tool_version(pipenv::PROGRAM).await.is_ok() {
+            let attempt = &response.failed_candidates[0];
+            assert_eq!(attempt.error_kind, "not_installed");
+            assert!(attempt.message.contains("run `pipenv install`"));
+        } else {
+            assert!(response.valid, "{:?}", response.message);
+            assert!(
+                response
+                    .warnings
+                    .iter()
+                    .any(|warning| warning.contains("pipenv was not found on PATH")),
+                "{:?}",
+                response.warnings
+            );
+        }
+        std::fs::remove_dir_all(project).unwrap();
+    }
+
+    #[tokio::test]
+    async fn py_version_goes_through_the_launcher_or_fails() {
+        let script = temp_script("py_version.py", "print('hi')\n");
+        let run = |py_version: &str, python_path: Option<&str>| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                py_version: Some(py_version.to_string()),
+                python_path: python_path.map(str::to_string),
+                ..Default::default()
+            })
+        };
+        let error = run("3.12", Some("python3")).await.unwrap_err();
+        assert!(error.ends_with("provide either python_path or py_version, not both"));
+        let error = run("3.12; calc", None).await.unwrap_err();
+        assert!(error.contains("is not a launcher version"), "{}", error);
+
+        match py_launcher::list().await {
+            Err(_) => {
+                let error = run("3.12", None).await.unwrap_err();
+                assert!(error.contains("launcher, which was not found"), "{}", error);
+            }
+            Ok(registered) => {
+                let error = run("2.1", None).await.unwrap_err();
+                assert!(error.contains("is not registered"), "{}", error);
+                if let Some(python) = registered.first() {
+                    let response = run(&python.version, None).await.unwrap();
+                    assert_eq!(response.interpreter_source.as_deref(), Some("py_launcher"));
+                    assert!(response.python_executable.is_some());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_poetry_project_runs_under_poetry_or_falls_back() {
+        let project = temp_dir("poetry-run");
+        std::fs::create_dir_all(project.join("scripts")).unwrap();
+        std::fs::write(
+            project.join(poetry::PYPROJECT_FILE),
+            "[tool.poetry]\nname = \"widgets\"\n",
+        )
+        .unwrap();
+        let script = project.join("scripts").join("cwd.py");
+        std::fs::write(&script, "import os\nprint(os.getcwd())\n").unwrap();
+        let script = script.to_string_lossy().to_string();
+        let run = |use_project_env: Option<bool>, python_path: Option<&str>| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                use_project_env,
+                python_path: python_path.map(str::to_string),
+                ..Default::default()
+            })
+        };
+
+        let error = run(Some(true), Some("python3")).await.unwrap_err();
+        assert!(error.ends_with("provide either python_path or use_project_env, not both"));
+        let poetry = InterpreterInfoCache::default()
+            .tool_version(poetry::PROGRAM)
+            .await;
+        let response = run(Some(true), None).await.unwrap();
+        if poetry.is_ok() {
+            assert_eq!(response.interpreter_source.as_deref(), Some("poetry"));
+            assert_eq!(Path::new(&response.working_dir), project);
+        } else {
+            assert_ne!(response.interpreter_source.as_deref(), Some("poetry"));
+            assert!(
+                response
+                    .warnings
+                    .iter()
+                    .any(|warning| warning.contains("poetry was not found on PATH")),
+                "{:?}",
+                response.warnings
+            );
+            assert_eq!(
+                response.working_dir,
+                project.join("scripts").to_string_lossy()
+            );
+        }
+        let response = run(Some(false), None).await.unwrap();
+        assert_ne!(response.interpreter_source.as_deref(), Some("poetry"));
+        assert!(!response
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("Poetry")));
+        std::fs::remove_dir_all(project).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_conda_env_is_resolved_by_name() {
+        let script = temp_script("conda_env.py", "import sys\nprint(sys.prefix)\n");
+        let run = |conda_env: &str, python_path: Option<&str>| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                conda_env: Some(conda_env.to_string()),
+                python_path: python_path.map(str::to_string),
+                ..Default::default()
+            })
+        };
+
+        let error = run("base", Some("python3")).await.unwrap_err();
+        assert!(error.contains("not both"), "{}", error);
+        let error = run("pdd-no-such-env", None).await.unwrap_err();
+        assert!(
+            error.contains("conda environment not found: pdd-no-such-env")
+                || error == "conda not found on PATH",
+            "{}",
+            error
+        );
+
+        let Ok(envs) = InterpreterInfoCache::default().conda_environments().await else {
+            return;
+        };
+        let base = envs.iter().find(|env| env.name == "base").unwrap();
+        let response = run("base", None).await.unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.interpreter_source.as_deref(), Some("conda"));
+        assert_eq!(
+            std::fs::canonicalize(response.stdout.trim()).unwrap(),
+            std::fs::canonicalize(&base.prefix).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn a_pyenv_pin_is_used_or_reported_missing() {
+        let Some(installed) = pyenv::root()
+            .and_then(|root| std::fs::read_dir(root.join("versions")).ok())
+            .and_then(|versions| {
+                versions
+                    .flatten()
+                    .filter_map(|version| version.file_name().into_string().ok())
+                    .find(|version| version.starts_with("3."))
+            })
+        else {
+            return;
+        };
+        let dir = temp_dir("pyenv-pin");
+        let script = dir.join("pinned.py");
+        std::fs::write(&script, "print(1)\n").unwrap();
+        let interpreters = InterpreterInfoCache::default();
+        let validate = || {
+            validate_script(
+                ValidatePythonScriptRequest {
+                    script_path: script.to_string_lossy().to_string(),
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+
+        std::fs::write(dir.join(pyenv::VERSION_FILE), format!("{}\n", installed)).unwrap();
+        let response = validate().await.unwrap();
+        assert!(response.valid, "{:?}", response.message);
+        assert_eq!(response.interpreter_source.as_deref(), Some("pyenv"));
+
+        std::fs::write(dir.join(pyenv::VERSION_FILE), "3.0.99\n").unwrap();
+        let response = validate().await.unwrap();
+        assert_eq!(response.failed_candidates[0].error_kind, "not_installed");
+        assert_eq!(
+            response.failed_candidates[0].message,
+            format!(
+                "pyenv version 3.0.99 from {} is not installed",
+                dir.join(pyenv::VERSION_FILE).display()
+            )
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn runner_uv_needs_uv_and_a_script_file() {
+        let script = temp_script(
+            "uv_runner.py",
+            "# /// script\n# dependencies = []\n# ///\nprint('hi')\n",
+        );
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            runner: Some("poetry".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(error, "unsupported runner: poetry");
+        let error = run_request(RunPythonScriptRequest {
+            module: Some("json.tool".to_string()),
+            runner: Some("uv".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(error, "runner uv only runs script files");
+
+        let interpreters = InterpreterInfoCache::default();
+        let uv = interpreters.tool_version(runner::UV_PROGRAM).await;
+        let run = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            runner: Some("UV".to_string()),
+            ..Default::default()
+        })
+        .await;
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script,
+                runner: Some("uv".to_string()),
+                ..Default::default()
+            },
+            &interpreters,
+        )
+        .await
+        .unwrap();
+        match uv {
+            Ok(version) => {
+                let response = run.unwrap();
+                assert_eq!(response.stdout.trim(), "hi");
+                assert_eq!(response.runner_version.as_ref(), Some(&version));
+                assert!(validation.valid, "{:?}", validation.message);
+                assert_eq!(validation.runner_version, Some(version));
+            }
+            Err(_) => {
+                let message = "runner uv: uv not found on PATH; install uv or leave runner unset";
+                assert!(run.unwrap_err().ends_with(message));
+                assert!(!validation.valid);
+                assert_eq!(validation.message.as_deref(), Some(message));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn runner_wsl_needs_the_distribution() {
+        let script = temp_script("wsl_runner.py", "print('hi')\n");
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            runner: Some("wsl".to_string()),
+            conda_env: Some("base".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(error, "conda_env cannot be used with runner wsl");
+
+        let distributions = ["Ubuntu".to_string(), "Debian".to_string()];
+        assert_eq!(
+            runner::wsl_unavailable(Some("ubuntu"), Ok(&distributions)),
+            None
+        );
+        assert_eq!(
+            runner::wsl_unavailable(Some("Arch"), Ok(&distributions)).as_deref(),
+            Some("WSL distribution not found: Arch (found: Ubuntu, Debian)")
+        );
+        assert!(runner::wsl_unavailable(None, Ok(&[])).is_some());
+
+        if cfg!(windows) {
+            return;
+        }
+        let message = "runner wsl: wsl.exe not found; is WSL installed?";
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            runner: Some("wsl".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.ends_with(message), "{}", error);
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script,
+                runner: Some("WSL".to_string()),
+                wsl_distro: Some("Ubuntu".to_string()),
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert!(!validation.valid);
+        assert_eq!(validation.message.as_deref(), Some(message));
+    }
+
+    #[tokio::test]
+    async fn a_venv_above_the_script_is_preferred() {
+        let project = temp_dir("local-venv");
+        std::fs::create_dir_all(project.join("widgets")).unwrap();
+        let python = if cfg!(windows) { "python" } else { "python3" };
+        let status = std::process::Command::new(python)
+            .args(["-m", "venv", "--without-pip"])
+            .arg(project.join(".venv"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let script = project.join("widgets").join("weather.py");
+        std::fs::write(&script, "import sys\nprint(sys.prefix)\n").unwrap();
+        let script = script.to_string_lossy().to_string();
+        let venv = std::fs::canonicalize(project.join(".venv")).unwrap();
+        let run = |use_local_venv: Option<bool>, local_venv_depth: u32| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                use_local_venv,
+                local_venv_depth,
+                ..Default::default()
+            })
+        };
+
+        let response = run(None, 1).await.unwrap();
+        assert_eq!(response.interpreter_source.as_deref(), Some("local_venv"));
+        let reported = response.local_venv.as_deref().map(PathBuf::from);
+        assert_eq!(
+            reported.map(|path| std::fs::canonicalize(path).unwrap()),
+            Some(venv.clone())
+        );
+        assert_eq!(std::fs::canonicalize(response.stdout.trim()).unwrap(), venv);
+        let response = run(None, 0).await.unwrap();
+        assert_eq!(response.interpreter_source.as_deref(), Some("default"));
+        assert_eq!(response.local_venv, None);
+        let response = run(Some(false), 1).await.unwrap();
+        assert_eq!(response.interpreter_source.as_deref(), Some("default"));
+
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script.clone(),
+                local_venv_depth: 1,
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert!(validation.valid, "{:?}", validation.message);
+        assert_eq!(validation.interpreter_source.as_deref(), Some("local_venv"));
+        assert!(validation.local_venv.is_some());
+        assert!(validation.interpreter_info.unwrap().is_virtualenv);
+        let _ = std::fs::remove_dir_all(project);
+    }
+
+    #[tokio::test]
+    async fn validation_reports_and_fixes_byte_order_marks() {
+        let script = temp_script("bom_fix.py", "");
+        std::fs::write(
+            &script,
+            b"\xef\xbb\xbf#!/usr/bin/env python3\r\nprint('hi')\r\n",
+        )
+        .unwrap();
+        let _ = std::fs::remove_file(format!("{}.bak", script));
+        let interpreters = InterpreterInfoCache::default();
+        let validate = |fix: bool| {
+            validate_script(
+                ValidatePythonScriptRequest {
+                    script_path: script.clone(),
+                    fix,
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+
+        let validation = validate(false).await.unwrap();
+        assert!(validation.valid, "{:?}", validation.message);
+        let kinds: Vec<&str> = validation
+            .source_issues
+            .iter()
+            .map(|issue| issue.kind)
+            .collect();
+        assert_eq!(kinds, ["utf8_bom", "crlf_shebang"]);
+        assert_eq!(validation.backup_path, None);
+
+        let validation = validate(true).await.unwrap();
+        assert!(validation.valid, "{:?}", validation.message);
+        assert!(validation.source_issues.is_empty());
+        let backup = validation.backup_path.unwrap();
+        assert!(std::fs::read(&backup).unwrap().starts_with(b"\xef\xbb\xbf"));
+        std::fs::remove_file(backup).unwrap();
+    }
+
+    #[tokio::test]
+    async fn script_output_is_checked_against_a_schema() {
+        let script = temp_script(
+            "contract.py",
+            "import json, sys\nif '--probe' not in sys.argv:\n    print('not ready')\nelif '--fail' in sys.argv:\n    sys.exit(3)\nelse:\n    print(json.dumps({'temp': 'warm'}))\n",
+        );
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["temp", "unit"],
+            "properties": {"temp": {"type": "number"}}
+        });
+        let (registry, queue) = (RunRegistry::default(), RunQueue::default());
+        let check = |args: &[&str]| {
+            check_script_output(
+                RunPythonScriptRequest {
+                    script_path: script.clone(),
+                    args: strings(args),
+                    ..Default::default()
+                },
+                &schema,
+                &registry,
+                &queue,
+            )
+        };
+
+        let result = check(&[]).await.unwrap();
+        assert_eq!(result.failure_kind.as_deref(), Some("not_json"));
+        let result = check(&["--probe", "--fail"]).await.unwrap();
+        assert_eq!(result.failure_kind.as_deref(), Some("run_failed"));
+        assert_eq!(result.message.as_deref(), Some("script exited with code 3"));
+
+        let result = check(&["--probe"]).await.unwrap();
+        assert!(!result.passed);
+        assert_eq!(result.failure_kind.as_deref(), Some("schema_mismatch"));
+        let pointers: Vec<&str> = result
+            .violations
+            .iter()
+            .map(|violation| violation.pointer.as_str())
+            .collect();
+        assert_eq!(pointers, ["", "/temp"]);
+        assert!(result.run.data.is_some());
+
+        let result = check_script_output(
+            RunPythonScriptRequest {
+                script_path: script.clone(),
+                args: strings(&["--probe"]),
+                ..Default::default()
+            },
+            &serde_json::json!({"type": "object"}),
+            &RunRegistry::default(),
+            &RunQueue::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.passed, "{:?}", result.message);
+    }
+
+    #[tokio::test]
+    async fn declared_dependencies_are_checked_against_the_interpreter() {
+        let script = temp_script(
+            "pep_723.py",
+            "# /// script\n# requires-python = \">=3.8\"\n# dependencies = [\"pdd-definitely-missing-package>=1\", \"also-missing; python_version < '3'\"]\n# ///\nprint('hi')\n",
+        );
+        let interpreters = InterpreterInfoCache::default();
+        let validate = |check_imports: bool| {
+            validate_script(
+                ValidatePythonScriptRequest {
+                    script_path: script.clone(),
+                    check_imports,
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+
+        let validation = validate(false).await.unwrap();
+        assert!(validation.valid, "{:?}", validation.message);
+        let inline = validation.metadata.unwrap().inline.unwrap();
+        assert_eq!(inline.requires_python.as_deref(), Some(">=3.8"));
+        assert_eq!(inline.dependencies.len(), 2);
+
+        let validation = validate(true).await.unwrap();
+        assert!(!validation.valid);
+        assert_eq!(
+            validation.missing_dependencies[0].name,
+            "pdd-definitely-missing-package>=1"
+        );
+        assert_eq!(validation.missing_dependencies[0].kind, "package");
+        assert!(validation
+            .message
+            .unwrap()
+            .starts_with("missing dependencies: pdd-definitely-missing-package>=1"));
+    }
+
+    #[tokio::test]
+    async fn required_stdlib_modules_are_imported_in_one_run() {
+        let script = temp_script(
+            "needs_stdlib.py",
+            "# pdd-requires-stdlib: json, pdd_no_such_stdlib_module\nprint('hi')\n",
+        );
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script,
+                required_stdlib: strings(&["sqlite3", "json", "pdd_other_missing_module"]),
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert!(!validation.valid);
+        let missing: Vec<(&str, &str)> = validation
+            .missing_dependencies
+            .iter()
+            .map(|dependency| (dependency.name.as_str(), dependency.kind))
+            .collect();
+        assert_eq!(
+            missing,
+            [
+                ("pdd_other_missing_module", "stdlib"),
+                ("pdd_no_such_stdlib_module", "stdlib")
+            ]
+        );
+        assert_eq!(
+            validation.message.as_deref(),
+            Some("missing standard library modules: pdd_other_missing_module, pdd_no_such_stdlib_module (this Python was built without them)")
+        );
+    }
+
+    #[test]
+    fn missing_stdlib_modules_suggest_system_packages() {
+        let stdlib = |name: &str, system_package: Option<&str>| MissingDependency {
+            name: name.to_string(),
+            kind: "stdlib",
+            system_package: system_package.map(str::to_string),
+        };
+        let missing = [
+            MissingDependency {
+                name: "rich".to_string(),
+                kind: "package",
+                system_package: None,
+            },
+            stdlib("tkinter", Some("python3-tk")),
+            stdlib("turtle", Some("python3-tk")),
+            stdlib("sqlite3", None),
+        ];
+        assert_eq!(
+            missing_dependencies_message(&strings(&["rich"]), &missing),
+            "missing dependencies: rich (try `pip install \"rich\"`); missing standard library modules: tkinter, turtle, sqlite3 (try installing python3-tk)"
+        );
+    }
+
+    #[tokio::test]
+    async fn validation_reports_metadata_even_without_an_interpreter() {
+        let script = temp_script(
+            "metadata_headers.py",
+            "# pdd-name: Weather\n# pdd-args: city, days:number\n\"\"\"Forecast.\"\"\"\n",
+        );
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script.clone(),
+                python_path: Some("pdd-no-such-python".to_string()),
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert!(!validation.valid);
+        let metadata = validation.metadata.unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("Weather"));
+        assert_eq!(metadata.description.as_deref(), Some("Forecast."));
+        assert_eq!(metadata.args.len(), 1);
+        assert_eq!(validation.warnings.len(), 1);
+
+        let response = script_metadata(&script, ScriptExtensions::default()).unwrap();
+        assert_eq!(response.metadata, metadata);
+        assert!(script_metadata("/no/such/script.py", ScriptExtensions::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn directories_are_validated_script_by_script() {
+        let dir = temp_dir("validate-dir");
+        for sub in ["nested", "__pycache__", ".hidden"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for (name, source) in [
+            ("good.py", "print('ok')\n"),
+            ("broken.py", "print(\n"),
+            ("notes.txt", "not python\n"),
+            (".dot.py", "print('hidden')\n"),
+            ("nested/inner.py", "import pdd_definitely_missing_module\n"),
+            ("__pycache__/cached.py", "print('skip')\n"),
+            (".hidden/secret.py", "print('skip')\n"),
+        ] {
+            std::fs::write(dir.join(name), source).unwrap();
+        }
+        let interpreters = InterpreterInfoCache::default();
+        let validated = ValidatedInterpreters::default();
+        let validate = |recursive: bool, include_hidden: bool| {
+            validate_scripts(
+                ValidatePythonScriptsRequest {
+                    dir_path: dir.to_string_lossy().to_string(),
+                    recursive,
+                    include_hidden,
+                    ..Default::default()
+                },
+                &interpreters,
+                &validated,
+            )
+        };
+        let names = |entries: &[ScriptValidationEntry]| -> Vec<String> {
+            entries
+                .iter()
+                .map(|entry| {
+                    Path::new(&entry.path)
+                        .strip_prefix(&dir)
+                        .unwrap()
+                        .to_string_lossy()
+                        .replace('\\', "/")
+                })
+                .collect()
+        };
+
+        let entries = validate(false, false).await.unwrap();
+        assert_eq!(names(&entries), ["broken.py", "good.py"]);
+        assert!(!entries[0].valid && !entries[0].syntax_ok);
+        assert!(entries[1].valid, "{:?}", entries[1].message);
+
+        let entries = validate(true, false).await.unwrap();
+        assert_eq!(names(&entries), ["broken.py", "good.py", "nested/inner.py"]);
+        assert_eq!(
+            entries[2].missing_modules,
+            ["pdd_definitely_missing_module"]
+        );
+
+        let entries = validate(true, true).await.unwrap();
+        assert_eq!(
+            names(&entries),
+            [
+                ".dot.py",
+                ".hidden/secret.py",
+                "broken.py",
+                "good.py",
+                "nested/inner.py"
+            ]
+        );
+
+        let timed_out = validate_scripts(
+            ValidatePythonScriptsRequest {
+                dir_path: dir.to_string_lossy().to_string(),
+                file_timeout_ms: Some(1),
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+            &ValidatedInterpreters::default(),
+        )
+        .await
+        .unwrap();
+        assert!(timed_out
+            .iter()
+            .all(|entry| !entry.valid && entry.message.as_deref().unwrap().contains("timed out")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verbatim_prefixes_are_dropped_when_safe() {
+        assert_eq!(
+            strip_verbatim_prefix(r"\\?\C:\scripts\weather.py").as_deref(),
+            Some(r"C:\scripts\weather.py")
+        );
+        assert_eq!(
+            strip_verbatim_prefix(r"\\?\UNC\nas\share\weather.py").as_deref(),
+            Some(r"\\nas\share\weather.py")
+        );
+        assert_eq!(strip_verbatim_prefix(r"C:\scripts\weather.py"), None);
+        assert_eq!(strip_verbatim_prefix(r"\\?\Volume{0b1c}\weather.py"), None);
+        let long = format!(r"\\?\C:\{}\weather.py", "d".repeat(300));
+        assert_eq!(strip_verbatim_prefix(&long), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinked_scripts_resolve_to_one_path() {
+        let dir = temp_dir("symlink-tests");
+        let real = dir.join("real.py");
+        std::fs::write(&real, "print('hi')\n").unwrap();
+        let link = |name: &str, target: &Path| {
+            let path = dir.join(name);
+            let _ = std::fs::remove_file(&path);
+            std::os::unix::fs::symlink(target, &path).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let alias = link("alias.py", &real);
+        let dangling = link("dangling.py", &dir.join("gone.py"));
+        let looped = link("loop_a.py", &dir.join("loop_b.py"));
+        link("loop_b.py", &dir.join("loop_a.py"));
+        let canonical = std::fs::canonicalize(&real).unwrap();
+        let canonical = canonical.to_string_lossy();
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: alias.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(response.ok);
+        assert_eq!(response.script_path.as_deref(), Some(&*canonical));
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: alias,
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(validation.script_path.as_deref(), Some(&*canonical));
+
+        let error = validate_script_path(&dangling, ScriptExtensions::default()).unwrap_err();
+        assert!(error.contains("dangling symlink"), "{}", error);
+        assert!(error.contains("gone.py"), "{}", error);
+        let error = validate_script_path(&looped, ScriptExtensions::default()).unwrap_err();
+        assert!(error.contains("symlink loop"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unreadable_and_world_writable_scripts_are_reported() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("permissions");
+        let locked = dir.join("locked");
+        std::fs::create_dir_all(&locked).unwrap();
+        let shared = dir.join("shared.py");
+        let secret = dir.join("secret.py");
+        let hidden = locked.join("inside.py");
+        for path in [&shared, &secret, &hidden] {
+            std::fs::write(path, "print('hi')\n").unwrap();
+        }
+        let chmod = |path: &Path, mode: u32| {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        chmod(&shared, 0o666);
+        chmod(&secret, 0o000);
+        chmod(&locked, 0o000);
+
+        let validation = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: shared.to_string_lossy().to_string(),
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        assert!(validation.valid, "{:?}", validation.message);
+        assert_eq!(validation.warnings.len(), 1, "{:?}", validation.warnings);
+        assert!(validation.warnings[0].contains("world-writable"));
+
+        // Root reads everything, so only the warning can be checked there.
+        if unsafe { libc::geteuid() } != 0 {
+            let error =
+                validate_script_path(&secret.to_string_lossy(), ScriptExtensions::default())
+                    .unwrap_err();
+            assert_eq!(
+                error,
+                format!("permission denied reading {}", secret.display())
+            );
+            let error =
+                validate_script_path(&hidden.to_string_lossy(), ScriptExtensions::default())
+                    .unwrap_err();
+            assert_eq!(
+                error,
+                format!(
+                    "permission denied: cannot enter directory {} to reach {}",
+                    locked.display(),
+                    hidden.display()
+                )
+            );
+        }
+        chmod(&locked, 0o755);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn script_arguments_are_read_without_running_the_script() {
+        let marker = temp_dir("args-marker").join("marker");
+        let declared = temp_script(
+            "declared_arguments.py",
+            &format!(
+                "import argparse\nopen({:?}, 'w').close()\nDAYS = 3\nparser = argparse.ArgumentParser()\nparser.add_argument('city', help='City name')\nparser.add_argument('-d', '--days', type=int, default=DAYS)\nparser.add_argument('--dry-run', action='store_true')\nparser.add_argument('--units', required=True, default='metric')\nparser.parse_args()\n",
+                marker.to_string_lossy()
+            ),
+        );
+        let dynamic = temp_script(
+            "dynamic_arguments.py",
+            "import argparse\nparser = argparse.ArgumentParser(prog='dyn')\nfor flag in ['--alpha']:\n    parser.add_argument(flag)\nparser.parse_args()\n",
+        );
+        let plain = temp_script("no_arguments.py", "print('hi')\n");
+        let interpreters = InterpreterInfoCache::default();
+        let arguments = |script_path: String| {
+            script_arguments(
+                GetScriptArgumentsRequest {
+                    script_path,
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+
+        let response = arguments(declared).await.unwrap();
+        assert!(!marker.exists());
+        assert_eq!(response.help_text, None);
+        let summary: Vec<(&str, bool, Option<&serde_json::Value>)> = response
+            .arguments
+            .iter()
+            .map(|argument| {
+                (
+                    argument.name.as_str(),
+                    argument.required,
+                    argument.default.as_ref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("city", true, None),
+                ("days", false, None),
+                ("dry_run", false, Some(&serde_json::json!(false))),
+                ("units", true, Some(&serde_json::json!("metric"))),
+            ]
+        );
+        assert_eq!(response.arguments[0].help.as_deref(), Some("City name"));
+        assert_eq!(response.arguments[1].flags, ["-d", "--days"]);
+
+        let response = arguments(dynamic).await.unwrap();
+        assert!(response.arguments.is_empty());
+        assert!(response.help_text.unwrap().contains("--alpha"));
+
+        let response = arguments(plain).await.unwrap();
+        assert!(response.arguments.is_empty() && response.help_text.is_none());
+    }
+
+    #[tokio::test]
+    async fn validation_checks_module_importability() {
+        let interpreters = InterpreterInfoCache::default();
+        let validate = |module: &str| {
+            validate_script(
+                ValidatePythonScriptRequest {
+                    module: Some(module.to_string()),
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+
+        let valid = validate("json").await.unwrap();
+        assert!(valid.valid);
+        assert_eq!(valid.interpreter_info.unwrap().version_info.0, 3);
+        let missing = validate("pdd_definitely_missing_module").await.unwrap();
+        assert!(!missing.valid);
+        assert!(missing.message.unwrap().contains("not importable"));
+    }
+
+    #[tokio::test]
+    async fn validation_can_check_syntax_without_leaving_bytecode() {
+        let interpreters = InterpreterInfoCache::default();
+        let broken = temp_script("broken_syntax.py", "import os\nprint(\n");
+        let validate = |script_path: &str, check_syntax: bool| {
+            validate_script(
+                ValidatePythonScriptRequest {
+                    script_path: script_path.to_string(),
+                    check_syntax,
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+
+        assert!(validate(&broken, false).await.unwrap().valid);
+        let checked = validate(&broken, true).await.unwrap();
+        assert!(!checked.valid && !checked.syntax_ok);
+        let error = checked.syntax_error.unwrap();
+        assert_eq!(error.line, Some(2));
+        assert!(error.message.contains("never closed"), "{}", error.message);
+        assert!(checked
+            .message
+            .unwrap()
+            .starts_with("syntax error at line 2"));
+
+        let dir = temp_dir("syntax");
+        let fine = dir.join("fine_syntax.py");
+        std::fs::write(&fine, "print('ok')\n").unwrap();
+        let checked = validate(&fine.to_string_lossy(), true).await.unwrap();
+        assert!(checked.valid && checked.syntax_ok && checked.syntax_error.is_none());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn validation_lists_missing_imports_with_pip_names() {
+        let dir = temp_dir("imports");
+        std::fs::write(dir.join("pdd_local_helper.py"), "").unwrap();
+        let script = dir.join("imports.py");
+        std::fs::write(
+            &script,
+            "import json, os.path\nimport pdd_local_helper\nfrom pdd_missing_pkg import thing\nimport pdd_other_missing.sub\nfrom . import sibling\n\ndef later():\n    import pdd_only_at_runtime\n",
+        )
+        .unwrap();
+
+        let response = validate_script(
+            ValidatePythonScriptRequest {
+                script_path: script.to_string_lossy().to_string(),
+                check_imports: true,
+                ..Default::default()
+            },
+            &InterpreterInfoCache::default(),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert!(!response.valid);
+        assert_eq!(
+            response.missing_modules,
+            strings(&["pdd_missing_pkg", "pdd_other_missing"])
+        );
+        let message = response.message.unwrap();
+        assert!(
+            message.ends_with("(try `pip install pdd_missing_pkg pdd_other_missing`)"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn pip_names_cover_common_mismatches() {
+        assert_eq!(pip_package_name("PIL"), "pillow");
+        assert_eq!(pip_package_name("yaml"), "pyyaml");
+        assert_eq!(pip_package_name("requests"), "requests");
+    }
+
+    #[tokio::test]
+    async fn inline_code_runs_from_a_temp_file_that_is_removed_afterwards() {
+        let response = run_code(
+            RunPythonCodeRequest {
+                code: "import sys\nprint(__file__)\nprint(sys.argv[1])\n".to_string(),
+                args: strings(&["hello"]),
+                ..Default::default()
+            },
+            &ExecutionSettings::default(),
+            &RunRegistry::default(),
+            &RunQueue::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        let mut lines = response.stdout.lines();
+        let temp_file = lines.next().unwrap();
+        assert_eq!(lines.next(), Some("hello"));
+        assert!(!Path::new(temp_file).exists());
+    }
+
+    #[tokio::test]
+    async fn single_line_code_uses_dash_c_and_size_is_capped() {
+        let registry = RunRegistry::default();
+        let response = run_code(
+            RunPythonCodeRequest {
+                code: "print(6 * 7)".to_string(),
+                ..Default::default()
+            },
+            &ExecutionSettings::default(),
+            &registry,
+            &RunQueue::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.stdout.trim(), "42");
+
+        let oversized = RunPythonCodeRequest {
+            code: "#".repeat(MAX_INLINE_CODE_BYTES + 1),
+            ..Default::default()
+        };
+        assert!(run_code(
+            oversized,
+            &ExecutionSettings::default(),
+            &registry,
+            &RunQueue::default(),
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn gbk_output_is_decoded_with_the_requested_encoding() {
+        let script = temp_script(
+            "print_gbk.py",
+            "import sys\nsys.stdout.buffer.write(bytes([0xD6, 0xD0, 0xCE, 0xC4]))\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            output_encoding: Some("gbk".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.stdout, "\u{4e2d}\u{6587}");
+        assert_eq!(response.detected_encoding, "GBK");
+
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script,
+            output_encoding: Some("not-an-encoding".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("unsupported output_encoding"));
+    }
+
+    #[test]
+    fn json_extraction_skips_leading_log_lines() {
+        let stdout = "[INFO] fetching\nwarning: {slow}\n{\"type\": \"scalar\", \"data\": [1, 2]}\n";
+        let value = extract_json(stdout).unwrap();
+        assert_eq!(value["type"], "scalar");
+
+        assert_eq!(
+            extract_json("  [1, 2]  ").unwrap(),
+            serde_json::json!([1, 2])
+        );
+        assert_eq!(
+            extract_json("{\"a\": 1}\ntrailing noise\n").unwrap(),
+            serde_json::json!({"a": 1})
+        );
+        assert!(extract_json("no json here").is_err());
+        assert!(extract_json("").is_err());
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_reported_without_failing_the_run() {
+        let script = temp_script("not_json.py", "print('{broken')\n");
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            parse_json: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok);
+        assert!(response.data.is_none());
+        assert!(response.parse_error.unwrap().contains("not valid JSON"));
+    }
+
+    #[tokio::test]
+    async fn combined_output_keeps_cross_stream_order() {
+        let script = temp_script(
+            "interleaved.py",
+            "import sys, time\n\
+             print('before', flush=True)\n\
+             time.sleep(0.1)\n\
+             print('boom', file=sys.stderr, flush=True)\n\
+             time.sleep(0.1)\n\
+             print('after', flush=True)\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            capture_combined: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(response.stdout.trim(), "before\nafter");
+        let combined = response.combined_output.unwrap();
+        let seen: Vec<_> = combined
+            .iter()
+            .map(|line| (line.stream, line.text.as_str()))
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                (OutputStream::Stdout, "before"),
+                (OutputStream::Stderr, "boom"),
+                (OutputStream::Stdout, "after"),
+            ]
+        );
+        assert!(combined[0].offset_ms <= combined[2].offset_ms);
+    }
+
+    #[tokio::test]
+    async fn runs_wait_for_a_slot_and_report_queue_time() {
+        let script = temp_script("queued.py", "print('ran')\n");
+        let queue = RunQueue::with_max(1);
+        let request = || RunPythonScriptRequest {
+            script_path: script.clone(),
+            queue_timeout_ms: Some(100),
+            ..Default::default()
+        };
+
+        let slot = queue.acquire(Priority::Normal, None).await.unwrap();
+        let error = run_script(
+            request(),
+            &RunRegistry::default(),
+            &queue,
+            EventSinks::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.contains("queue full"), "{}", error);
+        assert!(error.starts_with("run run-"), "{}", error);
+
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(slot);
+        });
+        let response = run_script(
+            request(),
+            &RunRegistry::default(),
+            &queue,
+            EventSinks::default(),
+        )
+        .await
+        .unwrap();
+        release.await.unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert!(response.queued_ms >= 40, "{}", response.queued_ms);
+    }
+
+    #[tokio::test]
+    async fn failing_runs_are_retried_and_keep_the_failed_stderr() {
+        let marker = temp_dir("retry").join("marker");
+        let script = temp_script(
+            "flaky.py",
+            "import os, sys\n\
+             marker = sys.argv[1]\n\
+             if not os.path.exists(marker):\n\
+             \x20   open(marker, 'w').close()\n\
+             \x20   sys.exit('transient failure')\n\
+             print('recovered')\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            args: vec![marker.to_string_lossy().to_string()],
+            retries: Some(2),
+            retry_delay_ms: Some(10),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let _ = std::fs::remove_file(&marker);
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.attempts, 2);
+        assert_eq!(response.stdout.trim(), "recovered");
+        assert!(response
+            .retried_stderr
+            .unwrap()
+            .contains("transient failure"));
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_the_limit_and_skip_timeouts() {
+        let failing = temp_script("always_fails.py", "raise SystemExit(3)\n");
+        let response = run_request(RunPythonScriptRequest {
+            script_path: failing,
+            retries: Some(2),
+            retry_delay_ms: Some(10),
+            retry_backoff: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.exit_code, Some(3));
+        assert_eq!(response.attempts, 3);
+
+        let slow = temp_script("retry_sleeps.py", "import time\ntime.sleep(30)\n");
+        let response = run_request(RunPythonScriptRequest {
+            script_path: slow,
+            timeout_ms: Some(1_000),
+            retries: Some(3),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(response.timed_out);
+        assert_eq!(response.attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn background_runs_report_status_and_keep_their_result() {
+        let script = temp_script(
+            "background.py",
+            "import time\ntime.sleep(0.3)\nprint('indexed')\n",
+        );
+        let registry = RunRegistry::default();
+        let started = start_script(
+            RunPythonScriptRequest {
+                script_path: script,
+                ..Default::default()
+            },
+            &registry,
+            &RunQueue::default(),
+            EventSinks::default(),
+        )
+        .unwrap();
+
+        assert_eq!(registry.state(&started.run_id), Ok(RunState::Running));
+        assert!(registry.result(&started.run_id).is_err());
+
+        while registry.state(&started.run_id) == Ok(RunState::Running) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(registry.state(&started.run_id), Ok(RunState::Finished));
+        let response = registry.result(&started.run_id).unwrap();
+        assert_eq!(response.run_id, started.run_id);
+        assert_eq!(response.stdout.trim(), "indexed");
+        // Fetching does not consume the result.
+        assert!(registry.result(&started.run_id).is_ok());
+
+        let invalid = start_script(
+            RunPythonScriptRequest::default(),
+            &registry,
+            &RunQueue::default(),
+            EventSinks::default(),
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[tokio::test]
+    async fn background_starts_share_min_interval_with_runs_by_canonical_path() {
+        let script = temp_script("rate_limited_background.py", "print('once')\n");
+        let path = Path::new(&script);
+        let dir = path.parent().unwrap();
+        let name = path.file_name().unwrap().to_string_lossy();
+        let roundabout = dir.join(".").join(&*name).to_string_lossy().to_string();
+        let mut settings = ExecutionSettings::default();
+        settings.min_interval_ms.insert(roundabout.clone(), 60_000);
+        let request = |script_path: &str| {
+            let mut request = RunPythonScriptRequest {
+                script_path: script_path.to_string(),
+                ..Default::default()
+            };
+            apply_settings(&mut request, &settings);
+            request
+        };
+        assert_eq!(request(&script).min_interval_ms, Some(60_000));
+
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+        let started =
+            start_script(request(&script), &registry, &queue, EventSinks::default()).unwrap();
+        assert!(!started.run_id.is_empty());
+        assert_eq!(started.error_kind, None);
+
+        let refused = start_script(
+            request(&roundabout),
+            &registry,
+            &queue,
+            EventSinks::default(),
+        )
+        .unwrap();
+        assert!(refused.run_id.is_empty());
+        assert_eq!(refused.error_kind.as_deref(), Some("rate_limited"));
+        assert!(refused.rate_limited_until_ms.is_some());
+
+        let refused = run_limited(
+            request(&roundabout),
+            &registry,
+            &queue,
+            EventSinks::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(refused.error_kind.as_deref(), Some("rate_limited"));
+
+        // An unresolvable path is an error and leaves no interval behind.
+        let missing = dir
+            .join("rate_limited_missing.py")
+            .to_string_lossy()
+            .to_string();
+        let mut failing = request(&missing);
+        failing.min_interval_ms = Some(60_000);
+        assert!(start_script(failing, &registry, &queue, EventSinks::default()).is_err());
+        assert!(registry
+            .rate_limiter()
+            .acquire(&missing, 60_000, unix_time_ms())
+            .is_ok());
+
+        while registry.state(&started.run_id) == Ok(RunState::Running) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_runs_do_not_use_up_min_interval() {
+        let script = temp_script("rate_limited_rejected.py", "print('once')\n");
+        let request = |interpreter_args: &[&str]| RunPythonScriptRequest {
+            script_path: script.clone(),
+            interpreter_args: strings(interpreter_args),
+            min_interval_ms: Some(60_000),
+            ..Default::default()
+        };
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+
+        let rejected = start_script(
+            request(&["not-a-flag"]),
+            &registry,
+            &queue,
+            EventSinks::default(),
+        );
+        assert!(rejected.unwrap_err().contains("interpreter_args"));
+        let started = start_script(request(&[]), &registry, &queue, EventSinks::default()).unwrap();
+        assert_eq!(started.error_kind, None);
+        assert!(!started.run_id.is_empty());
+        while registry.state(&started.run_id) == Ok(RunState::Running) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let registry = RunRegistry::default();
+        let rejected = run_limited(
+            request(&["not-a-flag"]),
+            &registry,
+            &queue,
+            EventSinks::default(),
+        )
+        .await;
+        assert!(rejected.unwrap_err().contains("interpreter_args"));
+        let ran = run_limited(request(&[]), &registry, &queue, EventSinks::default())
+            .await
+            .unwrap();
+        assert_eq!(ran.error_kind, None);
+        assert_eq!(ran.stdout.trim(), "once");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn peak_memory_and_cpu_time_are_reported() {
+        let script = temp_script(
+            "hungry.py",
+            "import time\n\
+             block = bytearray(64 * 1024 * 1024)\n\
+             end = time.process_time() + 0.3\n\
+             while time.process_time() < end:\n\
+             \x20   pass\n\
+             time.sleep(0.5)\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert!(response.peak_memory_bytes.unwrap() >= 64 * 1024 * 1024);
+        assert!(response.cpu_time_ms.unwrap() >= 250);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn exceeding_the_memory_limit_is_reported() {
+        let request = |source: &str, name: &str| RunPythonScriptRequest {
+            script_path: temp_script(name, source),
+            max_memory_bytes: Some(256 * 1024 * 1024),
+            retries: Some(2),
+            ..Default::default()
+        };
+
+        let response = run_request(request("block = bytearray(1 << 30)\n", "greedy.py"))
+            .await
+            .unwrap();
+        assert!(!response.ok);
+        assert!(response.oom_killed, "{}", response.stderr);
+        assert_eq!(response.attempts, 1);
+
+        let response = run_request(request("print('fits')\n", "modest.py"))
+            .await
+            .unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert!(!response.oom_killed);
+
+        assert!(run_request(RunPythonScriptRequest {
+            max_memory_bytes: Some(1024),
+            ..request("print('tiny')\n", "tiny.py")
+        })
+        .await
+        .is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn crashes_well_under_the_memory_limit_are_not_blamed_on_it() {
+        let response = run_request(RunPythonScriptRequest {
+            script_path: temp_script(
+                "segfault_under_limit.py",
+                "import os, signal\nos.kill(os.getpid(), signal.SIGSEGV)\n",
+            ),
+            max_memory_bytes: Some(512 * 1024 * 1024),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.signal, Some(libc::SIGSEGV));
+        assert!(!response.oom_killed);
+        assert_eq!(response.error_kind.as_deref(), Some("crashed"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn busy_loops_hit_the_cpu_limit_before_the_timeout() {
+        let script = temp_script("spin.py", "while True:\n    pass\n");
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            timeout_ms: Some(30_000),
+            max_cpu_time_ms: Some(1_000),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.cpu_limit_exceeded);
+        assert!(!response.timed_out);
+        assert!(!response.ok);
+        assert!(response.duration_ms < 10_000);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn low_priority_runs_are_niced_and_batch_scheduled() {
+        let script = temp_script(
+            "niceness.py",
+            "import os\nprint(os.nice(0), os.sched_getscheduler(0) == os.SCHED_BATCH)\n",
+        );
+        // SAFETY: reads this process's own niceness.
+        let own_nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+
+        let mut request = RunPythonScriptRequest {
+            script_path: script,
+            ..Default::default()
+        };
+        let settings = ExecutionSettings {
+            low_priority: true,
+            ..Default::default()
+        };
+        apply_settings(&mut request, &settings);
+        let response = run_request(request).await.unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(
+            response.stdout.trim(),
+            format!("{} True", (own_nice + 10).min(19))
+        );
+    }
+
+    #[tokio::test]
+    async fn scratch_dirs_are_removed_unless_kept() {
+        let script = temp_script(
+            "use_scratch.py",
+            "import os, sys, time\nscratch = os.environ['PDD_SCRATCH_DIR']\nopen(os.path.join(scratch, 'part.csv'), 'w').close()\nprint(scratch, flush=True)\nif sys.argv[1:] == ['slow']:\n    time.sleep(30)\n",
+        );
+        let run = |args: &[&str], keep_scratch: bool| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                args: strings(args),
+                keep_scratch,
+                timeout_ms: Some(1_000),
+                ..Default::default()
+            })
+        };
+
+        let response = run(&[], false).await.unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert!(response.scratch_dir.is_none());
+        assert!(!Path::new(response.stdout.trim()).exists());
+
+        let slow = run(&["slow"], false).await.unwrap();
+        assert!(slow.timed_out);
+        assert!(!Path::new(slow.stdout.trim()).exists());
+
+        let kept = run(&[], true).await.unwrap();
+        let scratch = PathBuf::from(kept.scratch_dir.unwrap());
+        assert_eq!(scratch, Path::new(kept.stdout.trim()));
+        assert!(scratch.join("part.csv").exists());
+        std::fs::remove_dir_all(scratch).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn scratch_cleanup_does_not_follow_a_swapped_in_symlink() {
+        let victim = temp_dir("scratch-victim");
+        std::fs::write(victim.join("important.txt"), "keep me").unwrap();
+        let script = temp_script(
+            "swap_scratch.py",
+            &format!(
+                "import os\nscratch = os.environ['PDD_SCRATCH_DIR']\nos.rmdir(scratch)\nos.symlink({:?}, scratch)\nprint(scratch)\n",
+                victim.display().to_string()
+            ),
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert!(std::fs::symlink_metadata(response.stdout.trim()).is_err());
+        assert!(victim.join("important.txt").exists());
+        std::fs::remove_dir_all(victim).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stop_on_pattern_ends_the_run_after_the_marker() {
+        let marker_file = temp_dir("after-marker").join("marker");
+        let script = temp_script(
+            "stop_marker.py",
+            &format!(
+                "import sys, time\nprint('row 1')\nprint('DONE', flush=True)\ntime.sleep(30)\nopen({:?}, 'w').close()\n",
+                marker_file.display().to_string()
+            ),
+        );
+
+        let started = Instant::now();
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            stop_on_pattern: Some("^DONE$".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert!(response.stopped_on_pattern);
+        assert_eq!(response.exit_code, None);
+        assert_eq!(response.stdout, format!("row 1{}DONE{}", NEWLINE, NEWLINE));
+        assert_eq!(
+            response.termination_reason.as_deref(),
+            Some("stopped on pattern")
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!marker_file.exists());
+    }
+
+    #[tokio::test]
+    async fn termination_reason_explains_how_a_run_ended() {
+        let run = |name: &str, source: &str, timeout_ms: Option<u64>| {
+            let script_path = temp_script(name, source);
+            run_request(RunPythonScriptRequest {
+                script_path,
+                timeout_ms,
+                ..Default::default()
+            })
+        };
+
+        let clean = run("clean_exit.py", "print('ok')\n", None).await.unwrap();
+        assert_eq!(clean.termination_reason, None);
+
+        let failed = run("exit_two.py", "raise SystemExit(2)\n", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            failed.termination_reason.as_deref(),
+            Some("exited with code 2")
+        );
+
+        let slow = run(
+            "reason_timeout.py",
+            "import time\ntime.sleep(30)\n",
+            Some(1_000),
+        )
+        .await
+        .unwrap();
+        assert_eq!(slow.termination_reason.as_deref(), Some("timed out"));
+
+        #[cfg(unix)]
+        {
+            let killed = run(
+                "self_kill.py",
+                "import os, signal\nos.kill(os.getpid(), signal.SIGKILL)\n",
+                None,
+            )
+            .await
+            .unwrap();
+            assert_eq!(killed.signal, Some(libc::SIGKILL));
+            assert_eq!(
+                killed.termination_reason.as_deref(),
+                Some("killed by SIGKILL")
+            );
+            assert!(!killed.crashed);
+
+            let crashed = run(
+                "segfault.py",
+                "import faulthandler, os, signal\nfaulthandler.enable()\nos.kill(os.getpid(), signal.SIGSEGV)\n",
+                None,
+            )
+            .await
+            .unwrap();
+            assert!(crashed.crashed);
+            assert_eq!(crashed.error_kind.as_deref(), Some("crashed"));
+            assert_eq!(
+                crashed.termination_reason.as_deref(),
+                Some("crashed: segmentation fault (SIGSEGV)")
+            );
+            assert!(
+                crashed.crash_report.as_deref().is_some_and(
+                    |report| report.starts_with("Fatal Python error: Segmentation fault")
+                )
+            );
+        }
+    }
+
+    /// An empty directory of this test process's own, removing what an
+    /// earlier run left in it.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pdd-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn temp_script(name: &str, source: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("pdd-commands-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, source).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    const PRINT_ENV_SCRIPT: &str = r#"
+import json, os
+print(json.dumps({key: os.environ.get(key) for key in ("PDD_TEST_TOKEN", "PDD_TEST_INHERITED")}))
+"#;
+
+    /// Set in the child process `rerun_with_env` starts.
+    const CHILD_TEST_ENV: &str = "PDD_CHILD_TEST";
+
+    /// Runs the test `name` again, alone, in a child test process that has
+    /// `vars` in its environment, and checks that it passed. Inherited
+    /// values are tested that way rather than with `set_var`, which would
+    /// leak into the interpreters other tests spawn meanwhile. `true` in the
+    /// child, which then does the actual checks.
+    fn rerun_with_env(name: &str, vars: &[(&str, &str)]) -> bool {
+        if std::env::var_os(CHILD_TEST_ENV).is_some() {
+            return true;
+        }
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([name, "--exact", "--test-threads=1"])
+            .env(CHILD_TEST_ENV, "1")
+            .envs(vars.iter().copied())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success() && stdout.contains("1 passed"),
+            "{}\n{}",
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        false
+    }
+
+    fn env_request(script_path: String, inherit_env: Option<bool>) -> RunPythonScriptRequest {
+        RunPythonScriptRequest {
+            script_path,
+            env: Some(HashMap::from([(
+                "PDD_TEST_TOKEN".to_string(),
+                "from-request".to_string(),
+            )])),
+            inherit_env,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn hidden_console_runs_still_capture_both_streams() {
+        let script = temp_script(
+            "console_streams.py",
+            "import sys\nprint('out')\nprint('err', file=sys.stderr)\n",
+        );
+        for show_console in [None, Some(true)] {
+            let response = run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                show_console,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+            assert!(response.ok, "{}", response.stderr);
+            assert_eq!(response.stdout.trim(), "out");
+            assert_eq!(response.stderr.trim(), "err");
+        }
+    }
+
+    #[tokio::test]
+    async fn unicode_output_and_stdin_round_trip() {
+        let script = temp_script(
+            "print_unicode.py",
+            "import sys\nprint('\\u2603 \\u4e2d\\u6587\\u5b57')\nprint(sys.stdin.read(), end='')\nprint(sys.stdout.encoding, file=sys.stderr)\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            stdin: Some("\u{1F600} \u{65E5}\u{672C}\n".to_string()),
+            utf8_io: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(
+            response.stdout.as_bytes(),
+            "\u{2603} \u{4E2D}\u{6587}\u{5B57}\n\u{1F600} \u{65E5}\u{672C}\n".as_bytes()
+        );
+        assert_eq!(response.stderr.trim(), "utf-8");
+    }
+
+    #[tokio::test]
+    async fn request_env_overrides_inherited_values() {
+        if !rerun_with_env(
+            "commands::tests::request_env_overrides_inherited_values",
+            &[
+                ("PDD_TEST_TOKEN", "from-parent"),
+                ("PDD_TEST_INHERITED", "parent-only"),
+            ],
+        ) {
+            return;
+        }
+        let script = temp_script("print_env_inherit.py", PRINT_ENV_SCRIPT);
+
+        let response = run_request(env_request(script, None)).await.unwrap();
+        let values: serde_json::Value = serde_json::from_str(response.stdout.trim()).unwrap();
+
+        assert!(response.ok);
+        assert_eq!(values["PDD_TEST_TOKEN"], "from-request");
+        assert_eq!(values["PDD_TEST_INHERITED"], "parent-only");
+    }
 
     #[test]
-    fn custom_python_path_has_highest_priority() {
-        let candidates = python_candidates(&Some("/custom/python".to_string()));
-        assert_eq!(candidates.len(), 1);
-        assert_eq!(candidates[0].program, "/custom/python".to_string());
+    fn request_env_is_applied_alike_for_launcher_and_path_candidates() {
+        let envs = |program: &str, pre_args: &[&str], request: &RunPythonScriptRequest| {
+            let mut command = Command::new(program);
+            command.args(pre_args);
+            apply_request_env(&mut command, request, None, &[]);
+            command
+                .as_std()
+                .get_envs()
+                .map(|(key, value)| {
+                    let value = value.map(|value| value.to_string_lossy().to_string());
+                    (key.to_string_lossy().to_string(), value)
+                })
+                .collect::<HashMap<String, Option<String>>>()
+        };
+        let overriding = RunPythonScriptRequest {
+            strip_env: strings(&["PDD_TEST_STRIPPED"]),
+            utf8_io: Some(true),
+            ..env_request(String::new(), None)
+        };
+        let cleaned = RunPythonScriptRequest {
+            clean_env: true,
+            ..env_request(String::new(), None)
+        };
+        // `py -3` on Windows, an interpreter path on Unix.
+        for (program, pre_args) in [("py", &["-3"][..]), ("/usr/bin/python3", &[][..])] {
+            let set = envs(program, pre_args, &overriding);
+            assert_eq!(set["PDD_TEST_TOKEN"].as_deref(), Some("from-request"));
+            assert_eq!(set["PDD_TEST_STRIPPED"], None);
+            assert_eq!(set["PYTHONIOENCODING"].as_deref(), Some("utf-8"));
+            // Anything not mentioned is inherited untouched.
+            assert!(!set.contains_key("PDD_TEST_INHERITED"));
+
+            let set = envs(program, pre_args, &cleaned);
+            assert_eq!(set["PDD_TEST_TOKEN"].as_deref(), Some("from-request"));
+            assert_eq!(
+                set.contains_key("PYTHONIOENCODING"),
+                cfg!(windows),
+                "{:?}",
+                set
+            );
+            for key in set.keys() {
+                assert!(
+                    CLEAN_ENV_KEEP.contains(&key.as_str())
+                        || ["PDD_TEST_TOKEN", "PYTHONIOENCODING"].contains(&key.as_str()),
+                    "{}",
+                    key
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn clean_env_keeps_only_the_basics_and_warns_about_startup_vars() {
+        if !rerun_with_env(
+            "commands::tests::clean_env_keeps_only_the_basics_and_warns_about_startup_vars",
+            &[
+                ("PDD_TEST_INHERITED", "parent-only"),
+                ("PYTHONSTARTUP", "/tmp/pdd-stray-startup.py"),
+            ],
+        ) {
+            return;
+        }
+        let script = temp_script(
+            "print_env_clean.py",
+            "import json, os\nprint(json.dumps({key: os.environ.get(key) for key in ('PDD_TEST_TOKEN', 'PDD_TEST_INHERITED', 'PYTHONSTARTUP', 'PATH')}))\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            clean_env: true,
+            ..env_request(script.clone(), None)
+        })
+        .await
+        .unwrap();
+        let values: serde_json::Value = serde_json::from_str(response.stdout.trim()).unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(values["PDD_TEST_TOKEN"], "from-request");
+        assert!(values["PDD_TEST_INHERITED"].is_null());
+        assert!(values["PYTHONSTARTUP"].is_null());
+        assert!(values["PATH"].is_string());
+        assert_eq!(
+            response.warnings,
+            ["removed PYTHONSTARTUP inherited from the app's environment"]
+        );
+
+        let mut request = env_request(script.clone(), None);
+        apply_settings(
+            &mut request,
+            &ExecutionSettings {
+                strip_env: strings(&["PDD_TEST_INHERITED"]),
+                ..Default::default()
+            },
+        );
+        let response = run_request(request).await.unwrap();
+        let values: serde_json::Value = serde_json::from_str(response.stdout.trim()).unwrap();
+        assert!(values["PDD_TEST_INHERITED"].is_null());
+        assert_eq!(values["PYTHONSTARTUP"], "/tmp/pdd-stray-startup.py");
+        assert_eq!(
+            response.warnings,
+            ["removed PDD_TEST_INHERITED inherited from the app's environment"]
+        );
+
+        let error = run_request(RunPythonScriptRequest {
+            clean_env: true,
+            ..env_request(script, Some(false))
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("clean_env"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn extra_python_paths_are_prepended_to_pythonpath() {
+        let script = temp_script(
+            "print_pythonpath.py",
+            "import os\nprint(os.environ['PYTHONPATH'])\n",
+        );
+        let helpers = Path::new(&script).parent().unwrap().to_path_buf();
+        let dir = temp_dir("extra-python-paths");
+        let existing = dir.join("existing");
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            env: Some(HashMap::from([(
+                "PYTHONPATH".to_string(),
+                existing.display().to_string(),
+            )])),
+            extra_python_paths: vec![helpers.display().to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        let entries: Vec<PathBuf> = std::env::split_paths(response.stdout.trim()).collect();
+        assert_eq!(entries, [helpers, existing]);
+
+        let missing = dir.join("missing-helpers");
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script,
+            extra_python_paths: vec![missing.display().to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("missing-helpers"), "{}", error);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn extra_path_dirs_go_in_front_of_the_login_shell_path() {
+        let script = temp_script("print_path.py", "import os\nprint(os.environ['PATH'])\n");
+        let tools = Path::new(&script).parent().unwrap().to_path_buf();
+        let app_path = std::env::var_os("PATH").unwrap_or_default();
+        let shell_path =
+            std::env::join_paths(std::env::split_paths(&app_path).chain([tools.clone()])).unwrap();
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            extra_path_dirs: vec![tools.display().to_string()],
+            shell_path: Some(shell_path.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        let entries: Vec<PathBuf> = std::env::split_paths(response.stdout.trim()).collect();
+        // Shims such as pyenv's put their own directories first.
+        let position = |dir: &PathBuf| entries.iter().position(|entry| entry == dir);
+        let first_app_entry = std::env::split_paths(&app_path)
+            .find_map(|dir| position(&dir))
+            .unwrap();
+        assert!(position(&tools).unwrap() < first_app_entry, "{:?}", entries);
+        assert_eq!(entries.iter().filter(|entry| **entry == tools).count(), 1);
+        let head = response.path_head.unwrap();
+        assert_eq!(head[0], tools.display().to_string());
+        assert!(head.len() <= PATH_HEAD_LEN);
+        assert!(response
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("login shell's PATH")));
+        assert!(response
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("now searched first")));
+
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script,
+            extra_path_dirs: vec!["relative/tools".to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("extra_path_dirs"), "{}", error);
+    }
+
+    const ECHO_PAYLOAD_SCRIPT: &str = r#"
+import json, os
+if "PDD_PAYLOAD_JSON" in os.environ:
+    payload, source = json.loads(os.environ["PDD_PAYLOAD_JSON"]), "env"
+else:
+    with open(os.environ["PDD_PAYLOAD_FILE"], encoding="utf-8") as file:
+        payload, source = json.load(file), os.environ["PDD_PAYLOAD_FILE"]
+print(json.dumps({"city": payload["config"]["city"], "source": source}))
+"#;
+
+    #[tokio::test]
+    async fn json_payload_reaches_the_script_through_env_or_file() {
+        let script = temp_script("echo_payload.py", ECHO_PAYLOAD_SCRIPT);
+        let run = |payload: serde_json::Value| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                parse_json: Some(true),
+                json_payload: Some(payload),
+                ..Default::default()
+            })
+        };
+
+        let small = run(serde_json::json!({ "config": { "city": "Paris" } }))
+            .await
+            .unwrap();
+        let data = small.data.expect("stdout should be JSON");
+        assert_eq!(data["city"], "Paris");
+        assert_eq!(data["source"], "env");
+
+        let padding = "x".repeat(MAX_ENV_PAYLOAD_BYTES);
+        let large = run(serde_json::json!({ "config": { "city": "Oslo" }, "padding": padding }))
+            .await
+            .unwrap();
+        let data = large.data.expect("stdout should be JSON");
+        assert_eq!(data["city"], "Oslo");
+        let file = data["source"].as_str().unwrap();
+        assert!(!Path::new(file).exists(), "payload file was not removed");
+    }
+
+    #[tokio::test]
+    async fn args_are_expanded_before_spawning() {
+        let script = temp_script(
+            "echo_args.py",
+            "import json, sys\nprint(json.dumps(sys.argv[1:]))\n",
+        );
+        let script_dir = Path::new(&script).parent().unwrap().display().to_string();
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            args: strings(&["{{script_dir}}", "{{{{literal", "{{date:%Y}}"]),
+            parse_json: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(
+            response.expanded_args[..2],
+            [script_dir, "{{literal".to_string()]
+        );
+        assert_eq!(response.expanded_args[2].len(), 4);
+        assert_eq!(
+            response.data,
+            Some(serde_json::json!(response.expanded_args))
+        );
+
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script,
+            args: strings(&["{{nope}}"]),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("unknown placeholder"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn timeouts_above_the_ceiling_are_lowered_with_a_warning() {
+        let script = temp_script("quick_timeout.py", "print('ok')\n");
+        let run = |timeout_ms: u64, settings: &ExecutionSettings| {
+            let mut request = RunPythonScriptRequest {
+                script_path: script.clone(),
+                timeout_ms: Some(timeout_ms),
+                ..Default::default()
+            };
+            apply_settings(&mut request, settings);
+            run_request(request)
+        };
+
+        let defaults = ExecutionSettings::default();
+        let response = run(600_000, &defaults).await.unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(
+            response.warnings,
+            ["timeout_ms 600000 exceeds the maximum of 120000 ms; the run was limited to 120000 ms"]
+        );
+        assert!(run(60_000, &defaults).await.unwrap().warnings.is_empty());
+
+        let error = run(500, &defaults).await.unwrap_err();
+        assert!(error.contains("below the minimum of 1000 ms"), "{}", error);
+
+        let raised = ExecutionSettings {
+            max_timeout_ms: 900_000,
+            ..Default::default()
+        };
+        assert!(raised.validate().is_ok());
+        assert!(run(600_000, &raised).await.unwrap().warnings.is_empty());
+        assert!(ExecutionSettings {
+            min_timeout_ms: 5_000,
+            max_timeout_ms: 1_000,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn silent_scripts_are_killed_after_the_idle_timeout() {
+        let script = temp_script(
+            "stall_after_output.py",
+            "import sys, time\nfor _ in range(4):\n    print('tick', flush=True)\n    time.sleep(0.3)\ntime.sleep(30)\n",
+        );
+
+        let started = Instant::now();
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            timeout_ms: Some(30_000),
+            idle_timeout_ms: Some(700),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.idle_timed_out);
+        assert!(!response.timed_out);
+        assert!(!response.ok);
+        assert_eq!(response.termination_reason.as_deref(), Some("idle_timeout"));
+        // Output kept resetting the timer, so all ticks made it out.
+        assert_eq!(response.stdout.matches("tick").count(), 4);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn progress_lines_become_events_instead_of_output() {
+        let script = temp_script(
+            "report_progress.py",
+            "for pct in (10, 60):\n    print('##PDD-PROGRESS {\"pct\": %d, \"msg\": \"step\"}' % pct)\nprint('##PDD-PROGRESS oops')\nprint('{\"value\": 1}')\n",
+        );
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sinks = EventSinks {
+            progress: Some(Arc::new({
+                let events = events.clone();
+                move |event: crate::progress::ProgressEvent| events.lock().unwrap().push(event)
+            })),
+            ..Default::default()
+        };
+
+        let response = run_script(
+            RunPythonScriptRequest {
+                script_path: script,
+                parse_json: Some(true),
+                ..Default::default()
+            },
+            &RunRegistry::default(),
+            &RunQueue::default(),
+            sinks,
+        )
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.progress_events, 2);
+        assert_eq!(response.progress.unwrap().percent, Some(60.0));
+        assert!(response.stdout.starts_with("##PDD-PROGRESS oops"));
+        assert_eq!(response.data, Some(serde_json::json!({ "value": 1 })));
+        let events = events.lock().unwrap();
+        let percents: Vec<_> = events.iter().map(|event| event.percent).collect();
+        assert_eq!(percents, [Some(10.0), Some(60.0)]);
+        assert!(events.iter().all(|event| event.run_id == response.run_id));
+    }
+
+    #[tokio::test]
+    async fn filtered_lines_are_dropped_and_counted() {
+        let script = temp_script(
+            "noisy_output.py",
+            "import sys\nprint('DeprecationWarning: old api', file=sys.stderr)\nprint('debug: x')\nprint('[1, 2]')\nprint('real error', file=sys.stderr)\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            stdout_filter: Some("^debug:".to_string()),
+            stderr_filter: Some("DeprecationWarning".to_string()),
+            parse_json: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.stdout.trim(), "[1, 2]");
+        assert_eq!(response.stderr.trim(), "real error");
+        assert_eq!(response.filtered_line_count, 2);
+
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script,
+            stderr_filter: Some("[unclosed".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("invalid stderr_filter"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn binary_stdout_is_returned_as_base64() {
+        let script = temp_script(
+            "emit_png.py",
+            "import sys\nsys.stdout.buffer.write(bytes([0x89, 0x50, 0x4e, 0x47, 0xff, 0xfe, 0x00, 0x0a]))\nprint('tb', file=sys.stderr)\n",
+        );
+        let run = |max_output_bytes: Option<u64>| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                output_format: Some("binary".to_string()),
+                max_output_bytes,
+                ..Default::default()
+            })
+        };
+
+        let response = run(None).await.unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.stdout, "");
+        let bytes = BASE64.decode(response.stdout_base64.unwrap()).unwrap();
+        assert_eq!(bytes, [0x89, 0x50, 0x4e, 0x47, 0xff, 0xfe, 0x00, 0x0a]);
+        assert_eq!(response.stderr.trim(), "tb");
+
+        let capped = run(Some(5)).await.unwrap();
+        assert!(capped.stdout_truncated);
+        let bytes = BASE64.decode(capped.stdout_base64.unwrap()).unwrap();
+        assert_eq!(bytes, [0x89, 0x50, 0x4e, 0x47, 0xff]);
+
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script,
+            output_format: Some("image".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("unsupported output_format"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn output_can_go_to_files_inside_the_output_dir() {
+        let script = temp_script(
+            "export_rows.py",
+            "import sys\nfor i in range(3):\n    print('row', i)\nprint('done', file=sys.stderr)\n",
+        );
+        let output_dir = temp_dir("output");
+        let run = |stdout_file: &str, append: bool| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                stdout_file: Some(stdout_file.to_string()),
+                stderr_file: Some("errors.log".to_string()),
+                append,
+                output_dir: Some(output_dir.clone()),
+                ..Default::default()
+            })
+        };
+
+        let response = run("rows.txt", false).await.unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.stdout, "");
+        assert_eq!(response.stderr, "");
+        let stdout_file = response.stdout_file.unwrap();
+        let expected = "row 0\nrow 1\nrow 2\n".replace('\n', NEWLINE);
+        assert_eq!(stdout_file.bytes_written, expected.len() as u64);
+        assert_eq!(
+            std::fs::read_to_string(&stdout_file.path).unwrap(),
+            expected
+        );
+        assert_eq!(
+            response.stderr_file.unwrap().bytes_written,
+            4 + NEWLINE.len() as u64
+        );
+
+        run("rows.txt", true).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&stdout_file.path).unwrap(),
+            expected.repeat(2)
+        );
+
+        for outside in ["../escape.txt", "missing/rows.txt"] {
+            let error = run(outside, false).await.unwrap_err();
+            assert!(error.contains("stdout_file"), "{}", error);
+        }
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+
+    #[tokio::test]
+    async fn cached_results_are_reused_until_invalidated() {
+        let counter = temp_dir("cache-count").join("count");
+        let script = temp_script(
+            "count_runs.py",
+            &format!(
+                "import sys\nwith open({:?}, 'a') as f:\n    f.write('x')\nprint(open({:?}).read().count('x'))\nsys.exit(int(sys.argv[1]))\n",
+                counter.display().to_string(),
+                counter.display().to_string()
+            ),
+        );
+        let cache = ResultCache::default();
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+        let run = |exit_code: &str, correlation_id: &str| {
+            run_cached(
+                RunPythonScriptRequest {
+                    script_path: script.clone(),
+                    args: strings(&[exit_code]),
+                    cache_ttl_ms: Some(60_000),
+                    correlation_id: Some(correlation_id.to_string()),
+                    ..Default::default()
+                },
+                &cache,
+                &registry,
+                &queue,
+                EventSinks::default(),
+            )
+        };
+
+        let first = run("0", "a").await.unwrap();
+        let second = run("0", "b").await.unwrap();
+        assert!(!first.from_cache);
+        assert!(second.from_cache);
+        assert_eq!(second.stdout.trim(), "1");
+        assert_eq!(second.correlation_id.as_deref(), Some("b"));
+
+        // Failures are not cached unless asked for.
+        assert_eq!(run("1", "c").await.unwrap().stdout.trim(), "2");
+        assert_eq!(run("1", "c").await.unwrap().stdout.trim(), "3");
+
+        assert_eq!(cache.invalidate(Some(&script)), 1);
+        let fresh = run("0", "d").await.unwrap();
+        assert!(!fresh.from_cache);
+        assert_eq!(fresh.stdout.trim(), "4");
+        let _ = std::fs::remove_file(counter);
+    }
+
+    #[tokio::test]
+    async fn pipeline_stages_feed_each_other_until_one_fails() {
+        let fetch = temp_script("pipeline_fetch.py", "print('{\"temp\": 21}')\n");
+        let transform = temp_script(
+            "pipeline_transform.py",
+            "import json, sys\nprint(json.load(sys.stdin)['temp'] * 2)\n",
+        );
+        let fail = temp_script(
+            "pipeline_fail.py",
+            "import sys\nsys.stdin.read()\nsys.exit('bad input')\n",
+        );
+        let stage = |script: &String| {
+            (
+                RunPythonScriptRequest {
+                    script_path: script.clone(),
+                    ..Default::default()
+                },
+                EventSinks::default(),
+            )
+        };
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+
+        let pipeline = run_pipeline(
+            vec![stage(&fetch), stage(&transform)],
+            Some(30_000),
+            &registry,
+            &queue,
+        )
+        .await
+        .unwrap();
+        assert!(pipeline.ok, "{:?}", pipeline.failed_stderr);
+        assert_eq!(pipeline.stages.len(), 2);
+        assert_eq!(pipeline.output.unwrap().stdout.trim(), "42");
+
+        let pipeline = run_pipeline(
+            vec![stage(&fetch), stage(&fail), stage(&transform)],
+            None,
+            &registry,
+            &queue,
+        )
+        .await
+        .unwrap();
+        assert!(!pipeline.ok);
+        assert_eq!(pipeline.failed_stage, Some(1));
+        assert_eq!(pipeline.stages.len(), 2);
+        assert_eq!(pipeline.stages[1].exit_code, Some(1));
+        assert!(pipeline.failed_stderr.unwrap().contains("bad input"));
+
+        let mut second = stage(&transform);
+        second.0.stdin = Some("{}".to_string());
+        let error = run_pipeline(vec![stage(&fetch), second], None, &registry, &queue)
+            .await
+            .unwrap_err();
+        assert_eq!(error, "stage 1: only the first stage may set stdin");
+    }
+
+    #[tokio::test]
+    async fn batches_report_every_entry_in_order() {
+        let script = temp_script(
+            "exit_with.py",
+            "import sys\nprint(sys.argv[1])\nsys.exit(int(sys.argv[1]))\n",
+        );
+        let entries = || -> Vec<BatchInput> {
+            vec![
+                Ok((
+                    RunPythonScriptRequest {
+                        script_path: script.clone(),
+                        args: strings(&["0"]),
+                        ..Default::default()
+                    },
+                    EventSinks::default(),
+                )),
+                Err("profile not found: missing".to_string()),
+                Ok((
+                    RunPythonScriptRequest {
+                        script_path: script.clone(),
+                        args: strings(&["3"]),
+                        ..Default::default()
+                    },
+                    EventSinks::default(),
+                )),
+            ]
+        };
+        let run = |mode, stop_on_failure, entries| async move {
+            run_batch(
+                entries,
+                mode,
+                stop_on_failure,
+                &ResultCache::default(),
+                &RunRegistry::default(),
+                &RunQueue::default(),
+            )
+            .await
+        };
+
+        let batch = run(BatchMode::Parallel, true, entries()).await;
+        assert_eq!((batch.succeeded, batch.failed, batch.skipped), (1, 2, 0));
+        let indexes: Vec<usize> = batch.results.iter().map(|entry| entry.index).collect();
+        assert_eq!(indexes, [0, 1, 2]);
+        assert_eq!(
+            batch.results[2].response.as_ref().unwrap().exit_code,
+            Some(3)
+        );
+        assert!(batch.results[1].error.is_some());
+
+        let batch = run(BatchMode::Sequential, true, entries()).await;
+        assert_eq!((batch.succeeded, batch.failed, batch.skipped), (1, 1, 1));
+        assert!(batch.results[2].skipped && batch.results[2].response.is_none());
+
+        let batch = run(BatchMode::Sequential, false, entries()).await;
+        assert_eq!((batch.succeeded, batch.failed, batch.skipped), (1, 2, 0));
+        assert!(BatchMode::parse(Some("fastest")).is_err());
+    }
+
+    #[tokio::test]
+    async fn kill_all_resolves_running_and_queued_runs_as_cancelled() {
+        let script = temp_script("sleep_long.py", "import time\ntime.sleep(30)\n");
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+        queue.set_max(1).unwrap();
+        let run = || {
+            run_script(
+                RunPythonScriptRequest {
+                    script_path: script.clone(),
+                    timeout_ms: Some(60_000),
+                    ..Default::default()
+                },
+                &registry,
+                &queue,
+                EventSinks::default(),
+            )
+        };
+
+        let (running, queued, summary) = tokio::join!(run(), run(), async {
+            while registry
+                .list_active(None)
+                .iter()
+                .all(|run| run.state != crate::runs::RunPhase::Running)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            registry.kill_all(KILL_ALL_WAIT).await
+        });
+        assert_eq!(
+            summary,
+            KillAllSummary {
+                running: 1,
+                queued: 1,
+                terminated: 1
+            }
+        );
+        for response in [running.unwrap(), queued.unwrap()] {
+            assert!(response.cancelled);
+            assert_eq!(response.error_kind.as_deref(), Some("cancelled"));
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_within_min_interval_are_refused_unless_cached() {
+        let script = temp_script("rate_limited.py", "print('fresh')\n");
+        let cache = ResultCache::default();
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+        let run = |cache_ttl_ms| {
+            run_cached(
+                RunPythonScriptRequest {
+                    script_path: script.clone(),
+                    min_interval_ms: Some(60_000),
+                    cache_ttl_ms,
+                    ..Default::default()
+                },
+                &cache,
+                &registry,
+                &queue,
+                EventSinks::default(),
+            )
+        };
+
+        let first = run(Some(60_000)).await.unwrap();
+        assert!(first.ok, "{}", first.stderr);
+        assert!(run(Some(60_000)).await.unwrap().from_cache);
+
+        let started = unix_time_ms();
+        let refused = run(None).await.unwrap();
+        assert!(!refused.ok);
+        assert!(refused.run_id.is_empty());
+        assert_eq!(refused.error_kind.as_deref(), Some("rate_limited"));
+        let until = refused.rate_limited_until_ms.unwrap();
+        assert!(until > started && until <= started + 60_000, "{}", until);
+    }
+
+    #[tokio::test]
+    async fn identical_coalesced_runs_share_one_execution() {
+        let counter = temp_dir("coalesce-count").join("count");
+        let script = temp_script(
+            "count_slow_runs.py",
+            &format!(
+                "import time\nwith open({:?}, 'a') as f:\n    f.write('x')\ntime.sleep(0.5)\nprint(open({:?}).read().count('x'))\n",
+                counter.display().to_string(),
+                counter.display().to_string()
+            ),
+        );
+        let cache = ResultCache::default();
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+        let run = |correlation_id: &str| {
+            run_cached(
+                RunPythonScriptRequest {
+                    script_path: script.clone(),
+                    coalesce: true,
+                    correlation_id: Some(correlation_id.to_string()),
+                    ..Default::default()
+                },
+                &cache,
+                &registry,
+                &queue,
+                EventSinks::default(),
+            )
+        };
+
+        let (first, second) = tokio::join!(run("a"), async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            run("b").await
+        });
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert!(!first.coalesced);
+        assert!(second.coalesced);
+        assert_eq!(first.run_id, second.run_id);
+        assert_eq!(second.stdout.trim(), "1");
+        assert_eq!(second.correlation_id.as_deref(), Some("b"));
+
+        // The finished run is gone, so the next request runs again.
+        let third = run("c").await.unwrap();
+        assert!(!third.coalesced);
+        assert_eq!(third.stdout.trim(), "2");
+        let _ = std::fs::remove_file(counter);
+    }
+
+    #[tokio::test]
+    async fn runs_that_write_elsewhere_are_not_coalesced() {
+        let script = temp_script(
+            "coalesce_sinks.py",
+            "import time\ntime.sleep(0.3)\nprint('report')\n",
+        );
+        let output_dir = temp_dir("coalesce-output");
+        let registry = RunRegistry::default();
+        let queue = RunQueue::default();
+        let run = |stdout_file: &str| {
+            run_coalesced(
+                RunPythonScriptRequest {
+                    script_path: script.clone(),
+                    coalesce: true,
+                    stdout_file: Some(stdout_file.to_string()),
+                    output_dir: Some(output_dir.clone()),
+                    ..Default::default()
+                },
+                &registry,
+                &queue,
+                EventSinks::default(),
+            )
+        };
+
+        let (first, second) = tokio::join!(run("first.txt"), async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            run("second.txt").await
+        });
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert!(!first.coalesced && !second.coalesced);
+        assert_ne!(first.run_id, second.run_id);
+        for name in ["first.txt", "second.txt"] {
+            let written = std::fs::read_to_string(output_dir.join(name)).unwrap();
+            assert_eq!(written.trim(), "report");
+        }
+        let _ = std::fs::remove_dir_all(&output_dir);
+    }
+
+    #[tokio::test]
+    async fn dry_runs_report_the_command_without_running_it() {
+        let marker = temp_dir("dry-run").join("marker");
+        let script = temp_script(
+            "dry_run.py",
+            &format!("open({:?}, 'w').close()\n", marker.display().to_string()),
+        );
+        let request = |dry_run| RunPythonScriptRequest {
+            script_path: script.clone(),
+            args: strings(&["--city", "Paris"]),
+            interpreter_args: strings(&["-u"]),
+            env: Some(HashMap::from([(
+                "API_TOKEN".to_string(),
+                "secret".to_string(),
+            )])),
+            dry_run,
+            ..Default::default()
+        };
+
+        let response = run_request(request(true)).await.unwrap();
+        assert!(!marker.exists());
+        let resolved = response.dry_run.unwrap();
+        assert!(resolved.env_keys.contains(&"API_TOKEN".to_string()));
+        assert!(!format!("{:?}", resolved).contains("secret"));
+        assert_eq!(resolved.args, ["--city", "Paris"]);
+        let script_path = validate_script_path(&script, ScriptExtensions::default())
+            .unwrap()
+            .display()
+            .to_string();
+        assert_eq!(resolved.script, script_path);
+        let mut expected = vec![resolved.program.clone()];
+        expected.extend(resolved.pre_args.iter().cloned());
+        expected.extend(strings(&["-u", &script_path, "--city", "Paris"]));
+        assert_eq!(response.resolved_command, expected);
+
+        let response = run_request(request(false)).await.unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert!(marker.exists());
+        assert!(response.dry_run.is_none());
+        assert_eq!(response.resolved_command, expected);
+        let _ = std::fs::remove_file(marker);
+    }
+
+    #[tokio::test]
+    async fn interpreters_older_than_the_requirement_are_skipped() {
+        let script = temp_script(
+            "requires_future_python.py",
+            "# pdd-requires-python: >=99\nprint('hi')\n",
+        );
+        let python = if cfg!(windows) { "python" } else { "python3" };
+        let run = |min_python_version: Option<&str>| {
+            run_request(RunPythonScriptRequest {
+                script_path: script.clone(),
+                python_path: Some(python.to_string()),
+                min_python_version: min_python_version.map(str::to_string),
+                ..Default::default()
+            })
+        };
+
+        let error = run(None).await.unwrap_err();
+        let error: serde_json::Value = serde_json::from_str(&error).unwrap();
+        let message = error["message"].as_str().unwrap();
+        assert!(
+            message.contains("requires Python >= 99, found 3."),
+            "{}",
+            message
+        );
+        assert!(message.ends_with(&format!("({})", python)), "{}", message);
+        assert_eq!(error["attempts"][0]["error_kind"], "unsupported_version");
+
+        let response = run(Some(">=3")).await.unwrap();
+        assert!(response.ok, "{}", response.stderr);
+        assert!(response.python_version.unwrap().starts_with("3."));
+        assert!(run(Some("three")).await.is_err());
+
+        let interpreters = InterpreterInfoCache::default();
+        let validate = |min_python_version: Option<&str>| {
+            validate_script(
+                ValidatePythonScriptRequest {
+                    script_path: script.clone(),
+                    python_path: Some(python.to_string()),
+                    min_python_version: min_python_version.map(str::to_string),
+                    ..Default::default()
+                },
+                &interpreters,
+            )
+        };
+        let validation = validate(None).await.unwrap();
+        assert!(!validation.valid);
+        assert!(validation
+            .message
+            .unwrap()
+            .starts_with("requires Python >= 99, found 3."));
+        assert!(validate(Some("<4")).await.unwrap().valid);
+        let validation = validate(Some(">=three")).await.unwrap();
+        assert!(validation.message.unwrap().starts_with("invalid python"));
+    }
+
+    #[tokio::test]
+    async fn timings_split_up_the_duration() {
+        let script = temp_script("sleep_briefly.py", "import time\ntime.sleep(0.3)\n");
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let timings = &response.timings;
+        assert!(timings.execute_ms >= 250, "{:?}", timings);
+        assert!(
+            timings.spawn_ms + timings.execute_ms + timings.drain_ms
+                <= response.duration_ms as u64 + 1,
+            "{:?} {}",
+            timings,
+            response.duration_ms
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_runs_carry_an_error_kind() {
+        let script = temp_script("missing_import.py", "import pdd_no_such_module\n");
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(response.error_kind.as_deref(), Some("module_not_found"));
+        assert_eq!(
+            response.missing_module.as_deref(),
+            Some("pdd_no_such_module")
+        );
+        assert_eq!(response.error_location.unwrap().line, 1);
+        let traceback = response.traceback.unwrap();
+        assert_eq!(traceback.exception_type, "ModuleNotFoundError");
+        assert_eq!(
+            traceback.frames[0].code.as_deref(),
+            Some("import pdd_no_such_module")
+        );
+    }
+
+    const PRINT_CWD_SCRIPT: &str = "import os\nprint(os.getcwd())\n";
+
+    #[tokio::test]
+    async fn working_dir_defaults_to_script_parent() {
+        let script = temp_script("print_cwd_default.py", PRINT_CWD_SCRIPT);
+        let expected = Path::new(&script).parent().unwrap().canonicalize().unwrap();
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(
+            Path::new(response.stdout.trim()).canonicalize().unwrap(),
+            expected
+        );
+        assert_eq!(
+            Path::new(&response.working_dir).canonicalize().unwrap(),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn explicit_working_dir_is_used_and_validated() {
+        let script = temp_script("print_cwd_explicit.py", PRINT_CWD_SCRIPT);
+        let target = std::env::temp_dir().canonicalize().unwrap();
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script.clone(),
+            working_dir: Some(target.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            Path::new(response.stdout.trim()).canonicalize().unwrap(),
+            target
+        );
+
+        let error = run_request(RunPythonScriptRequest {
+            script_path: script,
+            working_dir: Some("/definitely/missing/pdd-dir".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error.contains("working directory not found"));
+    }
+
+    #[tokio::test]
+    async fn large_stdin_payload_round_trips_without_deadlock() {
+        let script = temp_script(
+            "echo_stdin.py",
+            "import sys\nsys.stdout.write(sys.stdin.read())\n",
+        );
+        let payload = "0123456789abcdef".repeat(4 * 1024 * 1024 / 16);
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            stdin: Some(payload.clone()),
+            timeout_ms: Some(30_000),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.stdout.len(), payload.len());
+        assert!(response.stdout == payload);
+    }
+
+    fn collecting_sink() -> (OutputSink, Arc<std::sync::Mutex<Vec<ScriptOutputEvent>>>) {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let collected = events.clone();
+        let sink: OutputSink = Arc::new(move |event| collected.lock().unwrap().push(event));
+        (sink, events)
+    }
+
+    #[tokio::test]
+    async fn streaming_emits_lines_and_still_aggregates_output() {
+        let script = temp_script(
+            "stream_lines.py",
+            "import sys\nprint('first')\nprint('oops', file=sys.stderr)\nprint('x' * 10)\n",
+        );
+        let (sink, events) = collecting_sink();
+
+        let response = run_script(
+            RunPythonScriptRequest {
+                script_path: script,
+                stream: true,
+                stream_line_limit: Some(4),
+                correlation_id: Some("widget-7".to_string()),
+                ..Default::default()
+            },
+            &RunRegistry::default(),
+            &RunQueue::default(),
+            EventSinks {
+                output: Some(sink),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert!(response.stdout.contains("first"));
+        let events = events.lock().unwrap();
+        assert_eq!(response.correlation_id.as_deref(), Some("widget-7"));
+        assert!(events.iter().all(|event| event.run_id == response.run_id
+            && event.correlation_id == response.correlation_id));
+        let stdout_lines: Vec<&str> = events
+            .iter()
+            .filter(|event| event.stream == OutputStream::Stdout)
+            .map(|event| event.line.as_str())
+            .collect();
+        assert_eq!(stdout_lines, vec!["firs", "t", "xxxx", "xxxx", "xx"]);
+        assert!(events
+            .iter()
+            .any(|event| event.stream == OutputStream::Stderr && event.line == "oops"));
     }
 
     #[test]
-    fn invalid_script_extension_should_fail_validation() {
-        let result = validate_script_path("/tmp/not_python.txt");
-        assert!(result.is_err());
+    fn utf8_boundary_never_splits_a_code_point() {
+        let text = "a\u{96ea}b".as_bytes();
+        assert_eq!(utf8_boundary(text, 2), 1);
+        assert_eq!(utf8_boundary(text, 4), 4);
+        assert_eq!(utf8_boundary(text, 10), text.len());
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_run_resolves_it_and_frees_the_id() {
+        let script = temp_script("sleep_forever.py", "import time\ntime.sleep(60)\n");
+        let registry = RunRegistry::default();
+        let run = tokio::spawn({
+            let registry = registry.clone();
+            async move {
+                run_script(
+                    RunPythonScriptRequest {
+                        script_path: script,
+                        run_id: Some("cancel-me".to_string()),
+                        timeout_ms: Some(30_000),
+                        ..Default::default()
+                    },
+                    &registry,
+                    &RunQueue::default(),
+                    EventSinks::default(),
+                )
+                .await
+            }
+        });
+
+        while !registry.is_active("cancel-me") {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        registry.cancel("cancel-me").unwrap();
+
+        let response = run.await.unwrap().unwrap();
+        assert!(response.cancelled);
+        assert!(!response.ok);
+        assert!(!response.timed_out);
+        assert!(!registry.is_active("cancel-me"));
+        assert!(registry.cancel("cancel-me").is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_output_is_capped_and_flagged() {
+        let script = temp_script(
+            "flood_stdout.py",
+            "import sys\nfor _ in range(50):\n    sys.stdout.write('x' * (1024 * 1024))\nprint('done', file=sys.stderr)\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            max_output_bytes: Some(1024 * 1024),
+            timeout_ms: Some(60_000),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(response.stdout.len(), 1024 * 1024);
+        assert!(response.stdout_truncated);
+        assert!(!response.stderr_truncated);
+        assert_eq!(response.stderr.trim(), "done");
+    }
+
+    #[tokio::test]
+    async fn truncation_backs_off_to_a_utf8_boundary() {
+        let input = "ab\u{96ea}\u{96ea}".as_bytes();
+        let captured = Mutex::new(CapturedStream::default());
+        capture_stream(
+            input,
+            4,
+            None,
+            &captured,
+            &ActivityClock::new(),
+            LineStages::default(),
+        )
+        .await
+        .unwrap();
+
+        let captured = captured.into_inner().unwrap();
+        assert!(captured.truncated);
+        assert_eq!(captured.bytes, "ab".as_bytes());
+    }
+
+    #[cfg(target_os = "linux")]
+    fn process_is_running(pid: u32) -> bool {
+        match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            // Reparented zombies may linger unreaped in containers; they are dead.
+            Ok(stat) => !stat
+                .rsplit(')')
+                .next()
+                .is_some_and(|rest| rest.trim_start().starts_with('Z')),
+            Err(_) => false,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn timeout_kills_grandchildren_too() {
+        let script = temp_script(
+            "spawn_grandchild.py",
+            "import subprocess, sys, time\nchild = subprocess.Popen([sys.executable, '-c', 'import time; time.sleep(60)'])\nprint(child.pid, flush=True)\ntime.sleep(60)\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            timeout_ms: Some(1_000),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.timed_out);
+        let grandchild: u32 = response.stdout.trim().parse().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while process_is_running(grandchild) && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(!process_is_running(grandchild));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn grace_period_lets_scripts_clean_up_before_exiting() {
+        let script = temp_script(
+            "trap_sigterm.py",
+            "import signal, sys, time\ndef bye(*_):\n    print('cleaned up', flush=True)\n    sys.exit(3)\nsignal.signal(signal.SIGTERM, bye)\nprint('ready', flush=True)\ntime.sleep(60)\n",
+        );
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            timeout_ms: Some(1_000),
+            grace_period_ms: Some(5_000),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.timed_out);
+        assert!(!response.force_killed);
+        assert_eq!(response.exit_code, Some(3));
+        assert!(response.stdout.contains("cleaned up"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn scripts_ignoring_sigterm_are_killed_after_the_grace_period() {
+        let script = temp_script(
+            "ignore_sigterm.py",
+            "import signal, time\nsignal.signal(signal.SIGTERM, signal.SIG_IGN)\nprint('ready', flush=True)\ntime.sleep(60)\n",
+        );
+        let started = Instant::now();
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            timeout_ms: Some(1_000),
+            grace_period_ms: Some(500),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.timed_out);
+        assert!(response.force_killed);
+        assert_eq!(response.exit_code, None);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn timed_out_runs_keep_output_even_when_a_pipe_stays_open() {
+        // The grandchild escapes the process group and keeps stdout open, so
+        // the reader never sees EOF on its own.
+        let script = temp_script(
+            "marker_then_hang.py",
+            "import subprocess, sys, time\nsubprocess.Popen([sys.executable, '-c', 'import time; time.sleep(5)'], start_new_session=True)\nprint('MARKER-42', flush=True)\ntime.sleep(60)\n",
+        );
+        let started = Instant::now();
+
+        let response = run_request(RunPythonScriptRequest {
+            script_path: script,
+            timeout_ms: Some(1_000),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(response.timed_out);
+        assert!(response.stdout.contains("MARKER-42"));
+        assert!(started.elapsed() < Duration::from_millis(4_500));
+    }
+
+    #[tokio::test]
+    async fn clean_env_drops_inherited_values() {
+        if !rerun_with_env(
+            "commands::tests::clean_env_drops_inherited_values",
+            &[("PDD_TEST_INHERITED", "parent-only")],
+        ) {
+            return;
+        }
+        let script = temp_script("print_env_clean.py", PRINT_ENV_SCRIPT);
+
+        let response = run_request(env_request(script, Some(false))).await.unwrap();
+        let values: serde_json::Value = serde_json::from_str(response.stdout.trim()).unwrap();
+
+        assert!(response.ok, "{}", response.stderr);
+        assert_eq!(values["PDD_TEST_TOKEN"], "from-request");
+        assert!(values["PDD_TEST_INHERITED"].is_null());
     }
 }
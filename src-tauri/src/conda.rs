@@ -0,0 +1,164 @@
+//! Conda environments, as `conda env list --json` reports them, so a run can
+//! name an environment instead of the path of its interpreter.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// conda starts slowly, more so with many channels configured.
+const LIST_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CondaEnv {
+    /// "base" for conda's own prefix.
+    pub name: String,
+    pub prefix: PathBuf,
+}
+
+impl CondaEnv {
+    /// The environment's interpreter, whether or not it exists.
+    pub fn python(&self) -> PathBuf {
+        match cfg!(windows) {
+            true => self.prefix.join("python.exe"),
+            false => self.prefix.join("bin").join("python"),
+        }
+    }
+
+    /// Whether `name` is this environment's name or prefix.
+    pub fn is(&self, name: &str) -> bool {
+        self.name == name || self.prefix == Path::new(name)
+    }
+}
+
+/// The conda executable. An activated shell says where it is; otherwise it
+/// has to be on PATH.
+pub fn program() -> String {
+    std::env::var("CONDA_EXE")
+        .ok()
+        .filter(|exe| !exe.is_empty())
+        .unwrap_or_else(|| "conda".to_string())
+}
+
+/// Arguments to `program()` that start `python` in `env` the way an
+/// activated shell would, with output passed straight through.
+pub fn run_args(env: &CondaEnv) -> Vec<String> {
+    vec![
+        "run".to_string(),
+        "-p".to_string(),
+        env.prefix.to_string_lossy().to_string(),
+        "--no-capture-output".to_string(),
+        "python".to_string(),
+    ]
+}
+
+/// Every environment conda knows of. A missing conda is a `NotFound` error.
+pub async fn list() -> Result<Vec<CondaEnv>, std::io::Error> {
+    let program = program();
+    let mut command = Command::new(&program);
+    command
+        .args(["env", "list", "--json"])
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    crate::process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(LIST_TIMEOUT, command.output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out running {} env list", program),
+            ))
+        }
+    };
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "{} env list exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    parse_env_list(&String::from_utf8_lossy(&output.stdout)).map_err(std::io::Error::other)
+}
+
+#[derive(Deserialize)]
+struct EnvList {
+    envs: Vec<PathBuf>,
+    /// Only from newer versions of conda.
+    #[serde(default)]
+    envs_details: HashMap<PathBuf, EnvDetails>,
+}
+
+#[derive(Deserialize)]
+struct EnvDetails {
+    name: Option<String>,
+}
+
+fn parse_env_list(json: &str) -> Result<Vec<CondaEnv>, String> {
+    let list: EnvList = serde_json::from_str(json)
+        .map_err(|error| format!("unexpected output from conda env list: {}", error))?;
+    Ok(list
+        .envs
+        .iter()
+        .enumerate()
+        .map(|(index, prefix)| {
+            let named = list
+                .envs_details
+                .get(prefix)
+                .and_then(|details| details.name.clone())
+                .filter(|name| !name.is_empty());
+            // Environments under an `envs` directory are named after their
+            // directory. conda lists its own prefix, "base", first; others
+            // made with `--prefix` have no name but their path.
+            let name = named.unwrap_or_else(|| {
+                let in_envs = prefix
+                    .parent()
+                    .and_then(Path::file_name)
+                    .is_some_and(|dir| dir == "envs");
+                match (in_envs, prefix.file_name(), index) {
+                    (true, Some(name), _) => name.to_string_lossy().to_string(),
+                    (_, _, 0) => "base".to_string(),
+                    _ => prefix.to_string_lossy().to_string(),
+                }
+            });
+            CondaEnv {
+                name,
+                prefix: prefix.clone(),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environments_are_named_by_conda_or_their_directory() {
+        let old =
+            r#"{"envs": ["/opt/miniconda3", "/opt/miniconda3/envs/dash", "/work/project-env"]}"#;
+        let names: Vec<String> = parse_env_list(old)
+            .unwrap()
+            .into_iter()
+            .map(|env| env.name)
+            .collect();
+        assert_eq!(names, ["base", "dash", "/work/project-env"]);
+
+        let new = r#"{
+            "envs": ["/root/miniconda", "/root/miniconda/envs/ml"],
+            "envs_details": {
+                "/root/miniconda": {"name": "base", "base": true},
+                "/root/miniconda/envs/ml": {"name": "ml", "base": false}
+            }
+        }"#;
+        let envs = parse_env_list(new).unwrap();
+        assert_eq!(envs[1].name, "ml");
+        assert!(envs[1].is("ml"));
+        assert!(envs[1].is("/root/miniconda/envs/ml"));
+        assert!(!envs[0].is("ml"));
+        assert!(parse_env_list("conda: command not found").is_err());
+    }
+}
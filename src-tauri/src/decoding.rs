@@ -0,0 +1,128 @@
+use encoding_rs::{Encoding, UTF_8};
+
+/// How captured output bytes are turned into text.
+#[derive(Debug, Clone, Copy)]
+pub enum OutputEncoding {
+    /// A fixed encoding, e.g. `gbk` or `shift_jis`.
+    Fixed(&'static Encoding),
+    /// UTF-8 when the output is valid UTF-8, otherwise the system ANSI code
+    /// page on Windows (UTF-8 with replacement characters elsewhere).
+    Auto,
+}
+
+impl OutputEncoding {
+    /// Accepts `auto` or any WHATWG encoding label. `None` keeps plain UTF-8.
+    pub fn parse(label: Option<&str>) -> Result<Self, String> {
+        let Some(label) = label.map(str::trim).filter(|label| !label.is_empty()) else {
+            return Ok(OutputEncoding::Fixed(UTF_8));
+        };
+
+        if label.eq_ignore_ascii_case("auto") {
+            return Ok(OutputEncoding::Auto);
+        }
+
+        Encoding::for_label(label.as_bytes())
+            .map(OutputEncoding::Fixed)
+            .ok_or_else(|| format!("unsupported output_encoding: {}", label))
+    }
+
+    /// Picks one encoding for all streams of a run so stdout and stderr are
+    /// never decoded inconsistently.
+    pub fn resolve(self, streams: &[&[u8]]) -> &'static Encoding {
+        match self {
+            OutputEncoding::Fixed(encoding) => encoding,
+            OutputEncoding::Auto => {
+                if streams
+                    .iter()
+                    .all(|bytes| std::str::from_utf8(bytes).is_ok())
+                {
+                    UTF_8
+                } else {
+                    system_ansi_encoding().unwrap_or(UTF_8)
+                }
+            }
+        }
+    }
+}
+
+pub fn decode(bytes: &[u8], encoding: &'static Encoding) -> String {
+    let (text, _) = encoding.decode_without_bom_handling(bytes);
+    text.into_owned()
+}
+
+#[cfg(windows)]
+fn system_ansi_encoding() -> Option<&'static Encoding> {
+    // SAFETY: GetACP has no preconditions.
+    let code_page = unsafe { windows_sys::Win32::Globalization::GetACP() };
+    encoding_for_code_page(code_page)
+}
+
+#[cfg(not(windows))]
+fn system_ansi_encoding() -> Option<&'static Encoding> {
+    None
+}
+
+/// Windows ANSI code pages that have a WHATWG equivalent.
+#[cfg_attr(not(any(windows, test)), allow(dead_code))]
+fn encoding_for_code_page(code_page: u32) -> Option<&'static Encoding> {
+    let label: &[u8] = match code_page {
+        65001 => b"utf-8",
+        936 => b"gbk",
+        950 => b"big5",
+        932 => b"shift_jis",
+        949 => b"euc-kr",
+        874 => b"windows-874",
+        1250 => b"windows-1250",
+        1251 => b"windows-1251",
+        1252 => b"windows-1252",
+        1253 => b"windows-1253",
+        1254 => b"windows-1254",
+        1255 => b"windows-1255",
+        1256 => b"windows-1256",
+        1257 => b"windows-1257",
+        1258 => b"windows-1258",
+        _ => return None,
+    };
+    Encoding::for_label(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GBK_ZHONGWEN: &[u8] = &[0xD6, 0xD0, 0xCE, 0xC4];
+
+    #[test]
+    fn gbk_output_round_trips() {
+        let OutputEncoding::Fixed(encoding) = OutputEncoding::parse(Some("GBK")).unwrap() else {
+            panic!("gbk should be a fixed encoding");
+        };
+
+        assert_eq!(encoding.name(), "GBK");
+        assert_eq!(decode(GBK_ZHONGWEN, encoding), "\u{4e2d}\u{6587}");
+        let (encoded, _, _) = encoding.encode("\u{4e2d}\u{6587}");
+        assert_eq!(&*encoded, GBK_ZHONGWEN);
+    }
+
+    #[test]
+    fn auto_prefers_utf8_when_every_stream_is_valid() {
+        let auto = OutputEncoding::parse(Some("auto")).unwrap();
+        assert_eq!(auto.resolve(&["\u{2603}".as_bytes(), b"plain"]), UTF_8);
+    }
+
+    #[test]
+    fn unknown_labels_are_rejected() {
+        assert!(OutputEncoding::parse(Some("klingon")).is_err());
+        assert!(matches!(
+            OutputEncoding::parse(None),
+            Ok(OutputEncoding::Fixed(encoding)) if encoding == UTF_8
+        ));
+    }
+
+    #[test]
+    fn ansi_code_pages_map_to_encodings() {
+        assert_eq!(encoding_for_code_page(936).unwrap().name(), "GBK");
+        assert_eq!(encoding_for_code_page(932).unwrap().name(), "Shift_JIS");
+        assert!(encoding_for_code_page(37).is_none());
+    }
+}
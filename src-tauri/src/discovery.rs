@@ -0,0 +1,294 @@
+//! Finding the Pythons installed on this machine, so `python_path` can be
+//! picked from a list: PATH, the `py` launcher, pyenv, conda and Pipenv
+//! environments and the places installers usually put Python.
+
+use crate::interpreters::{InterpreterInfo, InterpreterInfoCache};
+use crate::pipenv;
+use crate::py_launcher;
+use crate::pyenv;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiscoveredInterpreter {
+    /// `sys.executable`, as found; usable as `python_path`.
+    pub executable: String,
+    pub version: String,
+    pub arch: String,
+    /// 32 or 64.
+    pub pointer_bits: u32,
+    /// e.g. "win-amd64"; see `InterpreterInfo::platform_tag`.
+    pub platform_tag: String,
+    pub implementation: String,
+    /// Where it was found first: "path", "py_launcher", "pyenv", "conda",
+    /// "pipenv", "system", "homebrew", "framework", "microsoft_store",
+    /// "python_org" or "default".
+    pub source: &'static str,
+    pub is_venv: bool,
+    /// What to pass as `py_version` to run it through the `py` launcher,
+    /// for those the launcher has registered.
+    pub py_version: Option<String>,
+    /// The interpreter runs would pick when no `python_path` is given and
+    /// the script has no shebang.
+    pub is_default: bool,
+}
+
+/// Probes every Python found and drops aliases of the same one: symlinks
+/// like `python3` -> `python3.12`, or one directory listed twice in PATH.
+/// `default` is what default candidate resolution picks, if anything.
+pub async fn discover(
+    interpreters: &InterpreterInfoCache,
+    default: Option<InterpreterInfo>,
+) -> Vec<DiscoveredInterpreter> {
+    let mut locations: Vec<(PathBuf, &'static str)> = Vec::new();
+    let mut seen = Vec::new();
+    let launcher = py_launcher_pythons().await;
+    for (path, source) in launcher
+        .iter()
+        .map(|python| (python.executable.clone(), "py_launcher"))
+        .chain(path_pythons().into_iter().map(|path| (path, "path")))
+        .chain(installed_pythons())
+        .chain(conda_pythons(interpreters).await)
+        .chain(
+            pipenv::environments()
+                .into_iter()
+                .map(|path| (path, "pipenv")),
+        )
+    {
+        // Only exact repeats are dropped unprobed: a venv's `python` is a
+        // symlink to the base interpreter, but runs as the venv.
+        if !seen.contains(&path) {
+            seen.push(path.clone());
+            locations.push((path, source));
+        }
+    }
+
+    let handles: Vec<_> = locations
+        .into_iter()
+        .map(|(path, source)| {
+            let interpreters = interpreters.clone();
+            tauri::async_runtime::spawn(async move {
+                let program = path.to_string_lossy().to_string();
+                interpreters
+                    .get(&program, &[])
+                    .await
+                    .ok()
+                    .map(|info| (info, source))
+            })
+        })
+        .collect();
+
+    let mut found: Vec<(InterpreterInfo, &'static str)> = Vec::new();
+    for handle in handles {
+        if let Ok(Some(probed)) = handle.await {
+            found.push(probed);
+        }
+    }
+    if let Some(default) = &default {
+        if !found.iter().any(|(info, _)| same(info, default)) {
+            found.push((default.clone(), "default"));
+        }
+    }
+
+    let mut discovered: Vec<DiscoveredInterpreter> = Vec::new();
+    let mut kept: Vec<InterpreterInfo> = Vec::new();
+    for (info, source) in found {
+        if kept.iter().any(|other| same(other, &info)) {
+            continue;
+        }
+        discovered.push(DiscoveredInterpreter {
+            executable: info.executable.clone(),
+            version: info.version.clone(),
+            arch: info.arch.clone(),
+            pointer_bits: info.pointer_bits,
+            platform_tag: info.platform_tag.clone(),
+            implementation: info.implementation.clone(),
+            source,
+            is_venv: info.is_virtualenv,
+            py_version: launcher
+                .iter()
+                .find(|python| Path::new(&info.executable) == python.executable)
+                .map(|python| python.version.clone()),
+            is_default: default.as_ref().is_some_and(|default| same(&info, default)),
+        });
+        kept.push(info);
+    }
+    discovered
+}
+
+/// The same interpreter: one canonical executable, run with one prefix.
+/// The prefix tells a venv apart from the Python it links to.
+fn same(a: &InterpreterInfo, b: &InterpreterInfo) -> bool {
+    let canonical = |info: &InterpreterInfo| {
+        std::fs::canonicalize(&info.executable).unwrap_or_else(|_| PathBuf::from(&info.executable))
+    };
+    a.prefix == b.prefix && canonical(a) == canonical(b)
+}
+
+/// `python`, `python3`, `python3.12` and free-threaded `python3.13t`
+/// (with `.exe` on Windows), but not `python3-config` or `python3.12-gdb.py`.
+fn is_python_name(name: &str) -> bool {
+    let name = match cfg!(windows) {
+        true => match name.len().checked_sub(4) {
+            Some(stem) if name[stem..].eq_ignore_ascii_case(".exe") => &name[..stem],
+            _ => return false,
+        },
+        false => name,
+    };
+    let Some(version) = name.strip_prefix("python") else {
+        return false;
+    };
+    let version = version.strip_suffix('t').unwrap_or(version);
+    match version.split_once('.') {
+        None => version.is_empty() || version == "3",
+        Some((major, minor)) => {
+            major == "3" && !minor.is_empty() && minor.chars().all(|c| c.is_ascii_digit())
+        }
+    }
+}
+
+/// Entries of `dir` that look like Python executables, sorted by name.
+fn pythons_in(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_str().is_some_and(is_python_name))
+        .map(|entry| entry.path())
+        // Microsoft Store aliases are reparse points, not regular files.
+        .filter(|path| !path.is_dir())
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn path_pythons() -> Vec<PathBuf> {
+    std::env::var_os("PATH")
+        .map(|path| {
+            std::env::split_paths(&path)
+                .flat_map(|dir| pythons_in(&dir))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The interpreter of each conda environment. None without conda.
+async fn conda_pythons(interpreters: &InterpreterInfoCache) -> Vec<(PathBuf, &'static str)> {
+    interpreters
+        .conda_environments()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|env| (env.python(), "conda"))
+        .filter(|(python, _)| python.is_file())
+        .collect()
+}
+
+/// Subdirectories of `dir`, sorted, each joined with `tail`, that exist.
+fn versions_in(dir: &Path, tail: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path().join(tail))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[cfg(not(windows))]
+fn installed_pythons() -> Vec<(PathBuf, &'static str)> {
+    let mut found = Vec::new();
+    if let Some(root) = pyenv::root() {
+        found.extend(
+            versions_in(&root.join("versions"), "bin/python")
+                .into_iter()
+                .map(|path| (path, "pyenv")),
+        );
+    }
+    for (dir, source) in [
+        ("/usr/bin", "system"),
+        ("/usr/local/bin", "system"),
+        ("/opt/homebrew/bin", "homebrew"),
+    ] {
+        found.extend(
+            pythons_in(Path::new(dir))
+                .into_iter()
+                .map(|path| (path, source)),
+        );
+    }
+    found.extend(
+        versions_in(
+            Path::new("/Library/Frameworks/Python.framework/Versions"),
+            "bin/python3",
+        )
+        .into_iter()
+        .map(|path| (path, "framework")),
+    );
+    found
+}
+
+#[cfg(windows)]
+fn installed_pythons() -> Vec<(PathBuf, &'static str)> {
+    let mut found = Vec::new();
+    if let Some(root) = pyenv::root() {
+        found.extend(
+            versions_in(&root.join("versions"), "python.exe")
+                .into_iter()
+                .map(|path| (path, "pyenv")),
+        );
+    }
+    if let Some(local) = std::env::var_os("LOCALAPPDATA").map(PathBuf::from) {
+        found.extend(
+            versions_in(&local.join("Programs").join("Python"), "python.exe")
+                .into_iter()
+                .map(|path| (path, "python_org")),
+        );
+        found.extend(
+            pythons_in(&local.join("Microsoft").join("WindowsApps"))
+                .into_iter()
+                .map(|path| (path, "microsoft_store")),
+        );
+    }
+    found
+}
+
+/// What `py --list-paths` reports. Empty without the launcher.
+#[cfg(windows)]
+async fn py_launcher_pythons() -> Vec<py_launcher::Registered> {
+    py_launcher::list().await.unwrap_or_default()
+}
+
+#[cfg(not(windows))]
+async fn py_launcher_pythons() -> Vec<py_launcher::Registered> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn python_executables_are_recognized_by_name() {
+        let name = |name: &str| match cfg!(windows) {
+            true => format!("{}.exe", name),
+            false => name.to_string(),
+        };
+        for python in ["python", "python3", "python3.12", "python3.13t"] {
+            assert!(is_python_name(&name(python)), "{}", python);
+        }
+        for other in [
+            "python3-config",
+            "python3.12-gdb.py",
+            "python2.7",
+            "pythonw3",
+            "python3.",
+            "ipython3",
+        ] {
+            assert!(!is_python_name(&name(other)), "{}", other);
+        }
+    }
+}
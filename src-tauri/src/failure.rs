@@ -0,0 +1,300 @@
+//! Sorts failed runs into a few categories the frontend can act on, so it
+//! doesn't have to pattern-match stderr itself.
+//!
+//! Only the stderr shapes CPython itself produces are recognized. Anything
+//! else is "unknown" rather than a guess.
+
+use crate::commands::RunPythonScriptResponse;
+use crate::interpreters::InterpreterInfo;
+use crate::traceback::{self, TRACEBACK_HEADER};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorLocation {
+    pub file: String,
+    pub line: u32,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Failure {
+    pub kind: Option<String>,
+    pub location: Option<ErrorLocation>,
+    /// Set for "module_not_found".
+    pub missing_module: Option<String>,
+}
+
+/// `kind` is `None` for successful runs.
+pub fn classify(response: &RunPythonScriptResponse) -> Failure {
+    let kind = |kind: &str| Failure {
+        kind: Some(kind.to_string()),
+        ..Failure::default()
+    };
+    if response.ok {
+        return Failure::default();
+    }
+    if response.timed_out || response.idle_timed_out {
+        return kind("timeout");
+    }
+    if response.cancelled {
+        return kind("cancelled");
+    }
+    if response.oom_killed || response.cpu_limit_exceeded {
+        return kind("resource_limit");
+    }
+    if response.crashed {
+        return kind("crashed");
+    }
+
+    let stderr = &response.stderr;
+    let Some(last_line) = stderr.lines().rev().find(|line| !line.trim().is_empty()) else {
+        return kind("unknown");
+    };
+    if is_launcher_error(last_line) {
+        return kind("interpreter_not_found");
+    }
+    // `python: can't open file '...': [Errno 13] Permission denied`
+    if last_line.contains("[Errno 13]") {
+        return kind("permission_denied");
+    }
+
+    let Some((exception, message)) = traceback::exception_line(last_line) else {
+        return kind("unknown");
+    };
+    // Only the last dotted part is the class name.
+    let exception = exception.rsplit('.').next().unwrap_or(exception);
+    let location = last_location(stderr);
+    let mut failure = match exception {
+        "ModuleNotFoundError" | "ImportError" if message.starts_with("No module named ") => {
+            Failure {
+                kind: Some("module_not_found".to_string()),
+                missing_module: missing_module(message),
+                ..Failure::default()
+            }
+        }
+        "ImportError" | "OSError" if is_architecture_mismatch(message) => {
+            kind("architecture_mismatch")
+        }
+        "SyntaxError" | "IndentationError" | "TabError" if location.is_some() => {
+            kind("syntax_error")
+        }
+        "PermissionError" => kind("permission_denied"),
+        _ if stderr.contains(TRACEBACK_HEADER) => kind("runtime_exception"),
+        _ => return kind("unknown"),
+    };
+    failure.location = location;
+    failure
+}
+
+/// How loading a compiled extension or a `ctypes` library built for another
+/// architecture fails: on Linux, on macOS and on Windows. Windows often
+/// says no more than "DLL load failed".
+const ARCHITECTURE_SIGNATURES: &[&str] = &[
+    "wrong ELF class",
+    "incompatible architecture",
+    "is not a valid Win32 application",
+    "DLL load failed",
+];
+
+fn is_architecture_mismatch(message: &str) -> bool {
+    ARCHITECTURE_SIGNATURES
+        .iter()
+        .any(|signature| message.contains(signature))
+}
+
+/// For "architecture_mismatch" failures: what the interpreter that ran is,
+/// next to what the extension was built for when the error tells.
+pub fn hint(response: &RunPythonScriptResponse, info: &InterpreterInfo) -> Option<String> {
+    if response.error_kind.as_deref() != Some("architecture_mismatch") || info.pointer_bits == 0 {
+        return None;
+    }
+    let message = response
+        .stderr
+        .lines()
+        .rev()
+        .find(|line| is_architecture_mismatch(line))?;
+    let platform = match info.platform_tag.is_empty() {
+        true => &info.arch,
+        false => &info.platform_tag,
+    };
+    let python = format!(
+        "{} is a {}-bit Python ({})",
+        info.executable, info.pointer_bits, platform
+    );
+    Some(match built_for(message, info.pointer_bits) {
+        Some(other) => format!(
+            "{}, but the extension was built for {}; reinstall the package with this Python or run the script with a {} Python",
+            python, other, other
+        ),
+        None => format!(
+            "{}; the extension that failed to load may be built for another architecture, so reinstall the package with this Python",
+            python
+        ),
+    })
+}
+
+/// The architecture the error says the extension has, if it does.
+fn built_for(message: &str, pointer_bits: u32) -> Option<String> {
+    if message.contains("ELFCLASS64") {
+        return Some("64-bit".to_string());
+    }
+    if message.contains("ELFCLASS32") {
+        return Some("32-bit".to_string());
+    }
+    // macOS: "... incompatible architecture (have 'x86_64', need 'arm64')"
+    if let Some((_, rest)) = message.split_once("(have '") {
+        return rest.split_once('\'').map(|(have, _)| have.to_string());
+    }
+    // Windows only says so for a DLL of the other size.
+    if message.contains("is not a valid Win32 application") {
+        return Some(format!("{}-bit", if pointer_bits == 32 { 64 } else { 32 }));
+    }
+    None
+}
+
+/// Errors from the Windows `py` launcher and venv redirector when the
+/// interpreter they point at is gone.
+fn is_launcher_error(line: &str) -> bool {
+    line.starts_with("No Python at ")
+        || line.starts_with("No suitable Python runtime found")
+        || line.contains("did not find executable at ")
+}
+
+/// The innermost `File "...", line N` entry, which is where the error was
+/// raised (or, for syntax errors, where parsing failed).
+fn last_location(stderr: &str) -> Option<ErrorLocation> {
+    stderr.lines().rev().find_map(|line| {
+        let rest = line.trim_start().strip_prefix("File \"")?;
+        let (file, rest) = rest.rsplit_once("\", line ")?;
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest, |end| &rest[..end]);
+        Some(ErrorLocation {
+            file: file.to_string(),
+            line: digits.parse().ok()?,
+        })
+    })
+}
+
+fn missing_module(message: &str) -> Option<String> {
+    let name = message
+        .strip_prefix("No module named ")?
+        .trim()
+        .trim_matches(|c| c == '\'' || c == '"');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(stderr: &str) -> Failure {
+        classify(&RunPythonScriptResponse {
+            exit_code: Some(1),
+            stderr: stderr.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn missing_modules_are_named() {
+        let failure = failed(
+            "Traceback (most recent call last):\n  File \"/s/fetch.py\", line 2, in <module>\n    import requests.adapters\nModuleNotFoundError: No module named 'requests'\n",
+        );
+        assert_eq!(failure.kind.as_deref(), Some("module_not_found"));
+        assert_eq!(failure.missing_module.as_deref(), Some("requests"));
+        assert_eq!(
+            failure.location,
+            Some(ErrorLocation {
+                file: "/s/fetch.py".to_string(),
+                line: 2
+            })
+        );
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_file_and_line() {
+        let failure = failed(
+            "  File \"/s/broken.py\", line 3\n    print(\n         ^\nSyntaxError: '(' was never closed\n",
+        );
+        assert_eq!(failure.kind.as_deref(), Some("syntax_error"));
+        assert_eq!(failure.location.unwrap().line, 3);
+    }
+
+    #[test]
+    fn runtime_exceptions_use_the_innermost_frame() {
+        let failure = failed(
+            "Traceback (most recent call last):\n  File \"/s/a.py\", line 9, in <module>\n    main()\n  File \"/s/b.py\", line 4, in main\n    raise app.errors.FetchFailed(\"503\")\napp.errors.FetchFailed: 503\n",
+        );
+        assert_eq!(failure.kind.as_deref(), Some("runtime_exception"));
+        assert_eq!(failure.location.unwrap().file, "/s/b.py");
+
+        let failure = failed(
+            "Traceback (most recent call last):\n  File \"/s/a.py\", line 1, in <module>\nPermissionError: [Errno 13] Permission denied: '/etc/shadow'\n",
+        );
+        assert_eq!(failure.kind.as_deref(), Some("permission_denied"));
+    }
+
+    #[test]
+    fn architecture_mismatches_get_a_hint_for_the_interpreter() {
+        // What the probe prints for a 32-bit Python on 64-bit Windows and a
+        // 64-bit one on Linux.
+        let windows: InterpreterInfo = serde_json::from_str(
+            r#"{"executable": "C:\\Python311-32\\python.exe", "version": "3.11.4", "version_info": [3, 11, 4], "implementation": "cpython", "platform": "win32", "arch": "x86", "pointer_bits": 32, "platform_tag": "win32", "prefix": "C:\\Python311-32", "is_virtualenv": false}"#,
+        )
+        .unwrap();
+        let linux: InterpreterInfo = serde_json::from_str(
+            r#"{"executable": "/usr/bin/python3", "version": "3.12.3", "version_info": [3, 12, 3], "implementation": "cpython", "platform": "linux", "arch": "x86_64", "pointer_bits": 64, "platform_tag": "linux-x86_64", "prefix": "/usr", "is_virtualenv": false}"#,
+        )
+        .unwrap();
+        let response = |stderr: &str| {
+            let mut response = RunPythonScriptResponse {
+                exit_code: Some(1),
+                stderr: stderr.to_string(),
+                ..Default::default()
+            };
+            response.error_kind = classify(&response).kind;
+            response
+        };
+
+        let dll = response("Traceback (most recent call last):\n  File \"C:\\s\\chart.py\", line 1, in <module>\n    import numpy\nImportError: DLL load failed while importing _multiarray_umath: %1 is not a valid Win32 application.\n");
+        assert_eq!(dll.error_kind.as_deref(), Some("architecture_mismatch"));
+        let text = hint(&dll, &windows).unwrap();
+        assert!(text.contains("32-bit Python (win32)"), "{}", text);
+        assert!(text.contains("built for 64-bit"), "{}", text);
+
+        let elf = response("Traceback (most recent call last):\n  File \"/s/chart.py\", line 1, in <module>\nImportError: /opt/lib/_speedups.so: wrong ELF class: ELFCLASS32\n");
+        assert_eq!(elf.error_kind.as_deref(), Some("architecture_mismatch"));
+        let text = hint(&elf, &linux).unwrap();
+        assert!(text.contains("64-bit Python (linux-x86_64)"), "{}", text);
+        assert!(text.contains("built for 32-bit"), "{}", text);
+
+        let unrelated = response("Traceback (most recent call last):\n  File \"/s/a.py\", line 1, in <module>\nImportError: cannot import name 'x' from 'y'\n");
+        assert_eq!(unrelated.error_kind.as_deref(), Some("runtime_exception"));
+        assert_eq!(hint(&unrelated, &linux), None);
+    }
+
+    #[test]
+    fn unclear_failures_are_unknown() {
+        for stderr in [
+            "",
+            "error: could not reach the API\n",
+            "SyntaxError: not really, just a log line\n",
+            "  + Exception Group Traceback (most recent call last):\n  +-+---------------- 1 ----------------\n",
+        ] {
+            assert_eq!(failed(stderr).kind.as_deref(), Some("unknown"), "{}", stderr);
+        }
+        assert_eq!(
+            failed("No Python at 'C:\\Python311\\python.exe'\n")
+                .kind
+                .as_deref(),
+            Some("interpreter_not_found")
+        );
+        assert_eq!(
+            classify(&RunPythonScriptResponse {
+                ok: true,
+                ..Default::default()
+            }),
+            Failure::default()
+        );
+    }
+}
@@ -0,0 +1,32 @@
+//! Small filesystem helpers shared by the modules that persist state in the
+//! app data directory.
+
+use std::path::Path;
+
+/// Writes `json` to `path` through a temp file next to it, so a crash never
+/// leaves half a file behind. Missing parent directories are created.
+pub fn write_json_atomically(path: &Path, json: &[u8]) -> Result<(), String> {
+    let temp_path = path.with_extension("json.tmp");
+    path.parent()
+        .map(std::fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| std::fs::write(&temp_path, json))
+        .and_then(|_| std::fs::rename(&temp_path, path))
+        .map_err(|error| format!("failed to save {}: {}", path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_replace_the_file_and_leave_no_temp_file() {
+        let dir = std::env::temp_dir().join(format!("pdd-fs-util-{}", std::process::id()));
+        let path = dir.join("nested").join("state.json");
+        write_json_atomically(&path, b"[1]").unwrap();
+        write_json_atomically(&path, b"[2]").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[2]");
+        assert!(!path.with_extension("json.tmp").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
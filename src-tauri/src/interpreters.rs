@@ -0,0 +1,662 @@
+//! What an interpreter candidate actually is once started: the executable
+//! behind a name like `py -3`, its version and whether it is a venv. Probe
+//! results are kept for a while, since starting Python can take hundreds of
+//! milliseconds on Windows.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+use crate::conda::{self, CondaEnv};
+use crate::pipenv;
+use crate::poetry;
+use crate::process_tree;
+use crate::wsl;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const PROBE_CODE: &str = r#"
+import json, platform, struct, sys, sysconfig
+machine = platform.machine().lower()
+arch = {"amd64": "x86_64", "aarch64": "arm64", "i386": "x86", "i686": "x86"}.get(machine, machine)
+if struct.calcsize("P") == 4:
+    arch = {"x86_64": "x86", "arm64": "arm"}.get(arch, arch)
+print(json.dumps({
+    "executable": sys.executable,
+    "version": platform.python_version(),
+    "version_info": list(sys.version_info[:3]),
+    "implementation": sys.implementation.name,
+    "platform": sys.platform,
+    "arch": arch,
+    "pointer_bits": struct.calcsize("P") * 8,
+    "platform_tag": sysconfig.get_platform(),
+    "prefix": sys.prefix,
+    "is_virtualenv": sys.prefix != getattr(sys, "base_prefix", sys.prefix),
+}))
+"#;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct InterpreterInfo {
+    /// `sys.executable`: the binary that really runs, after launchers and
+    /// PATH lookup.
+    pub executable: String,
+    /// e.g. "3.11.4".
+    pub version: String,
+    /// Major, minor, micro.
+    pub version_info: (u32, u32, u32),
+    /// e.g. "cpython" or "pypy".
+    pub implementation: String,
+    /// `sys.platform`, e.g. "win32" or "linux".
+    pub platform: String,
+    /// Of the interpreter, not the OS: "x86_64", "x86", "arm64" and so on.
+    /// A 32-bit Python on 64-bit Windows is "x86".
+    pub arch: String,
+    /// 32 or 64. Compiled extensions only load into a Python of their own
+    /// size.
+    #[serde(default)]
+    pub pointer_bits: u32,
+    /// `sysconfig.get_platform()`, e.g. "win-amd64", "win32" or
+    /// "macosx-11.0-arm64": what wheels must be built for.
+    #[serde(default)]
+    pub platform_tag: String,
+    pub prefix: String,
+    pub is_virtualenv: bool,
+}
+
+/// What `--version` printed, parsed. Python 2 prints it to stderr.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InterpreterVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    /// "cpython", "pypy", "graalpy", "ironpython" or "jython".
+    pub implementation: String,
+}
+
+impl InterpreterVersion {
+    /// Reads banners like `Python 3.11.2+`, `Python 3.13.0rc2`, PyPy's
+    /// two-line one or `IronPython 3.4.1 (3.4.1.1000)`.
+    pub fn parse(output: &str) -> Option<Self> {
+        let release = output.lines().find_map(|line| {
+            let version = line[line.find("Python ")? + "Python ".len()..].trim_start();
+            let end = version
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(version.len());
+            let numbers: Vec<u32> = version[..end]
+                .split('.')
+                .filter(|part| !part.is_empty())
+                .map(|part| part.parse().ok())
+                .collect::<Option<_>>()?;
+            match numbers[..] {
+                [major, minor] => Some((major, minor, 0)),
+                [major, minor, patch, ..] => Some((major, minor, patch)),
+                _ => None,
+            }
+        })?;
+        let banner = output.to_ascii_lowercase();
+        let implementation = ["pypy", "graalpy", "ironpython", "jython"]
+            .into_iter()
+            .find(|name| banner.contains(name))
+            .unwrap_or("cpython");
+        Some(InterpreterVersion {
+            major: release.0,
+            minor: release.1,
+            patch: release.2,
+            implementation: implementation.to_string(),
+        })
+    }
+}
+
+/// Shown as `3.11.2`.
+impl std::fmt::Display for InterpreterVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+type Outcome<T> = Result<T, (std::io::ErrorKind, String)>;
+/// Filled by the first caller; everyone else asking meanwhile waits for it.
+type ProbeCell<T> = Arc<tokio::sync::OnceCell<(Instant, Outcome<T>)>>;
+type ProbeCells<T> = HashMap<Vec<String>, ProbeCell<T>>;
+
+#[derive(Debug)]
+struct Entries {
+    ttl: Duration,
+    /// `None` when `--version` succeeded with something unexpected.
+    available: ProbeCells<Option<InterpreterVersion>>,
+    info: ProbeCells<InterpreterInfo>,
+    /// By conda executable.
+    conda: ProbeCells<Vec<CondaEnv>>,
+    /// `--version` of tools like uv, by program.
+    tools: ProbeCells<String>,
+    /// Virtualenvs of projects, by tool and project directory.
+    project_envs: ProbeCells<PathBuf>,
+    /// WSL distributions, by `wsl.exe`.
+    wsl: ProbeCells<Vec<String>>,
+}
+
+impl Default for Entries {
+    fn default() -> Self {
+        Entries {
+            ttl: Duration::from_millis(DEFAULT_INTERPRETER_CACHE_TTL_MS),
+            available: HashMap::new(),
+            info: HashMap::new(),
+            conda: HashMap::new(),
+            tools: HashMap::new(),
+            project_envs: HashMap::new(),
+            wsl: HashMap::new(),
+        }
+    }
+}
+
+/// Default for the `interpreter_cache_ttl_ms` execution setting.
+pub const DEFAULT_INTERPRETER_CACHE_TTL_MS: u64 = 5 * 60 * 1_000;
+
+/// Probe results by command line, failures included, each kept for the TTL
+/// so a Python installed mid-session is found soon enough. Managed Tauri
+/// state.
+#[derive(Debug, Clone, Default)]
+pub struct InterpreterInfoCache {
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl InterpreterInfoCache {
+    /// How long results are kept from now on. Zero turns caching off.
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.lock().ttl = ttl;
+    }
+
+    /// Forgets every result, so each interpreter is probed again next time.
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.available.clear();
+        entries.info.clear();
+        entries.conda.clear();
+        entries.tools.clear();
+        entries.project_envs.clear();
+        entries.wsl.clear();
+    }
+
+    /// Whether `program --version` (with launcher `pre_args`) succeeds, and
+    /// the version it printed. A missing program is a `NotFound` error, as
+    /// when spawning it; a non-zero exit is an `Other` error.
+    pub async fn available(
+        &self,
+        program: &str,
+        pre_args: &[String],
+    ) -> Result<Option<InterpreterVersion>, std::io::Error> {
+        let cell = {
+            let mut entries = self.lock();
+            let ttl = entries.ttl;
+            fresh_cell(&mut entries.available, key(program, pre_args), ttl)
+        };
+        resolve(cell, || check_available(program, pre_args)).await
+    }
+
+    /// Probes `program` (with launcher `pre_args`) unless that was done
+    /// within the TTL. Errors are as for `available`.
+    pub async fn get(
+        &self,
+        program: &str,
+        pre_args: &[String],
+    ) -> Result<InterpreterInfo, std::io::Error> {
+        let cell = {
+            let mut entries = self.lock();
+            let ttl = entries.ttl;
+            fresh_cell(&mut entries.info, key(program, pre_args), ttl)
+        };
+        resolve(cell, || probe(program, pre_args)).await
+    }
+
+    /// What `conda env list` reports, unless it was asked within the TTL.
+    /// A missing conda is a `NotFound` error.
+    pub async fn conda_environments(&self) -> Result<Vec<CondaEnv>, std::io::Error> {
+        let cell = {
+            let mut entries = self.lock();
+            let ttl = entries.ttl;
+            fresh_cell(&mut entries.conda, vec![conda::program()], ttl)
+        };
+        resolve(cell, conda::list).await
+    }
+
+    /// What `program --version` prints, unless it was asked within the
+    /// TTL. Errors are as for `available`.
+    pub async fn tool_version(&self, program: &str) -> Result<String, std::io::Error> {
+        let cell = {
+            let mut entries = self.lock();
+            let ttl = entries.ttl;
+            fresh_cell(&mut entries.tools, vec![program.to_string()], ttl)
+        };
+        resolve(cell, || tool_version(program)).await
+    }
+
+    /// Where Poetry keeps the virtualenv of the project in `dir`. Errors are
+    /// as for `poetry::env_path`.
+    pub async fn poetry_env(&self, dir: &Path) -> Result<PathBuf, std::io::Error> {
+        let cell = {
+            let mut entries = self.lock();
+            let ttl = entries.ttl;
+            let key = vec![
+                poetry::PROGRAM.to_string(),
+                dir.to_string_lossy().to_string(),
+            ];
+            fresh_cell(&mut entries.project_envs, key, ttl)
+        };
+        resolve(cell, || poetry::env_path(dir)).await
+    }
+
+    /// Where pipenv keeps the virtualenv of the project in `dir`. Errors are
+    /// as for `pipenv::env_path`.
+    pub async fn pipenv_env(&self, dir: &Path) -> Result<PathBuf, std::io::Error> {
+        let cell = {
+            let mut entries = self.lock();
+            let ttl = entries.ttl;
+            let key = vec![
+                pipenv::PROGRAM.to_string(),
+                dir.to_string_lossy().to_string(),
+            ];
+            fresh_cell(&mut entries.project_envs, key, ttl)
+        };
+        resolve(cell, || pipenv::env_path(dir)).await
+    }
+
+    /// What `wsl.exe -l` reports. A missing `wsl.exe` is a `NotFound`
+    /// error.
+    pub async fn wsl_distributions(&self) -> Result<Vec<String>, std::io::Error> {
+        let cell = {
+            let mut entries = self.lock();
+            let ttl = entries.ttl;
+            fresh_cell(&mut entries.wsl, vec![wsl::PROGRAM.to_string()], ttl)
+        };
+        resolve(cell, wsl::distributions).await
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+fn key(program: &str, pre_args: &[String]) -> Vec<String> {
+    std::iter::once(program.to_string())
+        .chain(pre_args.iter().cloned())
+        .collect()
+}
+
+/// The cell for `key`, replaced first when its result is older than `ttl`.
+/// A timeout says more about a busy machine than the interpreter, so it is
+/// never reused.
+fn fresh_cell<T>(cells: &mut ProbeCells<T>, key: Vec<String>, ttl: Duration) -> ProbeCell<T> {
+    let cell = cells.entry(key).or_default();
+    let stale = cell.get().is_some_and(|(probed_at, outcome)| {
+        probed_at.elapsed() >= ttl || matches!(outcome, Err((std::io::ErrorKind::TimedOut, _)))
+    });
+    if stale {
+        *cell = ProbeCell::default();
+    }
+    cell.clone()
+}
+
+async fn resolve<T, F, Fut>(cell: ProbeCell<T>, probe: F) -> Result<T, std::io::Error>
+where
+    T: Clone,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, std::io::Error>>,
+{
+    let (_, outcome) = cell
+        .get_or_init(|| async {
+            let outcome = probe().await;
+            (
+                Instant::now(),
+                outcome.map_err(|error| (error.kind(), error.to_string())),
+            )
+        })
+        .await;
+    outcome
+        .clone()
+        .map_err(|(kind, message)| std::io::Error::new(kind, message))
+}
+
+async fn check_available(
+    program: &str,
+    pre_args: &[String],
+) -> Result<Option<InterpreterVersion>, std::io::Error> {
+    let mut command = Command::new(program);
+    command
+        .args(pre_args)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(PROBE_TIMEOUT, command.output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out running {} --version", program),
+            ))
+        }
+    };
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "--version exited with {}",
+            output.status
+        )));
+    }
+    Ok(
+        InterpreterVersion::parse(&String::from_utf8_lossy(&output.stdout))
+            .or_else(|| InterpreterVersion::parse(&String::from_utf8_lossy(&output.stderr))),
+    )
+}
+
+async fn tool_version(program: &str) -> Result<String, std::io::Error> {
+    let mut command = Command::new(program);
+    command
+        .arg("--version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(PROBE_TIMEOUT, command.output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out running {} --version", program),
+            ))
+        }
+    };
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "{} --version exited with {}",
+            program, output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+async fn probe(program: &str, pre_args: &[String]) -> Result<InterpreterInfo, std::io::Error> {
+    let mut command = Command::new(program);
+    command
+        .args(pre_args)
+        .arg("-c")
+        .arg(PROBE_CODE)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(PROBE_TIMEOUT, command.output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out probing {}", program),
+            ))
+        }
+    };
+    serde_json::from_slice(&output.stdout).map_err(|error| {
+        std::io::Error::other(format!(
+            "unexpected probe output from {}: {} ({})",
+            program,
+            error,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    })
+}
+
+/// A Python version such as `3.10`, `3.12.1` or `3.13.0rc2`. Missing parts
+/// count as zero; pre-releases sort before the release.
+#[derive(Debug, Clone)]
+pub struct PythonVersion {
+    release: [u32; 3],
+    /// Two-part versions, so `==3.10.*` can match a series.
+    parts: usize,
+    /// (0 = a, 1 = b, 2 = rc, number)
+    pre: Option<(u8, u32)>,
+}
+
+impl PythonVersion {
+    pub fn parse(text: &str) -> Option<Self> {
+        // `3.11.4+` is a build from a branch; `+local` tags don't order.
+        let text = text.trim().split('+').next()?;
+        let numeric_end = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(text.len());
+        let (numbers, suffix) = text.split_at(numeric_end);
+        let numbers: Vec<u32> = numbers
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<_>>()?;
+        if numbers.is_empty() || numbers.len() > 3 {
+            return None;
+        }
+        let pre = match suffix {
+            "" => None,
+            _ => {
+                let (kind, number) = [("rc", 2), ("a", 0), ("b", 1), ("c", 2)]
+                    .into_iter()
+                    .find_map(|(label, kind)| Some((kind, suffix.strip_prefix(label)?)))?;
+                Some((kind, number.parse().ok()?))
+            }
+        };
+        let mut release = [0; 3];
+        release[..numbers.len()].copy_from_slice(&numbers);
+        Some(PythonVersion {
+            release,
+            parts: numbers.len(),
+            pre,
+        })
+    }
+}
+
+impl Ord for PythonVersion {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.release
+            .cmp(&other.release)
+            .then_with(|| match (self.pre, other.pre) {
+                (None, None) => std::cmp::Ordering::Equal,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (Some(ours), Some(theirs)) => ours.cmp(&theirs),
+            })
+    }
+}
+
+/// `3.10` and `3.10.0` are the same version.
+impl PartialEq for PythonVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for PythonVersion {}
+
+impl PartialOrd for PythonVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A `requires-python` style constraint such as `>=3.10`, `~=3.11` or
+/// `>=3.9, <3.13`. A bare version means at least that version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRequirement {
+    text: String,
+    clauses: Vec<(&'static str, PythonVersion, bool)>,
+}
+
+const OPERATORS: &[&str] = &["~=", ">=", "<=", "==", "!=", ">", "<"];
+
+impl VersionRequirement {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid python version requirement: {:?}", text);
+        let mut clauses = Vec::new();
+        let mut shown = Vec::new();
+        for clause in text.split(',').map(str::trim) {
+            let operator = OPERATORS
+                .iter()
+                .copied()
+                .find(|operator| clause.starts_with(operator));
+            let version = clause[operator.map_or(0, str::len)..].trim();
+            let operator = operator.unwrap_or(">=");
+            let (number, wildcard) = match version.strip_suffix(".*") {
+                Some(prefix) if matches!(operator, "==" | "!=") => (prefix, true),
+                _ => (version, false),
+            };
+            let version_number = PythonVersion::parse(number).ok_or_else(invalid)?;
+            // `~=3` has no series to stay within.
+            if operator == "~=" && version_number.parts < 2 {
+                return Err(invalid());
+            }
+            clauses.push((operator, version_number, wildcard));
+            shown.push(format!("{} {}", operator, version));
+        }
+        Ok(VersionRequirement {
+            text: shown.join(", "),
+            clauses,
+        })
+    }
+
+    /// `false` for versions that can't be parsed.
+    pub fn matches(&self, version: &str) -> bool {
+        let Some(version) = PythonVersion::parse(version) else {
+            return false;
+        };
+        self.clauses.iter().all(|(operator, wanted, wildcard)| {
+            let same_series = || version.release[..wanted.parts] == wanted.release[..wanted.parts];
+            match (*operator, wildcard) {
+                ("==", true) => same_series(),
+                ("!=", true) => !same_series(),
+                ("~=", _) => {
+                    let series = wanted.parts - 1;
+                    version >= *wanted && version.release[..series] == wanted.release[..series]
+                }
+                (">=", _) => version >= *wanted,
+                ("<=", _) => version <= *wanted,
+                (">", _) => version > *wanted,
+                ("<", _) => version < *wanted,
+                ("==", _) => version == *wanted,
+                _ => version != *wanted,
+            }
+        })
+    }
+}
+
+/// Shown as `>= 3.10, < 3.13`.
+impl std::fmt::Display for VersionRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn probes_are_cached_per_command_line() {
+        let program = if cfg!(windows) { "python" } else { "python3" };
+        let cache = InterpreterInfoCache::default();
+        let info = cache.get(program, &[]).await.unwrap();
+        assert_eq!(info.version_info.0, 3);
+        assert!(std::path::Path::new(&info.executable).exists());
+        assert!(info.version.starts_with("3."));
+
+        assert_eq!(cache.get(program, &[]).await.unwrap(), info);
+        assert_eq!(cache.lock().info.len(), 1);
+        let error = cache.get("pdd-no-such-python", &[]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        let version = cache.available(program, &[]).await.unwrap().unwrap();
+        assert_eq!(version.to_string(), info.version);
+        assert_eq!(cache.lock().info.len(), 2);
+        assert_eq!(cache.lock().available.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn results_expire_after_the_ttl() {
+        let cache = InterpreterInfoCache::default();
+        let cached = || {
+            let mut entries = cache.lock();
+            let ttl = entries.ttl;
+            fresh_cell(&mut entries.available, key("pdd-no-such-python", &[]), ttl).initialized()
+        };
+        assert!(cache.available("pdd-no-such-python", &[]).await.is_err());
+        assert!(cached());
+
+        cache.set_ttl(Duration::ZERO);
+        assert!(!cached());
+        cache.set_ttl(Duration::from_secs(60));
+        assert!(cache.available("pdd-no-such-python", &[]).await.is_err());
+        assert!(cached());
+        cache.clear();
+        assert!(!cached());
+    }
+
+    #[test]
+    fn version_banners_are_parsed() {
+        let version = |text: &str| InterpreterVersion::parse(text).unwrap();
+        let distro = version("Python 3.11.2+\n");
+        assert_eq!((distro.major, distro.minor, distro.patch), (3, 11, 2));
+        assert_eq!(distro.implementation, "cpython");
+        assert_eq!(version("Python 2.7.18").to_string(), "2.7.18");
+        assert_eq!(version("Python 3.13.0rc2").to_string(), "3.13.0");
+        assert_eq!(version("Python 3.12.1 :: Anaconda, Inc.").minor, 12);
+        let pypy = version("Python 3.10.14 (75b3de9d9035, Apr 21 2024)\n[PyPy 7.3.16 with GCC]");
+        assert_eq!((pypy.minor, pypy.implementation.as_str()), (10, "pypy"));
+        assert_eq!(
+            version("IronPython 3.4.1 (3.4.1.1000)").implementation,
+            "ironpython"
+        );
+        for unexpected in ["", "Python", "Python three", "uv 0.4.0", "Python 3"] {
+            assert_eq!(
+                InterpreterVersion::parse(unexpected),
+                None,
+                "{}",
+                unexpected
+            );
+        }
+    }
+
+    #[test]
+    fn versions_order_pre_releases_first() {
+        let version = |text: &str| PythonVersion::parse(text).unwrap();
+        assert_eq!(version("3.10"), version("3.10.0"));
+        assert!(version("3.10.1") > version("3.10"));
+        assert!(version("3.13.0rc1") < version("3.13.0"));
+        assert!(version("3.13.0a2") < version("3.13.0b1"));
+        assert_eq!(version("3.11.4+").release, [3, 11, 4]);
+        for invalid in ["", "three", "3.x", "3.1.2.3", "3.13.0dev1"] {
+            assert_eq!(PythonVersion::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn requirements_match_versions() {
+        let requirement = |text: &str| VersionRequirement::parse(text).unwrap();
+        let modern = requirement(">=3.10");
+        assert_eq!(modern.to_string(), ">= 3.10");
+        assert!(modern.matches("3.10.0") && modern.matches("3.12.4"));
+        assert!(!modern.matches("3.8.10") && !modern.matches("3.10.0rc1"));
+        assert!(requirement("3.9").matches("3.9.1"));
+
+        let range = requirement(">= 3.9, <3.13");
+        assert_eq!(range.to_string(), ">= 3.9, < 3.13");
+        assert!(range.matches("3.12.9") && !range.matches("3.13.0"));
+        assert!(requirement("==3.11.*").matches("3.11.7"));
+        assert!(!requirement("!=3.11.*").matches("3.11.7"));
+        assert!(!requirement("==3.11").matches("3.11.7"));
+        assert!(requirement("~=3.10").matches("3.12.1"));
+        assert!(!requirement("~=3.10").matches("4.0"));
+        assert!(!requirement("~=3.10.2").matches("3.11.0"));
+        assert!(VersionRequirement::parse("~=3").is_err());
+        assert!(VersionRequirement::parse(">=three").is_err());
+        assert!(VersionRequirement::parse("").is_err());
+    }
+}
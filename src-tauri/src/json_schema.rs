@@ -0,0 +1,559 @@
+//! Checks script output against the JSON Schema a widget expects. The
+//! structural keywords of draft 2020-12 are supported (`type`, `properties`,
+//! `items`, the combinators, local `$ref`s and the numeric, string and array
+//! limits); annotations and keywords like `format` are ignored.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Deep enough for any sensible schema, shallow enough that a `$ref` cycle
+/// fails quickly.
+const MAX_DEPTH: usize = 64;
+
+/// A numeric keyword, whether a number satisfies it, and how to say so.
+type Bound = (&'static str, fn(f64, f64) -> bool, &'static str);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaViolation {
+    /// JSON pointer into the output, e.g. "/items/0/temp". "" is the root.
+    pub pointer: String,
+    pub message: String,
+}
+
+/// Every place `instance` breaks `schema`. An `Err` means the schema itself
+/// is unusable.
+pub fn validate(schema: &Value, instance: &Value) -> Result<Vec<SchemaViolation>, String> {
+    let mut validator = Validator {
+        root: schema,
+        violations: Vec::new(),
+    };
+    validator.check(schema, instance, "", 0)?;
+    Ok(validator.violations)
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    violations: Vec<SchemaViolation>,
+}
+
+impl<'a> Validator<'a> {
+    fn violation(&mut self, pointer: &str, message: String) {
+        self.violations.push(SchemaViolation {
+            pointer: pointer.to_string(),
+            message,
+        });
+    }
+
+    /// Whether `instance` matches, without recording anything; for the
+    /// combinators.
+    fn matches(
+        &self,
+        schema: &'a Value,
+        instance: &Value,
+        pointer: &str,
+        depth: usize,
+    ) -> Result<bool, String> {
+        let mut inner = Validator {
+            root: self.root,
+            violations: Vec::new(),
+        };
+        inner.check(schema, instance, pointer, depth)?;
+        Ok(inner.violations.is_empty())
+    }
+
+    fn check(
+        &mut self,
+        schema: &'a Value,
+        instance: &Value,
+        pointer: &str,
+        depth: usize,
+    ) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("schema nests too deeply (is there a $ref cycle?)".to_string());
+        }
+        let schema = match schema {
+            Value::Bool(true) => return Ok(()),
+            Value::Bool(false) => {
+                self.violation(pointer, "no value is allowed here".to_string());
+                return Ok(());
+            }
+            Value::Object(schema) => schema,
+            _ => {
+                return Err(format!(
+                    "schema at {:?} must be an object or boolean",
+                    pointer
+                ))
+            }
+        };
+
+        if let Some(reference) = schema.get("$ref") {
+            let target = self.resolve(reference)?;
+            self.check(target, instance, pointer, depth + 1)?;
+        }
+        self.check_type(schema, instance, pointer)?;
+        if let Some(allowed) = schema.get("enum") {
+            let allowed = allowed
+                .as_array()
+                .ok_or_else(|| "enum must be an array".to_string())?;
+            if !allowed.contains(instance) {
+                self.violation(
+                    pointer,
+                    format!("must be one of {}", Value::Array(allowed.clone())),
+                );
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != instance {
+                self.violation(pointer, format!("must be {}", expected));
+            }
+        }
+        self.check_combinators(schema, instance, pointer, depth)?;
+
+        match instance {
+            Value::Object(object) => self.check_object(schema, object, pointer, depth)?,
+            Value::Array(items) => self.check_array(schema, items, pointer, depth)?,
+            Value::String(text) => self.check_string(schema, text, pointer)?,
+            Value::Number(number) => {
+                if let Some(number) = number.as_f64() {
+                    self.check_number(schema, number, pointer)?;
+                }
+            }
+            Value::Bool(_) | Value::Null => {}
+        }
+        Ok(())
+    }
+
+    /// Only references into this schema (`#` or `#/...`) are supported.
+    fn resolve(&self, reference: &Value) -> Result<&'a Value, String> {
+        let reference = reference
+            .as_str()
+            .ok_or_else(|| "$ref must be a string".to_string())?;
+        let pointer = reference.strip_prefix('#').ok_or_else(|| {
+            format!(
+                "unsupported $ref {:?}: only local references work",
+                reference
+            )
+        })?;
+        self.root
+            .pointer(pointer)
+            .ok_or_else(|| format!("$ref {:?} does not point into the schema", reference))
+    }
+
+    fn check_type(
+        &mut self,
+        schema: &Map<String, Value>,
+        instance: &Value,
+        pointer: &str,
+    ) -> Result<(), String> {
+        let Some(expected) = schema.get("type") else {
+            return Ok(());
+        };
+        let types: Vec<&str> = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names
+                .iter()
+                .map(|name| {
+                    name.as_str()
+                        .ok_or_else(|| "type names must be strings".to_string())
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err("type must be a string or an array of strings".to_string()),
+        };
+        for name in &types {
+            if !matches!(
+                *name,
+                "null" | "boolean" | "object" | "array" | "number" | "integer" | "string"
+            ) {
+                return Err(format!("unknown type {:?}", name));
+            }
+        }
+        if !types.iter().any(|name| has_type(instance, name)) {
+            self.violation(
+                pointer,
+                format!(
+                    "expected {}, found {}",
+                    types.join(" or "),
+                    type_name(instance)
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    fn check_combinators(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        instance: &Value,
+        pointer: &str,
+        depth: usize,
+    ) -> Result<(), String> {
+        if let Some(all) = schema.get("allOf") {
+            for branch in schema_list(all, "allOf")? {
+                self.check(branch, instance, pointer, depth + 1)?;
+            }
+        }
+        if let Some(any) = schema.get("anyOf") {
+            let mut matched = false;
+            for branch in schema_list(any, "anyOf")? {
+                if self.matches(branch, instance, pointer, depth + 1)? {
+                    matched = true;
+                    break;
+                }
+            }
+            if !matched {
+                self.violation(
+                    pointer,
+                    "must match at least one schema in anyOf".to_string(),
+                );
+            }
+        }
+        if let Some(one) = schema.get("oneOf") {
+            let mut matched = 0;
+            for branch in schema_list(one, "oneOf")? {
+                if self.matches(branch, instance, pointer, depth + 1)? {
+                    matched += 1;
+                }
+            }
+            if matched != 1 {
+                self.violation(
+                    pointer,
+                    format!(
+                        "must match exactly one schema in oneOf, matched {}",
+                        matched
+                    ),
+                );
+            }
+        }
+        if let Some(not) = schema.get("not") {
+            if self.matches(not, instance, pointer, depth + 1)? {
+                self.violation(pointer, "must not match the schema in not".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_object(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        object: &Map<String, Value>,
+        pointer: &str,
+        depth: usize,
+    ) -> Result<(), String> {
+        if let Some(required) = schema.get("required") {
+            let required = required
+                .as_array()
+                .ok_or_else(|| "required must be an array".to_string())?;
+            for name in required {
+                let name = name
+                    .as_str()
+                    .ok_or_else(|| "required must list property names".to_string())?;
+                if !object.contains_key(name) {
+                    self.violation(pointer, format!("missing required property {:?}", name));
+                }
+            }
+        }
+
+        let properties = match schema.get("properties") {
+            Some(Value::Object(properties)) => Some(properties),
+            Some(_) => return Err("properties must be an object".to_string()),
+            None => None,
+        };
+        for (name, value) in object {
+            let child = format!("{}/{}", pointer, escape_pointer(name));
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(property) => self.check(property, value, &child, depth + 1)?,
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        self.violation(pointer, format!("property {:?} is not allowed", name))
+                    }
+                    Some(additional) => self.check(additional, value, &child, depth + 1)?,
+                    None => {}
+                },
+            }
+        }
+
+        let count = object.len() as u64;
+        if let Some(min) = limit(schema, "minProperties")? {
+            if count < min {
+                self.violation(pointer, format!("must have at least {} properties", min));
+            }
+        }
+        if let Some(max) = limit(schema, "maxProperties")? {
+            if count > max {
+                self.violation(pointer, format!("must have at most {} properties", max));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_array(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        items: &[Value],
+        pointer: &str,
+        depth: usize,
+    ) -> Result<(), String> {
+        let prefix: &[Value] = match schema.get("prefixItems") {
+            Some(prefix) => schema_list(prefix, "prefixItems")?,
+            None => &[],
+        };
+        for (index, item) in items.iter().enumerate() {
+            let child = format!("{}/{}", pointer, index);
+            if let Some(item_schema) = prefix.get(index).or_else(|| schema.get("items")) {
+                self.check(item_schema, item, &child, depth + 1)?;
+            }
+        }
+
+        let count = items.len() as u64;
+        if let Some(min) = limit(schema, "minItems")? {
+            if count < min {
+                self.violation(pointer, format!("must have at least {} items", min));
+            }
+        }
+        if let Some(max) = limit(schema, "maxItems")? {
+            if count > max {
+                self.violation(pointer, format!("must have at most {} items", max));
+            }
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+            let duplicate = items
+                .iter()
+                .enumerate()
+                .any(|(index, item)| items[..index].contains(item));
+            if duplicate {
+                self.violation(pointer, "items must be unique".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_string(
+        &mut self,
+        schema: &Map<String, Value>,
+        text: &str,
+        pointer: &str,
+    ) -> Result<(), String> {
+        let length = text.chars().count() as u64;
+        if let Some(min) = limit(schema, "minLength")? {
+            if length < min {
+                self.violation(pointer, format!("must be at least {} characters long", min));
+            }
+        }
+        if let Some(max) = limit(schema, "maxLength")? {
+            if length > max {
+                self.violation(pointer, format!("must be at most {} characters long", max));
+            }
+        }
+        if let Some(pattern) = schema.get("pattern") {
+            let pattern = pattern
+                .as_str()
+                .ok_or_else(|| "pattern must be a string".to_string())?;
+            let regex = regex::Regex::new(pattern)
+                .map_err(|error| format!("invalid pattern {:?}: {}", pattern, error))?;
+            if !regex.is_match(text) {
+                self.violation(pointer, format!("must match the pattern {:?}", pattern));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_number(
+        &mut self,
+        schema: &Map<String, Value>,
+        number: f64,
+        pointer: &str,
+    ) -> Result<(), String> {
+        let bounds: [Bound; 4] = [
+            ("minimum", |number, bound| number >= bound, "at least"),
+            ("maximum", |number, bound| number <= bound, "at most"),
+            (
+                "exclusiveMinimum",
+                |number, bound| number > bound,
+                "greater than",
+            ),
+            (
+                "exclusiveMaximum",
+                |number, bound| number < bound,
+                "less than",
+            ),
+        ];
+        for (keyword, holds, wording) in bounds {
+            if let Some(bound) = schema.get(keyword) {
+                let bound = bound
+                    .as_f64()
+                    .ok_or_else(|| format!("{} must be a number", keyword))?;
+                if !holds(number, bound) {
+                    self.violation(pointer, format!("must be {} {}", wording, bound));
+                }
+            }
+        }
+        if let Some(divisor) = schema.get("multipleOf") {
+            let divisor = divisor
+                .as_f64()
+                .filter(|divisor| *divisor > 0.0)
+                .ok_or_else(|| "multipleOf must be a positive number".to_string())?;
+            let quotient = number / divisor;
+            if (quotient - quotient.round()).abs() > 1e-9 {
+                self.violation(pointer, format!("must be a multiple of {}", divisor));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn has_type(instance: &Value, name: &str) -> bool {
+    match (name, instance) {
+        ("integer", Value::Number(number)) => {
+            number.is_i64() || number.is_u64() || number.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        _ => type_name(instance) == name,
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+    }
+}
+
+fn schema_list<'a>(value: &'a Value, keyword: &str) -> Result<&'a [Value], String> {
+    value
+        .as_array()
+        .map(Vec::as_slice)
+        .ok_or_else(|| format!("{} must be an array of schemas", keyword))
+}
+
+fn limit(schema: &Map<String, Value>, keyword: &str) -> Result<Option<u64>, String> {
+    schema
+        .get(keyword)
+        .map(|value| {
+            value
+                .as_u64()
+                .ok_or_else(|| format!("{} must be a non-negative integer", keyword))
+        })
+        .transpose()
+}
+
+/// RFC 6901: `~` and `/` in a key become `~0` and `~1`.
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Sorted, since property order depends on serde_json's features.
+    fn pointers(schema: Value, instance: Value) -> Vec<(String, String)> {
+        let mut violations: Vec<(String, String)> = validate(&schema, &instance)
+            .unwrap()
+            .into_iter()
+            .map(|violation| (violation.pointer, violation.message))
+            .collect();
+        violations.sort();
+        violations
+    }
+
+    #[test]
+    fn violations_point_at_the_offending_value() {
+        let schema = json!({
+            "type": "object",
+            "required": ["city", "readings"],
+            "additionalProperties": false,
+            "properties": {
+                "city": {"type": "string", "minLength": 1},
+                "readings": {
+                    "type": "array",
+                    "items": {"$ref": "#/$defs/reading"}
+                }
+            },
+            "$defs": {
+                "reading": {
+                    "type": "object",
+                    "required": ["temp"],
+                    "properties": {
+                        "temp": {"type": "number", "minimum": -90, "maximum": 60},
+                        "unit": {"enum": ["C", "F"]}
+                    }
+                }
+            }
+        });
+        assert_eq!(
+            pointers(
+                schema.clone(),
+                json!({"city": "Oslo", "readings": [{"temp": 4.5, "unit": "C"}]})
+            ),
+            []
+        );
+        assert_eq!(
+            pointers(
+                schema,
+                json!({"city": "", "readings": [{"temp": 99}, {"unit": "K"}], "a/b": 1})
+            ),
+            [
+                (
+                    "".to_string(),
+                    "property \"a/b\" is not allowed".to_string()
+                ),
+                (
+                    "/city".to_string(),
+                    "must be at least 1 characters long".to_string()
+                ),
+                (
+                    "/readings/0/temp".to_string(),
+                    "must be at most 60".to_string()
+                ),
+                (
+                    "/readings/1".to_string(),
+                    "missing required property \"temp\"".to_string()
+                ),
+                (
+                    "/readings/1/unit".to_string(),
+                    "must be one of [\"C\",\"F\"]".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn types_and_combinators() {
+        assert_eq!(
+            pointers(json!({"type": "integer"}), json!(2.5)),
+            [("".to_string(), "expected integer, found number".to_string())]
+        );
+        assert!(pointers(json!({"type": ["integer", "null"]}), json!(3.0)).is_empty());
+        let one_of = json!({"oneOf": [{"type": "number"}, {"minimum": 0}]});
+        assert!(pointers(one_of.clone(), json!("text")).is_empty());
+        assert_eq!(pointers(one_of, json!(5)).len(), 1);
+        assert_eq!(
+            pointers(
+                json!({"anyOf": [{"type": "string"}, {"type": "null"}]}),
+                json!(1)
+            )
+            .len(),
+            1
+        );
+        assert_eq!(pointers(json!({"not": {"const": 0}}), json!(0)).len(), 1);
+        assert!(pointers(json!(true), json!({"anything": [1]})).is_empty());
+    }
+
+    #[test]
+    fn unusable_schemas_are_errors() {
+        for schema in [
+            json!({"type": "float"}),
+            json!({"$ref": "https://example.com/schema.json"}),
+            json!({"$ref": "#/missing"}),
+            json!({"$ref": "#"}),
+            json!({"pattern": "("}),
+            json!("object"),
+        ] {
+            assert!(validate(&schema, &json!("x")).is_err(), "{}", schema);
+        }
+    }
+}
@@ -1,11 +1,70 @@
+use std::time::Duration;
+use tauri::Manager;
+
+mod bundled_python;
+mod cache;
+mod coalesce;
 mod commands;
+mod conda;
+mod decoding;
+mod discovery;
+mod failure;
+mod fs_util;
+mod interpreters;
+mod json_schema;
+mod login_shell;
+mod metadata;
+mod orphans;
+mod outdated;
+mod output_filter;
+mod pipenv;
+mod poetry;
+mod process_tree;
+mod profiles;
+mod progress;
+mod py_launcher;
+mod pyenv;
+mod python_settings;
+mod queue;
+mod rate_limit;
+mod resources;
+mod runner;
+mod runs;
+mod script_watcher;
+mod settings;
+mod shebang;
+mod source_format;
+mod templating;
+mod termination;
+mod traceback;
+mod validated_interpreters;
+mod validation_cache;
+mod virtualenv;
+mod wsl;
+
+/// How long running scripts get to stop when the app exits.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let registry = runs::RunRegistry::default();
+    let interpreters = registry.interpreters().clone();
+    let validated_interpreters = registry.validated_interpreters().clone();
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_notification::init())
+        .manage(registry)
+        .manage(queue::RunQueue::default())
+        .manage(settings::SettingsStore::default())
+        .manage(python_settings::PythonSettingsStore::default())
+        .manage(profiles::ProfileStore::default())
+        .manage(cache::ResultCache::default())
+        .manage(interpreters)
+        .manage(validated_interpreters)
+        .manage(validation_cache::ValidationCache::default())
+        .manage(outdated::OutdatedCache::default())
+        .manage(script_watcher::ScriptWatcher::default())
         .setup(|app| {
             if cfg!(debug_assertions) {
                 app.handle().plugin(
@@ -14,12 +73,118 @@ pub fn run() {
                         .build(),
                 )?;
             }
+
+            let handle = app.handle().clone();
+            app.state::<script_watcher::ScriptWatcher>()
+                .on_change(move |change| commands::script_changed(&handle, change));
+
+            if let Ok(config_dir) = app.path().app_config_dir() {
+                let python_settings = config_dir.join(python_settings::PYTHON_SETTINGS_FILE);
+                if let Some(warning) = app
+                    .state::<python_settings::PythonSettingsStore>()
+                    .load(python_settings)
+                {
+                    log::warn!("{}", warning);
+                }
+            }
+
+            if let Ok(data_dir) = app.path().app_data_dir() {
+                let bundled =
+                    bundled_python::interpreter(&data_dir.join(bundled_python::RUNTIME_DIR));
+                if bundled_python::is_offered() || bundled.is_file() {
+                    app.state::<python_settings::PythonSettingsStore>()
+                        .set_bundled_python(Some(bundled));
+                }
+
+                let marker = data_dir.join(orphans::MARKER_FILE);
+                for leftover in orphans::sweep(&marker) {
+                    log::warn!("{}", leftover);
+                }
+                app.state::<runs::RunRegistry>()
+                    .enable_orphan_marker(marker);
+
+                let settings = data_dir.join(settings::SETTINGS_FILE);
+                if let Err(error) = app.state::<settings::SettingsStore>().load(settings) {
+                    log::warn!("{}", error);
+                }
+                let execution_settings = app.state::<settings::SettingsStore>();
+                if execution_settings.get().login_shell_path {
+                    commands::read_login_shell_path(execution_settings.inner().clone());
+                }
+                let ttl = execution_settings.get().interpreter_cache_ttl_ms;
+                app.state::<interpreters::InterpreterInfoCache>()
+                    .set_ttl(Duration::from_millis(ttl));
+
+                let profiles = data_dir.join(profiles::PROFILES_FILE);
+                if let Err(error) = app.state::<profiles::ProfileStore>().load(profiles) {
+                    log::warn!("{}", error);
+                }
+
+                let validated = data_dir.join(validated_interpreters::VALIDATED_INTERPRETERS_FILE);
+                if let Err(error) = app
+                    .state::<validated_interpreters::ValidatedInterpreters>()
+                    .load(validated)
+                {
+                    log::warn!("{}", error);
+                }
+            }
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             commands::run_python_script,
+            commands::run_python_scripts,
+            commands::run_python_pipeline,
             commands::validate_python_script,
+            commands::validate_python_scripts,
+            commands::get_script_metadata,
+            commands::list_python_interpreters,
+            commands::check_interpreter,
+            commands::create_virtualenv,
+            commands::install_python_packages,
+            commands::list_installed_packages,
+            commands::export_requirements,
+            commands::check_outdated_packages,
+            commands::refresh_interpreters,
+            commands::get_script_arguments,
+            commands::test_script_output,
+            commands::run_python_code,
+            commands::cancel_python_script,
+            commands::set_max_concurrent_runs,
+            commands::start_python_script,
+            commands::get_run_status,
+            commands::get_run_result,
+            commands::set_run_result_retention,
+            commands::list_active_runs,
+            commands::get_execution_settings,
+            commands::set_execution_settings,
+            commands::get_python_settings,
+            commands::set_python_settings,
+            commands::get_candidates,
+            commands::set_candidates,
+            commands::reset_candidates,
+            commands::list_interpreter_aliases,
+            commands::add_interpreter_alias,
+            commands::remove_interpreter_alias,
+            commands::find_alias_usages,
+            commands::repair_python_path,
+            commands::ensure_bundled_python,
+            commands::kill_all_runs,
+            commands::save_script_profile,
+            commands::get_script_profile,
+            commands::list_script_profiles,
+            commands::delete_script_profile,
+            commands::invalidate_script_cache,
+            commands::invalidate_validation_cache,
+            commands::watch_script,
+            commands::unwatch_script,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Scripts must not outlive the dashboard.
+            if let tauri::RunEvent::Exit = event {
+                app.state::<runs::RunRegistry>().shutdown(SHUTDOWN_GRACE);
+                app.state::<script_watcher::ScriptWatcher>().clear();
+            }
+        });
 }
@@ -0,0 +1,82 @@
+//! The `PATH` of the user's login shell. Apps started from the Dock or a
+//! desktop launcher get a short system `PATH`, without what the shell
+//! profile adds (Homebrew, `~/.local/bin`, ...). Asking `$SHELL -l` once
+//! gives the one scripts would see from a terminal.
+
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Profiles that load slowly or wait for input must not hold up the app.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Printed around the value, so whatever the profile itself prints is
+/// told apart from it.
+const MARKER: &str = "__PDD_LOGIN_PATH__";
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// Asks `$SHELL -l` for its `PATH`. Always an error on Windows, where GUI
+/// apps inherit the user's full `PATH`.
+pub async fn read_path() -> Result<String, String> {
+    if cfg!(windows) {
+        return Err("the login-shell PATH is only read on macOS and Linux".to_string());
+    }
+    let shell = std::env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.trim().is_empty())
+        .unwrap_or_else(|| FALLBACK_SHELL.to_string());
+    let mut command = Command::new(&shell);
+    command
+        .args(["-l", "-c", &print_command(&shell)])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    let output = match tokio::time::timeout(READ_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) => return Err(format!("failed to start {}: {}", shell, error)),
+        Err(_) => {
+            return Err(format!(
+                "{} -l took longer than {}s",
+                shell,
+                READ_TIMEOUT.as_secs()
+            ))
+        }
+    };
+    parse_output(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| format!("{} -l did not report a PATH", shell))
+}
+
+/// fish keeps `PATH` as a list.
+fn print_command(shell: &str) -> String {
+    let value = match shell.rsplit('/').next() {
+        Some("fish") => "(string join : $PATH)",
+        _ => "\"$PATH\"",
+    };
+    format!("printf '{m}%s{m}' {}", value, m = MARKER)
+}
+
+fn parse_output(output: &str) -> Option<String> {
+    let (_, rest) = output.split_once(MARKER)?;
+    let (path, _) = rest.split_once(MARKER)?;
+    Some(path.trim().to_string()).filter(|path| !path.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_path_is_picked_out_of_profile_noise() {
+        let output = format!(
+            "Welcome back!\n{m}/opt/homebrew/bin:/usr/bin:/bin{m}",
+            m = MARKER
+        );
+        assert_eq!(
+            parse_output(&output).as_deref(),
+            Some("/opt/homebrew/bin:/usr/bin:/bin")
+        );
+        assert_eq!(parse_output("no marker here"), None);
+        assert_eq!(parse_output(&format!("{m}{m}", m = MARKER)), None);
+        assert!(print_command("/usr/local/bin/fish").contains("string join"));
+        assert!(print_command("/bin/zsh").contains("\"$PATH\""));
+    }
+}
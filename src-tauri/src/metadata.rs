@@ -0,0 +1,515 @@
+//! What a script says about itself, read from its source without running
+//! it: the module docstring and an optional header block like
+//!
+//! ```text
+//! # pdd-name: Weather
+//! # pdd-description: Current conditions from the met office API
+//! # pdd-args: city:str, days:int
+//! # pdd-requires-python: >=3.10
+//! # pdd-requires-stdlib: tkinter, sqlite3
+//! ```
+//!
+//! Header lines are only recognized before the first statement after the
+//! docstring. A PEP 723 `# /// script` block, which may appear anywhere, is
+//! read too. Anything malformed is reported as a warning and skipped.
+
+use crate::interpreters::VersionRequirement;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+
+const HEADER_PREFIX: &str = "pdd-";
+/// Only the top of the file can hold a header or docstring worth reading.
+const MAX_SOURCE_BYTES: u64 = 64 * 1024;
+const ARG_TYPES: &[&str] = &["str", "int", "float", "bool"];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ScriptMetadata {
+    /// `pdd-name`.
+    pub name: Option<String>,
+    /// `pdd-description`, or else the first line of the docstring.
+    pub description: Option<String>,
+    /// Indentation removed, as `inspect.cleandoc` does.
+    pub docstring: Option<String>,
+    /// `pdd-args`, in order.
+    pub args: Vec<ScriptArg>,
+    /// `pdd-requires-python`, e.g. ">=3.10". Only set when it parses.
+    pub requires_python: Option<String>,
+    /// `pdd-requires-stdlib`: standard library modules that some builds of
+    /// Python leave out, like `tkinter`.
+    pub requires_stdlib: Vec<String>,
+    /// The PEP 723 block, if the script has one.
+    pub inline: Option<InlineMetadata>,
+}
+
+impl ScriptMetadata {
+    /// `pdd-requires-python`, or else the PEP 723 `requires-python`.
+    pub fn python_requirement(&self) -> Option<&str> {
+        self.requires_python.as_deref().or_else(|| {
+            self.inline
+                .as_ref()
+                .and_then(|inline| inline.requires_python.as_deref())
+        })
+    }
+}
+
+/// The `# /// script` block of PEP 723. Other tables, like `[tool]`, are
+/// ignored.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct InlineMetadata {
+    /// Only set when it parses.
+    pub requires_python: Option<String>,
+    /// PEP 508 requirements, as written.
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScriptArg {
+    pub name: String,
+    /// One of "str", "int", "float" or "bool". "str" when not given.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+/// Reads the metadata of the script at `path`, with warnings for headers
+/// that could not be used.
+pub fn read(path: &Path) -> Result<(ScriptMetadata, Vec<String>), String> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)
+        .and_then(|file| file.take(MAX_SOURCE_BYTES).read_to_end(&mut bytes))
+        .map_err(|error| format!("failed to read {}: {}", path.display(), error))?;
+    Ok(parse(&String::from_utf8_lossy(&bytes)))
+}
+
+pub fn parse(source: &str) -> (ScriptMetadata, Vec<String>) {
+    let mut metadata = ScriptMetadata::default();
+    let mut warnings = Vec::new();
+    let lines: Vec<&str> = source.trim_start_matches('\u{feff}').lines().collect();
+    let mut description = None;
+
+    let mut index = 0;
+    while let Some(line) = lines.get(index) {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            index += 1;
+        } else if let Some(comment) = trimmed.strip_prefix('#') {
+            if let Some(header) = comment.trim_start().strip_prefix(HEADER_PREFIX) {
+                apply_header(
+                    header,
+                    index + 1,
+                    &mut metadata,
+                    &mut description,
+                    &mut warnings,
+                );
+            }
+            index += 1;
+        } else if metadata.docstring.is_none() {
+            match docstring(&lines[index..]) {
+                Some((text, consumed)) => {
+                    metadata.docstring = Some(text);
+                    index += consumed;
+                }
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+
+    metadata.inline = inline_metadata(&lines, &mut warnings);
+    metadata.description = description.or_else(|| {
+        metadata
+            .docstring
+            .as_deref()
+            .and_then(|docstring| docstring.lines().next())
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+    });
+    (metadata, warnings)
+}
+
+fn apply_header(
+    header: &str,
+    line: usize,
+    metadata: &mut ScriptMetadata,
+    description: &mut Option<String>,
+    warnings: &mut Vec<String>,
+) {
+    let Some((key, value)) = header.split_once(':') else {
+        warnings.push(format!(
+            "line {}: expected `# {}key: value`",
+            line, HEADER_PREFIX
+        ));
+        return;
+    };
+    let (key, value) = (key.trim(), value.trim());
+    let slot = match key {
+        "name" => &mut metadata.name,
+        "description" => description,
+        "requires-python" => {
+            if let (false, Err(error)) = (value.is_empty(), VersionRequirement::parse(value)) {
+                warnings.push(format!("line {}: {}", line, error));
+                return;
+            }
+            &mut metadata.requires_python
+        }
+        "args" => {
+            if !metadata.args.is_empty() {
+                warnings.push(format!(
+                    "line {}: {}args given more than once, using the first",
+                    line, HEADER_PREFIX
+                ));
+                return;
+            }
+            metadata.args = parse_args(value, line, warnings);
+            return;
+        }
+        "requires-stdlib" => {
+            if !metadata.requires_stdlib.is_empty() {
+                warnings.push(format!(
+                    "line {}: {}requires-stdlib given more than once, using the first",
+                    line, HEADER_PREFIX
+                ));
+                return;
+            }
+            metadata.requires_stdlib = parse_modules(value, line, warnings);
+            return;
+        }
+        _ => {
+            warnings.push(format!(
+                "line {}: unknown header {}{}",
+                line, HEADER_PREFIX, key
+            ));
+            return;
+        }
+    };
+    if value.is_empty() {
+        warnings.push(format!("line {}: {}{} is empty", line, HEADER_PREFIX, key));
+    } else if slot.is_some() {
+        warnings.push(format!(
+            "line {}: {}{} given more than once, using the first",
+            line, HEADER_PREFIX, key
+        ));
+    } else {
+        *slot = Some(value.to_string());
+    }
+}
+
+/// Finds and parses the `# /// script` block. Per PEP 723 the block ends at
+/// the last `# ///` line before the comments stop.
+fn inline_metadata(lines: &[&str], warnings: &mut Vec<String>) -> Option<InlineMetadata> {
+    let mut starts = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.trim_end() == "# /// script")
+        .map(|(index, _)| index);
+    let start = starts.next()?;
+    if let Some(second) = starts.next() {
+        warnings.push(format!(
+            "line {}: more than one `# /// script` block, using the first",
+            second + 1
+        ));
+    }
+
+    let comments = lines[start + 1..]
+        .iter()
+        .take_while(|line| line.starts_with('#'))
+        .count();
+    let Some(end) = lines[start + 1..start + 1 + comments]
+        .iter()
+        .rposition(|line| line.trim_end() == "# ///")
+    else {
+        warnings.push(format!(
+            "line {}: `# /// script` block is never closed",
+            start + 1
+        ));
+        return None;
+    };
+
+    let mut toml = String::new();
+    for (offset, line) in lines[start + 1..start + 1 + end].iter().enumerate() {
+        let content = match line.strip_prefix('#') {
+            Some("") => "",
+            Some(content) => match content.strip_prefix(' ') {
+                Some(content) => content,
+                None => {
+                    warnings.push(format!(
+                        "line {}: lines in a `# /// script` block must start with `# `",
+                        start + offset + 2
+                    ));
+                    return None;
+                }
+            },
+            None => unreachable!("only comment lines are taken"),
+        };
+        toml.push_str(content);
+        toml.push('\n');
+    }
+
+    let mut inline: InlineMetadata = match toml::from_str(&toml) {
+        Ok(inline) => inline,
+        Err(error) => {
+            warnings.push(format!(
+                "line {}: invalid `# /// script` block: {}",
+                start + 1,
+                error.message()
+            ));
+            return None;
+        }
+    };
+    if let Some(error) = inline
+        .requires_python
+        .as_deref()
+        .and_then(|text| VersionRequirement::parse(text).err())
+    {
+        warnings.push(format!("line {}: {}", start + 1, error));
+        inline.requires_python = None;
+    }
+    Some(inline)
+}
+
+/// `city:str, days:int, verbose`
+fn parse_args(value: &str, line: usize, warnings: &mut Vec<String>) -> Vec<ScriptArg> {
+    let mut args: Vec<ScriptArg> = Vec::new();
+    for entry in value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (name, kind) = match entry.split_once(':') {
+            Some((name, kind)) => (name.trim(), kind.trim()),
+            None => (entry, "str"),
+        };
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            warnings.push(format!("line {}: invalid argument name `{}`", line, name));
+        } else if !ARG_TYPES.contains(&kind) {
+            warnings.push(format!(
+                "line {}: argument `{}` has unknown type `{}` (expected one of {})",
+                line,
+                name,
+                kind,
+                ARG_TYPES.join(", ")
+            ));
+        } else if args.iter().any(|arg| arg.name == name) {
+            warnings.push(format!("line {}: argument `{}` listed twice", line, name));
+        } else {
+            args.push(ScriptArg {
+                name: name.to_string(),
+                kind: kind.to_string(),
+            });
+        }
+    }
+    args
+}
+
+/// `tkinter, sqlite3, dbm.gnu`
+fn parse_modules(value: &str, line: usize, warnings: &mut Vec<String>) -> Vec<String> {
+    let mut modules: Vec<String> = Vec::new();
+    for module in value
+        .split(',')
+        .map(str::trim)
+        .filter(|module| !module.is_empty())
+    {
+        let valid = module.split('.').all(|part| {
+            part.chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+        });
+        if !valid {
+            warnings.push(format!("line {}: invalid module name `{}`", line, module));
+        } else if !modules.iter().any(|listed| listed == module) {
+            modules.push(module.to_string());
+        }
+    }
+    modules
+}
+
+/// The string literal starting at `lines[0]`, cleaned up, and how many
+/// lines it spans. `None` if the first statement isn't a string. Escapes are
+/// left as written.
+fn docstring(lines: &[&str]) -> Option<(String, usize)> {
+    let first = lines[0].trim_start();
+    let prefix_len = first
+        .find(|c: char| !matches!(c, 'r' | 'R' | 'u' | 'U'))
+        .filter(|&len| len <= 1)?;
+    let literal = &first[prefix_len..];
+    let quote = ["\"\"\"", "'''", "\"", "'"]
+        .into_iter()
+        .find(|quote| literal.starts_with(quote))?;
+    let rest = &literal[quote.len()..];
+
+    if quote.len() == 1 {
+        let end = rest.find(quote)?;
+        return Some((rest[..end].trim().to_string(), 1));
+    }
+    if let Some(end) = rest.find(quote) {
+        return Some((rest[..end].trim().to_string(), 1));
+    }
+    let mut body = vec![rest];
+    for (offset, line) in lines[1..].iter().enumerate() {
+        if let Some(end) = line.find(quote) {
+            body.push(&line[..end]);
+            return Some((clean_docstring(&body), offset + 2));
+        }
+        body.push(line);
+    }
+    None
+}
+
+/// Like `inspect.cleandoc`: the first line is stripped, the rest lose their
+/// common indentation, and blank lines at either end are dropped.
+fn clean_docstring(lines: &[&str]) -> String {
+    let indent = lines[1..]
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut cleaned: Vec<&str> = std::iter::once(lines[0].trim())
+        .chain(lines[1..].iter().map(|line| {
+            line.get(indent..)
+                .unwrap_or_else(|| line.trim_start())
+                .trim_end()
+        }))
+        .collect();
+    while cleaned.first().is_some_and(|line| line.is_empty()) {
+        cleaned.remove(0);
+    }
+    while cleaned.last().is_some_and(|line| line.is_empty()) {
+        cleaned.pop();
+    }
+    cleaned.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_and_docstring_are_read() {
+        let (metadata, warnings) = parse(
+            "#!/usr/bin/env python3\n# pdd-name: Weather\n# pdd-args: city:str, days:int, verbose\n\n\"\"\"Fetch the forecast.\n\n    Prints one JSON object.\n\"\"\"\n# pdd-description: Forecast for a city\nimport json\n# pdd-name: ignored after code\n",
+        );
+        assert_eq!(warnings, Vec::<String>::new());
+        assert_eq!(metadata.name.as_deref(), Some("Weather"));
+        assert_eq!(metadata.description.as_deref(), Some("Forecast for a city"));
+        assert_eq!(
+            metadata.docstring.as_deref(),
+            Some("Fetch the forecast.\n\nPrints one JSON object.")
+        );
+        let args: Vec<(&str, &str)> = metadata
+            .args
+            .iter()
+            .map(|arg| (arg.name.as_str(), arg.kind.as_str()))
+            .collect();
+        assert_eq!(args, [("city", "str"), ("days", "int"), ("verbose", "str")]);
+    }
+
+    #[test]
+    fn requires_python_must_be_a_requirement() {
+        let (metadata, warnings) = parse(
+            "# pdd-requires-python: >=3.10
+",
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(metadata.requires_python.as_deref(), Some(">=3.10"));
+
+        let (metadata, warnings) = parse(
+            "# pdd-requires-python: python 3
+",
+        );
+        assert_eq!(metadata.requires_python, None);
+        assert_eq!(
+            warnings,
+            ["line 1: invalid python version requirement: \"python 3\""]
+        );
+    }
+
+    #[test]
+    fn missing_headers_fall_back_to_the_docstring() {
+        let (metadata, warnings) = parse("r'''Stock prices.'''\nprint(1)\n");
+        assert!(warnings.is_empty());
+        assert_eq!(metadata.name, None);
+        assert_eq!(metadata.description.as_deref(), Some("Stock prices."));
+
+        assert_eq!(
+            parse("import os\n\"\"\"not a docstring\"\"\"\n").0,
+            ScriptMetadata::default()
+        );
+        assert_eq!(parse("\"\"\"never closed\n").0.docstring, None);
+    }
+
+    #[test]
+    fn pep_723_blocks_are_read() {
+        let (metadata, warnings) = parse(
+            "import sys\n\n# /// script\n# requires-python = \">=3.11\"\n# dependencies = [\n#   \"requests<3\",\n#   \"rich\",\n# ]\n#\n# [tool.uv]\n# exclude-newer = \"2024-01-01T00:00:00Z\"\n# ///\n\nprint(sys.argv)\n",
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let inline = metadata.inline.clone().unwrap();
+        assert_eq!(inline.dependencies, ["requests<3", "rich"]);
+        assert_eq!(metadata.python_requirement(), Some(">=3.11"));
+
+        let (metadata, _) = parse(
+            "# pdd-requires-python: >=3.12\n# /// script\n# requires-python = \">=3.11\"\n# ///\n",
+        );
+        assert_eq!(metadata.python_requirement(), Some(">=3.12"));
+        assert_eq!(parse("print(1)\n").0.inline, None);
+    }
+
+    #[test]
+    fn malformed_pep_723_blocks_are_warnings() {
+        for (source, warning) in [
+            (
+                "# /// script\n# dependencies = []\nprint(1)\n",
+                "never closed",
+            ),
+            (
+                "# /// script\n#dependencies = []\n# ///\n",
+                "must start with `# `",
+            ),
+            (
+                "# /// script\n# dependencies = [\n# ///\n",
+                "invalid `# /// script` block",
+            ),
+            (
+                "# /// script\n# requires-python = \"new\"\n# ///\n",
+                "invalid python version requirement",
+            ),
+        ] {
+            let (_, warnings) = parse(source);
+            assert_eq!(warnings.len(), 1, "{:?}", warnings);
+            assert!(warnings[0].contains(warning), "{:?}", warnings);
+        }
+        let (metadata, warnings) =
+            parse("# /// script\n# dependencies = [\"rich\"]\n# ///\n\n# /// script\n# ///\n");
+        assert_eq!(metadata.inline.unwrap().dependencies, ["rich"]);
+        assert!(warnings[0].starts_with("line 5: more than one"));
+    }
+
+    #[test]
+    fn required_stdlib_modules_are_listed() {
+        let (metadata, warnings) =
+            parse("# pdd-requires-stdlib: tkinter, sqlite3,, dbm.gnu, 2to3, tkinter\n");
+        assert_eq!(metadata.requires_stdlib, ["tkinter", "sqlite3", "dbm.gnu"]);
+        assert_eq!(warnings, ["line 1: invalid module name `2to3`"]);
+    }
+
+    #[test]
+    fn malformed_headers_are_warnings() {
+        let (metadata, warnings) = parse(
+            "# pdd-name Weather\n# pdd-colour: blue\n# pdd-args: city:str, days:integer, 2 x:int, city\n# pdd-description:\n",
+        );
+        assert_eq!(metadata.name, None);
+        assert_eq!(metadata.description, None);
+        assert_eq!(metadata.args.len(), 1);
+        assert_eq!(warnings.len(), 6, "{:?}", warnings);
+        assert!(warnings[0].starts_with("line 1: "));
+        assert!(warnings[2].contains("unknown type `integer`"));
+    }
+}
@@ -0,0 +1,222 @@
+//! Crash resilience for script processes. While scripts run, their process
+//! groups are recorded in a marker file in the app data dir; if the app dies
+//! without cleaning up, the next launch finds the file and kills whatever is
+//! still alive. On Windows the run's Job Object is kill-on-close, so the OS
+//! already takes care of this and the sweep only reports.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const MARKER_FILE: &str = "running-scripts.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MarkerEntry {
+    pid: u32,
+    /// Kernel start time of the process on Linux, so a recycled pid is never
+    /// mistaken for the script.
+    start_ticks: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct MarkerState {
+    path: Option<PathBuf>,
+    entries: HashMap<String, MarkerEntry>,
+}
+
+/// The live view of the marker file. Recording is a no-op until `enable`.
+#[derive(Debug, Clone, Default)]
+pub struct PidMarker {
+    state: Arc<Mutex<MarkerState>>,
+}
+
+impl PidMarker {
+    pub fn enable(&self, path: PathBuf) {
+        let mut state = self.lock();
+        state.path = Some(path);
+        write_marker(&state);
+    }
+
+    pub fn record(&self, run_id: &str, pid: u32) {
+        let mut state = self.lock();
+        state.entries.insert(
+            run_id.to_string(),
+            MarkerEntry {
+                pid,
+                start_ticks: process_start_ticks(pid),
+            },
+        );
+        write_marker(&state);
+    }
+
+    pub fn forget(&self, run_id: &str) {
+        let mut state = self.lock();
+        if state.entries.remove(run_id).is_some() {
+            write_marker(&state);
+        }
+    }
+
+    /// Last resort at shutdown for runs that didn't stop in time.
+    pub fn kill_all(&self) -> usize {
+        let mut state = self.lock();
+        let killed = state
+            .entries
+            .values()
+            .filter(|entry| kill_if_same_process(entry))
+            .count();
+        state.entries.clear();
+        write_marker(&state);
+        killed
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MarkerState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+fn write_marker(state: &MarkerState) {
+    let Some(path) = &state.path else {
+        return;
+    };
+
+    let result = if state.entries.is_empty() {
+        match std::fs::remove_file(path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    } else {
+        let json = serde_json::to_vec(&state.entries).unwrap_or_default();
+        path.parent()
+            .map(std::fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| std::fs::write(path, json))
+    };
+    if let Err(error) = result {
+        log::warn!("failed to update {}: {}", path.display(), error);
+    }
+}
+
+/// Cleans up after a previous session and removes its marker file. Returns
+/// one line per leftover run for the log.
+pub fn sweep(path: &Path) -> Vec<String> {
+    let Ok(contents) = std::fs::read(path) else {
+        return Vec::new();
+    };
+    let _ = std::fs::remove_file(path);
+    let entries: HashMap<String, MarkerEntry> =
+        serde_json::from_slice(&contents).unwrap_or_default();
+
+    entries
+        .into_iter()
+        .filter(|(_, entry)| is_alive(entry.pid))
+        .map(|(run_id, entry)| {
+            if kill_if_same_process(&entry) {
+                format!("killed leftover run {} (pid {})", run_id, entry.pid)
+            } else {
+                format!(
+                    "run {} from a previous session may still be running (pid {})",
+                    run_id, entry.pid
+                )
+            }
+        })
+        .collect()
+}
+
+fn is_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: signal 0 only checks that the process exists.
+        unsafe { libc::kill(pid as i32, 0) == 0 }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        false
+    }
+}
+
+/// Kills the process group led by `entry.pid`, but only when the process can
+/// be proven to be the one that was recorded.
+fn kill_if_same_process(entry: &MarkerEntry) -> bool {
+    match (entry.start_ticks, process_start_ticks(entry.pid)) {
+        (Some(recorded), Some(current)) if recorded == current => {}
+        _ => return false,
+    }
+
+    #[cfg(unix)]
+    {
+        // SAFETY: signalling a process group has no memory-safety
+        // preconditions.
+        unsafe { libc::kill(-(entry.pid as i32), libc::SIGKILL) == 0 }
+    }
+
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_start_ticks(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // `starttime` is field 22; fields resume after the command name's ')'.
+    stat.get(stat.rfind(')')? + 1..)?
+        .split_whitespace()
+        .nth(19)?
+        .parse()
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_start_ticks(_pid: u32) -> Option<u64> {
+    None
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+
+    #[test]
+    fn sweep_kills_recorded_groups_from_a_crashed_session() {
+        let dir = std::env::temp_dir().join(format!("pdd-orphans-{}", std::process::id()));
+        let path = dir.join(MARKER_FILE);
+        let mut orphan = std::process::Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+
+        let marker = PidMarker::default();
+        marker.enable(path.clone());
+        marker.record("crashed-run", orphan.id());
+        assert!(path.exists());
+
+        let report = sweep(&path);
+        assert_eq!(report.len(), 1, "{:?}", report);
+        assert!(report[0].starts_with("killed leftover run crashed-run"));
+        assert!(orphan.wait().unwrap().code().is_none());
+        assert!(!path.exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn recycled_pids_are_left_alone() {
+        let mut process = std::process::Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let stale = MarkerEntry {
+            pid: process.id(),
+            start_ticks: process_start_ticks(process.id()).map(|ticks| ticks + 1),
+        };
+
+        assert!(!kill_if_same_process(&stale));
+        assert!(process.try_wait().unwrap().is_none());
+        process.kill().unwrap();
+        process.wait().unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! `pip list --outdated`: which installed packages have newer releases on
+//! the package index. The check goes over the network, so results are kept
+//! for a while, and an unreachable index is an outcome of its own rather
+//! than an error.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+use crate::process_tree;
+
+/// pip gives up on an index after `--retries` and `--timeout`; this is for
+/// a pip that hangs anyway.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(120);
+/// Per request, in seconds. pip's defaults (5 retries of 15s) keep an
+/// offline check going for minutes.
+const PIP_TIMEOUT_SECS: &str = "10";
+const PIP_RETRIES: &str = "1";
+
+/// What pip and the networking code under it print when the index can't be
+/// reached. pip itself only warns and reports nothing outdated.
+const OFFLINE_SIGNATURES: &[&str] = &[
+    "NewConnectionError",
+    "Failed to establish a new connection",
+    "ConnectTimeoutError",
+    "Temporary failure in name resolution",
+    "Name or service not known",
+    "nodename nor servname provided",
+    "getaddrinfo failed",
+    "Network is unreachable",
+    "No route to host",
+    "Could not fetch URL",
+];
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct OutdatedPackage {
+    pub name: String,
+    #[serde(rename(deserialize = "version"))]
+    pub current: String,
+    #[serde(rename(deserialize = "latest_version"))]
+    pub latest: String,
+    /// "wheel" or "sdist".
+    pub latest_filetype: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Checked(Vec<OutdatedPackage>),
+    /// The index couldn't be reached; says why.
+    Offline(String),
+}
+
+/// Runs `program pre_args -m pip list --outdated` against `index_url`, or
+/// pip's configured index.
+pub async fn check(
+    program: &str,
+    pre_args: &[String],
+    index_url: Option<&str>,
+) -> Result<Outcome, String> {
+    let mut command = Command::new(program);
+    command
+        .args(pre_args)
+        .args(["-m", "pip", "list", "--outdated", "--format=json"])
+        .args(["--disable-pip-version-check", "--no-input"])
+        .args(["--retries", PIP_RETRIES, "--timeout", PIP_TIMEOUT_SECS])
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    if let Some(url) = index_url {
+        command.args(["--index-url", url]);
+    }
+    process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(CHECK_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) => return Err(format!("failed to run pip: {}", error)),
+        Err(_) => {
+            return Ok(Outcome::Offline(format!(
+                "pip did not finish within {}s",
+                CHECK_TIMEOUT.as_secs()
+            )))
+        }
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(reason) = offline_reason(&stderr) {
+        return Ok(Outcome::Offline(reason));
+    }
+    if !output.status.success() {
+        let reason = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("failed");
+        return Err(match reason.contains("No module named pip") {
+            true => format!("pip is not installed in {}", program),
+            false => format!("pip list --outdated: {}", reason.trim()),
+        });
+    }
+    parse(&String::from_utf8_lossy(&output.stdout)).map(Outcome::Checked)
+}
+
+/// The first stderr line that says the network is the problem.
+fn offline_reason(stderr: &str) -> Option<String> {
+    stderr
+        .lines()
+        .find(|line| {
+            OFFLINE_SIGNATURES
+                .iter()
+                .any(|signature| line.contains(signature))
+        })
+        .map(|line| line.trim().to_string())
+}
+
+fn parse(stdout: &str) -> Result<Vec<OutdatedPackage>, String> {
+    // Anything before the JSON, like a notice from a pip plugin, is skipped.
+    let json = stdout.find('[').map_or(stdout, |start| &stdout[start..]);
+    let mut packages: Vec<OutdatedPackage> = serde_json::from_str(json.trim())
+        .map_err(|error| format!("unexpected output from pip list: {}", error))?;
+    packages.sort_by_key(|package| package.name.to_lowercase());
+    Ok(packages)
+}
+
+/// The interpreter's `sys.executable` and the index asked.
+type Key = (String, Option<String>);
+
+#[derive(Debug, Clone)]
+struct Entry {
+    packages: Vec<OutdatedPackage>,
+    checked_at: Instant,
+    checked_at_ms: u64,
+}
+
+/// Recent successful checks, by interpreter and index. Managed Tauri state.
+#[derive(Debug, Clone, Default)]
+pub struct OutdatedCache {
+    entries: Arc<Mutex<HashMap<Key, Entry>>>,
+}
+
+impl OutdatedCache {
+    /// The packages and when they were checked, if that was within `ttl`.
+    pub fn get(
+        &self,
+        executable: &str,
+        index_url: Option<&str>,
+        ttl: Duration,
+    ) -> Option<(Vec<OutdatedPackage>, u64)> {
+        let key = (executable.to_string(), index_url.map(str::to_string));
+        let mut entries = self.lock();
+        let entry = entries.get(&key)?;
+        if entry.checked_at.elapsed() >= ttl {
+            entries.remove(&key);
+            return None;
+        }
+        Some((entry.packages.clone(), entry.checked_at_ms))
+    }
+
+    pub fn insert(
+        &self,
+        executable: &str,
+        index_url: Option<&str>,
+        packages: Vec<OutdatedPackage>,
+        checked_at_ms: u64,
+    ) {
+        self.lock().insert(
+            (executable.to_string(), index_url.map(str::to_string)),
+            Entry {
+                packages,
+                checked_at: Instant::now(),
+                checked_at_ms,
+            },
+        );
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Key, Entry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pip_output_is_parsed_and_offline_runs_recognized() {
+        let stdout = r#"[{"name": "requests", "version": "2.31.0", "latest_version": "2.32.3", "latest_filetype": "wheel"}, {"name": "beautifulsoup4", "version": "4.12.2", "latest_version": "4.12.3", "latest_filetype": "sdist"}]"#;
+        let packages = parse(stdout).unwrap();
+        assert_eq!(packages[0].name, "beautifulsoup4");
+        assert_eq!(packages[1].current, "2.31.0");
+        assert_eq!(packages[1].latest, "2.32.3");
+        assert_eq!(packages[1].latest_filetype, "wheel");
+        assert!(parse("[]\n").unwrap().is_empty());
+        assert!(parse("pip exploded").is_err());
+
+        let stderr = "WARNING: Retrying (Retry(total=0, connect=None, read=None, redirect=None, status=None)) after connection broken by 'NewConnectionError('<pip._vendor.urllib3.connection.HTTPSConnection object at 0x7f>: Failed to establish a new connection: [Errno -3] Temporary failure in name resolution')': /simple/requests/\n";
+        assert!(offline_reason(stderr)
+            .unwrap()
+            .contains("NewConnectionError"));
+        assert_eq!(offline_reason("WARNING: something else\n"), None);
+
+        let cache = OutdatedCache::default();
+        cache.insert("/venv/bin/python", None, packages.clone(), 1_000);
+        let ttl = Duration::from_secs(60);
+        assert_eq!(
+            cache.get("/venv/bin/python", None, ttl),
+            Some((packages, 1_000))
+        );
+        assert_eq!(
+            cache.get("/venv/bin/python", Some("https://mirror/simple"), ttl),
+            None
+        );
+        assert_eq!(cache.get("/venv/bin/python", None, Duration::ZERO), None);
+    }
+}
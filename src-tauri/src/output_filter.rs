@@ -0,0 +1,220 @@
+//! Drops unwanted lines (`stdout_filter` / `stderr_filter`) while a pipe is
+//! read, before anything is captured or streamed. `stop_on_pattern` is
+//! matched at the same stage.
+
+use regex::bytes::Regex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Longer lines are kept without being matched, so a script that never
+/// prints a newline can't make the reader buffer without bound.
+const MAX_FILTERED_LINE_BYTES: usize = 64 * 1024;
+
+pub fn compile(field: &str, pattern: Option<&str>) -> Result<Option<Regex>, String> {
+    pattern
+        .map(|pattern| Regex::new(pattern).map_err(|error| format!("invalid {}: {}", field, error)))
+        .transpose()
+}
+
+pub struct LineFilter {
+    regex: Regex,
+    dropped: Arc<AtomicU64>,
+    pending: Vec<u8>,
+    /// The current line grew past the limit and is being passed through.
+    passing: bool,
+}
+
+impl LineFilter {
+    /// `dropped` is shared so one count can cover both streams.
+    pub fn new(regex: Regex, dropped: Arc<AtomicU64>) -> Self {
+        LineFilter {
+            regex,
+            dropped,
+            pending: Vec::new(),
+            passing: false,
+        }
+    }
+
+    /// Returns the bytes of `chunk` on lines that don't match.
+    pub fn filter(&mut self, mut chunk: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(chunk.len());
+
+        while !chunk.is_empty() {
+            let line_end = chunk.iter().position(|byte| *byte == b'\n');
+            let (part, rest) = chunk.split_at(line_end.map_or(chunk.len(), |end| end + 1));
+            chunk = rest;
+
+            if self.passing {
+                output.extend_from_slice(part);
+                self.passing = line_end.is_none();
+                continue;
+            }
+
+            self.pending.extend_from_slice(part);
+            if line_end.is_some() {
+                let line = std::mem::take(&mut self.pending);
+                self.keep_unless_matching(line, &mut output);
+            } else if self.pending.len() > MAX_FILTERED_LINE_BYTES {
+                output.append(&mut self.pending);
+                self.passing = true;
+            }
+        }
+
+        output
+    }
+
+    /// Handles a final line that had no newline.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut output = Vec::new();
+        let line = std::mem::take(&mut self.pending);
+        self.passing = false;
+        if !line.is_empty() {
+            self.keep_unless_matching(line, &mut output);
+        }
+        output
+    }
+
+    fn keep_unless_matching(&self, line: Vec<u8>, output: &mut Vec<u8>) {
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if self.regex.is_match(content) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            output.extend_from_slice(&line);
+        }
+    }
+}
+
+/// Set once the stop pattern matched; the run waits on it to end the script.
+#[derive(Debug, Default)]
+pub struct StopSignal {
+    triggered: AtomicBool,
+    notify: Notify,
+}
+
+impl StopSignal {
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::Acquire)
+    }
+
+    /// Resolves once the pattern matched. Never resolves without a pattern.
+    pub async fn triggered(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if self.is_triggered() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn trigger(&self) {
+        self.triggered.store(true, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+}
+
+/// Passes output through up to and including the first line matching
+/// `stop_on_pattern`, then drops everything after it.
+pub struct StopMarker {
+    regex: Regex,
+    signal: Arc<StopSignal>,
+    /// The current line so far, for matching once it is complete. Output
+    /// itself is passed on right away.
+    line: Vec<u8>,
+    /// The current line grew past the limit and won't be matched.
+    skipping: bool,
+}
+
+impl StopMarker {
+    pub fn new(regex: Regex, signal: Arc<StopSignal>) -> Self {
+        StopMarker {
+            regex,
+            signal,
+            line: Vec::new(),
+            skipping: false,
+        }
+    }
+
+    pub fn apply(&mut self, mut chunk: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(chunk.len());
+
+        while !chunk.is_empty() && !self.signal.is_triggered() {
+            let line_end = chunk.iter().position(|byte| *byte == b'\n');
+            let (part, rest) = chunk.split_at(line_end.map_or(chunk.len(), |end| end + 1));
+            chunk = rest;
+            output.extend_from_slice(part);
+
+            if !self.skipping {
+                self.line.extend_from_slice(part);
+            }
+            if line_end.is_some() {
+                self.check_line();
+            } else if self.line.len() > MAX_FILTERED_LINE_BYTES {
+                self.line.clear();
+                self.skipping = true;
+            }
+        }
+
+        output
+    }
+
+    /// Matches a final line that had no newline.
+    pub fn finish(&mut self) {
+        if !self.signal.is_triggered() {
+            self.check_line();
+        }
+    }
+
+    fn check_line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        let skipped = std::mem::take(&mut self.skipping);
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if !skipped && !line.is_empty() && self.regex.is_match(content) {
+            self.signal.trigger();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_lines_are_dropped_across_chunks() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let regex = compile("stderr_filter", Some("^.*DeprecationWarning")).unwrap();
+        let mut filter = LineFilter::new(regex.unwrap(), dropped.clone());
+
+        let mut output = filter.filter(b"keep 1\r\nlib.py:3: Deprec");
+        output.extend(filter.filter(b"ationWarning: old\nkeep 2\nDeprecationWarning"));
+        output.extend(filter.finish());
+
+        assert_eq!(output, b"keep 1\r\nkeep 2\n");
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn output_stops_after_the_first_matching_line() {
+        let signal = Arc::new(StopSignal::default());
+        let regex = compile("stop_on_pattern", Some("^DONE$")).unwrap();
+        let mut marker = StopMarker::new(regex.unwrap(), signal.clone());
+
+        let mut output = marker.apply(b"data 1\nDO");
+        assert!(!signal.is_triggered());
+        output.extend(marker.apply(b"NE\r\ncleanup\n"));
+        output.extend(marker.apply(b"more cleanup\n"));
+
+        assert_eq!(output, b"data 1\nDONE\r\n");
+        assert!(signal.is_triggered());
+    }
+
+    #[test]
+    fn invalid_patterns_name_the_field() {
+        let error = compile("stdout_filter", Some("(unclosed")).unwrap_err();
+        assert!(error.starts_with("invalid stdout_filter: "), "{}", error);
+        assert!(compile("stdout_filter", None).unwrap().is_none());
+    }
+}
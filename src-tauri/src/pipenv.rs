@@ -0,0 +1,122 @@
+//! Pipenv projects: a `Pipfile` in the script's directory or just above
+//! it. Pipenv keeps the project's virtualenv elsewhere, under a name derived
+//! from the project path, so `pipenv --venv` is asked where it is.
+
+use crate::virtualenv;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+pub const PROGRAM: &str = "pipenv";
+pub const PIPFILE: &str = "Pipfile";
+
+/// Directories searched for a Pipfile, the script's own first. Pipenv's
+/// `PIPENV_MAX_DEPTH` defaults to the same.
+const MAX_DEPTH: usize = 3;
+/// Pipenv imports a lot before answering.
+const VENV_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The nearest Pipfile above `script`.
+pub fn pipfile(script: &Path) -> Option<PathBuf> {
+    script
+        .ancestors()
+        .skip(1)
+        .take(MAX_DEPTH)
+        .map(|dir| dir.join(PIPFILE))
+        .find(|file| file.is_file())
+}
+
+/// The virtualenv `pipenv --venv` reports for the project in `dir`. A
+/// missing pipenv is a `NotFound` error; so is a project whose environment
+/// was never created.
+pub async fn env_path(dir: &Path) -> Result<PathBuf, std::io::Error> {
+    let mut command = Command::new(PROGRAM);
+    command
+        .arg("--venv")
+        .current_dir(dir)
+        // Otherwise an app started from an activated virtualenv gets that
+        // one back.
+        .env("PIPENV_IGNORE_VIRTUALENVS", "1")
+        .env("PIPENV_VERBOSITY", "-1")
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    crate::process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(VENV_TIMEOUT, command.output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out running {} --venv", PROGRAM),
+            ))
+        }
+    };
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || path.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "no Pipenv environment for {}: {}",
+                dir.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(PathBuf::from(path))
+}
+
+/// Where pipenv creates virtualenvs that aren't in the project: `WORKON_HOME`,
+/// else `~/.virtualenvs` on Windows and `~/.local/share/virtualenvs`
+/// elsewhere.
+fn workon_home() -> Option<PathBuf> {
+    std::env::var_os("WORKON_HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            let home = crate::pyenv::home_dir()?;
+            Some(match cfg!(windows) {
+                true => home.join(".virtualenvs"),
+                false => home.join(".local").join("share").join("virtualenvs"),
+            })
+        })
+}
+
+/// The interpreters of the virtualenvs pipenv created, sorted. Each has the
+/// `.project` file pipenv leaves pointing back at its project.
+pub fn environments() -> Vec<PathBuf> {
+    let Some(Ok(entries)) = workon_home().map(std::fs::read_dir) else {
+        return Vec::new();
+    };
+    let mut pythons: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|env| env.join(".project").is_file() && virtualenv::is_venv(env))
+        .map(|env| virtualenv::python_in(&env))
+        .collect();
+    pythons.sort();
+    pythons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_nearest_pipfile_within_reach_is_used() {
+        let dir = std::env::temp_dir().join(format!("pdd-pipenv-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let deep = dir.join("a").join("b").join("c");
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::write(dir.join(PIPFILE), "[packages]\n").unwrap();
+        assert_eq!(pipfile(&deep.join("far.py")), None);
+        assert_eq!(
+            pipfile(&dir.join("a").join("b").join("near.py")),
+            Some(dir.join(PIPFILE))
+        );
+
+        std::fs::write(deep.join(PIPFILE), "[packages]\n").unwrap();
+        assert_eq!(pipfile(&deep.join("far.py")), Some(deep.join(PIPFILE)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
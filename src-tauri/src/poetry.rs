@@ -0,0 +1,118 @@
+//! Poetry projects: a `pyproject.toml` with a `[tool.poetry]` table in the
+//! script's directory or a parent. Their dependencies live in a virtualenv
+//! Poetry manages, so scripts in them run under `poetry run python`.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+pub const PROGRAM: &str = "poetry";
+pub const PYPROJECT_FILE: &str = "pyproject.toml";
+
+/// Poetry loads its plugins and the lock file before answering.
+const ENV_INFO_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The directory of the nearest `pyproject.toml` above `script` that
+/// configures Poetry. A `pyproject.toml` without it stops the search, as it
+/// is the project the script belongs to.
+pub fn project(script: &Path) -> Option<PathBuf> {
+    let pyproject = script
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(PYPROJECT_FILE))
+        .find(|file| file.is_file())?;
+    let contents = std::fs::read_to_string(&pyproject).ok()?;
+    let table: toml::Table = toml::from_str(&contents).ok()?;
+    let poetry = table
+        .get("tool")
+        .and_then(|tool| tool.get("poetry"))
+        .is_some_and(toml::Value::is_table);
+    match poetry {
+        true => pyproject.parent().map(Path::to_path_buf),
+        false => None,
+    }
+}
+
+/// Arguments to `PROGRAM` that start `python` in the project's environment
+/// from any directory.
+pub fn run_args(project: &Path) -> Vec<String> {
+    vec![
+        "--directory".to_string(),
+        project.to_string_lossy().to_string(),
+        "run".to_string(),
+        "python".to_string(),
+    ]
+}
+
+/// The virtualenv `poetry env info -p` reports for the project. A missing
+/// poetry is a `NotFound` error; so is a project whose environment hasn't
+/// been created yet.
+pub async fn env_path(project: &Path) -> Result<PathBuf, std::io::Error> {
+    let mut command = Command::new(PROGRAM);
+    command
+        .args(["env", "info", "-p"])
+        .current_dir(project)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    crate::process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(ENV_INFO_TIMEOUT, command.output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out running {} env info", PROGRAM),
+            ))
+        }
+    };
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || path.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "no Poetry environment for {}: {}",
+                project.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_pyprojects_configuring_poetry_are_projects() {
+        let dir = std::env::temp_dir().join(format!("pdd-poetry-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let scripts = dir.join("app").join("scripts");
+        std::fs::create_dir_all(&scripts).unwrap();
+        let script = scripts.join("report.py");
+        assert_eq!(project(&script), None);
+
+        std::fs::write(
+            dir.join(PYPROJECT_FILE),
+            "[tool.poetry]\nname = \"outer\"\n",
+        )
+        .unwrap();
+        assert_eq!(project(&script), Some(dir.clone()));
+
+        std::fs::write(
+            dir.join("app").join(PYPROJECT_FILE),
+            "[project]\nname = \"app\"\n[tool.ruff]\nline-length = 100\n",
+        )
+        .unwrap();
+        assert_eq!(project(&script), None);
+
+        std::fs::write(
+            dir.join("app").join(PYPROJECT_FILE),
+            "[tool.poetry.dependencies]\npython = \"^3.11\"\n",
+        )
+        .unwrap();
+        assert_eq!(project(&script), Some(dir.join("app")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
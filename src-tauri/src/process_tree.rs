@@ -0,0 +1,289 @@
+//! Keeps track of everything a script run spawns so it can be torn down as a
+//! unit. On Unix the interpreter leads its own process group; on Windows it is
+//! assigned to a Job Object right after spawning.
+
+use tokio::process::{Child, Command};
+
+/// Must be called before spawning so the child starts in its own group.
+/// On Windows the script gets no console window unless `show_console` is set.
+pub fn isolate(command: &mut Command, show_console: bool) {
+    #[cfg(unix)]
+    {
+        let _ = show_console;
+        command.process_group(0);
+    }
+
+    // A separate console process group is what lets `terminate` deliver
+    // CTRL_BREAK_EVENT to the script and nothing else. A hidden console is
+    // never shared with us, but neither is the window a release build would
+    // otherwise open; either way the job is terminated after the grace period.
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Threading::{CREATE_NEW_PROCESS_GROUP, CREATE_NO_WINDOW};
+        let mut flags = CREATE_NEW_PROCESS_GROUP;
+        if !show_console {
+            flags |= CREATE_NO_WINDOW;
+        }
+        command.creation_flags(flags);
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (command, show_console);
+    }
+}
+
+/// Keeps short-lived helpers, such as interpreter probes, from flashing a
+/// console window on Windows. Piped output is unaffected.
+pub fn hide_console(command: &mut Command) {
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Threading::CREATE_NO_WINDOW;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    #[cfg(not(windows))]
+    {
+        let _ = command;
+    }
+}
+
+pub struct ProcessTree {
+    #[cfg(unix)]
+    pgid: Option<i32>,
+    #[cfg(windows)]
+    job: Option<windows::JobHandle>,
+    #[cfg(windows)]
+    pid: Option<u32>,
+}
+
+impl ProcessTree {
+    pub fn attach(child: &Child) -> Self {
+        #[cfg(unix)]
+        {
+            ProcessTree {
+                pgid: child.id().map(|pid| pid as i32),
+            }
+        }
+
+        #[cfg(windows)]
+        {
+            let job = child.raw_handle().and_then(|handle| {
+                windows::JobHandle::for_process(handle)
+                    .map_err(|error| log::warn!("failed to create job object: {}", error))
+                    .ok()
+            });
+            ProcessTree {
+                job,
+                pid: child.id(),
+            }
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = child;
+            ProcessTree {}
+        }
+    }
+
+    /// Asks the tree to exit: SIGTERM to the process group on Unix,
+    /// CTRL_BREAK_EVENT to the console process group on Windows.
+    pub fn terminate(&self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.pgid {
+            // SAFETY: see `kill`.
+            unsafe {
+                libc::kill(-pgid, libc::SIGTERM);
+            }
+        }
+
+        #[cfg(windows)]
+        if let Some(pid) = self.pid {
+            windows::send_ctrl_break(pid);
+        }
+    }
+
+    /// CPU time and peak committed memory of the whole job, including
+    /// processes that already exited. Windows only.
+    #[cfg(windows)]
+    pub fn job_usage(&self) -> Option<crate::resources::ResourceUsage> {
+        self.job.as_ref().map(windows::JobHandle::usage)
+    }
+
+    /// Caps the committed memory of the whole job. Windows only; Unix limits
+    /// are set before exec instead.
+    #[cfg(windows)]
+    pub fn limit_job_memory(&self, bytes: u64) {
+        use windows_sys::Win32::System::JobObjects::JOB_OBJECT_LIMIT_JOB_MEMORY;
+
+        let Some(job) = &self.job else {
+            return;
+        };
+        let result = job.update_limits(|limits| {
+            limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+            limits.JobMemoryLimit = bytes as usize;
+        });
+        if let Err(error) = result {
+            log::warn!("failed to set job memory limit: {}", error);
+        }
+    }
+
+    /// Force-kills every process in the tree. The direct child still has to
+    /// be reaped by the caller.
+    pub fn kill(&self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.pgid {
+            // SAFETY: signalling a process group id has no memory-safety
+            // preconditions; a stale group just yields ESRCH.
+            unsafe {
+                libc::kill(-pgid, libc::SIGKILL);
+            }
+        }
+
+        #[cfg(windows)]
+        if let Some(job) = &self.job {
+            job.terminate(1);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::os::windows::io::RawHandle;
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::Console::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectBasicAccountingInformation,
+        JobObjectExtendedLimitInformation, QueryInformationJobObject, SetInformationJobObject,
+        TerminateJobObject, JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+
+    use crate::resources::ResourceUsage;
+
+    /// Only reaches the child when it shares a console with us; otherwise the
+    /// grace period simply runs out and the job is terminated.
+    pub fn send_ctrl_break(pid: u32) {
+        // SAFETY: plain Win32 call; an unknown group id just fails.
+        unsafe {
+            GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid);
+        }
+    }
+
+    pub struct JobHandle(HANDLE);
+
+    // SAFETY: a job object handle is a kernel handle that may be used from any
+    // thread.
+    unsafe impl Send for JobHandle {}
+    unsafe impl Sync for JobHandle {}
+
+    impl JobHandle {
+        /// The job is kill-on-close: when its last handle goes away, at the
+        /// end of the run or because the app died, anything still in it is
+        /// terminated.
+        pub fn for_process(process: RawHandle) -> std::io::Result<Self> {
+            // SAFETY: null attributes and name create an anonymous job; the
+            // process handle comes from a live tokio child.
+            unsafe {
+                let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+                if job.is_null() {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let job = JobHandle(job);
+                job.update_limits(|limits| {
+                    limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                })?;
+                if AssignProcessToJobObject(job.0, process as HANDLE) == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(job)
+            }
+        }
+
+        /// Reads the job's current limits, lets `update` adjust them and
+        /// writes them back, so limits set separately don't clobber each other.
+        pub fn update_limits(
+            &self,
+            update: impl FnOnce(&mut JOBOBJECT_EXTENDED_LIMIT_INFORMATION),
+        ) -> std::io::Result<()> {
+            // SAFETY: the struct is plain data sized for its info class, and
+            // the handle is owned by `self` and still open.
+            unsafe {
+                let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                let size = std::mem::size_of_val(&limits) as u32;
+                if QueryInformationJobObject(
+                    self.0,
+                    JobObjectExtendedLimitInformation,
+                    &mut limits as *mut _ as *mut _,
+                    size,
+                    std::ptr::null_mut(),
+                ) == 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+
+                update(&mut limits);
+                if SetInformationJobObject(
+                    self.0,
+                    JobObjectExtendedLimitInformation,
+                    &limits as *const _ as *const _,
+                    size,
+                ) == 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            }
+        }
+
+        pub fn usage(&self) -> ResourceUsage {
+            // SAFETY: both structs are plain data sized for their info class,
+            // and the handle is owned by `self` and still open.
+            unsafe {
+                let mut accounting: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION = std::mem::zeroed();
+                let cpu_time_ms = (QueryInformationJobObject(
+                    self.0,
+                    JobObjectBasicAccountingInformation,
+                    &mut accounting as *mut _ as *mut _,
+                    std::mem::size_of_val(&accounting) as u32,
+                    std::ptr::null_mut(),
+                ) != 0)
+                    .then(|| {
+                        // 100-nanosecond ticks.
+                        (accounting.TotalUserTime + accounting.TotalKernelTime) as u64 / 10_000
+                    });
+
+                let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                let peak_memory_bytes = (QueryInformationJobObject(
+                    self.0,
+                    JobObjectExtendedLimitInformation,
+                    &mut limits as *mut _ as *mut _,
+                    std::mem::size_of_val(&limits) as u32,
+                    std::ptr::null_mut(),
+                ) != 0)
+                    .then_some(limits.PeakJobMemoryUsed as u64);
+
+                ResourceUsage {
+                    peak_memory_bytes,
+                    cpu_time_ms,
+                }
+            }
+        }
+
+        pub fn terminate(&self, exit_code: u32) {
+            // SAFETY: the handle is owned by `self` and still open.
+            unsafe {
+                TerminateJobObject(self.0, exit_code);
+            }
+        }
+    }
+
+    impl Drop for JobHandle {
+        fn drop(&mut self) {
+            // SAFETY: the handle is owned by `self` and closed exactly once.
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
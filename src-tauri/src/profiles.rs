@@ -0,0 +1,216 @@
+//! Named run defaults for scripts that are started the same way from several
+//! widgets. Stored as one JSON file in the app data dir.
+
+use crate::commands::RunPythonScriptRequest;
+use crate::fs_util::write_json_atomically;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub const PROFILES_FILE: &str = "script-profiles.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptProfile {
+    pub name: String,
+    pub script_path: String,
+    pub python_path: Option<String>,
+    pub timeout_ms: Option<u64>,
+    pub env: HashMap<String, String>,
+    pub args: Vec<String>,
+    pub working_dir: Option<String>,
+    /// Least time between two `run_python_script` starts of this profile's
+    /// script.
+    pub min_interval_ms: Option<u64>,
+}
+
+impl ScriptProfile {
+    /// Fills in whatever the request leaves unset. Request env keys win over
+    /// the profile's.
+    pub fn apply_to(&self, request: &mut RunPythonScriptRequest) {
+        if request.script_path.trim().is_empty() && request.module.is_none() {
+            request.script_path = self.script_path.clone();
+        }
+        if request.args.is_empty() {
+            request.args = self.args.clone();
+        }
+        let chosen_env = request.conda_env.is_some() || request.use_project_env == Some(true);
+        if request.python_path.is_none() && !chosen_env {
+            request.python_path = self.python_path.clone();
+        }
+        if request.timeout_ms.is_none() {
+            request.timeout_ms = self.timeout_ms;
+        }
+        if request.working_dir.is_none() {
+            request.working_dir = self.working_dir.clone();
+        }
+        if request.min_interval_ms.is_none() {
+            request.min_interval_ms = self.min_interval_ms;
+        }
+        if !self.env.is_empty() {
+            let env = request.env.get_or_insert_with(HashMap::new);
+            for (key, value) in &self.env {
+                env.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProfileState {
+    path: Option<PathBuf>,
+    profiles: BTreeMap<String, ScriptProfile>,
+}
+
+/// Saved profiles by name. Managed Tauri state; kept in memory only until
+/// `load` points it at a file.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileStore {
+    state: Arc<Mutex<ProfileState>>,
+}
+
+impl ProfileStore {
+    /// Reads existing profiles from `path` and saves future changes there.
+    pub fn load(&self, path: PathBuf) -> Result<(), String> {
+        let profiles = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|error| format!("failed to parse {}: {}", path.display(), error))?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+        };
+
+        let mut state = self.lock();
+        state.path = Some(path);
+        state.profiles = profiles;
+        Ok(())
+    }
+
+    /// Creates or replaces the profile with the same name.
+    pub fn save(&self, mut profile: ScriptProfile) -> Result<(), String> {
+        profile.name = profile.name.trim().to_string();
+        if profile.name.is_empty() {
+            return Err("profile name is required".to_string());
+        }
+        if profile.script_path.trim().is_empty() {
+            return Err("profile script_path is required".to_string());
+        }
+
+        let mut state = self.lock();
+        let previous = state.profiles.insert(profile.name.clone(), profile.clone());
+        if let Err(error) = write_profiles(&state) {
+            match previous {
+                Some(previous) => state.profiles.insert(profile.name, previous),
+                None => state.profiles.remove(&profile.name),
+            };
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<ScriptProfile, String> {
+        self.lock()
+            .profiles
+            .get(name.trim())
+            .cloned()
+            .ok_or_else(|| format!("profile not found: {}", name))
+    }
+
+    /// Sorted by name.
+    pub fn list(&self) -> Vec<ScriptProfile> {
+        self.lock().profiles.values().cloned().collect()
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), String> {
+        let mut state = self.lock();
+        let removed = state
+            .profiles
+            .remove(name.trim())
+            .ok_or_else(|| format!("profile not found: {}", name))?;
+        if let Err(error) = write_profiles(&state) {
+            state.profiles.insert(removed.name.clone(), removed);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ProfileState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+fn write_profiles(state: &ProfileState) -> Result<(), String> {
+    let Some(path) = &state.path else {
+        return Ok(());
+    };
+
+    let json = serde_json::to_vec_pretty(&state.profiles)
+        .map_err(|error| format!("failed to serialize profiles: {}", error))?;
+    write_json_atomically(path, &json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> ScriptProfile {
+        ScriptProfile {
+            name: "weather".to_string(),
+            script_path: "/scripts/weather.py".to_string(),
+            python_path: Some("/venv/bin/python".to_string()),
+            timeout_ms: Some(30_000),
+            env: HashMap::from([
+                ("CITY".to_string(), "Paris".to_string()),
+                ("UNITS".to_string(), "metric".to_string()),
+            ]),
+            args: vec!["--daily".to_string()],
+            working_dir: None,
+            min_interval_ms: None,
+        }
+    }
+
+    #[test]
+    fn explicit_request_fields_win_over_the_profile() {
+        let mut request = RunPythonScriptRequest {
+            timeout_ms: Some(5_000),
+            env: Some(HashMap::from([("CITY".to_string(), "Oslo".to_string())])),
+            ..Default::default()
+        };
+        profile().apply_to(&mut request);
+
+        assert_eq!(request.script_path, "/scripts/weather.py");
+        assert_eq!(request.args, ["--daily"]);
+        assert_eq!(request.python_path.as_deref(), Some("/venv/bin/python"));
+        assert_eq!(request.timeout_ms, Some(5_000));
+        let env = request.env.unwrap();
+        assert_eq!(env["CITY"], "Oslo");
+        assert_eq!(env["UNITS"], "metric");
+    }
+
+    #[test]
+    fn profiles_survive_a_reload() {
+        let dir = std::env::temp_dir().join(format!("pdd-profiles-{}", std::process::id()));
+        let path = dir.join(PROFILES_FILE);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let store = ProfileStore::default();
+        store.load(path.clone()).unwrap();
+        store.save(profile()).unwrap();
+        assert!(store
+            .save(ScriptProfile {
+                name: " ".to_string(),
+                ..profile()
+            })
+            .is_err());
+
+        let reloaded = ProfileStore::default();
+        reloaded.load(path.clone()).unwrap();
+        assert_eq!(reloaded.get("weather").unwrap().timeout_ms, Some(30_000));
+        assert_eq!(reloaded.list().len(), 1);
+
+        reloaded.delete("weather").unwrap();
+        assert!(reloaded.get("weather").is_err());
+        assert!(reloaded.delete("weather").is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
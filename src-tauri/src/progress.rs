@@ -0,0 +1,228 @@
+//! Progress lines on stdout: `##PDD-PROGRESS {"pct": 42, "msg": "downloading"}`.
+//!
+//! Well-formed lines are taken out of the captured output and reported as
+//! `script-progress` events. Anything that doesn't parse stays ordinary
+//! output.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub const PROGRESS_EVENT: &str = "script-progress";
+const PROGRESS_PREFIX: &[u8] = b"##PDD-PROGRESS ";
+/// Longer lines are passed through without being buffered further.
+const MAX_PROGRESS_LINE_BYTES: usize = 4 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+    /// 0 to 100.
+    pub percent: Option<f64>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    pub run_id: String,
+    pub correlation_id: Option<String>,
+    pub labels: Option<HashMap<String, String>>,
+    /// Position of the run in its `run_python_scripts` batch or
+    /// `run_python_pipeline`.
+    pub batch_index: Option<usize>,
+    pub percent: Option<f64>,
+    pub message: Option<String>,
+    pub ts_ms: u64,
+}
+
+pub type ProgressSink = Arc<dyn Fn(ProgressEvent) + Send + Sync>;
+
+#[derive(Debug, Clone, Default)]
+pub struct ProgressSummary {
+    pub last: Option<Progress>,
+    pub count: u64,
+}
+
+#[derive(Deserialize)]
+struct RawProgress {
+    pct: Option<f64>,
+    msg: Option<String>,
+}
+
+fn parse_line(line: &[u8]) -> Option<Progress> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let raw: RawProgress = serde_json::from_slice(line.strip_prefix(PROGRESS_PREFIX)?).ok()?;
+    if raw.pct.is_none() && raw.msg.is_none() {
+        return None;
+    }
+    if raw
+        .pct
+        .is_some_and(|pct| !pct.is_finite() || !(0.0..=100.0).contains(&pct))
+    {
+        return None;
+    }
+
+    Some(Progress {
+        percent: raw.pct,
+        message: raw.msg,
+    })
+}
+
+/// Sits between the stdout pipe and the capture. Only lines that could still
+/// turn out to be progress are held back; everything else passes straight
+/// through, even without a trailing newline.
+pub struct ProgressReporter {
+    run_id: String,
+    correlation_id: Option<String>,
+    labels: Option<HashMap<String, String>>,
+    sink: Option<ProgressSink>,
+    summary: Arc<Mutex<ProgressSummary>>,
+    pending: Vec<u8>,
+    /// The current line was already ruled out and partly passed on.
+    passing: bool,
+}
+
+impl ProgressReporter {
+    pub fn new(
+        run_id: String,
+        correlation_id: Option<String>,
+        labels: Option<HashMap<String, String>>,
+        sink: Option<ProgressSink>,
+    ) -> Self {
+        ProgressReporter {
+            run_id,
+            correlation_id,
+            labels,
+            sink,
+            summary: Arc::default(),
+            pending: Vec::new(),
+            passing: false,
+        }
+    }
+
+    pub fn summary(&self) -> Arc<Mutex<ProgressSummary>> {
+        self.summary.clone()
+    }
+
+    /// Returns the bytes of `chunk` that are ordinary output.
+    pub fn filter(&mut self, mut chunk: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(chunk.len());
+
+        while !chunk.is_empty() {
+            let line_end = chunk.iter().position(|byte| *byte == b'\n');
+            let (part, rest) = chunk.split_at(line_end.map_or(chunk.len(), |end| end + 1));
+            chunk = rest;
+
+            if self.passing {
+                output.extend_from_slice(part);
+                self.passing = line_end.is_none();
+                continue;
+            }
+
+            self.pending.extend_from_slice(part);
+            if line_end.is_some() {
+                let line = std::mem::take(&mut self.pending);
+                let content = &line[..line.len() - 1];
+                if !self.report(content) {
+                    output.extend_from_slice(&line);
+                }
+            } else if !self.could_be_progress() {
+                output.append(&mut self.pending);
+                self.passing = true;
+            }
+        }
+
+        output
+    }
+
+    /// Handles a final line that had no newline.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let line = std::mem::take(&mut self.pending);
+        self.passing = false;
+        if self.report(&line) {
+            Vec::new()
+        } else {
+            line
+        }
+    }
+
+    fn could_be_progress(&self) -> bool {
+        let pending = self.pending.as_slice();
+        pending.len() <= MAX_PROGRESS_LINE_BYTES
+            && (pending.starts_with(PROGRESS_PREFIX) || PROGRESS_PREFIX.starts_with(pending))
+    }
+
+    fn report(&self, line: &[u8]) -> bool {
+        let Some(progress) = parse_line(line) else {
+            return false;
+        };
+
+        if let Some(sink) = &self.sink {
+            sink(ProgressEvent {
+                run_id: self.run_id.clone(),
+                correlation_id: self.correlation_id.clone(),
+                labels: self.labels.clone(),
+                batch_index: None,
+                percent: progress.percent,
+                message: progress.message.clone(),
+                ts_ms: crate::commands::unix_time_ms(),
+            });
+        }
+
+        let mut summary = self
+            .summary
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        summary.count += 1;
+        summary.last = Some(progress);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(chunks: &[&str]) -> (String, ProgressSummary) {
+        let mut reporter = ProgressReporter::new("run".to_string(), None, None, None);
+        let mut output = Vec::new();
+        for chunk in chunks {
+            output.extend(reporter.filter(chunk.as_bytes()));
+        }
+        output.extend(reporter.finish());
+        let summary = reporter.summary().lock().unwrap().clone();
+        (String::from_utf8(output).unwrap(), summary)
+    }
+
+    #[test]
+    fn progress_lines_are_taken_out_of_the_output() {
+        let (output, summary) = run(&[
+            "start\n##PDD-PRO",
+            "GRESS {\"pct\": 42, \"msg\": \"downloading\"}\r\nmid",
+            "dle\n##PDD-PROGRESS {\"msg\": \"done\"}",
+        ]);
+
+        assert_eq!(output, "start\nmiddle\n");
+        assert_eq!(summary.count, 2);
+        assert_eq!(
+            summary.last,
+            Some(Progress {
+                percent: None,
+                message: Some("done".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_progress_lines_stay_ordinary_output() {
+        let lines = [
+            "##PDD-PROGRESS not json\n",
+            "##PDD-PROGRESS {\"pct\": 140}\n",
+            "##PDD-PROGRESS {}\n",
+            "  ##PDD-PROGRESS {\"pct\": 1}\n",
+            "##PDD-PROGRESS {\"pct\": \"half\"}",
+        ];
+        let (output, summary) = run(&lines);
+
+        assert_eq!(output, lines.concat());
+        assert_eq!(summary.count, 0);
+    }
+}
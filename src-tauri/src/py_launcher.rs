@@ -0,0 +1,154 @@
+//! The Windows `py` launcher: which Pythons it has registered, from
+//! `py --list-paths`, and how to ask it for one of them. `py -3.11` picks
+//! the registered 3.11; plain `py -3` whichever 3.x the launcher prefers,
+//! which can change when another Python is installed.
+
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+pub const PROGRAM: &str = "py";
+
+const LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One Python the launcher knows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registered {
+    /// What goes after `-` on the launcher's command line, e.g. "3.12" or
+    /// "3.11-32".
+    pub version: String,
+    pub executable: PathBuf,
+    /// The one plain `py` starts.
+    pub is_default: bool,
+}
+
+/// Checks a `py_version` like "3.11", "3.12-32" or "3.13t" and turns it
+/// into the launcher's argument, `-3.11`.
+pub fn version_arg(py_version: &str) -> Result<String, String> {
+    let version = py_version.trim();
+    let (number, suffix) = match version.split_once('-') {
+        Some((number, bits)) => (number, Some(bits)),
+        None => (version, None),
+    };
+    let number = number.strip_suffix('t').unwrap_or(number);
+    let valid_number = !number.is_empty()
+        && number
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    let valid_suffix = suffix.map_or(true, |bits| ["32", "64", "arm64"].contains(&bits));
+    if !valid_number || !valid_suffix {
+        return Err(format!(
+            "py_version {:?} is not a launcher version like 3.11 or 3.12-32",
+            py_version
+        ));
+    }
+    Ok(format!("-{}", version))
+}
+
+/// The registered Python `py_version` selects. A version without `-32` or
+/// `-64` matches the one listed with `-64`, as older launchers list them.
+pub fn find<'a>(registered: &'a [Registered], py_version: &str) -> Option<&'a Registered> {
+    fn bare(version: &str) -> &str {
+        version.strip_suffix("-64").unwrap_or(version)
+    }
+    let wanted = py_version.trim();
+    registered
+        .iter()
+        .find(|python| python.version == wanted)
+        .or_else(|| {
+            registered
+                .iter()
+                .find(|python| bare(&python.version) == bare(wanted))
+        })
+}
+
+/// What `py --list-paths` reports. A missing launcher is a `NotFound` error.
+pub async fn list() -> Result<Vec<Registered>, std::io::Error> {
+    let mut command = Command::new(PROGRAM);
+    command
+        .arg("--list-paths")
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    crate::process_tree::hide_console(&mut command);
+    let output = match tokio::time::timeout(LIST_TIMEOUT, command.output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out running {} --list-paths", PROGRAM),
+            ))
+        }
+    };
+    // With nothing registered the launcher exits non-zero.
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+    Ok(parse_list_paths(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Lines look like ` -V:3.12 *        C:\Python312\python.exe` or, from
+/// older launchers, ` -3.11-64        C:\...\python.exe`. Pythons not from
+/// python.org are listed with their company, as `-V:Company/Tag`.
+fn parse_list_paths(output: &str) -> Vec<Registered> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let start = line
+                .as_bytes()
+                .windows(3)
+                .position(|window| window[0].is_ascii_alphabetic() && &window[1..] == b":\\")?;
+            let (head, executable) = line.split_at(start);
+            let mut words = head.split_whitespace();
+            let tag = words.next()?;
+            let tag = tag.strip_prefix("-V:").or_else(|| tag.strip_prefix('-'))?;
+            Some(Registered {
+                version: tag.strip_prefix("PythonCore/").unwrap_or(tag).to_string(),
+                executable: PathBuf::from(executable.trim()),
+                is_default: words.any(|word| word == "*"),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launcher_listings_are_parsed_and_matched() {
+        let output = " -V:3.12 *        C:\\Users\\me\\AppData\\Local\\Programs\\Python\\Python312\\python.exe\r\n -3.11-64        C:\\Program Files\\Python311\\python.exe\r\n -V:3.10-32       C:\\Python310-32\\python.exe\r\n -V:ContinuumAnalytics/Anaconda39-64 C:\\Anaconda3\\python.exe\r\n\r\n";
+        let registered = parse_list_paths(output);
+        let versions: Vec<&str> = registered
+            .iter()
+            .map(|python| python.version.as_str())
+            .collect();
+        assert_eq!(
+            versions,
+            [
+                "3.12",
+                "3.11-64",
+                "3.10-32",
+                "ContinuumAnalytics/Anaconda39-64"
+            ]
+        );
+        assert!(registered[0].is_default && !registered[1].is_default);
+        assert_eq!(
+            registered[1].executable,
+            PathBuf::from("C:\\Program Files\\Python311\\python.exe")
+        );
+
+        assert_eq!(find(&registered, "3.12"), Some(&registered[0]));
+        assert_eq!(find(&registered, "3.11"), Some(&registered[1]));
+        assert_eq!(find(&registered, "3.10-32"), Some(&registered[2]));
+        assert_eq!(find(&registered, "3.10"), None);
+        assert_eq!(find(&registered, "3.9"), None);
+
+        assert_eq!(version_arg(" 3.11 ").as_deref(), Ok("-3.11"));
+        assert_eq!(version_arg("3.12-32").as_deref(), Ok("-3.12-32"));
+        assert_eq!(version_arg("3.13t").as_deref(), Ok("-3.13t"));
+        for bad in ["", "3.", "three", "3.11-16", "3.11 -c pass"] {
+            assert!(version_arg(bad).is_err(), "{}", bad);
+        }
+    }
+}
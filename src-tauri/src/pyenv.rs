@@ -0,0 +1,130 @@
+//! pyenv pins: the `.python-version` file in a script's directory or a
+//! parent, resolved to the interpreter pyenv installed for it. Going
+//! straight to that interpreter keeps runs from depending on the `python3`
+//! shim and the directory the app was started from.
+
+use crate::interpreters::PythonVersion;
+use std::path::{Path, PathBuf};
+
+pub const VERSION_FILE: &str = ".python-version";
+
+/// `PYENV_ROOT`, else `~/.pyenv` (`~/.pyenv/pyenv-win` for pyenv-win).
+/// Whether pyenv is actually installed there is up to the caller.
+pub fn root() -> Option<PathBuf> {
+    std::env::var_os("PYENV_ROOT")
+        .filter(|root| !root.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            let home = home_dir()?.join(".pyenv");
+            Some(match cfg!(windows) {
+                true => home.join("pyenv-win"),
+                false => home,
+            })
+        })
+}
+
+/// `USERPROFILE` on Windows, `HOME` elsewhere.
+pub fn home_dir() -> Option<PathBuf> {
+    let name = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    std::env::var_os(name)
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The interpreter of an installed version directory.
+pub fn python_in(version_dir: &Path) -> PathBuf {
+    match cfg!(windows) {
+        true => version_dir.join("python.exe"),
+        false => version_dir.join("bin").join("python"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pin {
+    /// As written, e.g. "3.12.1", "3.12" or a pyenv-virtualenv name.
+    pub version: String,
+    pub file: PathBuf,
+}
+
+/// The version the nearest `.python-version` above `script` names first.
+/// `None` when there is none or it pins "system", which pyenv leaves to
+/// PATH.
+pub fn pin(script: &Path) -> Option<Pin> {
+    let file = script
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(VERSION_FILE))
+        .find(|file| file.is_file())?;
+    let contents = std::fs::read_to_string(&file).ok()?;
+    let version = contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .find(|line| !line.is_empty())?
+        .split_whitespace()
+        .next()?
+        .to_string();
+    (version != "system").then_some(Pin { version, file })
+}
+
+/// The interpreter for `version` under `root`. A prefix like "3.12" picks
+/// the newest installed 3.12 release, as pyenv does.
+pub fn installed(root: &Path, version: &str) -> Option<PathBuf> {
+    let versions = root.join("versions");
+    let exact = python_in(&versions.join(version));
+    if exact.is_file() {
+        return Some(exact);
+    }
+
+    let prefix = format!("{}.", version);
+    std::fs::read_dir(&versions)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with(&prefix))
+        .filter_map(|name| Some((PythonVersion::parse(&name)?, name)))
+        .filter(|(_, name)| python_in(&versions.join(name)).is_file())
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, name)| python_in(&versions.join(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pinned_versions_resolve_to_installed_interpreters() {
+        let dir = std::env::temp_dir().join(format!("pdd-pyenv-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let root = dir.join("pyenv");
+        for version in ["3.11.4", "3.11.10", "3.12.0", "3.12-dev"] {
+            let python = python_in(&root.join("versions").join(version));
+            std::fs::create_dir_all(python.parent().unwrap()).unwrap();
+            std::fs::write(python, "").unwrap();
+        }
+        assert_eq!(
+            installed(&root, "3.11"),
+            Some(python_in(&root.join("versions").join("3.11.10")))
+        );
+        assert_eq!(
+            installed(&root, "3.12.0"),
+            Some(python_in(&root.join("versions").join("3.12.0")))
+        );
+        assert_eq!(installed(&root, "3.13"), None);
+
+        let project = dir.join("project");
+        std::fs::create_dir_all(project.join("scripts")).unwrap();
+        let script = project.join("scripts").join("weather.py");
+        assert_eq!(pin(&script), None);
+        std::fs::write(project.join(VERSION_FILE), "# pinned\n3.11 3.10\n").unwrap();
+        assert_eq!(
+            pin(&script),
+            Some(Pin {
+                version: "3.11".to_string(),
+                file: project.join(VERSION_FILE),
+            })
+        );
+        std::fs::write(project.join(VERSION_FILE), "system\n").unwrap();
+        assert_eq!(pin(&script), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,504 @@
+//! Interpreter defaults shared by every widget: a `python_path` for
+//! requests that name none, interpreter arguments, the default candidates,
+//! the order candidates are tried in and names for interpreters. Kept in the app config dir, apart from the execution
+//! settings, and written with a format version so later changes can
+//! migrate old files.
+
+use crate::fs_util::write_json_atomically;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+pub const PYTHON_SETTINGS_FILE: &str = "python-settings.json";
+/// Of the file format. Files without one predate versioning and read the
+/// same way.
+const SETTINGS_VERSION: u32 = 1;
+
+/// What `candidate_order` can arrange, in the default order: a local venv,
+/// the pyenv pin, the shebang, the global `python_path`, then the default
+/// candidates, and last the bundled runtime.
+pub const CANDIDATE_SOURCES: &[&str] = &[
+    "local_venv",
+    "pyenv",
+    "shebang",
+    "settings",
+    "default",
+    "bundled",
+];
+
+/// The key of this platform's list in `candidates`.
+pub const PLATFORM: &str = match cfg!(windows) {
+    true => "windows",
+    false => "unix",
+};
+const PLATFORMS: &[&str] = &["windows", "unix"];
+/// Characters a shell would act on. Spaces, parentheses and `~` are
+/// allowed, as in `C:\Program Files (x86)` or `C:\PROGRA~1`; programs are
+/// never started by a shell.
+const SHELL_METACHARACTERS: &[char] = &[
+    '|', '&', ';', '<', '>', '$', '`', '"', '\'', '*', '?', '[', ']', '{', '}', '!', '%', '^',
+    '\n', '\r',
+];
+
+/// One of the default candidates, source "default".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CandidateEntry {
+    /// A name looked up on PATH or a full path.
+    pub program: String,
+    /// Put before the interpreter's own arguments, like `-3` for `py`.
+    #[serde(default)]
+    pub pre_args: Vec<String>,
+    /// The command line when left empty.
+    #[serde(default)]
+    pub display_name: String,
+}
+
+impl CandidateEntry {
+    fn new(program: &str, pre_args: &[&str]) -> Self {
+        CandidateEntry {
+            program: program.to_string(),
+            pre_args: pre_args.iter().map(|arg| arg.to_string()).collect(),
+            display_name: String::new(),
+        }
+        .normalized()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let program = self.program.trim();
+        if program.is_empty() {
+            return Err("program must not be empty".to_string());
+        }
+        if let Some(c) = program.chars().find(|c| SHELL_METACHARACTERS.contains(c)) {
+            return Err(format!(
+                "program {:?} contains the shell metacharacter {:?}; give the program itself, \
+                 with any arguments in pre_args",
+                program, c
+            ));
+        }
+        if self.pre_args.iter().any(|arg| arg.is_empty()) {
+            return Err("pre_args must not contain empty arguments".to_string());
+        }
+        Ok(())
+    }
+
+    /// Trimmed, with the display name filled in.
+    fn normalized(mut self) -> Self {
+        self.program = self.program.trim().to_string();
+        self.display_name = self.display_name.trim().to_string();
+        if self.display_name.is_empty() {
+            self.display_name = std::iter::once(&self.program)
+                .chain(&self.pre_args)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+        self
+    }
+}
+
+/// What the default candidates are until `candidates` has a list for the
+/// platform: `python` then the `py -3` launcher on Windows, `python3` then
+/// `python` elsewhere.
+pub fn default_candidates(platform: &str) -> Vec<CandidateEntry> {
+    match platform {
+        "windows" => vec![
+            CandidateEntry::new("python", &[]),
+            CandidateEntry::new("py", &["-3"]),
+        ],
+        _ => vec![
+            CandidateEntry::new("python3", &[]),
+            CandidateEntry::new("python", &[]),
+        ],
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PythonSettings {
+    /// For requests without a `python_path`. Unlike a request's own it is
+    /// one candidate among the others, source "settings".
+    pub python_path: Option<String>,
+    /// For runs whose request has no `interpreter_args`, except with runner
+    /// "uv".
+    pub interpreter_args: Vec<String>,
+    /// Candidate sources from `CANDIDATE_SOURCES`, most preferred first.
+    /// Those left out are tried last, in their default order.
+    pub candidate_order: Vec<String>,
+    /// The default candidates by platform ("windows" or "unix"), in the
+    /// order they are tried. A platform without a list uses
+    /// `default_candidates`.
+    pub candidates: BTreeMap<String, Vec<CandidateEntry>>,
+    /// Names like "work-venv" for interpreter executables. A request's
+    /// `python_path` that is one of these names runs its interpreter.
+    pub aliases: BTreeMap<String, String>,
+    /// Where the bundled runtime's interpreter is, installed or not, on
+    /// platforms that have one. Filled in by the store; never saved.
+    #[serde(skip)]
+    pub bundled_python: Option<PathBuf>,
+}
+
+impl Default for PythonSettings {
+    fn default() -> Self {
+        PythonSettings {
+            python_path: None,
+            interpreter_args: Vec::new(),
+            candidate_order: CANDIDATE_SOURCES.iter().map(|s| s.to_string()).collect(),
+            candidates: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            bundled_python: None,
+        }
+    }
+}
+
+impl PythonSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, source) in self.candidate_order.iter().enumerate() {
+            if !CANDIDATE_SOURCES.contains(&source.as_str()) {
+                return Err(format!(
+                    "unknown candidate source {:?} (expected one of {})",
+                    source,
+                    CANDIDATE_SOURCES.join(", ")
+                ));
+            }
+            if self.candidate_order[..index].contains(source) {
+                return Err(format!("candidate source {:?} is listed twice", source));
+            }
+        }
+        if self.interpreter_args.iter().any(|arg| arg.is_empty()) {
+            return Err("interpreter_args must not contain empty arguments".to_string());
+        }
+        for (platform, candidates) in &self.candidates {
+            if !PLATFORMS.contains(&platform.as_str()) {
+                return Err(format!(
+                    "unknown platform {:?} in candidates (expected one of {})",
+                    platform,
+                    PLATFORMS.join(", ")
+                ));
+            }
+            if candidates.is_empty() {
+                return Err(format!(
+                    "the {} candidates must not be empty; reset them to use the defaults",
+                    platform
+                ));
+            }
+            for (index, candidate) in candidates.iter().enumerate() {
+                candidate
+                    .validate()
+                    .map_err(|error| format!("{} candidate {}: {}", platform, index + 1, error))?;
+            }
+        }
+        for (name, python_path) in &self.aliases {
+            validate_alias_name(name)?;
+            if python_path.trim().is_empty() {
+                return Err(format!("alias {:?} has no python_path", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The executable `python_path` names: an alias's, or `python_path`
+    /// itself.
+    pub fn resolve_alias<'a>(&'a self, python_path: &'a str) -> &'a str {
+        self.aliases
+            .get(python_path)
+            .map_or(python_path, |path| path.trim())
+    }
+
+    /// `python_path`, trimmed, when it is an alias.
+    pub fn alias_name(&self, python_path: Option<&str>) -> Option<String> {
+        python_path
+            .map(str::trim)
+            .filter(|name| self.aliases.contains_key(*name))
+            .map(str::to_string)
+    }
+
+    /// This platform's default candidates.
+    pub fn candidates(&self) -> Vec<CandidateEntry> {
+        match self.candidates.get(PLATFORM) {
+            Some(candidates) => candidates.clone(),
+            None => default_candidates(PLATFORM),
+        }
+    }
+
+    /// Replaces this platform's default candidates, or with `None` goes
+    /// back to `default_candidates`.
+    pub fn set_candidates(&mut self, candidates: Option<Vec<CandidateEntry>>) {
+        match candidates {
+            Some(candidates) => {
+                let candidates = candidates.into_iter().map(CandidateEntry::normalized);
+                self.candidates
+                    .insert(PLATFORM.to_string(), candidates.collect());
+            }
+            None => {
+                self.candidates.remove(PLATFORM);
+            }
+        }
+    }
+
+    /// The global interpreter, if one is set.
+    pub fn python_path(&self) -> Option<&str> {
+        self.python_path
+            .as_deref()
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+    }
+
+    /// Every source, in the order candidates are tried.
+    pub fn order(&self) -> Vec<&str> {
+        let listed = self.candidate_order.iter().map(String::as_str);
+        let rest = CANDIDATE_SOURCES
+            .iter()
+            .copied()
+            .filter(|source| !self.candidate_order.iter().any(|listed| listed == source));
+        listed.chain(rest).collect()
+    }
+}
+
+/// Aliases are names, not paths: letters, digits, `-`, `_` and `.`,
+/// starting with a letter or digit.
+pub fn validate_alias_name(name: &str) -> Result<(), String> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    match valid {
+        true => Ok(()),
+        false => Err(format!(
+            "invalid alias {:?}: use letters, digits, '-', '_' and '.', like \"work-venv\"",
+            name
+        )),
+    }
+}
+
+/// What `get_python_settings` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct PythonSettingsView {
+    #[serde(flatten)]
+    pub settings: PythonSettings,
+    /// Why the saved file was not used, until settings are saved again.
+    pub warning: Option<String>,
+}
+
+/// The file: the settings with the format version.
+#[derive(Serialize, Deserialize)]
+struct SavedSettings {
+    #[serde(default)]
+    version: u32,
+    #[serde(flatten)]
+    settings: PythonSettings,
+}
+
+#[derive(Debug, Default)]
+struct StoreState {
+    path: Option<PathBuf>,
+    settings: PythonSettings,
+    warning: Option<String>,
+    bundled_python: Option<PathBuf>,
+}
+
+/// Current Python settings. Managed Tauri state. Kept in memory only until
+/// `load` points it at a file.
+#[derive(Debug, Clone, Default)]
+pub struct PythonSettingsStore {
+    state: Arc<RwLock<StoreState>>,
+}
+
+impl PythonSettingsStore {
+    /// Reads saved settings from `path`, if any, and saves future changes
+    /// there. A file that can't be used leaves the defaults in place and
+    /// returns the warning `get_python_settings` reports, so runs keep
+    /// working.
+    pub fn load(&self, path: PathBuf) -> Option<String> {
+        let loaded = read_settings(&path).map_err(|error| {
+            format!(
+                "ignoring {}: {}; using default Python settings",
+                path.display(),
+                error
+            )
+        });
+        let mut state = self.write();
+        state.path = Some(path);
+        state.warning = loaded.as_ref().err().cloned();
+        state.settings = loaded.unwrap_or_default();
+        state.warning.clone()
+    }
+
+    pub fn get(&self) -> PythonSettings {
+        let state = self.read();
+        PythonSettings {
+            bundled_python: state.bundled_python.clone(),
+            ..state.settings.clone()
+        }
+    }
+
+    pub fn view(&self) -> PythonSettingsView {
+        PythonSettingsView {
+            settings: self.get(),
+            warning: self.read().warning.clone(),
+        }
+    }
+
+    /// Where the bundled runtime's interpreter goes, for `get` to report.
+    pub fn set_bundled_python(&self, interpreter: Option<PathBuf>) {
+        self.write().bundled_python = interpreter;
+    }
+
+    pub fn set(&self, settings: PythonSettings) -> Result<(), String> {
+        settings.validate()?;
+        let mut state = self.write();
+        if let Some(path) = &state.path {
+            let saved = SavedSettings {
+                version: SETTINGS_VERSION,
+                settings: settings.clone(),
+            };
+            let json = serde_json::to_vec_pretty(&saved)
+                .map_err(|error| format!("failed to serialize Python settings: {}", error))?;
+            write_json_atomically(path, &json)?;
+        }
+        state.settings = settings;
+        state.warning = None;
+        Ok(())
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, StoreState> {
+        self.state.read().unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, StoreState> {
+        self.state
+            .write()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// The defaults when there is no file yet.
+fn read_settings(path: &Path) -> Result<PythonSettings, String> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(PythonSettings::default())
+        }
+        Err(error) => return Err(error.to_string()),
+    };
+    let saved: SavedSettings =
+        serde_json::from_slice(&contents).map_err(|error| error.to_string())?;
+    if saved.version > SETTINGS_VERSION {
+        return Err(format!(
+            "written by a newer version of the app (format {})",
+            saved.version
+        ));
+    }
+    saved.settings.validate()?;
+    Ok(saved.settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_files_fall_back_to_the_defaults_with_a_warning() {
+        let dir = std::env::temp_dir().join(format!("pdd-python-settings-{}", std::process::id()));
+        let path = dir.join(PYTHON_SETTINGS_FILE);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let store = PythonSettingsStore::default();
+        assert_eq!(store.load(path.clone()), None);
+        let settings = PythonSettings {
+            python_path: Some("/opt/python/bin/python3".to_string()),
+            candidate_order: vec!["settings".to_string(), "local_venv".to_string()],
+            ..Default::default()
+        };
+        store.set(settings.clone()).unwrap();
+        assert_eq!(
+            settings.order(),
+            [
+                "settings",
+                "local_venv",
+                "pyenv",
+                "shebang",
+                "default",
+                "bundled"
+            ]
+        );
+        assert!(store
+            .set(PythonSettings {
+                candidate_order: vec!["conda".to_string()],
+                ..Default::default()
+            })
+            .unwrap_err()
+            .starts_with("unknown candidate source"));
+
+        let reloaded = PythonSettingsStore::default();
+        assert_eq!(reloaded.load(path.clone()), None);
+        assert_eq!(reloaded.get(), settings);
+
+        // Written before the format had a version.
+        std::fs::write(&path, r#"{"python_path": "C:\\Python312\\python.exe"}"#).unwrap();
+        assert_eq!(reloaded.load(path.clone()), None);
+        assert_eq!(reloaded.get().order(), CANDIDATE_SOURCES);
+
+        for broken in [
+            "{not json",
+            r#"{"version": 9}"#,
+            r#"{"candidate_order": ["default", "default"]}"#,
+        ] {
+            std::fs::write(&path, broken).unwrap();
+            let warning = reloaded.load(path.clone()).unwrap();
+            assert!(
+                warning.contains("using default Python settings"),
+                "{}",
+                warning
+            );
+            assert_eq!(reloaded.get(), PythonSettings::default());
+            assert_eq!(reloaded.view().warning, Some(warning));
+        }
+        reloaded.set(PythonSettings::default()).unwrap();
+        assert_eq!(reloaded.view().warning, None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn candidate_lists_are_checked_and_reset() {
+        let mut settings = PythonSettings::default();
+        assert_eq!(settings.candidates(), default_candidates(PLATFORM));
+        settings.set_candidates(Some(vec![
+            CandidateEntry {
+                program: " python3.12 ".to_string(),
+                pre_args: Vec::new(),
+                display_name: String::new(),
+            },
+            CandidateEntry {
+                program: r"C:\Program Files (x86)\Python\python.exe".to_string(),
+                pre_args: vec!["-E".to_string()],
+                display_name: "Program Files Python".to_string(),
+            },
+        ]));
+        assert_eq!(settings.validate(), Ok(()));
+        let candidates = settings.candidates();
+        assert_eq!(candidates[0].program, "python3.12");
+        assert_eq!(candidates[0].display_name, "python3.12");
+        assert_eq!(candidates[1].display_name, "Program Files Python");
+
+        for (program, error) in [
+            ("  ", "program must not be empty"),
+            ("python3; rm -rf ~", "shell metacharacter ';'"),
+            ("$(which python3)", "shell metacharacter '$'"),
+        ] {
+            settings.set_candidates(Some(vec![CandidateEntry::new(program, &[])]));
+            let message = settings.validate().unwrap_err();
+            assert!(message.contains(error), "{}", message);
+            assert!(message.contains("candidate 1"), "{}", message);
+        }
+        settings.set_candidates(Some(Vec::new()));
+        assert!(settings.validate().is_err());
+
+        settings.set_candidates(None);
+        assert_eq!(settings.validate(), Ok(()));
+        assert_eq!(settings.candidates(), default_candidates(PLATFORM));
+    }
+}
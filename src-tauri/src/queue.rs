@@ -0,0 +1,397 @@
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+pub const DEFAULT_MAX_CONCURRENT_RUNS: usize = 4;
+pub const MAX_CONCURRENT_RUNS_LIMIT: usize = 64;
+/// A waiting run moves up one priority level per interval, so low-priority
+/// work still gets a slot under a steady stream of interactive runs.
+const PROMOTION_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn parse(label: Option<&str>) -> Result<Self, String> {
+        match label.map(str::trim).filter(|label| !label.is_empty()) {
+            None => Ok(Priority::Normal),
+            Some(label) if label.eq_ignore_ascii_case("high") => Ok(Priority::High),
+            Some(label) if label.eq_ignore_ascii_case("normal") => Ok(Priority::Normal),
+            Some(label) if label.eq_ignore_ascii_case("low") => Ok(Priority::Low),
+            Some(label) => Err(format!("unsupported priority: {}", label)),
+        }
+    }
+
+    fn promoted(self, waited: Duration, interval: Duration) -> Self {
+        let levels = waited.as_millis() / interval.as_millis().max(1);
+        match (self, levels) {
+            (priority, 0) => priority,
+            (Priority::Low, 1) => Priority::Normal,
+            _ => Priority::High,
+        }
+    }
+}
+
+/// A waiter's place in line at some point while it was queued.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuePosition {
+    /// Milliseconds since the run entered the queue.
+    pub offset_ms: u64,
+    /// Number of runs ahead of it.
+    pub position: usize,
+}
+
+/// How a run got its slot.
+#[derive(Debug, Clone, Default)]
+pub struct Admission {
+    /// Priority after age-based promotion at the time it was admitted.
+    pub priority: Priority,
+    pub positions: Vec<QueuePosition>,
+}
+
+#[derive(Debug)]
+struct Waiting {
+    ticket: u64,
+    priority: Priority,
+    enqueued_at: Instant,
+}
+
+impl Waiting {
+    /// Higher sorts first: effective priority, then arrival order.
+    fn rank(&self, now: Instant) -> (Priority, std::cmp::Reverse<u64>) {
+        let waited = now.saturating_duration_since(self.enqueued_at);
+        (
+            self.priority.promoted(waited, PROMOTION_INTERVAL),
+            std::cmp::Reverse(self.ticket),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct QueueState {
+    max_running: usize,
+    running: usize,
+    next_ticket: u64,
+    waiting: Vec<Waiting>,
+}
+
+#[derive(Debug)]
+struct QueueInner {
+    state: Mutex<QueueState>,
+    changed: Notify,
+}
+
+/// Limits how many interpreters run at once. Requests beyond the limit wait
+/// for a slot, highest priority first and FIFO within a priority. Managed
+/// Tauri state.
+#[derive(Debug, Clone)]
+pub struct RunQueue {
+    inner: Arc<QueueInner>,
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        RunQueue::with_max(DEFAULT_MAX_CONCURRENT_RUNS)
+    }
+}
+
+impl RunQueue {
+    pub fn with_max(max_running: usize) -> Self {
+        RunQueue {
+            inner: Arc::new(QueueInner {
+                state: Mutex::new(QueueState {
+                    max_running: max_running.max(1),
+                    running: 0,
+                    next_ticket: 0,
+                    waiting: Vec::new(),
+                }),
+                changed: Notify::new(),
+            }),
+        }
+    }
+
+    /// Runs already holding a slot keep it when the limit is lowered; new
+    /// runs wait until the count drops below the new limit.
+    pub fn set_max(&self, max_running: usize) -> Result<(), String> {
+        if !(1..=MAX_CONCURRENT_RUNS_LIMIT).contains(&max_running) {
+            return Err(format!(
+                "max concurrent runs must be between 1 and {}",
+                MAX_CONCURRENT_RUNS_LIMIT
+            ));
+        }
+
+        self.lock().max_running = max_running;
+        self.inner.changed.notify_waiters();
+        Ok(())
+    }
+
+    /// Waits for a slot, or gives up after `timeout`. Dropping the future
+    /// leaves the queue without taking a slot.
+    pub async fn acquire(
+        &self,
+        priority: Priority,
+        timeout: Option<Duration>,
+    ) -> Option<RunPermit> {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.wait_for_slot(priority))
+                .await
+                .ok(),
+            None => Some(self.wait_for_slot(priority).await),
+        }
+    }
+
+    async fn wait_for_slot(&self, priority: Priority) -> RunPermit {
+        let enqueued_at = Instant::now();
+        let mut waiter = Waiter {
+            queue: self,
+            ticket: self.enqueue(priority, enqueued_at),
+            admitted: false,
+        };
+        let mut positions: Vec<QueuePosition> = Vec::new();
+
+        loop {
+            // Registered before checking so a release between the check and
+            // the await can't be missed.
+            let notified = self.inner.changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            match self.try_admit(waiter.ticket) {
+                Ok(priority) => {
+                    waiter.admitted = true;
+                    return RunPermit {
+                        queue: self.clone(),
+                        admission: Admission {
+                            priority,
+                            positions,
+                        },
+                    };
+                }
+                Err(position) => {
+                    if positions.last().map(|last| last.position) != Some(position) {
+                        positions.push(QueuePosition {
+                            offset_ms: enqueued_at.elapsed().as_millis() as u64,
+                            position,
+                        });
+                    }
+                }
+            }
+            notified.await;
+        }
+    }
+
+    fn enqueue(&self, priority: Priority, enqueued_at: Instant) -> u64 {
+        let mut state = self.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push(Waiting {
+            ticket,
+            priority,
+            enqueued_at,
+        });
+        drop(state);
+        // Lets existing waiters record that they were overtaken.
+        self.inner.changed.notify_waiters();
+        ticket
+    }
+
+    /// Admits `ticket` if a slot is free and it ranks first. Otherwise returns
+    /// how many waiters rank ahead of it.
+    fn try_admit(&self, ticket: u64) -> Result<Priority, usize> {
+        let now = Instant::now();
+        let mut state = self.lock();
+        let Some(index) = state
+            .waiting
+            .iter()
+            .position(|waiting| waiting.ticket == ticket)
+        else {
+            return Err(0);
+        };
+
+        let rank = state.waiting[index].rank(now);
+        let ahead = state
+            .waiting
+            .iter()
+            .filter(|waiting| waiting.rank(now) > rank)
+            .count();
+        if ahead > 0 || state.running >= state.max_running {
+            return Err(ahead);
+        }
+
+        state.waiting.swap_remove(index);
+        state.running += 1;
+        drop(state);
+        // The next waiter may fit as well.
+        self.inner.changed.notify_waiters();
+        Ok(rank.0)
+    }
+
+    #[cfg(test)]
+    pub fn running(&self) -> usize {
+        self.lock().running
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// A place in line. Leaving the line early (timeout, cancellation) must not
+/// block the waiters behind it.
+struct Waiter<'a> {
+    queue: &'a RunQueue,
+    ticket: u64,
+    admitted: bool,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if self.admitted {
+            return;
+        }
+
+        self.queue
+            .lock()
+            .waiting
+            .retain(|waiting| waiting.ticket != self.ticket);
+        self.queue.inner.changed.notify_waiters();
+    }
+}
+
+/// An execution slot, released when dropped.
+pub struct RunPermit {
+    queue: RunQueue,
+    admission: Admission,
+}
+
+impl RunPermit {
+    pub fn admission(&self) -> &Admission {
+        &self.admission
+    }
+}
+
+impl Drop for RunPermit {
+    fn drop(&mut self) {
+        self.queue.lock().running -= 1;
+        self.queue.inner.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_a_free_slot_instead_of_failing() {
+        let queue = RunQueue::with_max(1);
+        let first = queue.acquire(Priority::Normal, None).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire(Priority::Normal, None).await.is_some() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        assert!(waiting.await.unwrap());
+        assert_eq!(queue.running(), 0);
+    }
+
+    #[tokio::test]
+    async fn timing_out_in_the_queue_does_not_block_later_waiters() {
+        let queue = RunQueue::with_max(1);
+        let first = queue.acquire(Priority::Normal, None).await.unwrap();
+
+        assert!(queue
+            .acquire(Priority::Normal, Some(Duration::from_millis(20)))
+            .await
+            .is_none());
+
+        let next = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire(Priority::Normal, None).await.is_some() }
+        });
+        drop(first);
+        assert!(next.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn raising_the_limit_admits_waiters() {
+        let queue = RunQueue::with_max(1);
+        let _first = queue.acquire(Priority::Normal, None).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire(Priority::Normal, None).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        queue.set_max(2).unwrap();
+        assert!(waiting.await.unwrap().is_some());
+
+        assert!(queue.set_max(0).is_err());
+        assert!(queue.set_max(MAX_CONCURRENT_RUNS_LIMIT + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn high_priority_waiters_jump_ahead_of_queued_low_ones() {
+        let queue = RunQueue::with_max(1);
+        let first = queue.acquire(Priority::Normal, None).await.unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let spawn_waiter = |priority: Priority| {
+            let queue = queue.clone();
+            let order = order.clone();
+            tokio::spawn(async move {
+                let permit = queue.acquire(priority, None).await.unwrap();
+                order.lock().unwrap().push(priority);
+                permit.admission().positions.clone()
+            })
+        };
+        let low = spawn_waiter(Priority::Low);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let high = spawn_waiter(Priority::High);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        drop(first);
+        let low_positions = low.await.unwrap();
+        high.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), [Priority::High, Priority::Low]);
+        // First in line, then overtaken by the high-priority waiter.
+        let seen: Vec<usize> = low_positions.iter().map(|entry| entry.position).collect();
+        assert_eq!(seen[..2], [0, 1]);
+    }
+
+    #[test]
+    fn waiting_promotes_priority_over_time() {
+        let interval = Duration::from_secs(10);
+        assert_eq!(
+            Priority::Low.promoted(Duration::from_secs(9), interval),
+            Priority::Low
+        );
+        assert_eq!(
+            Priority::Low.promoted(Duration::from_secs(10), interval),
+            Priority::Normal
+        );
+        assert_eq!(
+            Priority::Low.promoted(Duration::from_secs(25), interval),
+            Priority::High
+        );
+        assert_eq!(
+            Priority::High.promoted(Duration::from_secs(60), interval),
+            Priority::High
+        );
+        assert!(Priority::parse(Some("urgent")).is_err());
+        assert_eq!(Priority::parse(Some(" HIGH ")), Ok(Priority::High));
+    }
+}
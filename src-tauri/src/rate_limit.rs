@@ -0,0 +1,58 @@
+//! Minimum spacing between runs of the same script (`min_interval_ms`), so a
+//! widget refreshing too often can't hammer whatever the script talks to.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Earliest next start per script, in unix ms. Held by the `RunRegistry`, so
+/// it lasts for the app session and survives webview reloads.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    next_allowed: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl RateLimiter {
+    /// Records a start of `script` at `now_ms` unless it is too soon after
+    /// the previous one. The error holds the earliest allowed start.
+    pub fn acquire(&self, script: &str, min_interval_ms: u64, now_ms: u64) -> Result<(), u64> {
+        let mut next_allowed = self
+            .next_allowed
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        next_allowed.retain(|_, allowed_at| *allowed_at > now_ms);
+        if let Some(allowed_at) = next_allowed.get(script) {
+            return Err(*allowed_at);
+        }
+
+        next_allowed.insert(script.to_string(), now_ms.saturating_add(min_interval_ms));
+        Ok(())
+    }
+
+    /// Forgets the start `acquire` just recorded for `script`, for a run that
+    /// was refused before it began.
+    pub fn release(&self, script: &str) {
+        self.next_allowed
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .remove(script);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_too_close_together_are_refused() {
+        let limiter = RateLimiter::default();
+        assert_eq!(limiter.acquire("/s/weather.py", 60_000, 1_000), Ok(()));
+        assert_eq!(
+            limiter.acquire("/s/weather.py", 60_000, 30_000),
+            Err(61_000)
+        );
+        assert_eq!(limiter.acquire("/s/news.py", 60_000, 30_000), Ok(()));
+        assert_eq!(limiter.acquire("/s/weather.py", 60_000, 61_000), Ok(()));
+        limiter.release("/s/weather.py");
+        assert_eq!(limiter.acquire("/s/weather.py", 60_000, 62_000), Ok(()));
+    }
+}
@@ -0,0 +1,390 @@
+//! Per-run resource accounting and limits. Linux samples the interpreter
+//! process through `/proc`; Windows reads the accounting of the run's Job
+//! Object after exit. Anything a platform can't measure is reported as `None`.
+
+use std::process::ExitStatus;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+
+use crate::process_tree::ProcessTree;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub peak_memory_bytes: Option<u64>,
+    pub cpu_time_ms: Option<u64>,
+}
+
+/// Below this the interpreter can't even start, which would only show up as
+/// a confusing failure.
+const MIN_MEMORY_LIMIT_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceLimits {
+    pub max_memory_bytes: Option<u64>,
+    pub max_cpu_time: Option<Duration>,
+    /// Run below normal OS priority: nice 10 (plus SCHED_BATCH on Linux) on
+    /// Unix, BELOW_NORMAL_PRIORITY_CLASS on Windows. Child processes inherit
+    /// it on both.
+    pub low_priority: bool,
+}
+
+impl ResourceLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.max_memory_bytes {
+            Some(bytes) if bytes < MIN_MEMORY_LIMIT_BYTES => Err(format!(
+                "max_memory_bytes must be at least {} bytes",
+                MIN_MEMORY_LIMIT_BYTES
+            )),
+            _ => Ok(()),
+        }?;
+
+        match self.max_cpu_time {
+            Some(cpu_time) if cpu_time.is_zero() => {
+                Err("max_cpu_time_ms must be greater than zero".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Limits that must be in place before the interpreter starts. On Unix
+    /// the address-space limit applies to each process of the tree on its
+    /// own, so one script can never eat into another's allowance.
+    pub fn apply_before_spawn(&self, command: &mut Command) {
+        #[cfg(unix)]
+        if let Some(bytes) = self.max_memory_bytes {
+            let limit = libc::rlimit {
+                rlim_cur: bytes as libc::rlim_t,
+                rlim_max: bytes as libc::rlim_t,
+            };
+            // SAFETY: setrlimit is async-signal-safe and the closure touches
+            // nothing but its own copy of `limit`.
+            unsafe {
+                command.pre_exec(move || os_result(libc::setrlimit(libc::RLIMIT_AS, &limit)));
+            }
+        }
+
+        // RLIMIT_CPU counts whole seconds: SIGXCPU at the soft limit, SIGKILL
+        // a second later for a process that ignores it.
+        #[cfg(unix)]
+        if let Some(cpu_time) = self.max_cpu_time {
+            let seconds = cpu_time.as_millis().div_ceil(1_000).max(1) as libc::rlim_t;
+            let limit = libc::rlimit {
+                rlim_cur: seconds,
+                rlim_max: seconds + 1,
+            };
+            // SAFETY: as above.
+            unsafe {
+                command.pre_exec(move || os_result(libc::setrlimit(libc::RLIMIT_CPU, &limit)));
+            }
+        }
+
+        // Best effort: a failure to lower the priority never fails the run.
+        #[cfg(unix)]
+        if self.low_priority {
+            // SAFETY: nice and sched_setscheduler are async-signal-safe system
+            // calls on the child itself.
+            unsafe {
+                command.pre_exec(|| {
+                    libc::nice(10);
+                    #[cfg(target_os = "linux")]
+                    {
+                        let param = libc::sched_param { sched_priority: 0 };
+                        libc::sched_setscheduler(0, libc::SCHED_BATCH, &param);
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = command;
+        }
+    }
+
+    /// Whether the run was stopped by the CPU-time limit. Windows runs are
+    /// killed by `cpu_limit_reached` instead and flagged by the caller.
+    pub fn cpu_exceeded(&self, status: &ExitStatus, usage: &ResourceUsage) -> bool {
+        let Some(limit) = self.max_cpu_time else {
+            return false;
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            match status.signal() {
+                Some(libc::SIGXCPU) => true,
+                Some(libc::SIGKILL) => usage
+                    .cpu_time_ms
+                    .is_some_and(|cpu_time| u128::from(cpu_time) >= limit.as_millis()),
+                _ => false,
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = (status, usage, limit);
+            false
+        }
+    }
+
+    /// Limits enforced through the process tree once it exists. On Windows
+    /// the memory limit covers the whole job.
+    pub fn apply_after_spawn(&self, child: &Child, process_tree: &ProcessTree) {
+        #[cfg(windows)]
+        {
+            if let Some(bytes) = self.max_memory_bytes {
+                process_tree.limit_job_memory(bytes);
+            }
+            if self.low_priority {
+                lower_priority_class(child);
+            }
+        }
+
+        #[cfg(not(windows))]
+        {
+            let _ = (child, process_tree);
+        }
+    }
+
+    /// Whether a failed run most likely died from hitting `max_memory_bytes`.
+    /// An allocation refused by the limit surfaces as Python's
+    /// `MemoryError`, or as an abort or a crash in native code. A crash only
+    /// counts with evidence: a failed allocation in stderr, or a peak close
+    /// to the limit. Scripts crash for other reasons too.
+    pub fn memory_exceeded(
+        &self,
+        status: &ExitStatus,
+        stderr: &str,
+        usage: &ResourceUsage,
+    ) -> bool {
+        let Some(limit) = self.max_memory_bytes else {
+            return false;
+        };
+        if status.success() {
+            return false;
+        }
+
+        ALLOCATION_FAILURES
+            .iter()
+            .any(|failure| stderr.contains(failure))
+            || usage
+                .peak_memory_bytes
+                .is_some_and(|peak| peak >= limit / 10 * 9)
+    }
+}
+
+/// How a refused allocation shows in stderr: Python's exception, glibc's
+/// `strerror(ENOMEM)`, C++'s exception and what allocators abort with.
+const ALLOCATION_FAILURES: &[&str] = &[
+    "MemoryError",
+    "Cannot allocate memory",
+    "std::bad_alloc",
+    "out of memory",
+];
+
+/// Background I/O priority can only be set by a process on itself, so only
+/// the CPU priority class is lowered.
+#[cfg(windows)]
+fn lower_priority_class(child: &Child) {
+    use windows_sys::Win32::System::Threading::{SetPriorityClass, BELOW_NORMAL_PRIORITY_CLASS};
+
+    if let Some(handle) = child.raw_handle() {
+        // SAFETY: the handle belongs to a live tokio child.
+        if unsafe { SetPriorityClass(handle as _, BELOW_NORMAL_PRIORITY_CLASS) } == 0 {
+            log::warn!(
+                "failed to lower priority class: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+fn os_result(result: libc::c_int) -> std::io::Result<()> {
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Resolves once the tree has used up `limit` of CPU time. Only Windows needs
+/// this; on Unix the kernel enforces RLIMIT_CPU and this never resolves.
+pub async fn cpu_limit_reached(process_tree: &ProcessTree, limit: Option<Duration>) {
+    #[cfg(windows)]
+    if let Some(limit) = limit {
+        let mut interval = tokio::time::interval(CPU_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let used = process_tree.job_usage().and_then(|usage| usage.cpu_time_ms);
+            if used.is_some_and(|used| u128::from(used) >= limit.as_millis()) {
+                return;
+            }
+        }
+    }
+
+    let _ = (process_tree, limit);
+    std::future::pending::<()>().await
+}
+
+#[cfg(windows)]
+const CPU_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct ResourceMonitor {
+    #[cfg(target_os = "linux")]
+    linux: Option<linux::Sampler>,
+}
+
+impl ResourceMonitor {
+    /// Starts sampling right after spawn. Never blocks.
+    pub fn start(child: &Child) -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            ResourceMonitor {
+                linux: child.id().map(linux::Sampler::start),
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = child;
+            ResourceMonitor {}
+        }
+    }
+
+    /// Same as `child.wait()`, but on Linux takes a last CPU sample while the
+    /// exited child is still a zombie.
+    pub async fn wait(&self, child: &mut Child) -> std::io::Result<ExitStatus> {
+        #[cfg(target_os = "linux")]
+        if let Some(sampler) = &self.linux {
+            sampler.sample_at_exit().await;
+        }
+
+        child.wait().await
+    }
+
+    pub fn finish(self, process_tree: &ProcessTree) -> ResourceUsage {
+        #[cfg(target_os = "linux")]
+        {
+            let _ = process_tree;
+            self.linux.map(linux::Sampler::finish).unwrap_or_default()
+        }
+
+        #[cfg(windows)]
+        {
+            process_tree.job_usage().unwrap_or_default()
+        }
+
+        #[cfg(not(any(target_os = "linux", windows)))]
+        {
+            let _ = process_tree;
+            ResourceUsage::default()
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::task::JoinHandle;
+
+    use super::ResourceUsage;
+
+    const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub struct Sampler {
+        pid: u32,
+        usage: Arc<Mutex<ResourceUsage>>,
+        task: JoinHandle<()>,
+    }
+
+    impl Sampler {
+        pub fn start(pid: u32) -> Self {
+            let usage = Arc::new(Mutex::new(ResourceUsage::default()));
+            let task = tokio::spawn({
+                let usage = usage.clone();
+                async move {
+                    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        record(&usage, pid);
+                    }
+                }
+            });
+            Sampler { pid, usage, task }
+        }
+
+        /// Blocks a pool thread until the child exits without reaping it, so
+        /// `/proc/<pid>/stat` still holds its final CPU times. Peak memory is
+        /// gone by then and comes from the periodic samples.
+        pub async fn sample_at_exit(&self) {
+            let pid = self.pid;
+            let exited = tokio::task::spawn_blocking(move || {
+                // SAFETY: `info` is a plain out-parameter; WNOWAIT leaves the
+                // child for tokio to reap.
+                unsafe {
+                    let mut info: libc::siginfo_t = std::mem::zeroed();
+                    libc::waitid(
+                        libc::P_PID,
+                        pid as libc::id_t,
+                        &mut info,
+                        libc::WEXITED | libc::WNOWAIT,
+                    ) == 0
+                }
+            })
+            .await
+            .unwrap_or(false);
+
+            if exited {
+                record(&self.usage, self.pid);
+            }
+        }
+
+        pub fn finish(self) -> ResourceUsage {
+            self.task.abort();
+            *self.usage.lock().unwrap_or_else(|error| error.into_inner())
+        }
+    }
+
+    fn record(usage: &Mutex<ResourceUsage>, pid: u32) {
+        let peak = peak_rss_bytes(pid);
+        let cpu = cpu_time_ms(pid);
+        let mut usage = usage.lock().unwrap_or_else(|error| error.into_inner());
+        if let Some(peak) = peak {
+            usage.peak_memory_bytes = Some(usage.peak_memory_bytes.unwrap_or(0).max(peak));
+        }
+        if let Some(cpu) = cpu {
+            usage.cpu_time_ms = Some(usage.cpu_time_ms.unwrap_or(0).max(cpu));
+        }
+    }
+
+    /// `VmHWM`, the resident high-water mark. Missing once the process exited.
+    fn peak_rss_bytes(pid: u32) -> Option<u64> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+        let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kib * 1024)
+    }
+
+    /// User and system time of the process plus that of children it waited
+    /// for.
+    fn cpu_time_ms(pid: u32) -> Option<u64> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name may contain spaces; fields resume after its ')'.
+        let fields: Vec<&str> = stat
+            .get(stat.rfind(')')? + 1..)?
+            .split_whitespace()
+            .collect();
+        // utime, stime, cutime and cstime are fields 14 to 17 of the file.
+        let ticks: u64 = fields
+            .get(11..15)?
+            .iter()
+            .map(|field| field.parse::<u64>().ok())
+            .sum::<Option<u64>>()?;
+
+        // SAFETY: sysconf has no preconditions.
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        (ticks_per_second > 0).then(|| ticks * 1_000 / ticks_per_second as u64)
+    }
+}
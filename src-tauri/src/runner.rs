@@ -0,0 +1,81 @@
+//! What starts a script: the interpreter itself, `uv run`, which first
+//! installs the dependencies the script's PEP 723 block declares into an
+//! environment uv caches, or `wsl.exe`, for the Python of a WSL
+//! distribution.
+
+/// The uv executable, found on PATH.
+pub const UV_PROGRAM: &str = "uv";
+/// Default timeout of uv runs without a `timeout_ms`. The first run of a
+/// script resolves and downloads its dependencies.
+pub const UV_DEFAULT_TIMEOUT_MS: u64 = 120_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runner {
+    Python,
+    Uv,
+    Wsl,
+}
+
+impl Runner {
+    pub fn parse(label: Option<&str>) -> Result<Self, String> {
+        match label.map(str::trim).filter(|label| !label.is_empty()) {
+            None => Ok(Runner::Python),
+            Some(label) if label.eq_ignore_ascii_case("python") => Ok(Runner::Python),
+            Some(label) if label.eq_ignore_ascii_case("uv") => Ok(Runner::Uv),
+            Some(label) if label.eq_ignore_ascii_case("wsl") => Ok(Runner::Wsl),
+            Some(label) => Err(format!("unsupported runner: {}", label)),
+        }
+    }
+}
+
+/// Arguments to uv that run the script path following them with `python`.
+pub fn uv_run_args(python: &str) -> Vec<String> {
+    vec![
+        "run".to_string(),
+        "--python".to_string(),
+        python.to_string(),
+        "--script".to_string(),
+    ]
+}
+
+/// Why a WSL run or validation can't happen in `distro` (`None` for the
+/// default one), given what `wsl.exe -l` found.
+pub fn wsl_unavailable(
+    distro: Option<&str>,
+    distributions: Result<&[String], &std::io::Error>,
+) -> Option<String> {
+    let distributions = match distributions {
+        Ok(distributions) => distributions,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Some("runner wsl: wsl.exe not found; is WSL installed?".to_string())
+        }
+        Err(error) => return Some(format!("runner wsl: {}", error)),
+    };
+    match distro {
+        _ if distributions.is_empty() => {
+            Some("runner wsl: no WSL distributions are installed".to_string())
+        }
+        Some(distro)
+            if !distributions
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(distro)) =>
+        {
+            Some(format!(
+                "WSL distribution not found: {} (found: {})",
+                distro,
+                distributions.join(", ")
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Why a uv run or validation can't happen, from probing `uv --version`.
+pub fn uv_unavailable(error: &std::io::Error) -> String {
+    match error.kind() {
+        std::io::ErrorKind::NotFound => {
+            "runner uv: uv not found on PATH; install uv or leave runner unset".to_string()
+        }
+        _ => format!("runner uv: {}", error),
+    }
+}
@@ -0,0 +1,664 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::coalesce::RunCoalescer;
+use crate::commands::{unix_time_ms, RunPythonScriptResponse};
+use crate::interpreters::InterpreterInfoCache;
+use crate::orphans::PidMarker;
+use crate::rate_limit::RateLimiter;
+use crate::validated_interpreters::ValidatedInterpreters;
+
+const DEFAULT_RETAINED_RESULTS: usize = 50;
+const DEFAULT_RESULT_RETENTION: Duration = Duration::from_secs(15 * 60);
+const MAX_RETAINED_RESULTS: usize = 1_000;
+const MAX_RESULT_RETENTION_MS: u64 = 24 * 60 * 60 * 1_000;
+
+/// One-shot cancellation flag shared between a run and whoever wants to stop it.
+#[derive(Debug, Clone, Default)]
+pub struct CancelSignal {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl CancelSignal {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once `cancel` has been called, immediately if it already was.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// What an in-flight run is doing right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunPhase {
+    Queued,
+    Running,
+    /// Timed out or cancelled; the process tree is being torn down.
+    Killing,
+}
+
+/// Fixed facts about a run, known when it is registered.
+#[derive(Debug, Clone, Default)]
+pub struct RunDescription {
+    pub script: String,
+    pub streaming: bool,
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+struct RunProgress {
+    phase: RunPhase,
+    interpreter: Option<String>,
+}
+
+/// Lets a run report its phase to the registry while it executes.
+#[derive(Debug, Clone)]
+pub struct RunTracker {
+    run_id: String,
+    progress: Arc<Mutex<RunProgress>>,
+    marker: PidMarker,
+}
+
+impl RunTracker {
+    /// Called once per spawned interpreter; `pid` also leads the run's
+    /// process group.
+    pub fn set_running(&self, interpreter: &str, pid: Option<u32>) {
+        {
+            let mut progress = self.lock();
+            progress.phase = RunPhase::Running;
+            progress.interpreter = Some(interpreter.to_string());
+        }
+        if let Some(pid) = pid {
+            self.marker.record(&self.run_id, pid);
+        }
+    }
+
+    pub fn set_killing(&self) {
+        self.lock().phase = RunPhase::Killing;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RunProgress> {
+        self.progress
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveRunInfo {
+    pub run_id: String,
+    pub script: String,
+    /// Display name of the interpreter, once one has been picked.
+    pub interpreter: Option<String>,
+    pub started_at_ms: u64,
+    pub elapsed_ms: u64,
+    pub state: RunPhase,
+    pub streaming: bool,
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+struct ActiveRun {
+    cancel: CancelSignal,
+    description: RunDescription,
+    started_at: Instant,
+    started_at_ms: u64,
+    tracker: RunTracker,
+}
+
+pub type RunResult = Result<RunPythonScriptResponse, String>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct KillAllSummary {
+    /// Runs that had started an interpreter.
+    pub running: usize,
+    /// Runs still waiting for a slot; they end as cancelled right away.
+    pub queued: usize,
+    /// Running ones that were gone within the wait.
+    pub terminated: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunState {
+    Running,
+    Finished,
+    TimedOut,
+    Cancelled,
+    /// The run never produced a response, e.g. no interpreter was found.
+    Failed,
+}
+
+impl RunState {
+    fn of(result: &RunResult) -> Self {
+        match result {
+            Ok(response) if response.timed_out => RunState::TimedOut,
+            Ok(response) if response.cancelled => RunState::Cancelled,
+            Ok(_) => RunState::Finished,
+            Err(_) => RunState::Failed,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FinishedRun {
+    run_id: String,
+    finished_at: Instant,
+    result: RunResult,
+}
+
+/// Results of background runs, oldest first, kept until they age out or the
+/// count limit pushes them out.
+#[derive(Debug)]
+struct FinishedRuns {
+    runs: VecDeque<FinishedRun>,
+    max_results: usize,
+    retention: Duration,
+}
+
+impl Default for FinishedRuns {
+    fn default() -> Self {
+        FinishedRuns {
+            runs: VecDeque::new(),
+            max_results: DEFAULT_RETAINED_RESULTS,
+            retention: DEFAULT_RESULT_RETENTION,
+        }
+    }
+}
+
+impl FinishedRuns {
+    fn evict(&mut self, now: Instant) {
+        while self.runs.len() > self.max_results
+            || self
+                .runs
+                .front()
+                .is_some_and(|run| now.saturating_duration_since(run.finished_at) > self.retention)
+        {
+            self.runs.pop_front();
+        }
+    }
+
+    fn get(&mut self, run_id: &str) -> Option<&FinishedRun> {
+        self.evict(Instant::now());
+        self.runs.iter().find(|run| run.run_id == run_id)
+    }
+}
+
+/// Runs that are currently in flight, keyed by run id, plus the retained
+/// results of finished background runs. Managed Tauri state.
+#[derive(Debug, Clone, Default)]
+pub struct RunRegistry {
+    runs: Arc<Mutex<HashMap<String, ActiveRun>>>,
+    finished: Arc<Mutex<FinishedRuns>>,
+    marker: PidMarker,
+    coalescer: RunCoalescer,
+    rate_limiter: RateLimiter,
+    interpreters: InterpreterInfoCache,
+    validated_interpreters: ValidatedInterpreters,
+}
+
+impl RunRegistry {
+    /// What each interpreter candidate turned out to be, shared with
+    /// validation.
+    pub fn interpreters(&self) -> &InterpreterInfoCache {
+        &self.interpreters
+    }
+
+    /// What each script was last validated with, to compare runs against.
+    pub fn validated_interpreters(&self) -> &ValidatedInterpreters {
+        &self.validated_interpreters
+    }
+
+    /// In-flight `coalesce: true` runs by request key.
+    pub fn coalescer(&self) -> &RunCoalescer {
+        &self.coalescer
+    }
+
+    /// Last starts of scripts that have a `min_interval_ms`.
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Registers a run and returns a guard that unregisters it when dropped,
+    /// so every exit path of a run cleans up after itself.
+    pub fn register(&self, run_id: &str, description: RunDescription) -> Result<RunGuard, String> {
+        let mut runs = self.runs.lock().unwrap_or_else(|error| error.into_inner());
+        if runs.contains_key(run_id) || self.lock_finished().get(run_id).is_some() {
+            return Err(format!("run id is already in use: {}", run_id));
+        }
+
+        let cancel = CancelSignal::default();
+        let tracker = RunTracker {
+            run_id: run_id.to_string(),
+            progress: Arc::new(Mutex::new(RunProgress {
+                phase: RunPhase::Queued,
+                interpreter: None,
+            })),
+            marker: self.marker.clone(),
+        };
+        runs.insert(
+            run_id.to_string(),
+            ActiveRun {
+                cancel: cancel.clone(),
+                description,
+                started_at: Instant::now(),
+                started_at_ms: unix_time_ms(),
+                tracker: tracker.clone(),
+            },
+        );
+
+        Ok(RunGuard {
+            registry: self.clone(),
+            run_id: run_id.to_string(),
+            cancel,
+            tracker,
+        })
+    }
+
+    pub fn cancel(&self, run_id: &str) -> Result<(), String> {
+        let runs = self.runs.lock().unwrap_or_else(|error| error.into_inner());
+        match runs.get(run_id) {
+            Some(run) => {
+                run.cancel.cancel();
+                Ok(())
+            }
+            None => Err(format!("no active run with id: {}", run_id)),
+        }
+    }
+
+    /// Cancels every in-flight run, queued ones included. Returns how many
+    /// were cancelled.
+    pub fn cancel_all(&self) -> usize {
+        let runs = self.lock_runs();
+        for run in runs.values() {
+            run.cancel.cancel();
+        }
+        runs.len()
+    }
+
+    /// Emergency stop: cancels everything, then waits up to `wait` for the
+    /// running ones to tear down their process trees. Process groups still
+    /// around after that are killed directly.
+    pub async fn kill_all(&self, wait: Duration) -> KillAllSummary {
+        let mut running = Vec::new();
+        let mut queued = 0;
+        for (run_id, run) in self.lock_runs().iter() {
+            run.cancel.cancel();
+            if run.tracker.lock().phase == RunPhase::Queued {
+                queued += 1;
+            } else {
+                running.push(run_id.clone());
+            }
+        }
+
+        let deadline = Instant::now() + wait;
+        loop {
+            let left = {
+                let runs = self.lock_runs();
+                running
+                    .iter()
+                    .filter(|run_id| runs.contains_key(*run_id))
+                    .count()
+            };
+            if left == 0 || Instant::now() >= deadline {
+                if left > 0 {
+                    let killed = self.marker.kill_all();
+                    log::warn!(
+                        "kill_all: {} runs did not stop, killed {} process groups",
+                        left,
+                        killed
+                    );
+                }
+                return KillAllSummary {
+                    running: running.len(),
+                    queued,
+                    terminated: running.len() - left,
+                };
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+    }
+
+    /// Records spawned process groups in `path` from now on, so a crashed
+    /// session can be cleaned up by `orphans::sweep` on the next launch.
+    pub fn enable_orphan_marker(&self, path: std::path::PathBuf) {
+        self.marker.enable(path);
+    }
+
+    /// Used on app exit: cancels everything, gives the runs `timeout` to tear
+    /// down their process trees, then kills whatever is left directly.
+    pub fn shutdown(&self, timeout: Duration) {
+        if self.cancel_all() == 0 {
+            return;
+        }
+
+        let deadline = Instant::now() + timeout;
+        while !self.lock_runs().is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(25));
+        }
+        let killed = self.marker.kill_all();
+        if killed > 0 {
+            log::warn!("killed {} script process groups at shutdown", killed);
+        }
+    }
+
+    /// A snapshot taken under the registry lock, so a run finishing meanwhile
+    /// is either listed completely or not at all. Oldest first. With
+    /// `labels`, only runs carrying every one of them are included.
+    pub fn list_active(&self, labels: Option<&HashMap<String, String>>) -> Vec<ActiveRunInfo> {
+        let runs = self.lock_runs();
+        let mut active: Vec<ActiveRunInfo> = runs
+            .iter()
+            .filter(|(_, run)| {
+                labels.map_or(true, |labels| {
+                    labels
+                        .iter()
+                        .all(|(key, value)| run.description.labels.get(key) == Some(value))
+                })
+            })
+            .map(|(run_id, run)| {
+                let progress = run.tracker.lock();
+                ActiveRunInfo {
+                    run_id: run_id.clone(),
+                    script: run.description.script.clone(),
+                    interpreter: progress.interpreter.clone(),
+                    started_at_ms: run.started_at_ms,
+                    elapsed_ms: run.started_at.elapsed().as_millis() as u64,
+                    state: progress.phase,
+                    streaming: run.description.streaming,
+                    labels: run.description.labels.clone(),
+                }
+            })
+            .collect();
+        active.sort_by_key(|run| std::cmp::Reverse(run.elapsed_ms));
+        active
+    }
+
+    /// Finished runs are looked up first: a run stores its result before it
+    /// leaves the active set, so there is no moment where it is in neither.
+    pub fn state(&self, run_id: &str) -> Result<RunState, String> {
+        if let Some(run) = self.lock_finished().get(run_id) {
+            return Ok(RunState::of(&run.result));
+        }
+        if self.lock_runs().contains_key(run_id) {
+            return Ok(RunState::Running);
+        }
+        Err(format!("no run with id: {}", run_id))
+    }
+
+    pub fn result(&self, run_id: &str) -> RunResult {
+        if let Some(run) = self.lock_finished().get(run_id) {
+            return run.result.clone();
+        }
+        if self.lock_runs().contains_key(run_id) {
+            return Err(format!("run is still in progress: {}", run_id));
+        }
+        Err(format!("no run with id: {}", run_id))
+    }
+
+    pub fn set_result_retention(
+        &self,
+        max_results: usize,
+        retention_ms: u64,
+    ) -> Result<(), String> {
+        if !(1..=MAX_RETAINED_RESULTS).contains(&max_results) {
+            return Err(format!(
+                "max retained results must be between 1 and {}",
+                MAX_RETAINED_RESULTS
+            ));
+        }
+        if !(1_000..=MAX_RESULT_RETENTION_MS).contains(&retention_ms) {
+            return Err(format!(
+                "result retention must be between 1000 and {} ms",
+                MAX_RESULT_RETENTION_MS
+            ));
+        }
+
+        let mut finished = self.lock_finished();
+        finished.max_results = max_results;
+        finished.retention = Duration::from_millis(retention_ms);
+        finished.evict(Instant::now());
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn is_active(&self, run_id: &str) -> bool {
+        self.runs
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .contains_key(run_id)
+    }
+
+    fn remove(&self, run_id: &str) {
+        self.lock_runs().remove(run_id);
+        self.marker.forget(run_id);
+    }
+
+    fn store_result(&self, run_id: &str, result: RunResult) {
+        let now = Instant::now();
+        let mut finished = self.lock_finished();
+        finished.runs.push_back(FinishedRun {
+            run_id: run_id.to_string(),
+            finished_at: now,
+            result,
+        });
+        finished.evict(now);
+    }
+
+    fn lock_runs(&self) -> std::sync::MutexGuard<'_, HashMap<String, ActiveRun>> {
+        self.runs.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn lock_finished(&self) -> std::sync::MutexGuard<'_, FinishedRuns> {
+        self.finished
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+pub struct RunGuard {
+    registry: RunRegistry,
+    run_id: String,
+    cancel: CancelSignal,
+    tracker: RunTracker,
+}
+
+impl RunGuard {
+    pub fn cancel_signal(&self) -> CancelSignal {
+        self.cancel.clone()
+    }
+
+    pub fn tracker(&self) -> RunTracker {
+        self.tracker.clone()
+    }
+
+    /// Keeps the result of a background run so it can be fetched by id, then
+    /// unregisters the run.
+    pub fn finish(self, result: RunResult) {
+        self.registry.store_result(&self.run_id, result);
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.registry.remove(&self.run_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropping_the_guard_unregisters_the_run() {
+        let registry = RunRegistry::default();
+        let guard = registry
+            .register("run-1", RunDescription::default())
+            .unwrap();
+        assert!(registry.is_active("run-1"));
+        assert!(registry
+            .register("run-1", RunDescription::default())
+            .is_err());
+
+        drop(guard);
+        assert!(!registry.is_active("run-1"));
+        assert!(registry.cancel("run-1").is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel_wakes_waiters_even_after_the_fact() {
+        let registry = RunRegistry::default();
+        let guard = registry
+            .register("run-2", RunDescription::default())
+            .unwrap();
+        let signal = guard.cancel_signal();
+
+        registry.cancel("run-2").unwrap();
+        signal.cancelled().await;
+        assert!(signal.is_cancelled());
+    }
+
+    #[test]
+    fn active_runs_are_listed_with_their_phase() {
+        let registry = RunRegistry::default();
+        let queued = registry
+            .register(
+                "list-1",
+                RunDescription {
+                    script: "/scripts/weather.py".to_string(),
+                    streaming: true,
+                    labels: HashMap::from([("widget".to_string(), "weather".to_string())]),
+                },
+            )
+            .unwrap();
+        let running = registry
+            .register("list-2", RunDescription::default())
+            .unwrap();
+        running.tracker().set_running("python3", None);
+
+        let active = registry.list_active(None);
+        assert_eq!(active.len(), 2);
+        let first = active.iter().find(|run| run.run_id == "list-1").unwrap();
+        assert_eq!(first.state, RunPhase::Queued);
+        assert_eq!(first.script, "/scripts/weather.py");
+        assert!(first.streaming && first.interpreter.is_none());
+        let second = active.iter().find(|run| run.run_id == "list-2").unwrap();
+        assert_eq!(second.state, RunPhase::Running);
+        assert_eq!(second.interpreter.as_deref(), Some("python3"));
+        let weather = HashMap::from([("widget".to_string(), "weather".to_string())]);
+        let filtered = registry.list_active(Some(&weather));
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].run_id, "list-1");
+
+        running.tracker().set_killing();
+        assert!(registry
+            .list_active(None)
+            .iter()
+            .any(|run| run.state == RunPhase::Killing));
+        drop(running);
+        drop(queued);
+        assert!(registry.list_active(None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancel_all_reaches_every_active_run() {
+        let registry = RunRegistry::default();
+        let first = registry
+            .register("all-1", RunDescription::default())
+            .unwrap();
+        let second = registry
+            .register("all-2", RunDescription::default())
+            .unwrap();
+
+        assert_eq!(registry.cancel_all(), 2);
+        first.cancel_signal().cancelled().await;
+        second.cancel_signal().cancelled().await;
+
+        drop((first, second));
+        assert_eq!(registry.cancel_all(), 0);
+        registry.shutdown(Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn kill_all_reports_running_and_queued_runs() {
+        let registry = RunRegistry::default();
+        let queued = registry
+            .register("kill-1", RunDescription::default())
+            .unwrap();
+        let running = registry
+            .register("kill-2", RunDescription::default())
+            .unwrap();
+        running.tracker().set_running("python3", None);
+        let stopper = tokio::spawn(async move {
+            running.cancel_signal().cancelled().await;
+            drop(running);
+        });
+
+        let summary = registry.kill_all(Duration::from_secs(5)).await;
+        assert_eq!(
+            summary,
+            KillAllSummary {
+                running: 1,
+                queued: 1,
+                terminated: 1
+            }
+        );
+        assert!(queued.cancel_signal().is_cancelled());
+        stopper.await.unwrap();
+
+        drop(queued);
+        assert_eq!(
+            registry.kill_all(Duration::from_secs(5)).await,
+            KillAllSummary::default()
+        );
+    }
+
+    #[test]
+    fn finished_results_stay_reachable_until_evicted() {
+        let registry = RunRegistry::default();
+        registry.set_result_retention(2, 60_000).unwrap();
+
+        let guard = registry
+            .register("bg-1", RunDescription::default())
+            .unwrap();
+        assert_eq!(registry.state("bg-1"), Ok(RunState::Running));
+        assert!(registry.result("bg-1").unwrap_err().contains("in progress"));
+
+        guard.finish(Err("python interpreter not found: python".to_string()));
+        assert!(!registry.is_active("bg-1"));
+        assert_eq!(registry.state("bg-1"), Ok(RunState::Failed));
+        assert!(registry
+            .register("bg-1", RunDescription::default())
+            .is_err());
+
+        for run_id in ["bg-2", "bg-3"] {
+            registry
+                .register(run_id, RunDescription::default())
+                .unwrap()
+                .finish(Err("failed".to_string()));
+        }
+        assert!(registry.state("bg-1").is_err());
+        assert!(registry.result("bg-3").is_err());
+        assert!(registry.state("bg-3").is_ok());
+        assert!(registry.set_result_retention(0, 60_000).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! Watches script files so edits made in another editor reach the dashboard.
+//! Editors often save by writing a temp file and renaming it over the
+//! script, so the script's directory is watched rather than the file, and
+//! the burst of events a save makes is reported once.
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const SCRIPT_CHANGED_EVENT: &str = "script-changed";
+/// How long a script must be left alone before its change is reported.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScriptChange {
+    /// Canonical, as given to `watch`.
+    pub path: String,
+    /// "modified", "renamed" or "deleted", judged by what is at `path` once
+    /// the changes settle. A script replaced by an atomic save is
+    /// "modified".
+    pub kind: &'static str,
+}
+
+type Sink = Arc<dyn Fn(ScriptChange) + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    sink: Option<Sink>,
+    /// Started with the first watch and dropped with the last, which also
+    /// ends the debounce thread.
+    watcher: Option<RecommendedWatcher>,
+    /// How many times each script is watched.
+    scripts: HashMap<PathBuf, usize>,
+}
+
+/// Managed Tauri state.
+#[derive(Clone, Default)]
+pub struct ScriptWatcher {
+    state: Arc<Mutex<State>>,
+}
+
+impl ScriptWatcher {
+    /// Where changes are reported, on a background thread.
+    pub fn on_change(&self, sink: impl Fn(ScriptChange) + Send + Sync + 'static) {
+        self.lock().sink = Some(Arc::new(sink));
+    }
+
+    /// Starts reporting changes to the script at canonical `script`. Each
+    /// call needs its own `unwatch`.
+    pub fn watch(&self, script: &Path) -> Result<(), String> {
+        let dir = script
+            .parent()
+            .ok_or_else(|| format!("cannot watch {}", script.display()))?;
+        let mut state = self.lock();
+        if state.watcher.is_none() {
+            let (sender, events) = mpsc::channel();
+            let watcher = notify::recommended_watcher(sender)
+                .map_err(|error| format!("failed to start watching scripts: {}", error))?;
+            let shared = self.state.clone();
+            std::thread::Builder::new()
+                .name("script-watcher".to_string())
+                .spawn(move || debounce(events, shared))
+                .map_err(|error| format!("failed to start watching scripts: {}", error))?;
+            state.watcher = Some(watcher);
+        }
+
+        let watching_dir = state
+            .scripts
+            .keys()
+            .any(|watched| watched.parent() == Some(dir));
+        if !watching_dir {
+            if let Some(watcher) = state.watcher.as_mut() {
+                watcher
+                    .watch(dir, RecursiveMode::NonRecursive)
+                    .map_err(|error| format!("failed to watch {}: {}", script.display(), error))?;
+            }
+        }
+        *state.scripts.entry(script.to_path_buf()).or_default() += 1;
+        Ok(())
+    }
+
+    /// Undoes one `watch` of `script`. `false` if it wasn't watched.
+    pub fn unwatch(&self, script: &Path) -> bool {
+        let mut state = self.lock();
+        let Some(count) = state.scripts.get_mut(script) else {
+            return false;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return true;
+        }
+
+        state.scripts.remove(script);
+        if state.scripts.is_empty() {
+            state.watcher = None;
+            return true;
+        }
+        let dir = script.parent();
+        let watching_dir = state.scripts.keys().any(|watched| watched.parent() == dir);
+        if let (false, Some(dir), Some(watcher)) = (watching_dir, dir, state.watcher.as_mut()) {
+            if let Err(error) = watcher.unwatch(dir) {
+                log::warn!("failed to stop watching {}: {}", dir.display(), error);
+            }
+        }
+        true
+    }
+
+    /// Stops every watch, as when the app exits.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.scripts.clear();
+        state.watcher = None;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+fn lock(state: &Mutex<State>) -> std::sync::MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// Collects events until none arrived for `DEBOUNCE`, then reports each
+/// watched script that was touched. Runs until the watcher is dropped.
+fn debounce(events: Receiver<notify::Result<notify::Event>>, state: Arc<Mutex<State>>) {
+    // Whether the script was renamed, by path.
+    let mut pending: HashMap<PathBuf, bool> = HashMap::new();
+    loop {
+        let received = match pending.is_empty() {
+            true => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            false => events.recv_timeout(DEBOUNCE),
+        };
+        let event = match received {
+            Ok(Ok(event)) => event,
+            Ok(Err(error)) => {
+                log::warn!("script watcher: {}", error);
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                report(std::mem::take(&mut pending), &state);
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        if matches!(event.kind, EventKind::Access(_)) {
+            continue;
+        }
+        let renamed = matches!(
+            event.kind,
+            EventKind::Modify(notify::event::ModifyKind::Name(_))
+        );
+        let state = lock(&state);
+        for path in event.paths {
+            if state.scripts.contains_key(&path) {
+                *pending.entry(path).or_default() |= renamed;
+            }
+        }
+    }
+}
+
+fn report(pending: HashMap<PathBuf, bool>, state: &Mutex<State>) {
+    let (sink, changes) = {
+        let state = lock(state);
+        let changes: Vec<ScriptChange> = pending
+            .into_iter()
+            .filter(|(path, _)| state.scripts.contains_key(path))
+            .map(|(path, renamed)| ScriptChange {
+                kind: match (path.exists(), renamed) {
+                    (true, _) => "modified",
+                    (false, true) => "renamed",
+                    (false, false) => "deleted",
+                },
+                path: path.to_string_lossy().to_string(),
+            })
+            .collect();
+        (state.sink.clone(), changes)
+    };
+    if let Some(sink) = sink {
+        for change in changes {
+            sink(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_atomic_save_is_reported_once() {
+        let dir = std::env::temp_dir().join(format!("pdd-script-watcher-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        let script = dir.join("weather.py");
+        std::fs::write(&script, "print(1)\n").unwrap();
+        std::fs::write(dir.join("other.py"), "print(1)\n").unwrap();
+
+        let watcher = ScriptWatcher::default();
+        let (sender, changes) = mpsc::channel();
+        watcher.on_change(move |change| {
+            let _ = sender.send((change.path, change.kind));
+        });
+        watcher.watch(&script).unwrap();
+        let next = || changes.recv_timeout(Duration::from_secs(5)).unwrap();
+        let path = script.to_string_lossy().to_string();
+
+        let temp = dir.join(".weather.py.swp");
+        std::fs::write(&temp, "print(2)\n").unwrap();
+        std::fs::rename(&temp, &script).unwrap();
+        std::fs::write(&script, "print(3)\n").unwrap();
+        std::fs::write(dir.join("other.py"), "print(2)\n").unwrap();
+        assert_eq!(next(), (path.clone(), "modified"));
+
+        std::fs::rename(&script, dir.join("renamed.py")).unwrap();
+        assert_eq!(next(), (path.clone(), "renamed"));
+        std::fs::write(&script, "print(4)\n").unwrap();
+        assert_eq!(next(), (path.clone(), "modified"));
+        std::fs::remove_file(&script).unwrap();
+        assert_eq!(next(), (path, "deleted"));
+
+        assert!(watcher.unwatch(&script));
+        assert!(!watcher.unwatch(&script));
+        assert!(watcher.lock().watcher.is_none());
+        std::fs::write(&script, "print(5)\n").unwrap();
+        assert!(changes.recv_timeout(DEBOUNCE * 3).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
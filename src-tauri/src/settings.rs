@@ -0,0 +1,322 @@
+use crate::fs_util::write_json_atomically;
+use crate::interpreters::DEFAULT_INTERPRETER_CACHE_TTL_MS;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+pub const SETTINGS_FILE: &str = "execution-settings.json";
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+pub const DEFAULT_MIN_TIMEOUT_MS: u64 = 1_000;
+pub const DEFAULT_MAX_TIMEOUT_MS: u64 = 120_000;
+pub const DEFAULT_SCRIPT_EXTENSIONS: &[&str] = &[".py", ".pyw"];
+pub const DEFAULT_LOCAL_VENV_DEPTH: u32 = 2;
+/// Six hours.
+pub const DEFAULT_OUTDATED_CHECK_TTL_MS: u64 = 6 * 60 * 60 * 1_000;
+const MAX_LOCAL_VENV_DEPTH: u32 = 10;
+/// Highest `max_timeout_ms` the settings accept: one day.
+const TIMEOUT_CEILING_MS: u64 = 24 * 60 * 60 * 1_000;
+
+/// Defaults applied to every run unless the request says otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutionSettings {
+    /// Start scripts at reduced OS priority.
+    pub low_priority: bool,
+    /// Added to `PYTHONPATH` for every run, after the request's own entries.
+    pub extra_python_paths: Vec<String>,
+    /// Prepended to the child's `PATH` for every run, after the request's
+    /// own entries.
+    pub extra_path_dirs: Vec<String>,
+    /// macOS and Linux: start scripts with the `PATH` of the user's login
+    /// shell instead of the app's, which is much shorter when the app is
+    /// started from the Dock or a launcher. Read once, in the background.
+    pub login_shell_path: bool,
+    /// The login shell's `PATH`, once read.
+    #[serde(skip)]
+    pub shell_path: Option<String>,
+    /// Timeout for requests that don't set `timeout_ms`.
+    pub default_timeout_ms: u64,
+    /// Requests asking for a shorter `timeout_ms` are rejected.
+    pub min_timeout_ms: u64,
+    /// Longer `timeout_ms` values are lowered to this, with a warning.
+    pub max_timeout_ms: u64,
+    /// The only directory `stdout_file` / `stderr_file` may write into.
+    /// Defaults to `script-output` in the app data dir.
+    pub output_dir: Option<String>,
+    /// Least time between two `run_python_script` starts, by script path.
+    /// A profile's `min_interval_ms` takes precedence.
+    pub min_interval_ms: HashMap<String, u64>,
+    /// Variables never passed on from the app's environment, such as a stray
+    /// `PYTHONHOME`. A request's own `env` can still set them.
+    pub strip_env: Vec<String>,
+    /// File extensions accepted as scripts, compared case-insensitively.
+    /// Files without an extension are accepted too if their shebang names
+    /// Python.
+    pub script_extensions: Vec<String>,
+    /// How long interpreter probes are reused. Zero probes every time.
+    pub interpreter_cache_ttl_ms: u64,
+    /// How many directories above a script are searched for a local venv.
+    /// Zero searches the script's own directory only.
+    pub local_venv_depth: u32,
+    /// Let runs and validations use Python 2 interpreters, which are passed
+    /// over otherwise.
+    pub allow_python2: bool,
+    /// Package index for pip, instead of the one pip is configured with.
+    /// `install_python_packages` requests can name their own.
+    pub pip_index_url: Option<String>,
+    /// How long `check_outdated_packages` results are reused.
+    pub outdated_check_ttl_ms: u64,
+}
+
+impl Default for ExecutionSettings {
+    fn default() -> Self {
+        ExecutionSettings {
+            low_priority: false,
+            extra_python_paths: Vec::new(),
+            extra_path_dirs: Vec::new(),
+            login_shell_path: false,
+            shell_path: None,
+            default_timeout_ms: DEFAULT_TIMEOUT_MS,
+            min_timeout_ms: DEFAULT_MIN_TIMEOUT_MS,
+            max_timeout_ms: DEFAULT_MAX_TIMEOUT_MS,
+            output_dir: None,
+            min_interval_ms: HashMap::new(),
+            strip_env: Vec::new(),
+            script_extensions: DEFAULT_SCRIPT_EXTENSIONS
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+            interpreter_cache_ttl_ms: DEFAULT_INTERPRETER_CACHE_TTL_MS,
+            local_venv_depth: DEFAULT_LOCAL_VENV_DEPTH,
+            allow_python2: false,
+            pip_index_url: None,
+            outdated_check_ttl_ms: DEFAULT_OUTDATED_CHECK_TTL_MS,
+        }
+    }
+}
+
+impl ExecutionSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(key) = self
+            .strip_env
+            .iter()
+            .find(|key| key.is_empty() || key.contains('='))
+        {
+            return Err(format!("invalid strip_env entry: {:?}", key));
+        }
+        if self.extra_path_dirs.iter().any(|dir| dir.trim().is_empty()) {
+            return Err("extra_path_dirs entries must not be empty".to_string());
+        }
+        if let Some(url) = self
+            .pip_index_url
+            .as_deref()
+            .filter(|url| !url.contains("://"))
+        {
+            return Err(format!("pip_index_url is not a URL: {}", url));
+        }
+        if self.script_extensions.is_empty() {
+            return Err("script_extensions must not be empty".to_string());
+        }
+        if let Some(extension) = self.script_extensions.iter().find(|extension| {
+            extension.len() < 2
+                || !extension.starts_with('.')
+                || extension.contains(['/', '\\'])
+                || extension.contains(char::is_whitespace)
+        }) {
+            return Err(format!(
+                "invalid script_extensions entry: {:?} (expected something like \".py\")",
+                extension
+            ));
+        }
+        if self.local_venv_depth > MAX_LOCAL_VENV_DEPTH {
+            return Err(format!(
+                "local_venv_depth must be at most {}",
+                MAX_LOCAL_VENV_DEPTH
+            ));
+        }
+        if self.min_timeout_ms == 0 {
+            return Err("min_timeout_ms must be at least 1".to_string());
+        }
+        if self.max_timeout_ms > TIMEOUT_CEILING_MS {
+            return Err(format!(
+                "max_timeout_ms must be at most {} (one day)",
+                TIMEOUT_CEILING_MS
+            ));
+        }
+        if self.min_timeout_ms > self.max_timeout_ms {
+            return Err("min_timeout_ms must not exceed max_timeout_ms".to_string());
+        }
+        if !(self.min_timeout_ms..=self.max_timeout_ms).contains(&self.default_timeout_ms) {
+            return Err(format!(
+                "default_timeout_ms must be between min_timeout_ms ({}) and max_timeout_ms ({})",
+                self.min_timeout_ms, self.max_timeout_ms
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn timeout_bounds(&self) -> TimeoutBounds {
+        TimeoutBounds {
+            default_ms: self.default_timeout_ms,
+            min_ms: self.min_timeout_ms,
+            max_ms: self.max_timeout_ms,
+        }
+    }
+}
+
+/// Default and accepted range for a run's `timeout_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TimeoutBounds {
+    pub default_ms: u64,
+    pub min_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Default for TimeoutBounds {
+    fn default() -> Self {
+        ExecutionSettings::default().timeout_bounds()
+    }
+}
+
+#[derive(Debug, Default)]
+struct SettingsState {
+    path: Option<PathBuf>,
+    settings: ExecutionSettings,
+    shell_path: Option<String>,
+}
+
+/// Current execution settings. Managed Tauri state; changes apply to runs
+/// started afterwards. Kept in memory only until `load` points it at a file.
+#[derive(Debug, Clone, Default)]
+pub struct SettingsStore {
+    state: Arc<RwLock<SettingsState>>,
+}
+
+impl SettingsStore {
+    /// Reads saved settings from `path`, if any, and saves future changes
+    /// there. Invalid saved settings are reported and the defaults kept.
+    pub fn load(&self, path: PathBuf) -> Result<(), String> {
+        let loaded = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice::<ExecutionSettings>(&contents)
+                .map_err(|error| error.to_string())
+                .and_then(|settings| settings.validate().map(|_| settings))
+                .map_err(|error| format!("ignoring {}: {}", path.display(), error)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                Ok(ExecutionSettings::default())
+            }
+            Err(error) => Err(format!("failed to read {}: {}", path.display(), error)),
+        };
+
+        let mut state = self.write();
+        state.path = Some(path);
+        state.settings = loaded.clone().unwrap_or_default();
+        loaded.map(|_| ())
+    }
+
+    pub fn get(&self) -> ExecutionSettings {
+        let state = self.state.read().unwrap_or_else(|error| error.into_inner());
+        ExecutionSettings {
+            shell_path: state.shell_path.clone(),
+            ..state.settings.clone()
+        }
+    }
+
+    /// Records the login shell's `PATH`, read by `login_shell::read_path`.
+    pub fn set_shell_path(&self, path: Option<String>) {
+        self.write().shell_path = path;
+    }
+
+    pub fn set(&self, settings: ExecutionSettings) -> Result<(), String> {
+        settings.validate()?;
+        let mut state = self.write();
+        if let Some(path) = &state.path {
+            write_settings(path, &settings)?;
+        }
+        state.settings = settings;
+        Ok(())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, SettingsState> {
+        self.state
+            .write()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+fn write_settings(path: &Path, settings: &ExecutionSettings) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(settings)
+        .map_err(|error| format!("failed to serialize settings: {}", error))?;
+    write_json_atomically(path, &json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_default_timeout_must_lie_within_the_bounds() {
+        let settings = ExecutionSettings {
+            default_timeout_ms: 900_000,
+            ..Default::default()
+        };
+        let error = settings.validate().unwrap_err();
+        assert!(
+            error.starts_with("default_timeout_ms must be between"),
+            "{}",
+            error
+        );
+        assert!(ExecutionSettings {
+            max_timeout_ms: 900_000,
+            ..settings
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn script_extensions_must_look_like_extensions() {
+        for extensions in [vec![], vec!["py"], vec!["."], vec![".p y"], vec![".py/x"]] {
+            let settings = ExecutionSettings {
+                script_extensions: extensions.iter().map(|e| e.to_string()).collect(),
+                ..Default::default()
+            };
+            assert!(settings.validate().is_err(), "{:?}", extensions);
+        }
+        assert!(ExecutionSettings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn settings_persist_across_loads() {
+        let dir = std::env::temp_dir().join(format!("pdd-settings-{}", std::process::id()));
+        let path = dir.join(SETTINGS_FILE);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let store = SettingsStore::default();
+        store.load(path.clone()).unwrap();
+        store
+            .set(ExecutionSettings {
+                default_timeout_ms: 900_000,
+                max_timeout_ms: 1_800_000,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(store
+            .set(ExecutionSettings {
+                min_timeout_ms: 5_000,
+                max_timeout_ms: 1_000,
+                ..Default::default()
+            })
+            .is_err());
+
+        let reloaded = SettingsStore::default();
+        reloaded.load(path.clone()).unwrap();
+        assert_eq!(reloaded.get().timeout_bounds().default_ms, 900_000);
+
+        std::fs::write(&path, r#"{"min_timeout_ms": 0}"#).unwrap();
+        let broken = SettingsStore::default();
+        assert!(broken.load(path).is_err());
+        assert_eq!(broken.get().default_timeout_ms, DEFAULT_TIMEOUT_MS);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,188 @@
+//! Reads the interpreter a script asks for on its `#!` line.
+//!
+//! Only Python interpreters are considered, and only ones that exist: a
+//! shebang for a venv that was deleted falls back to the usual candidates
+//! instead of failing the run. Arguments after the interpreter are ignored.
+
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Longer first lines are not shebangs worth honoring.
+const MAX_SHEBANG_LEN: u64 = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShebangInterpreter {
+    pub program: String,
+    pub pre_args: Vec<String>,
+}
+
+/// `None` when the script has no usable Python shebang.
+pub fn interpreter(script: &Path) -> Option<ShebangInterpreter> {
+    resolve(&read(script)?)
+}
+
+/// Whether the shebang names Python, whether or not that interpreter is
+/// installed here.
+pub fn names_python(script: &Path) -> bool {
+    read(script).is_some()
+}
+
+fn read(script: &Path) -> Option<ShebangCommand> {
+    let file = std::fs::File::open(script).ok()?;
+    let mut line = String::new();
+    BufReader::new(file.take(MAX_SHEBANG_LEN))
+        .read_line(&mut line)
+        .ok()?;
+    parse(&line)
+}
+
+/// The interpreter named on the line, e.g. `/usr/bin/python3` or, for
+/// `#!/usr/bin/env python3.11`, `python3.11`.
+#[derive(Debug, PartialEq, Eq)]
+enum ShebangCommand {
+    Path(String),
+    /// Looked up on PATH, as `env` would.
+    Env(String),
+}
+
+fn parse(line: &str) -> Option<ShebangCommand> {
+    let line = line.trim_start_matches('\u{feff}').strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let first = words.next()?;
+    let command = match file_name(first) {
+        "env" | "env.exe" => {
+            // `env -S python3 -u` and the like: skip env's own options.
+            let program = words.find(|word| !word.starts_with('-'))?;
+            ShebangCommand::Env(program.to_string())
+        }
+        _ => ShebangCommand::Path(first.to_string()),
+    };
+    let program = match &command {
+        ShebangCommand::Path(program) | ShebangCommand::Env(program) => program,
+    };
+    python_version(file_name(program))?;
+    Some(command)
+}
+
+/// `Some("3.11")` for `python3.11`, `Some("")` for `python`, `None` for
+/// anything that isn't a Python interpreter name.
+fn python_version(name: &str) -> Option<&str> {
+    let name = name.strip_suffix(".exe").unwrap_or(name);
+    let version = name.strip_prefix("python")?;
+    version
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == '.')
+        .then_some(version)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+#[cfg(not(windows))]
+fn resolve(command: &ShebangCommand) -> Option<ShebangInterpreter> {
+    let program = match command {
+        ShebangCommand::Path(path) => Path::new(path).is_file().then(|| path.clone())?,
+        ShebangCommand::Env(name) => find_on_path(name)?.to_string_lossy().to_string(),
+    };
+    Some(ShebangInterpreter {
+        program,
+        pre_args: Vec::new(),
+    })
+}
+
+/// Windows ignores shebangs, but the `py` launcher understands the Unix
+/// spellings: `#!/usr/bin/env python3.11` means `py -3.11`. A real Windows
+/// path, like a venv's `Scripts\python.exe`, is used as is.
+#[cfg(windows)]
+fn resolve(command: &ShebangCommand) -> Option<ShebangInterpreter> {
+    let program = match command {
+        ShebangCommand::Path(path) => path,
+        ShebangCommand::Env(name) => name,
+    };
+    if Path::new(program).is_absolute() && Path::new(program).is_file() {
+        return Some(ShebangInterpreter {
+            program: program.clone(),
+            pre_args: Vec::new(),
+        });
+    }
+
+    let version = python_version(file_name(program))?;
+    find_on_path("py")?;
+    Some(ShebangInterpreter {
+        program: "py".to_string(),
+        pre_args: (!version.is_empty())
+            .then(|| format!("-{}", version))
+            .into_iter()
+            .collect(),
+    })
+}
+
+fn find_on_path(name: &str) -> Option<PathBuf> {
+    let extensions: &[&str] = if cfg!(windows) { &["", ".exe"] } else { &[""] };
+    std::env::split_paths(&std::env::var_os("PATH")?).find_map(|dir| {
+        extensions
+            .iter()
+            .map(|extension| dir.join(format!("{}{}", name, extension)))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn python_shebangs_are_parsed() {
+        assert_eq!(
+            parse("#!/usr/bin/env python3.11\n"),
+            Some(ShebangCommand::Env("python3.11".to_string()))
+        );
+        assert_eq!(
+            parse("#!/usr/bin/env -S python3 -u\n"),
+            Some(ShebangCommand::Env("python3".to_string()))
+        );
+        assert_eq!(
+            parse("#! /home/me/.venvs/dash/bin/python -X utf8\n"),
+            Some(ShebangCommand::Path(
+                "/home/me/.venvs/dash/bin/python".to_string()
+            ))
+        );
+        assert_eq!(
+            parse("#!C:\\venv\\Scripts\\python.exe\r\n"),
+            Some(ShebangCommand::Path(
+                "C:\\venv\\Scripts\\python.exe".to_string()
+            ))
+        );
+        for line in [
+            "import sys\n",
+            "#!/bin/sh\n",
+            "#!/usr/bin/env node\n",
+            "#!/usr/bin/python3-config\n",
+            "#!/usr/bin/env\n",
+            "# !/usr/bin/python3\n",
+        ] {
+            assert_eq!(parse(line), None, "{}", line);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_existing_interpreters_are_used() {
+        let dir = std::env::temp_dir().join(format!("pdd-shebang-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = |name: &str, first_line: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("{}\nprint('hi')\n", first_line)).unwrap();
+            interpreter(&path)
+        };
+
+        let found = script("env.py", "#!/usr/bin/env python3").unwrap();
+        assert!(Path::new(&found.program).is_absolute());
+        assert!(found.program.ends_with("python3"));
+        assert_eq!(script("missing.py", "#!/nowhere/venv/bin/python3"), None);
+        assert_eq!(script("env_missing.py", "#!/usr/bin/env python9.99"), None);
+        assert_eq!(script("none.py", "print('no shebang')"), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! Byte-level problems in a script file that break it in confusing ways: a
+//! byte order mark in front of the `#!` line, UTF-16 text, a `#!` line
+//! ending in CRLF (so Unix looks for `python3\r`), mixed line endings and
+//! bytes that aren't UTF-8. Only the start of the file is inspected.
+
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const MAX_INSPECTED_BYTES: u64 = 8 * 1024;
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const UTF16_LE_BOM: &[u8] = b"\xff\xfe";
+const UTF16_BE_BOM: &[u8] = b"\xfe\xff";
+/// `weather.py.bak`, then `weather.py.bak.1` and so on.
+const MAX_BACKUPS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceIssue {
+    /// "utf8_bom", "utf16", "crlf_shebang", "mixed_line_endings" or
+    /// "invalid_utf8".
+    pub kind: &'static str,
+    /// Where in the file the problem starts.
+    pub offset: u64,
+    pub message: String,
+}
+
+pub fn inspect(path: &Path) -> Result<Vec<SourceIssue>, String> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)
+        .and_then(|file| file.take(MAX_INSPECTED_BYTES).read_to_end(&mut bytes))
+        .map_err(|error| format!("failed to read {}: {}", path.display(), error))?;
+    Ok(issues(&bytes))
+}
+
+fn issues(bytes: &[u8]) -> Vec<SourceIssue> {
+    if bytes.starts_with(UTF16_LE_BOM) || bytes.starts_with(UTF16_BE_BOM) {
+        return vec![SourceIssue {
+            kind: "utf16",
+            offset: 0,
+            message: "file is UTF-16 encoded; Python expects UTF-8 source".to_string(),
+        }];
+    }
+
+    let mut issues = Vec::new();
+    let body = match bytes.strip_prefix(UTF8_BOM) {
+        Some(body) => {
+            issues.push(SourceIssue {
+                kind: "utf8_bom",
+                offset: 0,
+                message: "file starts with a UTF-8 byte order mark, which hides a #! line"
+                    .to_string(),
+            });
+            body
+        }
+        None => bytes,
+    };
+    let start = (bytes.len() - body.len()) as u64;
+
+    if body.starts_with(b"#!") {
+        if let Some(end) = body.iter().position(|byte| *byte == b'\n') {
+            if end > 0 && body[end - 1] == b'\r' {
+                issues.push(SourceIssue {
+                    kind: "crlf_shebang",
+                    offset: start + end as u64 - 1,
+                    message:
+                        "#! line ends in CRLF, so Unix looks for an interpreter name ending in \\r"
+                            .to_string(),
+                });
+            }
+        }
+    }
+
+    let endings = line_endings(body);
+    let crlf = endings.iter().filter(|(_, crlf)| *crlf).count();
+    let lf = endings.len() - crlf;
+    if crlf > 0 && lf > 0 {
+        let first_crlf = endings[0].1;
+        let (offset, _) = endings
+            .iter()
+            .find(|(_, crlf)| *crlf != first_crlf)
+            .expect("both kinds are present");
+        issues.push(SourceIssue {
+            kind: "mixed_line_endings",
+            offset: start + *offset as u64,
+            message: format!("mixed line endings: {} CRLF and {} LF", crlf, lf),
+        });
+    }
+
+    if !declares_other_encoding(body) {
+        if let Err(error) = std::str::from_utf8(body) {
+            // A character cut off by the inspection limit isn't an error.
+            if error.error_len().is_some() {
+                issues.push(SourceIssue {
+                    kind: "invalid_utf8",
+                    offset: start + error.valid_up_to() as u64,
+                    message: "invalid UTF-8; Python rejects the file without a coding declaration"
+                        .to_string(),
+                });
+            }
+        }
+    }
+    issues
+}
+
+/// Offset of each `\n`, and whether a `\r` precedes it.
+fn line_endings(bytes: &[u8]) -> Vec<(usize, bool)> {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'\n')
+        .map(|(offset, _)| match offset {
+            0 => (offset, false),
+            _ if bytes[offset - 1] == b'\r' => (offset - 1, true),
+            _ => (offset, false),
+        })
+        .collect()
+}
+
+/// A PEP 263 `coding:` comment on one of the first two lines that names
+/// something other than UTF-8; non-UTF-8 bytes are expected then.
+fn declares_other_encoding(bytes: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(bytes);
+    text.lines()
+        .take(2)
+        .filter(|line| line.trim_start().starts_with('#'))
+        .find_map(|line| {
+            let at = line.find("coding")?;
+            let rest = line[at + "coding".len()..].strip_prefix([':', '='])?;
+            let name: String = rest
+                .trim_start()
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                .collect();
+            Some(name.to_ascii_lowercase())
+        })
+        .is_some_and(|name| !matches!(name.as_str(), "utf-8" | "utf8" | "utf_8"))
+}
+
+/// Rewrites the file as UTF-8 without a BOM and with LF line endings, after
+/// copying the original next to it. Returns the backup's path, or `None`
+/// when nothing needed fixing. Invalid UTF-8 is left alone.
+pub fn fix(path: &Path) -> Result<Option<PathBuf>, String> {
+    let original = std::fs::read(path)
+        .map_err(|error| format!("failed to read {}: {}", path.display(), error))?;
+    let text = if original.starts_with(UTF16_LE_BOM) {
+        encoding_rs::UTF_16LE.decode_with_bom_removal(&original).0
+    } else if original.starts_with(UTF16_BE_BOM) {
+        encoding_rs::UTF_16BE.decode_with_bom_removal(&original).0
+    } else {
+        match std::str::from_utf8(original.strip_prefix(UTF8_BOM).unwrap_or(&original)) {
+            Ok(text) => text.into(),
+            // Normalizing line endings alone would still leave the file
+            // unusable, and rewriting it risks mangling the bytes.
+            Err(_) if !original.starts_with(UTF8_BOM) => return Ok(None),
+            Err(_) => {
+                return Err(format!(
+                    "{} is not valid UTF-8 after its byte order mark; not rewriting it",
+                    path.display()
+                ))
+            }
+        }
+    };
+    let fixed = text.replace("\r\n", "\n");
+    if fixed.as_bytes() == original.as_slice() {
+        return Ok(None);
+    }
+
+    let backup = backup_path(path)?;
+    std::fs::copy(path, &backup)
+        .map_err(|error| format!("failed to back up {}: {}", path.display(), error))?;
+    std::fs::write(path, fixed)
+        .map_err(|error| format!("failed to rewrite {}: {}", path.display(), error))?;
+    Ok(Some(backup))
+}
+
+fn backup_path(path: &Path) -> Result<PathBuf, String> {
+    let name = path.as_os_str().to_string_lossy();
+    (0..MAX_BACKUPS)
+        .map(|index| match index {
+            0 => PathBuf::from(format!("{}.bak", name)),
+            _ => PathBuf::from(format!("{}.bak.{}", name, index)),
+        })
+        .find(|candidate| !candidate.exists())
+        .ok_or_else(|| format!("too many backups of {} already exist", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(bytes: &[u8]) -> Vec<(&'static str, u64)> {
+        issues(bytes)
+            .into_iter()
+            .map(|issue| (issue.kind, issue.offset))
+            .collect()
+    }
+
+    #[test]
+    fn byte_order_marks_and_line_endings_are_found() {
+        assert_eq!(kinds(b"#!/usr/bin/env python3\nprint(1)\n"), []);
+        assert_eq!(
+            kinds(b"\xef\xbb\xbf#!/usr/bin/env python3\nprint(1)\n"),
+            [("utf8_bom", 0)]
+        );
+        assert_eq!(
+            kinds(b"\xef\xbb\xbf#!/usr/bin/python3\r\nprint(1)\r\n"),
+            [("utf8_bom", 0), ("crlf_shebang", 21)]
+        );
+        assert_eq!(
+            kinds(b"import os\r\nprint(1)\nprint(2)\r\n"),
+            [("mixed_line_endings", 19)]
+        );
+        assert_eq!(kinds(b"\xff\xfe#\x00!\x00"), [("utf16", 0)]);
+        assert_eq!(kinds(b"\xfe\xff\x00#\x00!"), [("utf16", 0)]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_unless_declared() {
+        assert_eq!(kinds(b"name = 'caf\xe9'\n"), [("invalid_utf8", 11)]);
+        assert_eq!(kinds(b"# -*- coding: latin-1 -*-\nname = 'caf\xe9'\n"), []);
+        assert_eq!(
+            kinds(b"# coding=utf-8\nname = 'caf\xe9'\n"),
+            [("invalid_utf8", 26)]
+        );
+        // Cut off by the inspection limit.
+        assert_eq!(kinds(b"name = 'caf\xc3"), []);
+    }
+
+    #[test]
+    fn fixing_strips_the_bom_and_keeps_a_backup() {
+        let dir = std::env::temp_dir().join(format!("pdd-source-format-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("notepad.py");
+        let original = b"\xef\xbb\xbf#!/usr/bin/env python3\r\nprint(1)\r\n";
+        std::fs::write(&script, original).unwrap();
+
+        let backup = fix(&script).unwrap().unwrap();
+        assert_eq!(backup, dir.join("notepad.py.bak"));
+        assert_eq!(std::fs::read(&backup).unwrap(), original);
+        assert_eq!(
+            std::fs::read_to_string(&script).unwrap(),
+            "#!/usr/bin/env python3\nprint(1)\n"
+        );
+        assert_eq!(inspect(&script).unwrap(), []);
+        assert_eq!(fix(&script).unwrap(), None);
+
+        let utf16: Vec<u8> = b"\xff\xfe"
+            .iter()
+            .copied()
+            .chain("print('é')\r\n".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        std::fs::write(&script, utf16).unwrap();
+        assert_eq!(fix(&script).unwrap(), Some(dir.join("notepad.py.bak.1")));
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), "print('é')\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! Placeholder expansion for script arguments.
+//!
+//! `{{date:%Y-%m-%d}}` (format optional), `{{now_iso}}`, `{{app_data_dir}}`
+//! and `{{script_dir}}` are replaced; `{{{{` stands for a literal `{{`.
+//! Times are local. Anything else between `{{` and `}}` is rejected so a typo
+//! never reaches the script verbatim.
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, SecondsFormat};
+use std::fmt::Write;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Directories placeholders may refer to. `None` when the run has no such
+/// directory, which makes the matching placeholder an error.
+#[derive(Debug, Default)]
+pub struct TemplateDirs {
+    pub app_data_dir: Option<String>,
+    pub script_dir: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Date(String),
+    NowIso,
+}
+
+/// Parsed arguments. Directories are resolved when parsing; times are
+/// rendered right before each spawn.
+#[derive(Debug, Clone, Default)]
+pub struct ArgTemplate {
+    args: Vec<Vec<Segment>>,
+}
+
+impl ArgTemplate {
+    pub fn parse(args: &[String], dirs: &TemplateDirs) -> Result<Self, String> {
+        let args = args
+            .iter()
+            .map(|arg| parse_arg(arg, dirs).map_err(|error| format!("args: {}: {}", arg, error)))
+            .collect::<Result<_, _>>()?;
+        Ok(ArgTemplate { args })
+    }
+
+    pub fn render(&self, now: &DateTime<Local>) -> Vec<String> {
+        self.args
+            .iter()
+            .map(|segments| {
+                let mut arg = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(text) => arg.push_str(text),
+                        Segment::Date(format) => {
+                            // The format was checked when parsing.
+                            let _ = write!(arg, "{}", now.format(format));
+                        }
+                        Segment::NowIso => {
+                            arg.push_str(&now.to_rfc3339_opts(SecondsFormat::Secs, false))
+                        }
+                    }
+                }
+                arg
+            })
+            .collect()
+    }
+}
+
+fn parse_arg(arg: &str, dirs: &TemplateDirs) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut rest = arg;
+
+    while let Some(start) = rest.find("{{") {
+        literal.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        if let Some(after) = rest.strip_prefix("{{") {
+            literal.push_str("{{");
+            rest = after;
+            continue;
+        }
+
+        let end = rest
+            .find("}}")
+            .ok_or_else(|| "unterminated placeholder, write {{{{ for a literal {{".to_string())?;
+        let placeholder = rest[..end].trim();
+        rest = &rest[end + 2..];
+
+        let (name, parameter) = match placeholder.split_once(':') {
+            Some((name, parameter)) => (name.trim(), Some(parameter)),
+            None => (placeholder, None),
+        };
+        let segment = match (name, parameter) {
+            ("date", format) => {
+                let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+                if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                    return Err(format!("invalid date format: {}", format));
+                }
+                Segment::Date(format.to_string())
+            }
+            ("now_iso", None) => Segment::NowIso,
+            ("app_data_dir", None) => Segment::Literal(
+                dirs.app_data_dir
+                    .clone()
+                    .ok_or("{{app_data_dir}} is not available for this run")?,
+            ),
+            ("script_dir", None) => Segment::Literal(
+                dirs.script_dir
+                    .clone()
+                    .ok_or("{{script_dir}} is only available when running a script file")?,
+            ),
+            _ => return Err(format!("unknown placeholder: {{{{{}}}}}", placeholder)),
+        };
+
+        match segment {
+            Segment::Literal(text) => literal.push_str(&text),
+            segment => {
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(segment);
+            }
+        }
+    }
+
+    literal.push_str(rest);
+    if !literal.is_empty() || segments.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn render(args: &[&str], dirs: &TemplateDirs) -> Result<Vec<String>, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let now = Local.with_ymd_and_hms(2024, 3, 9, 7, 5, 0).unwrap();
+        ArgTemplate::parse(&args, dirs).map(|template| template.render(&now))
+    }
+
+    #[test]
+    fn placeholders_expand_inside_arguments() {
+        let dirs = TemplateDirs {
+            app_data_dir: Some("/data".to_string()),
+            script_dir: Some("/scripts".to_string()),
+        };
+
+        let args = render(
+            &[
+                "--day={{date:%Y-%m-%d}}",
+                "{{ date }}",
+                "{{app_data_dir}}/cache.json",
+                "{{script_dir}}",
+                "plain",
+                "",
+            ],
+            &dirs,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--day=2024-03-09",
+                "2024-03-09",
+                "/data/cache.json",
+                "/scripts",
+                "plain",
+                ""
+            ]
+        );
+
+        let iso = &render(&["{{now_iso}}"], &dirs).unwrap()[0];
+        assert!(iso.starts_with("2024-03-09T07:05:00"), "{}", iso);
+    }
+
+    #[test]
+    fn double_braces_can_be_escaped() {
+        let args = render(&["{{{{date}} }}", "{ a }"], &TemplateDirs::default()).unwrap();
+        assert_eq!(args, ["{{date}} }}", "{ a }"]);
+    }
+
+    #[test]
+    fn unknown_or_unavailable_placeholders_are_rejected() {
+        let dirs = TemplateDirs::default();
+        let error = render(&["{{today}}"], &dirs).unwrap_err();
+        assert!(
+            error.contains("unknown placeholder: {{today}}"),
+            "{}",
+            error
+        );
+        assert!(render(&["{{date"], &dirs).is_err());
+        assert!(render(&["{{date:%Q}}"], &dirs).is_err());
+        assert!(render(&["{{script_dir}}"], &dirs).is_err());
+        assert!(render(&["{{now_iso:x}}"], &dirs).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! Turns how a run ended into one human-readable reason.
+
+use crate::commands::RunPythonScriptResponse;
+
+const FATAL_ERROR_HEADER: &str = "Fatal Python error: ";
+
+/// Why a run ended abnormally, or `None` when it exited with code 0. Our own
+/// decisions (timeout, cancellation, limits) win over the raw exit status
+/// they caused.
+pub fn reason(response: &RunPythonScriptResponse) -> Option<String> {
+    if response.timed_out {
+        return Some("timed out".to_string());
+    }
+    if response.stopped_on_pattern {
+        return Some("stopped on pattern".to_string());
+    }
+    if response.idle_timed_out {
+        return Some("idle_timeout".to_string());
+    }
+    if response.cancelled {
+        return Some("cancelled".to_string());
+    }
+    if response.cpu_limit_exceeded {
+        return Some("exceeded CPU time limit".to_string());
+    }
+    if response.oom_killed {
+        return Some("exceeded memory limit".to_string());
+    }
+    if let Some(reason) = crash_reason(response) {
+        return Some(reason);
+    }
+    if let Some(signal) = response.signal {
+        return Some(format!("killed by {}", signal_name(signal)));
+    }
+
+    match response.exit_code {
+        Some(0) => None,
+        Some(code) => {
+            #[cfg(windows)]
+            if let Some(reason) = ntstatus_reason(code as u32) {
+                return Some(reason.to_string());
+            }
+            Some(format!("exited with code {}", code))
+        }
+        None => Some("terminated without an exit code".to_string()),
+    }
+}
+
+/// Whether the interpreter itself died, typically inside a native extension,
+/// rather than the script failing or us stopping it.
+pub fn crashed(response: &RunPythonScriptResponse) -> bool {
+    let stopped_by_us = response.timed_out
+        || response.idle_timed_out
+        || response.cancelled
+        || response.cpu_limit_exceeded
+        || response.oom_killed
+        || response.stopped_on_pattern;
+    !stopped_by_us && crash_reason(response).is_some()
+}
+
+/// What `faulthandler` printed when the interpreter died: everything from the
+/// last "Fatal Python error:" line on.
+pub fn fatal_error_report(stderr: &str) -> Option<String> {
+    let start = stderr.rfind(FATAL_ERROR_HEADER)?;
+    if start > 0 && !stderr[..start].ends_with('\n') {
+        return None;
+    }
+    Some(stderr[start..].trim_end().to_string())
+}
+
+fn crash_reason(response: &RunPythonScriptResponse) -> Option<String> {
+    #[cfg(unix)]
+    {
+        // Shell wrappers report a signal N as exit code 128 + N.
+        let signal = response.signal.or(response
+            .exit_code
+            .filter(|code| (129..=128 + 64).contains(code))
+            .map(|code| code - 128))?;
+        crash_signal(signal).map(|name| format!("crashed: {} ({})", name, signal_name(signal)))
+    }
+
+    #[cfg(windows)]
+    {
+        ntstatus_reason(response.exit_code? as u32)
+            .filter(|reason| reason.starts_with("crashed: "))
+            .map(str::to_string)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = response;
+        None
+    }
+}
+
+#[cfg(unix)]
+fn crash_signal(signal: i32) -> Option<&'static str> {
+    let name = match signal {
+        libc::SIGSEGV => "segmentation fault",
+        libc::SIGABRT => "abort",
+        libc::SIGBUS => "bus error",
+        libc::SIGILL => "illegal instruction",
+        libc::SIGFPE => "floating point exception",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(unix)]
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
+
+#[cfg(not(unix))]
+pub fn signal_name(signal: i32) -> String {
+    format!("signal {}", signal)
+}
+
+/// Windows reports crashes as NTSTATUS codes in the exit code.
+#[cfg_attr(not(any(windows, test)), allow(dead_code))]
+fn ntstatus_reason(code: u32) -> Option<&'static str> {
+    let reason = match code {
+        0xC000_0005 => "crashed: access violation (0xC0000005)",
+        0xC000_00FD => "crashed: stack overflow (0xC00000FD)",
+        0xC000_0409 => "crashed: stack buffer overrun (0xC0000409)",
+        0xC000_001D => "crashed: illegal instruction (0xC000001D)",
+        0xC000_0094 => "crashed: integer division by zero (0xC0000094)",
+        0xC000_0017 => "crashed: out of memory (0xC0000017)",
+        0xC000_0374 => "crashed: heap corruption (0xC0000374)",
+        0xC000_013A => "interrupted with Ctrl+C (0xC000013A)",
+        0xC000_0135 => "failed to start: a required DLL was not found (0xC0000135)",
+        0xC000_0142 => "failed to start: DLL initialization failed (0xC0000142)",
+        0x8000_0003 => "crashed: breakpoint (0x80000003)",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crash_codes_have_readable_reasons() {
+        assert_eq!(
+            ntstatus_reason(0xC000_0005),
+            Some("crashed: access violation (0xC0000005)")
+        );
+        assert!(ntstatus_reason(1).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crash_signals_and_wrapped_exit_codes_count_as_crashes() {
+        let ended = |signal: Option<i32>, exit_code: Option<i32>| RunPythonScriptResponse {
+            signal,
+            exit_code,
+            ..Default::default()
+        };
+
+        let segfault = ended(Some(libc::SIGSEGV), None);
+        assert!(crashed(&segfault));
+        assert_eq!(
+            reason(&segfault).as_deref(),
+            Some("crashed: segmentation fault (SIGSEGV)")
+        );
+        assert_eq!(
+            reason(&ended(None, Some(134))).as_deref(),
+            Some("crashed: abort (SIGABRT)")
+        );
+        assert!(!crashed(&ended(Some(libc::SIGKILL), None)));
+        assert!(!crashed(&ended(None, Some(1))));
+        assert!(!crashed(&RunPythonScriptResponse {
+            timed_out: true,
+            ..segfault
+        }));
+    }
+
+    #[test]
+    fn faulthandler_output_is_extracted() {
+        let stderr = "loading\nFatal Python error: Segmentation fault\n\nCurrent thread 0x00007f (most recent call first):\n  File \"/s/a.py\", line 3 in <module>\n";
+        let report = fatal_error_report(stderr).unwrap();
+        assert!(report.starts_with("Fatal Python error: Segmentation fault"));
+        assert!(report.ends_with("line 3 in <module>"));
+        assert!(fatal_error_report("print('Fatal Python error: no')\n").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signals_are_named() {
+        assert_eq!(signal_name(libc::SIGKILL), "SIGKILL");
+        assert_eq!(signal_name(libc::SIGSEGV), "SIGSEGV");
+        assert_eq!(signal_name(200), "signal 200");
+    }
+}
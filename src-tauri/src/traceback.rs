@@ -0,0 +1,240 @@
+//! Parses the CPython traceback a crashed script leaves at the end of stderr.
+//!
+//! Only the standard layout is understood. Anything else, exception groups
+//! included, yields `None` and the raw stderr stays the only record.
+
+use serde::Serialize;
+
+pub const TRACEBACK_HEADER: &str = "Traceback (most recent call last):";
+const CAUSE_SEPARATOR: &str =
+    "The above exception was the direct cause of the following exception:";
+const CONTEXT_SEPARATOR: &str =
+    "During handling of the above exception, another exception occurred:";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TracebackFrame {
+    pub file: String,
+    pub line: u32,
+    pub function: String,
+    /// Source line as printed, when Python could read it.
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParsedTraceback {
+    /// The exception that ended the run, e.g. `KeyError` or
+    /// `app.errors.FetchFailed`.
+    pub exception_type: String,
+    /// Everything after `Type: `, including continuation lines and notes.
+    pub message: String,
+    /// Outermost call first, as printed.
+    pub frames: Vec<TracebackFrame>,
+    /// Exceptions printed above this one, oldest first.
+    pub chain: Vec<ChainedTraceback>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainedTraceback {
+    pub exception_type: String,
+    pub message: String,
+    pub frames: Vec<TracebackFrame>,
+    /// How the exception printed after this one relates to it: "cause" for
+    /// `raise ... from`, "context" when it was raised while handling this one.
+    pub relation: String,
+}
+
+struct Block {
+    exception_type: String,
+    message: String,
+    frames: Vec<TracebackFrame>,
+}
+
+/// `None` unless stderr ends in a traceback.
+pub fn parse(stderr: &str) -> Option<ParsedTraceback> {
+    let lines: Vec<&str> = stderr.lines().collect();
+    let end = last_content_line(&lines)? + 1;
+    let header = lines[..end]
+        .iter()
+        .rposition(|line| line.trim_end() == TRACEBACK_HEADER)?;
+    let last = parse_block(&lines[header + 1..end])?;
+
+    let mut chain = Vec::new();
+    let mut before = &lines[..header];
+    while let Some(separator) = last_content_line(before) {
+        let relation = match before[separator].trim_end() {
+            CAUSE_SEPARATOR => "cause",
+            CONTEXT_SEPARATOR => "context",
+            _ => break,
+        };
+        let Some(block_end) = last_content_line(&before[..separator]) else {
+            break;
+        };
+        let Some(header) = before[..block_end]
+            .iter()
+            .rposition(|line| line.trim_end() == TRACEBACK_HEADER)
+        else {
+            break;
+        };
+        let Some(block) = parse_block(&before[header + 1..=block_end]) else {
+            break;
+        };
+        chain.push(ChainedTraceback {
+            exception_type: block.exception_type,
+            message: block.message,
+            frames: block.frames,
+            relation: relation.to_string(),
+        });
+        before = &before[..header];
+    }
+    chain.reverse();
+
+    Some(ParsedTraceback {
+        exception_type: last.exception_type,
+        message: last.message,
+        frames: last.frames,
+        chain,
+    })
+}
+
+/// `ValueError: bad input` or a bare `KeyboardInterrupt`. The name keeps its
+/// module prefix.
+pub fn exception_line(line: &str) -> Option<(&str, &str)> {
+    let (name, message) = match line.split_once(": ") {
+        Some((name, message)) => (name, message),
+        None => (line.trim_end().trim_end_matches(':'), ""),
+    };
+    let is_identifier = |part: &str| {
+        part.chars()
+            .next()
+            .is_some_and(|first| first.is_alphabetic() || first == '_')
+            && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+    };
+    name.split('.')
+        .all(is_identifier)
+        .then_some((name, message))
+}
+
+fn last_content_line(lines: &[&str]) -> Option<usize> {
+    lines.iter().rposition(|line| !line.trim().is_empty())
+}
+
+/// The frames and exception line(s) that follow one traceback header.
+fn parse_block(lines: &[&str]) -> Option<Block> {
+    let mut frames = Vec::new();
+    let mut index = 0;
+    while let Some(line) = lines.get(index) {
+        index += 1;
+        if let Some(mut frame) = parse_frame(line) {
+            if let Some(code) = lines
+                .get(index)
+                .filter(|code| code.starts_with("    ") && parse_frame(code).is_none())
+            {
+                frame.code = Some(code.trim().to_string());
+                index += 1;
+            }
+            frames.push(frame);
+        } else if !line.starts_with(' ') {
+            // Carets, "[Previous line repeated ...]" and the location lines
+            // of a syntax error are indented; the exception line isn't.
+            index -= 1;
+            break;
+        }
+    }
+
+    let (first, rest) = lines[index..].split_first()?;
+    let (exception_type, message) = exception_line(first)?;
+    let mut message = message.to_string();
+    for line in rest {
+        message.push('\n');
+        message.push_str(line);
+    }
+    Some(Block {
+        exception_type: exception_type.to_string(),
+        message: message.trim_end().to_string(),
+        frames,
+    })
+}
+
+/// `  File "/s/a.py", line 4, in main`
+fn parse_frame(line: &str) -> Option<TracebackFrame> {
+    let rest = line.strip_prefix("  File \"")?;
+    let (file, rest) = rest.rsplit_once("\", line ")?;
+    let (line, function) = rest.split_once(", in ")?;
+    Some(TracebackFrame {
+        file: file.to_string(),
+        line: line.parse().ok()?,
+        function: function.trim_end().to_string(),
+        code: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_and_the_exception_are_parsed() {
+        let stderr = "starting\nTraceback (most recent call last):\n  File \"/s/a.py\", line 9, in <module>\n    main()\n  File \"/s/b.py\", line 4, in main\n    return data[\"temp\"]\n           ~~~~^^^^^^^^\nKeyError: 'temp'\n";
+        let traceback = parse(stderr).unwrap();
+
+        assert_eq!(traceback.exception_type, "KeyError");
+        assert_eq!(traceback.message, "'temp'");
+        assert!(traceback.chain.is_empty());
+        assert_eq!(
+            traceback.frames,
+            [
+                TracebackFrame {
+                    file: "/s/a.py".to_string(),
+                    line: 9,
+                    function: "<module>".to_string(),
+                    code: Some("main()".to_string()),
+                },
+                TracebackFrame {
+                    file: "/s/b.py".to_string(),
+                    line: 4,
+                    function: "main".to_string(),
+                    code: Some("return data[\"temp\"]".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn chained_exceptions_are_listed_oldest_first() {
+        let stderr = "Traceback (most recent call last):\n  File \"/s/a.py\", line 3, in load\nFileNotFoundError: [Errno 2] No such file or directory: 'c.json'\n\nDuring handling of the above exception, another exception occurred:\n\nTraceback (most recent call last):\n  File \"/s/a.py\", line 5, in load\nValueError: no config\n\nThe above exception was the direct cause of the following exception:\n\nTraceback (most recent call last):\n  File \"/s/a.py\", line 9, in <module>\napp.ConfigError: startup failed\nsee the docs\n";
+        let traceback = parse(stderr).unwrap();
+
+        assert_eq!(traceback.exception_type, "app.ConfigError");
+        assert_eq!(traceback.message, "startup failed\nsee the docs");
+        let chain: Vec<(&str, &str)> = traceback
+            .chain
+            .iter()
+            .map(|chained| (chained.exception_type.as_str(), chained.relation.as_str()))
+            .collect();
+        assert_eq!(
+            chain,
+            [("FileNotFoundError", "context"), ("ValueError", "cause")]
+        );
+        assert_eq!(traceback.chain[0].frames[0].line, 3);
+    }
+
+    #[test]
+    fn syntax_errors_in_imports_keep_their_frames() {
+        let stderr = "Traceback (most recent call last):\n  File \"/s/main.py\", line 1, in <module>\n    import broken\n  File \"/s/broken.py\", line 2\n    print(\n         ^\nSyntaxError: '(' was never closed\n";
+        let traceback = parse(stderr).unwrap();
+        assert_eq!(traceback.exception_type, "SyntaxError");
+        assert_eq!(traceback.frames.len(), 1);
+    }
+
+    #[test]
+    fn other_stderr_is_not_a_traceback() {
+        for stderr in [
+            "",
+            "warning: slow API\n",
+            "Traceback (most recent call last):\n  File \"/s/a.py\", line 1, in <module>\n",
+            "  + Exception Group Traceback (most recent call last):\n  |   File \"/s/a.py\", line 1, in <module>\n  | ExceptionGroup: boom (1 sub-exception)\n  +-+---------------- 1 ----------------\n",
+        ] {
+            assert!(parse(stderr).is_none(), "{}", stderr);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! The interpreter each script last validated successfully with, so a run
+//! that ends up on a different Python says so instead of quietly producing
+//! different results. Stored as one JSON file in the app data dir.
+
+use crate::commands::ValidatePythonScriptResponse;
+use crate::fs_util::write_json_atomically;
+use crate::interpreters::InterpreterInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub const VALIDATED_INTERPRETERS_FILE: &str = "validated-interpreters.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidatedInterpreter {
+    /// `sys.executable` of the interpreter.
+    pub executable: String,
+    /// Major and minor version.
+    pub version: (u32, u32),
+}
+
+impl ValidatedInterpreter {
+    fn of(info: &InterpreterInfo) -> Self {
+        ValidatedInterpreter {
+            executable: info.executable.clone(),
+            version: (info.version_info.0, info.version_info.1),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidatedInterpreter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}.{})",
+            self.executable, self.version.0, self.version.1
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    path: Option<PathBuf>,
+    /// By canonical script path.
+    scripts: BTreeMap<String, ValidatedInterpreter>,
+}
+
+/// Managed Tauri state; kept in memory only until `load` points it at a
+/// file.
+#[derive(Debug, Clone, Default)]
+pub struct ValidatedInterpreters {
+    state: Arc<Mutex<State>>,
+}
+
+impl ValidatedInterpreters {
+    /// Reads what was recorded before from `path` and saves future changes
+    /// there.
+    pub fn load(&self, path: PathBuf) -> Result<(), String> {
+        let scripts = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|error| format!("failed to parse {}: {}", path.display(), error))?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+        };
+
+        let mut state = self.lock();
+        state.path = Some(path);
+        state.scripts = scripts;
+        Ok(())
+    }
+
+    /// Remembers the interpreter of a successful validation. Anything else,
+    /// including a validation whose interpreter could not be probed, is
+    /// ignored.
+    pub fn record(&self, response: &ValidatePythonScriptResponse) {
+        let (true, Some(script_path), Some(info)) = (
+            response.valid,
+            &response.script_path,
+            &response.interpreter_info,
+        ) else {
+            return;
+        };
+
+        let validated = ValidatedInterpreter::of(info);
+        let mut state = self.lock();
+        if state.scripts.get(script_path) == Some(&validated) {
+            return;
+        }
+        state.scripts.insert(script_path.clone(), validated);
+        if let Err(error) = write_scripts(&state) {
+            log::warn!("{}", error);
+        }
+    }
+
+    /// What the script at canonical `script_path` last validated with.
+    pub fn get(&self, script_path: &str) -> Option<ValidatedInterpreter> {
+        self.lock().scripts.get(script_path).cloned()
+    }
+
+    /// A warning when `info`, the interpreter a run of the script at
+    /// canonical `script_path` used, is not the one it was validated with.
+    pub fn changed(&self, script_path: &str, info: &InterpreterInfo) -> Option<String> {
+        let state = self.lock();
+        let validated = state.scripts.get(script_path)?;
+        let used = ValidatedInterpreter::of(info);
+        (used != *validated).then(|| {
+            format!(
+                "ran with {}, but the script was last validated with {}",
+                used, validated
+            )
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+fn write_scripts(state: &State) -> Result<(), String> {
+    let Some(path) = &state.path else {
+        return Ok(());
+    };
+
+    let json = serde_json::to_vec_pretty(&state.scripts)
+        .map_err(|error| format!("failed to serialize validated interpreters: {}", error))?;
+    write_json_atomically(path, &json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(executable: &str, version_info: (u32, u32, u32)) -> InterpreterInfo {
+        InterpreterInfo {
+            executable: executable.to_string(),
+            version: format!("{}.{}.{}", version_info.0, version_info.1, version_info.2),
+            version_info,
+            implementation: "cpython".to_string(),
+            platform: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            prefix: "/usr".to_string(),
+            pointer_bits: 64,
+            platform_tag: "linux-x86_64".to_string(),
+            is_virtualenv: false,
+        }
+    }
+
+    fn validation(valid: bool, info: InterpreterInfo) -> ValidatePythonScriptResponse {
+        ValidatePythonScriptResponse {
+            valid,
+            script_path: Some("/scripts/weather.py".to_string()),
+            interpreter_info: Some(info),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn runs_on_another_interpreter_are_flagged() {
+        let dir =
+            std::env::temp_dir().join(format!("pdd-validated-interpreters-{}", std::process::id()));
+        let path = dir.join(VALIDATED_INTERPRETERS_FILE);
+        let _ = std::fs::remove_dir_all(&dir);
+        let venv = info("/home/me/venv/bin/python", (3, 11, 4));
+
+        let store = ValidatedInterpreters::default();
+        store.load(path.clone()).unwrap();
+        assert_eq!(store.changed("/scripts/weather.py", &venv), None);
+        store.record(&validation(false, venv.clone()));
+        assert!(!path.exists());
+        store.record(&validation(true, venv.clone()));
+
+        let reloaded = ValidatedInterpreters::default();
+        reloaded.load(path).unwrap();
+        assert_eq!(reloaded.changed("/scripts/weather.py", &venv), None);
+        assert_eq!(
+            reloaded.changed(
+                "/scripts/weather.py",
+                &info("/home/me/venv/bin/python", (3, 11, 9))
+            ),
+            None
+        );
+        assert_eq!(
+            reloaded
+                .changed("/scripts/weather.py", &info("/usr/bin/python3", (3, 8, 10)))
+                .as_deref(),
+            Some(
+                "ran with /usr/bin/python3 (3.8), but the script was last validated with /home/me/venv/bin/python (3.11)"
+            )
+        );
+        assert!(reloaded
+            .changed(
+                "/scripts/weather.py",
+                &info("/home/me/venv/bin/python", (3, 12, 0))
+            )
+            .is_some());
+        assert_eq!(
+            reloaded.changed("/scripts/other.py", &info("/usr/bin/python3", (3, 8, 10))),
+            None
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
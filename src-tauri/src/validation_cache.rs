@@ -0,0 +1,223 @@
+//! Reuse of recent `validate_python_script` results while the script is
+//! unchanged. A script counts as changed when its size or modification time
+//! does, or its contents when the platform reports no modification time.
+
+use crate::commands::{ValidatePythonScriptRequest, ValidatePythonScriptResponse};
+use crate::python_settings::PythonSettings;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+const MAX_CACHE_ENTRIES: usize = 200;
+/// Interpreters come and go too, so even an unchanged script is checked
+/// again after this long.
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Everything a validation result depends on. Serialized as the cache key.
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    script_path: &'a Path,
+    size: u64,
+    modified_ns: Option<u128>,
+    content_hash: Option<u64>,
+    python_path: Option<&'a str>,
+    conda_env: Option<&'a str>,
+    py_version: Option<&'a str>,
+    python_requirement: Option<&'a str>,
+    runner: Option<&'a str>,
+    wsl_distro: Option<&'a str>,
+    allow_python2: Option<bool>,
+    use_local_venv: Option<bool>,
+    use_project_env: Option<bool>,
+    local_venv_depth: u32,
+    python_settings: &'a PythonSettings,
+    min_python_version: Option<&'a str>,
+    check_syntax: bool,
+    check_imports: bool,
+    required_stdlib: &'a [String],
+}
+
+/// `None` when `script` can't be read, so nothing is cached for it.
+pub fn key(request: &ValidatePythonScriptRequest, script: &Path) -> Option<String> {
+    let metadata = std::fs::metadata(script).ok()?;
+    let modified_ns = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_nanos());
+    let content_hash = match modified_ns {
+        Some(_) => None,
+        None => {
+            let mut hasher = DefaultHasher::new();
+            std::fs::read(script).ok()?.hash(&mut hasher);
+            Some(hasher.finish())
+        }
+    };
+    let key = CacheKey {
+        script_path: script,
+        size: metadata.len(),
+        modified_ns,
+        content_hash,
+        python_path: request
+            .python_path
+            .as_deref()
+            .map(str::trim)
+            .filter(|path| !path.is_empty()),
+        conda_env: request
+            .conda_env
+            .as_deref()
+            .map(str::trim)
+            .filter(|env| !env.is_empty()),
+        py_version: request
+            .py_version
+            .as_deref()
+            .map(str::trim)
+            .filter(|version| !version.is_empty()),
+        python_requirement: request
+            .python_requirement
+            .as_deref()
+            .map(str::trim)
+            .filter(|requirement| !requirement.is_empty()),
+        runner: request
+            .runner
+            .as_deref()
+            .map(str::trim)
+            .filter(|runner| !runner.is_empty()),
+        wsl_distro: request
+            .wsl_distro
+            .as_deref()
+            .map(str::trim)
+            .filter(|distro| !distro.is_empty()),
+        allow_python2: request.allow_python2,
+        use_local_venv: request.use_local_venv,
+        use_project_env: request.use_project_env,
+        local_venv_depth: request.local_venv_depth,
+        python_settings: &request.python_settings,
+        min_python_version: request
+            .min_python_version
+            .as_deref()
+            .map(str::trim)
+            .filter(|version| !version.is_empty()),
+        check_syntax: request.check_syntax,
+        check_imports: request.check_imports,
+        required_stdlib: &request.required_stdlib,
+    };
+    serde_json::to_string(&key).ok()
+}
+
+#[derive(Debug)]
+struct Entry {
+    script_path: PathBuf,
+    response: ValidatePythonScriptResponse,
+    expires_at: Instant,
+}
+
+/// Recent validation results by key. Managed Tauri state.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl ValidationCache {
+    pub fn get(&self, key: &str) -> Option<ValidatePythonScriptResponse> {
+        let mut entries = self.lock();
+        let entry = entries.get(key)?;
+        if entry.expires_at <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+
+        let mut response = entry.response.clone();
+        response.from_cache = true;
+        Some(response)
+    }
+
+    pub fn insert(&self, key: String, script_path: &Path, response: &ValidatePythonScriptResponse) {
+        let now = Instant::now();
+        let mut entries = self.lock();
+        entries.retain(|_, entry| entry.expires_at > now);
+        if entries.len() >= MAX_CACHE_ENTRIES && !entries.contains_key(&key) {
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+
+        entries.insert(
+            key,
+            Entry {
+                script_path: script_path.to_path_buf(),
+                response: response.clone(),
+                expires_at: now + CACHE_TTL,
+            },
+        );
+    }
+
+    /// Drops the entries for one script, or everything. `script_path` must
+    /// be canonical. Returns how many entries were removed.
+    pub fn invalidate(&self, script_path: Option<&Path>) -> usize {
+        let mut entries = self.lock();
+        let before = entries.len();
+        match script_path {
+            Some(script_path) => entries.retain(|_, entry| entry.script_path != script_path),
+            None => entries.clear(),
+        }
+        before - entries.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_change_with_the_file_and_the_interpreter() {
+        let dir =
+            std::env::temp_dir().join(format!("pdd-validation-cache-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("weather.py");
+        std::fs::write(&script, "print('hi')\n").unwrap();
+        let request = |python_path: Option<&str>| ValidatePythonScriptRequest {
+            script_path: script.to_string_lossy().to_string(),
+            python_path: python_path.map(str::to_string),
+            ..Default::default()
+        };
+
+        let before = key(&request(None), &script).unwrap();
+        assert_eq!(key(&request(Some("  ")), &script).unwrap(), before);
+        assert_ne!(
+            key(&request(Some("/venv/bin/python")), &script).unwrap(),
+            before
+        );
+        std::fs::write(&script, "print('hello')\n").unwrap();
+        let after = key(&request(None), &script).unwrap();
+        assert_ne!(after, before);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(key(&request(None), &script), None);
+
+        let cache = ValidationCache::default();
+        let response = ValidatePythonScriptResponse {
+            valid: true,
+            ..Default::default()
+        };
+        cache.insert(after.clone(), &script, &response);
+        assert!(cache.get(&after).unwrap().from_cache);
+        assert!(cache.get(&before).is_none());
+        assert_eq!(cache.invalidate(Some(&dir.join("other.py"))), 0);
+        assert_eq!(cache.invalidate(Some(&script)), 1);
+        assert!(cache.get(&after).is_none());
+    }
+}
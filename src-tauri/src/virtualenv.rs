@@ -0,0 +1,81 @@
+//! Where things are inside a virtualenv, and whether a directory may become
+//! one. `python -m venv` itself runs through the ordinary execution path.
+
+use std::path::{Path, PathBuf};
+
+/// Marks a directory as a virtualenv.
+pub const VENV_CONFIG_FILE: &str = "pyvenv.cfg";
+
+/// The interpreter of the virtualenv at `venv`, whether or not it exists.
+pub fn python_in(venv: &Path) -> PathBuf {
+    match cfg!(windows) {
+        true => venv.join("Scripts").join("python.exe"),
+        false => venv.join("bin").join("python"),
+    }
+}
+
+/// A virtualenv, rather than any directory that happens to be called
+/// `.venv` or `.env`.
+pub fn is_venv(dir: &Path) -> bool {
+    dir.join(VENV_CONFIG_FILE).is_file() && python_in(dir).is_file()
+}
+
+/// What creating a virtualenv at a path would do to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Nothing is there yet.
+    New,
+    /// An empty directory.
+    Empty,
+    /// A directory with contents, which `--clear` deletes.
+    Replace,
+}
+
+/// Refuses to touch a directory with contents unless `force` is set.
+pub fn check_target(dir: &Path, force: bool) -> Result<Target, String> {
+    let metadata = match std::fs::metadata(dir) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Target::New),
+        Err(error) => return Err(format!("failed to inspect {}: {}", dir.display(), error)),
+    };
+    if !metadata.is_dir() {
+        return Err(format!("{} exists and is not a directory", dir.display()));
+    }
+
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|error| format!("failed to read {}: {}", dir.display(), error))?;
+    match (entries.next().is_some(), force) {
+        (false, _) => Ok(Target::Empty),
+        (true, true) => Ok(Target::Replace),
+        (true, false) => Err(format!(
+            "{} is not empty; pass force: true to replace its contents",
+            dir.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_empty_or_forced_directories_are_used() {
+        let dir = std::env::temp_dir().join(format!("pdd-virtualenv-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(check_target(&dir, false), Ok(Target::New));
+
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(check_target(&dir, false), Ok(Target::Empty));
+
+        std::fs::write(dir.join("notes.txt"), "keep me").unwrap();
+        assert!(check_target(&dir, false)
+            .unwrap_err()
+            .contains("is not empty; pass force: true"));
+        assert_eq!(check_target(&dir, true), Ok(Target::Replace));
+        assert!(check_target(&dir.join("notes.txt"), true)
+            .unwrap_err()
+            .contains("is not a directory"));
+        assert!(!is_venv(&dir));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+//! Running scripts inside a WSL distribution: `wsl.exe` starts the
+//! distribution's `python3` on the script's `/mnt/<drive>/...` path. Killing
+//! `wsl.exe` leaves the Linux process running, so each run starts it as the
+//! leader of a new session, records its pid and signals that process group
+//! through `wsl.exe` when the run has to stop.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+pub const PROGRAM: &str = "wsl.exe";
+pub const DEFAULT_PYTHON: &str = "python3";
+
+/// The first call after boot starts the WSL VM.
+const LIST_TIMEOUT: Duration = Duration::from_secs(20);
+const SIGNAL_TIMEOUT: Duration = Duration::from_secs(10);
+/// Run by `setsid -w` with the pid file as `$0`: the shell's pid is also
+/// the new session's process group, and `exec` hands both to Python.
+const LAUNCH_SCRIPT: &str = r#"echo $$ > "$0" && exec "$@""#;
+/// Signal `$0` to the process group recorded in the file `$1`.
+const SIGNAL_SCRIPT: &str = r#"kill -s "$0" -- "-$(cat "$1")" 2>/dev/null"#;
+
+/// How `path` looks from inside WSL, as `wslpath` would put it: drive
+/// paths move under `/mnt/<drive>`, `\\wsl$\<distro>\...` and
+/// `\\wsl.localhost\<distro>\...` paths are the distribution's own, and
+/// POSIX or relative paths only change separators.
+pub fn to_linux_path(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        return Ok(path.to_string());
+    }
+    let windows = path.replace('/', "\\");
+    let windows = match windows.strip_prefix(r"\\?\") {
+        Some(rest) => match rest.strip_prefix(r"UNC\") {
+            Some(unc) => format!(r"\\{}", unc),
+            None => rest.to_string(),
+        },
+        None => windows,
+    };
+    let join = |parts: &[&str]| {
+        parts
+            .iter()
+            .filter(|part| !part.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+
+    if let Some(unc) = windows.strip_prefix(r"\\") {
+        let parts: Vec<&str> = unc.split('\\').collect();
+        let server = parts[0];
+        if !(server.eq_ignore_ascii_case("wsl$") || server.eq_ignore_ascii_case("wsl.localhost")) {
+            return Err(format!("{} is a network path WSL can't reach", path));
+        }
+        return Ok(format!("/{}", join(parts.get(2..).unwrap_or_default())));
+    }
+    let bytes = windows.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        let drive = (bytes[0] as char).to_ascii_lowercase();
+        let rest: Vec<&str> = windows[2..].split('\\').collect();
+        return Ok(format!("/mnt/{}/{}", drive, join(&rest)));
+    }
+    if windows.starts_with('\\') {
+        return Err(format!("{} has no drive letter", path));
+    }
+    Ok(windows.replace('\\', "/"))
+}
+
+/// `-d <distro>`, or nothing for the default distribution.
+fn distro_args(distro: Option<&str>) -> Vec<String> {
+    match distro {
+        Some(distro) => vec!["-d".to_string(), distro.to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// Arguments to `PROGRAM` that start `python` in the distribution, as
+/// interpreter probes need.
+pub fn python_args(distro: Option<&str>, python: &str) -> Vec<String> {
+    let mut args = distro_args(distro);
+    args.extend(["--exec".to_string(), python.to_string()]);
+    args
+}
+
+/// Every installed distribution. A missing `wsl.exe` is a `NotFound` error.
+pub async fn distributions() -> Result<Vec<String>, std::io::Error> {
+    let mut command = Command::new(PROGRAM);
+    command
+        .args(["-l", "-q"])
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    crate::process_tree::hide_console(&mut command);
+
+    let output = match tokio::time::timeout(LIST_TIMEOUT, command.output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out running {} -l", PROGRAM),
+            ))
+        }
+    };
+    // With no distribution installed wsl.exe exits non-zero, printing help.
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+    Ok(parse_distributions(&output.stdout))
+}
+
+/// `wsl.exe -l` writes UTF-16LE, except when `WSL_UTF8` is set.
+fn parse_distributions(output: &[u8]) -> Vec<String> {
+    let text = match output.len() % 2 == 0 && output.iter().skip(1).step_by(2).any(|b| *b == 0) {
+        true => {
+            let units: Vec<u16> = output
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        false => String::from_utf8_lossy(output).to_string(),
+    };
+    text.lines()
+        .map(|line| line.trim_matches(|c: char| c.is_whitespace() || c == '\u{feff}'))
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// One run inside WSL, with every path already as the distribution sees it.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// `None` for the default distribution.
+    pub distro: Option<String>,
+    pub python: String,
+    /// For script files.
+    pub script: Option<String>,
+    pub working_dir: String,
+    pid_file: String,
+}
+
+impl Session {
+    /// The pid file goes in `scratch`, which is removed with the run.
+    pub fn new(
+        distro: Option<&str>,
+        python: Option<&str>,
+        script: Option<&Path>,
+        working_dir: &Path,
+        scratch: &Path,
+    ) -> Result<Self, String> {
+        let linux = |path: &Path| to_linux_path(&path.to_string_lossy());
+        Ok(Session {
+            distro: distro
+                .map(str::trim)
+                .filter(|distro| !distro.is_empty())
+                .map(str::to_string),
+            python: python
+                .map(str::trim)
+                .filter(|python| !python.is_empty())
+                .unwrap_or(DEFAULT_PYTHON)
+                .to_string(),
+            script: script.map(linux).transpose()?,
+            working_dir: linux(working_dir)?,
+            pid_file: linux(&scratch.join("wsl.pid"))?,
+        })
+    }
+
+    /// Arguments to `PROGRAM` that start `python` in `working_dir` as a new
+    /// session, followed by whatever Python is given.
+    pub fn launch_args(&self) -> Vec<String> {
+        let mut args = distro_args(self.distro.as_deref());
+        args.extend(
+            [
+                "--cd",
+                &self.working_dir,
+                "--exec",
+                "setsid",
+                "-w",
+                "sh",
+                "-c",
+                LAUNCH_SCRIPT,
+                &self.pid_file,
+                &self.python,
+            ]
+            .map(str::to_string),
+        );
+        args
+    }
+
+    /// Sends `signal` ("TERM", "KILL") to the script's process group. Does
+    /// nothing once the group is gone.
+    pub async fn signal(&self, signal: &str) {
+        let mut command = Command::new(PROGRAM);
+        command
+            .args(distro_args(self.distro.as_deref()))
+            .args(["--exec", "sh", "-c", SIGNAL_SCRIPT, signal, &self.pid_file])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+        crate::process_tree::hide_console(&mut command);
+        match tokio::time::timeout(SIGNAL_TIMEOUT, command.status()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(error)) => log::warn!("failed to signal WSL process: {}", error),
+            Err(_) => log::warn!("timed out signalling WSL process"),
+        }
+    }
+}
+
+/// Passes the variables set on `command` through to Linux with `WSLENV`,
+/// which WSL otherwise leaves behind. `path_vars` hold one Windows path
+/// each and are translated; so are the entries of `PYTHONPATH`.
+pub fn forward_env(command: &mut Command, path_vars: &[&str]) {
+    let forwarded: Vec<String> = command
+        .as_std()
+        .get_envs()
+        .filter(|(_, value)| value.is_some())
+        .map(|(key, _)| key.to_string_lossy().to_string())
+        .filter(|key| key != "WSLENV")
+        .map(|key| match key.as_str() {
+            "PYTHONPATH" => format!("{}/l", key),
+            name if path_vars.contains(&name) => format!("{}/p", key),
+            _ => key,
+        })
+        .collect();
+    if forwarded.is_empty() {
+        return;
+    }
+    let wslenv: Vec<String> = std::env::var("WSLENV")
+        .ok()
+        .filter(|wslenv| !wslenv.is_empty())
+        .into_iter()
+        .chain(forwarded)
+        .collect();
+    command.env("WSLENV", wslenv.join(":"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_paths_are_translated_like_wslpath() {
+        let cases = [
+            (
+                r"C:\Users\me\scripts\weather.py",
+                "/mnt/c/Users/me/scripts/weather.py",
+            ),
+            (r"\\?\D:\data\run", "/mnt/d/data/run"),
+            ("e:/mixed/separators", "/mnt/e/mixed/separators"),
+            (r"C:\", "/mnt/c/"),
+            (r"\\wsl$\Ubuntu\home\me\job.py", "/home/me/job.py"),
+            (r"\\?\UNC\wsl.localhost\Debian\srv", "/srv"),
+            ("/home/me/job.py", "/home/me/job.py"),
+            (r"scripts\job.py", "scripts/job.py"),
+        ];
+        for (windows, linux) in cases {
+            assert_eq!(to_linux_path(windows).as_deref(), Ok(linux), "{}", windows);
+        }
+        assert!(to_linux_path(r"\\fileserver\share\job.py")
+            .unwrap_err()
+            .contains("network path"));
+        assert!(to_linux_path(r"\rooted").is_err());
+    }
+
+    #[test]
+    fn distribution_lists_are_decoded() {
+        let utf16: Vec<u8> = "\u{feff}Ubuntu-22.04\r\n\r\nDebian\r\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(parse_distributions(&utf16), ["Ubuntu-22.04", "Debian"]);
+        assert_eq!(
+            parse_distributions(b"Ubuntu\nkali-linux\n"),
+            ["Ubuntu", "kali-linux"]
+        );
+        assert!(parse_distributions(b"").is_empty());
+
+        let session = Session::new(
+            Some(" Debian "),
+            None,
+            Some(Path::new(r"C:\jobs\a b.py")),
+            Path::new(r"C:\jobs"),
+            Path::new(r"C:\Temp\pdd-scratch"),
+        )
+        .unwrap();
+        assert_eq!(session.script.as_deref(), Some("/mnt/c/jobs/a b.py"));
+        assert_eq!(
+            python_args(session.distro.as_deref(), &session.python),
+            ["-d", "Debian", "--exec", "python3"]
+        );
+        let launch = session.launch_args();
+        assert_eq!(launch[..4], ["-d", "Debian", "--cd", "/mnt/c/jobs"]);
+        assert_eq!(
+            launch[launch.len() - 2..],
+            ["/mnt/c/Temp/pdd-scratch/wsl.pid", "python3"]
+        );
+    }
+}